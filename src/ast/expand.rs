@@ -0,0 +1,419 @@
+use super::*;
+use crate::error::{Result, SlangError};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+
+// 展開した結果の中の呼び出しも続けて展開する。自分自身を呼び続けるマクロはこの深さで止める
+const MAX_EXPANSION_DEPTH: usize = 64;
+
+// 関数の本体にある `名前!(引数, ...)` を、マクロの本体の識別子パターンを引数の式で置き換えたものに展開する。
+// 文として書かれた呼び出しはその位置に本体の文を並べる。式の中に書けるのは本体が式 1 つだけのマクロに限る。
+// 本体で束縛する変数は展開ごとに新しい名前にするので、呼び出し側の同じ名前の変数とは混ざらない
+pub fn expand_macros(program: &mut Program) -> Result<()> {
+    let mut macros = HashMap::new();
+    collect_macros(&program.items, &mut macros)?;
+    let mut expander = Expander { macros, depth: 0, expansions: 0, error: None };
+    expand_items(&mut program.items, &mut expander);
+    expander.error.map_or(Ok(()), Err)
+}
+
+// モジュールの中のマクロも含めて名前で引けるようにする。モジュールは名前空間を分けない
+fn collect_macros(items: &[Item], macros: &mut HashMap<String, Macro>) -> Result<()> {
+    for item in items {
+        match item {
+            Item::Macro(macro_) => match macros.entry(macro_.name.clone()) {
+                Entry::Occupied(_) => {
+                    return Err(SlangError::Syntax(format!("Macro `{}` is defined more than once", macro_.name)));
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(macro_.clone());
+                }
+            },
+            Item::Module(module) => collect_macros(&module.items, macros)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn expand_items(items: &mut [Item], expander: &mut Expander) {
+    for item in items {
+        match item {
+            Item::Function(function) => expander.visit_function_mut(function),
+            Item::Module(module) => expand_items(&mut module.items, expander),
+            _ => {}
+        }
+    }
+}
+
+struct Expander {
+    macros: HashMap<String, Macro>,
+    depth: usize,
+    // これまでに展開した回数。本体で束縛する変数の新しい名前に使う
+    expansions: usize,
+    // 最初に見つかったエラー。`VisitorMut` は失敗を返せないので、ここに残して走査は続ける
+    error: Option<SlangError>,
+}
+
+impl Expander {
+    fn fail(&mut self, error: SlangError) {
+        self.error.get_or_insert(error);
+    }
+
+    // 呼び出しを展開したブロック。中にある呼び出しも展開済み
+    fn expand(&mut self, call: &CallExpression) -> Option<Block> {
+        if self.depth >= MAX_EXPANSION_DEPTH {
+            self.fail(SlangError::Syntax(format!(
+                "Macro `{}` expands recursively more than {} times",
+                call.function, MAX_EXPANSION_DEPTH
+            )));
+            return None;
+        }
+        let mut body = self.instantiate(call)?;
+        self.depth += 1;
+        self.visit_block_mut(&mut body);
+        self.depth -= 1;
+        Some(body)
+    }
+
+    // 引数をパターンに当てはめ、本体の中の名前を置き換える
+    fn instantiate(&mut self, call: &CallExpression) -> Option<Block> {
+        let name = call.function.strip_suffix('!')?;
+        let Some(macro_) = self.macros.get(name).cloned() else {
+            self.fail(SlangError::Syntax(format!("Undefined macro: {}", call.function)));
+            return None;
+        };
+        if macro_.patterns.len() != call.arguments.len() {
+            self.fail(SlangError::Syntax(format!(
+                "Macro `{}` expects {} arguments, got {}",
+                call.function,
+                macro_.patterns.len(),
+                call.arguments.len()
+            )));
+            return None;
+        }
+        let mut bindings = HashMap::new();
+        for (pattern, argument) in macro_.patterns.iter().zip(&call.arguments) {
+            match &pattern.pattern {
+                Pattern::Identifier(parameter) => {
+                    bindings.insert(parameter.clone(), argument.clone());
+                }
+                Pattern::Wildcard => {}
+                pattern => {
+                    self.fail(SlangError::Syntax(format!(
+                        "Macro `{}` only supports identifier patterns, got `{}`",
+                        call.function, pattern
+                    )));
+                    return None;
+                }
+            }
+        }
+        let mut body = macro_.body;
+        self.rename_locals(&mut body, &bindings);
+        let mut substitute = Substitute { bindings: &bindings, error: None };
+        substitute.visit_block_mut(&mut body);
+        if let Some(error) = substitute.error {
+            self.fail(error);
+            return None;
+        }
+        Some(body)
+    }
+}
+
+impl Expander {
+    // 本体で束縛する名前 (パターンの名前を除く) を `名前$mN` にする。引数の式を当てはめる前に行うので、
+    // 引数に書かれた呼び出し側の変数は元の名前のまま。`$` はソースの識別子に使えないので衝突しない
+    fn rename_locals(&mut self, body: &mut Block, parameters: &HashMap<String, Expression>) {
+        let mut binders = Binders::default();
+        binders.visit_block(body);
+        let renames: HashMap<String, String> = binders
+            .names
+            .into_iter()
+            .filter(|name| !parameters.contains_key(name))
+            .map(|name| {
+                let fresh = format!("{}$m{}", name, self.expansions);
+                (name, fresh)
+            })
+            .collect();
+        self.expansions += 1;
+        if !renames.is_empty() {
+            Rename { renames: &renames }.visit_block_mut(body);
+        }
+    }
+}
+
+// 文としての呼び出しはブロックの中で本体の文に置き換えるので、`visit_block_mut` で扱う
+fn macro_call(statement: &Statement) -> Option<&CallExpression> {
+    match statement {
+        Statement::Expression(expression) => match expression.as_ref() {
+            Expression::Call(call) if call.function.ends_with('!') => Some(call),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+impl VisitorMut for Expander {
+    fn visit_block_mut(&mut self, block: &mut Block) {
+        for mut statement in std::mem::take(&mut block.statements) {
+            if let Some(call) = macro_call(&statement) {
+                if let Some(mut body) = self.expand(call) {
+                    block.statements.append(&mut body.statements);
+                }
+                continue;
+            }
+            self.visit_statement_mut(&mut statement);
+            block.statements.push(statement);
+        }
+    }
+
+    fn visit_expression_mut(&mut self, expression: &mut Expression) {
+        let Expression::Call(call) = expression else {
+            return walk_expression_mut(self, expression);
+        };
+        if !call.function.ends_with('!') {
+            return walk_expression_mut(self, expression);
+        }
+        let Some(body) = self.expand(call) else {
+            return;
+        };
+        match <[Statement; 1]>::try_from(body.statements) {
+            Ok([Statement::Expression(value)]) => *expression = *value,
+            _ => {
+                let error = format!("Macro `{}` expands to statements and cannot be used as an expression", call.function);
+                self.fail(SlangError::Syntax(error));
+            }
+        }
+    }
+}
+
+// `let`・`for`・パターン・ラムダの引数で束縛される名前
+#[derive(Default)]
+struct Binders {
+    names: HashSet<String>,
+}
+
+impl Visitor for Binders {
+    fn visit_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Let(stmt) => {
+                self.names.insert(stmt.name.clone());
+            }
+            Statement::For(stmt) => {
+                self.names.insert(stmt.variable.clone());
+            }
+            Statement::IfLet(IfLetStatement { pattern, .. }) | Statement::WhileLet(WhileLetStatement { pattern, .. }) => {
+                pattern_names(pattern, &mut self.names);
+            }
+            Statement::Match(stmt) => {
+                for arm in &stmt.arms {
+                    pattern_names(&arm.pattern, &mut self.names);
+                }
+            }
+            _ => {}
+        }
+        walk_statement(self, statement);
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        if let Expression::Lambda { params, .. } = expression {
+            self.names.extend(params.iter().map(|param| param.name.clone()));
+        }
+        walk_expression(self, expression);
+    }
+}
+
+fn pattern_names(pattern: &Pattern, names: &mut HashSet<String>) {
+    match pattern {
+        Pattern::Identifier(name) => {
+            names.insert(name.clone());
+        }
+        Pattern::Tuple(patterns) => patterns.iter().for_each(|pattern| pattern_names(pattern, names)),
+        Pattern::Struct { fields, .. } => fields.iter().for_each(|field| pattern_names(&field.pattern, names)),
+        Pattern::Literal(_) | Pattern::Wildcard => {}
+    }
+}
+
+// 束縛している位置も参照している位置も同じ新しい名前にする
+struct Rename<'a> {
+    renames: &'a HashMap<String, String>,
+}
+
+impl Rename<'_> {
+    fn rename(&self, name: &mut String) {
+        if let Some(fresh) = self.renames.get(name) {
+            *name = fresh.clone();
+        }
+    }
+
+    fn rename_pattern(&self, pattern: &mut Pattern) {
+        match pattern {
+            Pattern::Identifier(name) => self.rename(name),
+            Pattern::Tuple(patterns) => patterns.iter_mut().for_each(|pattern| self.rename_pattern(pattern)),
+            Pattern::Struct { fields, .. } => fields.iter_mut().for_each(|field| self.rename_pattern(&mut field.pattern)),
+            Pattern::Literal(_) | Pattern::Wildcard => {}
+        }
+    }
+}
+
+impl VisitorMut for Rename<'_> {
+    fn visit_statement_mut(&mut self, statement: &mut Statement) {
+        match statement {
+            Statement::Let(stmt) => self.rename(&mut stmt.name),
+            Statement::For(stmt) => self.rename(&mut stmt.variable),
+            Statement::IfLet(IfLetStatement { pattern, .. }) | Statement::WhileLet(WhileLetStatement { pattern, .. }) => {
+                self.rename_pattern(pattern);
+            }
+            Statement::Match(stmt) => stmt.arms.iter_mut().for_each(|arm| self.rename_pattern(&mut arm.pattern)),
+            Statement::Transfer(stmt) => {
+                self.rename(&mut stmt.from);
+                self.rename(&mut stmt.to);
+            }
+            _ => {}
+        }
+        walk_statement_mut(self, statement);
+    }
+
+    fn visit_expression_mut(&mut self, expression: &mut Expression) {
+        match expression {
+            Expression::Identifier(name) => self.rename(name),
+            Expression::Assignment(assignment) => self.rename(&mut assignment.target),
+            // ラムダを入れた変数の呼び出し
+            Expression::Call(call) => self.rename(&mut call.function),
+            Expression::Lambda { params, .. } => params.iter_mut().for_each(|param| self.rename(&mut param.name)),
+            _ => {}
+        }
+        walk_expression_mut(self, expression);
+    }
+}
+
+// マクロの本体で、パターンの名前を呼び出し側の引数の式に置き換える。置き換えた式の中はそれ以上置き換えない
+struct Substitute<'a> {
+    bindings: &'a HashMap<String, Expression>,
+    error: Option<SlangError>,
+}
+
+impl VisitorMut for Substitute<'_> {
+    fn visit_expression_mut(&mut self, expression: &mut Expression) {
+        match expression {
+            Expression::Identifier(name) => {
+                if let Some(argument) = self.bindings.get(name) {
+                    *expression = argument.clone();
+                }
+            }
+            Expression::Assignment(assignment) => {
+                // 代入先は変数名なので、変数を渡されたときだけ置き換えられる
+                match self.bindings.get(&assignment.target) {
+                    Some(Expression::Identifier(target)) => assignment.target = target.clone(),
+                    Some(argument) => {
+                        self.error.get_or_insert(SlangError::Syntax(format!(
+                            "Cannot assign to `{}` in a macro: `{}` is not a variable",
+                            assignment.target, argument
+                        )));
+                    }
+                    None => {}
+                }
+                walk_expression_mut(self, expression);
+            }
+            _ => walk_expression_mut(self, expression),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn expand(source: &str) -> Result<Program> {
+        let mut program = Parser::new(Lexer::new(source)).parse_program()?;
+        expand_macros(&mut program)?;
+        Ok(program)
+    }
+
+    fn function_body(program: &Program, name: &str) -> Vec<String> {
+        let function = program
+            .items
+            .iter()
+            .find_map(|item| match item {
+                Item::Function(function) if function.name == name => Some(function),
+                _ => None,
+            })
+            .unwrap();
+        function.body.statements.iter().map(|statement| statement.to_string().trim().to_string()).collect()
+    }
+
+    #[test]
+    fn test_swap_macro_is_substituted_in_place() {
+        let program = expand(
+            "macro swap(a, b) { let tmp = a; a = b; b = tmp; } \
+             fn main() -> int { let mut x = 1; let mut y = 2; swap!(x, y); return x; }",
+        )
+        .unwrap();
+        assert_eq!(function_body(&program, "main"), vec![
+            "let mut x = 1;",
+            "let mut y = 2;",
+            "let tmp$m0 = x;",
+            "x = y;",
+            "y = tmp$m0;",
+            "return x;",
+        ]);
+    }
+
+    #[test]
+    fn test_macro_locals_do_not_capture_caller_variables() {
+        let swap = "macro swap(a, b) { let tmp = a; a = b; b = tmp; } ";
+        let program = expand(&format!("{}fn main() -> void {{ let mut tmp = 1; let mut y = 2; swap!(tmp, y); swap!(y, tmp); }}", swap))
+            .unwrap();
+        assert_eq!(function_body(&program, "main"), vec![
+            "let mut tmp = 1;",
+            "let mut y = 2;",
+            "let tmp$m0 = tmp;",
+            "tmp = y;",
+            "y = tmp$m0;",
+            "let tmp$m1 = y;",
+            "y = tmp;",
+            "tmp = tmp$m1;",
+        ]);
+        crate::run(&format!(
+            "{}fn main() -> void {{ let mut tmp = 1; let mut y = 2; swap!(tmp, y); assert_eq(tmp, 2); assert_eq(y, 1); }}",
+            swap
+        ))
+        .unwrap();
+
+        // `for` の変数・パターン・ラムダの引数も呼び出し側の変数と混ざらない
+        let source = "macro sum_to(n, out) { let mut acc = 0; for i in 0..n { acc = acc + i; } \
+                                             let add = fn(i: int) -> int { return i + acc; }; out = add(0); } \
+                      fn main() -> void { let i = 10; let mut acc = 3; let mut add = 0; sum_to!(i, add); \
+                                          assert_eq(add, 45); assert_eq(acc, 3); }";
+        crate::run(source).unwrap();
+    }
+
+    #[test]
+    fn test_expression_macros_and_nested_calls() {
+        let program = expand(
+            "macro square(v) { v * v; } macro twice_square(v) { square!(v) + square!(v); } \
+             fn f(n: int) -> int { return twice_square!(n + 1); }",
+        )
+        .unwrap();
+        assert_eq!(function_body(&program, "f"), vec!["return (n + 1) * (n + 1) + (n + 1) * (n + 1);"]);
+
+        let error = |source: &str| expand(source).unwrap_err().to_string();
+        assert!(error("fn f() -> void { nothing!(1); }").contains("Undefined macro: nothing!"));
+        assert!(error("macro m(a) { a; } fn f() -> void { m!(1, 2); }").contains("expects 1 arguments, got 2"));
+        assert!(error("macro m(a) { a = 1; } fn f() -> void { m!(2); }").contains("is not a variable"));
+        assert!(error("macro m(a) { let x = a; a; } fn f() -> int { return m!(1); }").contains("cannot be used as an expression"));
+        assert!(error("macro m(a) { m!(a); } fn f() -> void { m!(1); }").contains("expands recursively"));
+        assert!(error("macro m(a) { a; } module inner { macro m(b) { b; } }").contains("defined more than once"));
+    }
+
+    #[test]
+    fn test_expanded_program_type_checks_and_runs() {
+        let source = "macro swap(a, b) { let tmp = a; a = b; b = tmp; } \
+                      fn main() -> int { let mut x = 1; let mut y = 2; swap!(x, y); return x * 10 + y; }";
+        let ir = crate::compile(source).unwrap();
+        assert_eq!(ir.functions.len(), 1);
+        crate::run(source).unwrap();
+    }
+}
@@ -1,6 +1,14 @@
+use crate::lexer::escape;
 use crate::type_system::Type;
 use std::fmt;
 
+mod expand;
+mod span;
+mod visitor;
+pub use expand::expand_macros;
+pub use span::SpanMap;
+pub use visitor::*;
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct AST {
     pub functions: Vec<Function>,
@@ -24,6 +32,67 @@ impl AST {
     }
 }
 
+impl Default for AST {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// `AST` の型定義は優先所有格のない構造体として `Program` に移す
+impl From<AST> for Program {
+    fn from(ast: AST) -> Self {
+        let structs = ast.type_definitions.into_iter().map(Item::from);
+        Self { items: structs.chain(ast.functions.into_iter().map(Item::Function)).collect() }
+    }
+}
+
+// コンパイラが扱う形に平らにする。モジュールの中の関数と構造体は出現順に並べ、
+// IR に対応するもののないトレイトとマクロは落とす
+impl From<Program> for AST {
+    fn from(program: Program) -> Self {
+        fn flatten(items: Vec<Item>, ast: &mut AST) {
+            for item in items {
+                match item {
+                    Item::Function(function) => ast.add_function(function),
+                    Item::Struct(struct_) => ast.add_type_definition(TypeDefinition::from(&struct_)),
+                    Item::Module(module) => flatten(module.items, ast),
+                    Item::Trait(_) | Item::Macro(_) => {}
+                }
+            }
+        }
+        let mut ast = AST::new();
+        flatten(program.items, &mut ast);
+        ast
+    }
+}
+
+impl From<TypeDefinition> for Item {
+    fn from(definition: TypeDefinition) -> Self {
+        Item::Struct(Struct {
+            name: definition.name,
+            type_params: Vec::new(),
+            fields: definition
+                .fields
+                .into_iter()
+                .map(|field| StructField { name: field.name, type_: field.type_annotation, priority: None })
+                .collect(),
+        })
+    }
+}
+
+impl From<&Struct> for TypeDefinition {
+    fn from(struct_: &Struct) -> Self {
+        Self {
+            name: struct_.name.clone(),
+            fields: struct_
+                .fields
+                .iter()
+                .map(|field| Field { name: field.name.clone(), type_annotation: field.type_.clone() })
+                .collect(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Program {
     pub items: Vec<Item>,
@@ -41,9 +110,13 @@ pub enum Item {
 #[derive(Debug, Clone, PartialEq)]
 pub struct Function {
     pub name: String,
+    // `fn identity<T>` の `T`。本体では `Type::Named` として現れる
+    pub type_params: Vec<String>,
     pub parameters: Vec<Parameter>,
     pub return_type: Type,
-    pub priority: i32,
+    // `priority 2, 1` で指定する優先度の列。先頭が最も重く、同じ値なら次の値で比べる
+    // (`MemoryPriority::MultiLevel` と同じ順序)。空なら既定の優先度 0 として扱う
+    pub priorities: Vec<i32>,
     pub body: Block,
 }
 
@@ -51,6 +124,8 @@ pub struct Function {
 pub struct Parameter {
     pub name: String,
     pub type_annotation: Type,
+    // `mut n: int` と書いた引数だけ本体の中で代入できる
+    pub mutable: bool,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -75,17 +150,33 @@ pub enum Statement {
     Let(LetStatement),
     Return(ReturnStatement),
     If(IfStatement),
+    IfLet(IfLetStatement),
     While(WhileStatement),
+    WhileLet(WhileLetStatement),
     For(ForStatement),
     Match(MatchStatement),
+    Break,
+    Continue,
+    Transfer(TransferStatement),
     Expression(Box<Expression>),
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct LetStatement {
     pub name: String,
+    // `let mut` なら再代入できる
+    pub mutable: bool,
     pub type_annotation: Option<Type>,
     pub value: Box<Expression>,
+    // `Var:type:priority:` で指定した優先所有格
+    pub priority: Option<MemoryPriority>,
+}
+
+// `transfer x -> y;`。`x` の優先所有格を `y` に移す
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransferStatement {
+    pub from: String,
+    pub to: String,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -106,6 +197,23 @@ pub struct WhileStatement {
     pub body: Block,
 }
 
+// `if let パターン = 式 { .. } else { .. }`。パターンで束縛した変数は then 側だけで使える
+#[derive(Debug, Clone, PartialEq)]
+pub struct IfLetStatement {
+    pub pattern: Pattern,
+    pub expression: Box<Expression>,
+    pub then_block: Block,
+    pub else_block: Option<Block>,
+}
+
+// `while let パターン = 式 { .. }`。毎回 `式` を評価し、合わなくなったら抜ける
+#[derive(Debug, Clone, PartialEq)]
+pub struct WhileLetStatement {
+    pub pattern: Pattern,
+    pub expression: Box<Expression>,
+    pub body: Block,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct ForStatement {
     pub variable: String,
@@ -140,6 +248,19 @@ pub struct FieldPattern {
     pub pattern: Box<Pattern>,
 }
 
+// 式の結合の強さ。パーサの優先順位 (parse_assignment → parse_postfix) と同じ順
+pub(crate) const ASSIGNMENT: u8 = 0;
+pub(crate) const RANGE: u8 = 1;
+pub(crate) const OR: u8 = 2;
+pub(crate) const AND: u8 = 3;
+pub(crate) const EQUALITY: u8 = 4;
+pub(crate) const COMPARISON: u8 = 5;
+pub(crate) const TERM: u8 = 6;
+pub(crate) const FACTOR: u8 = 7;
+pub(crate) const CAST: u8 = 8;
+pub(crate) const UNARY: u8 = 9;
+pub(crate) const POSTFIX: u8 = 10;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Expression {
     Literal(Literal),
@@ -148,6 +269,46 @@ pub enum Expression {
     UnaryOp(Box<UnaryOpExpression>),
     Call(Box<CallExpression>),
     Assignment(Box<AssignmentExpression>),
+    Array(Vec<Expression>),
+    Tuple(Vec<Expression>),
+    Index {
+        array: Box<Expression>,
+        index: Box<Expression>,
+    },
+    FieldAccess {
+        base: Box<Expression>,
+        field: String,
+    },
+    StructLiteral {
+        name: String,
+        fields: Vec<(String, Expression)>,
+    },
+    // `receiver.method(args)`。`Call` が関数名をスコープから解決するのに対し、
+    // メソッドはレシーバの型とメソッド名の組で解決する。同名の関数があっても
+    // `a.f(x)` が `f(a, x)` にフォールバックすることはない
+    MethodCall {
+        receiver: Box<Expression>,
+        method: String,
+        arguments: Vec<Expression>,
+    },
+    // `start..end` / `start..=end`。今のところ `for` の繰り返し対象としてだけ使える
+    Range {
+        start: Box<Expression>,
+        end: Box<Expression>,
+        inclusive: bool,
+    },
+    // `expr as type`。変換できる型の組は `TypeCast::is_cast_allowed` で決まる
+    Cast {
+        expr: Box<Expression>,
+        target: Type,
+    },
+    // `fn(x: int) -> int { ... }`。戻り値の型を省略すると本体の `return` から推論する。
+    // 評価した時点で本体が参照する外側の変数の値を捕まえる
+    Lambda {
+        params: Vec<Parameter>,
+        return_type: Option<Type>,
+        body: Block,
+    },
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -155,7 +316,10 @@ pub enum Literal {
     Int(i64),
     Float(f64),
     Bool(bool),
+    Char(char),
     String(String),
+    // 実部と虚部。ソースの虚数リテラル `3i` は実部が 0
+    Complex(f64, f64),
     Null,
 }
 
@@ -194,7 +358,7 @@ pub enum BinaryOperator {
 #[derive(Debug, Clone, PartialEq)]
 pub struct UnaryOpExpression {
     pub op: UnaryOperator,
-    pub right: Box<Expression>,
+    pub expr: Box<Expression>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -207,7 +371,7 @@ pub enum UnaryOperator {
 #[derive(Debug, Clone, PartialEq)]
 pub struct CallExpression {
     pub function: String,
-    pub arguments: Vec<Box<Expression>>,
+    pub arguments: Vec<Expression>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -219,6 +383,7 @@ pub struct AssignmentExpression {
 #[derive(Debug, Clone, PartialEq)]
 pub struct Struct {
     pub name: String,
+    pub type_params: Vec<String>,
     pub fields: Vec<StructField>,
 }
 
@@ -293,6 +458,31 @@ pub enum MemoryPriority {
     MostHigh,
 }
 
+impl MemoryPriority {
+    // 実行時に記録する優先度の列。`most_high`/`most_low` はどの段階よりも上/下に置く
+    pub fn levels(&self) -> Vec<i32> {
+        match self {
+            MemoryPriority::Level(level) => vec![*level],
+            MemoryPriority::MultiLevel(levels) => levels.clone(),
+            MemoryPriority::MostLow => vec![i32::MIN],
+            MemoryPriority::MostHigh => vec![i32::MAX],
+        }
+    }
+}
+
+impl fmt::Display for MemoryPriority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MemoryPriority::Level(level) => write!(f, "{}", level),
+            MemoryPriority::MultiLevel(levels) => {
+                write!(f, "{}", levels.iter().map(|l| l.to_string()).collect::<Vec<_>>().join(", "))
+            }
+            MemoryPriority::MostLow => write!(f, "most_low"),
+            MemoryPriority::MostHigh => write!(f, "most_high"),
+        }
+    }
+}
+
 impl fmt::Display for Program {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         for item in &self.items {
@@ -326,15 +516,38 @@ impl fmt::Display for AST {
     }
 }
 
+impl Function {
+    pub fn primary_priority(&self) -> i32 {
+        self.priorities.first().copied().unwrap_or(0)
+    }
+}
+
 impl fmt::Display for Function {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "fn {}({}) -> {} [priority: {}] {}", self.name, self.parameters.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", "), self.return_type, self.priority, self.body)
+        write!(f, "fn {}{}({}) -> {}{} {}", self.name, format_type_params(&self.type_params), self.parameters.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", "), self.return_type, format_priorities(&self.priorities), self.body)
+    }
+}
+
+// パーサが読む `priority 2, 1` の形
+fn format_priorities(priorities: &[i32]) -> String {
+    if priorities.is_empty() {
+        String::new()
+    } else {
+        format!(" priority {}", priorities.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", "))
+    }
+}
+
+fn format_type_params(type_params: &[String]) -> String {
+    if type_params.is_empty() {
+        String::new()
+    } else {
+        format!("<{}>", type_params.join(", "))
     }
 }
 
 impl fmt::Display for Parameter {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}: {}", self.name, self.type_annotation)
+        write!(f, "{}{}: {}", if self.mutable { "mut " } else { "" }, self.name, self.type_annotation)
     }
 }
 
@@ -356,17 +569,25 @@ impl fmt::Display for Statement {
             Statement::Let(stmt) => write!(f, "{}", stmt),
             Statement::Return(stmt) => write!(f, "{}", stmt),
             Statement::If(stmt) => write!(f, "{}", stmt),
+            Statement::IfLet(stmt) => write!(f, "{}", stmt),
             Statement::While(stmt) => write!(f, "{}", stmt),
+            Statement::WhileLet(stmt) => write!(f, "{}", stmt),
             Statement::For(stmt) => write!(f, "{}", stmt),
             Statement::Match(stmt) => write!(f, "{}", stmt),
-            Statement::Expression(expr) => write!(f, "{}", expr),
+            Statement::Break => writeln!(f, "break;"),
+            Statement::Continue => writeln!(f, "continue;"),
+            Statement::Transfer(stmt) => writeln!(f, "transfer {} -> {};", stmt.from, stmt.to),
+            Statement::Expression(expr) => writeln!(f, "{};", expr),
         }
     }
 }
 
 impl fmt::Display for LetStatement {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "let {}", self.name)?;
+        if let Some(priority) = &self.priority {
+            write!(f, "Var:type:priority: {} ", priority)?;
+        }
+        write!(f, "let {}{}", if self.mutable { "mut " } else { "" }, self.name)?;
         if let Some(type_) = &self.type_annotation {
             write!(f, ": {}", type_)?;
         }
@@ -386,14 +607,30 @@ impl fmt::Display for ReturnStatement {
 
 impl fmt::Display for IfStatement {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f, "if {} {{", self.condition)?;
+        writeln!(f, "if {} {{", Condition(&self.condition))?;
+        for stmt in &self.then_block.statements {
+            write!(f, "    {}", stmt)?;
+        }
+        if let Some(else_block) = &self.else_block {
+            writeln!(f, "}} else {{")?;
+            for stmt in &else_block.statements {
+                write!(f, "    {}", stmt)?;
+            }
+        }
+        writeln!(f, "}}")
+    }
+}
+
+impl fmt::Display for IfLetStatement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "if let {} = {} {{", self.pattern, Condition(&self.expression))?;
         for stmt in &self.then_block.statements {
-            writeln!(f, "    {}", stmt)?;
+            write!(f, "    {}", stmt)?;
         }
         if let Some(else_block) = &self.else_block {
             writeln!(f, "}} else {{")?;
             for stmt in &else_block.statements {
-                writeln!(f, "    {}", stmt)?;
+                write!(f, "    {}", stmt)?;
             }
         }
         writeln!(f, "}}")
@@ -402,9 +639,19 @@ impl fmt::Display for IfStatement {
 
 impl fmt::Display for WhileStatement {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f, "while {} {{", self.condition)?;
+        writeln!(f, "while {} {{", Condition(&self.condition))?;
+        for stmt in &self.body.statements {
+            write!(f, "    {}", stmt)?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+impl fmt::Display for WhileLetStatement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "while let {} = {} {{", self.pattern, Condition(&self.expression))?;
         for stmt in &self.body.statements {
-            writeln!(f, "    {}", stmt)?;
+            write!(f, "    {}", stmt)?;
         }
         writeln!(f, "}}")
     }
@@ -412,9 +659,9 @@ impl fmt::Display for WhileStatement {
 
 impl fmt::Display for ForStatement {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f, "for {} in {} {{", self.variable, self.iterator)?;
+        writeln!(f, "for {} in {} {{", self.variable, Condition(&self.iterator))?;
         for stmt in &self.body.statements {
-            writeln!(f, "    {}", stmt)?;
+            write!(f, "    {}", stmt)?;
         }
         writeln!(f, "}}")
     }
@@ -422,9 +669,9 @@ impl fmt::Display for ForStatement {
 
 impl fmt::Display for MatchStatement {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f, "match {} {{", self.expression)?;
+        writeln!(f, "match {} {{", Condition(&self.expression))?;
         for arm in &self.arms {
-            writeln!(f, "    {}", arm)?;
+            write!(f, "    {}", arm)?;
         }
         writeln!(f, "}}")
     }
@@ -434,7 +681,7 @@ impl fmt::Display for MatchArm {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(f, "{} => {{", self.pattern)?;
         for stmt in &self.body.statements {
-            writeln!(f, "        {}", stmt)?;
+            write!(f, "        {}", stmt)?;
         }
         writeln!(f, "    }},")
     }
@@ -456,14 +703,14 @@ impl fmt::Display for Pattern {
                 write!(f, ")")
             }
             Pattern::Struct { name, fields } => {
-                write!(f, "{} {{", name)?;
+                write!(f, "{} {{ ", name)?;
                 for (i, field) in fields.iter().enumerate() {
                     if i > 0 {
                         write!(f, ", ")?;
                     }
                     write!(f, "{}: {}", field.name, field.pattern)?;
                 }
-                write!(f, "}}")
+                write!(f, " }}")
             }
             Pattern::Wildcard => write!(f, "_"),
         }
@@ -476,6 +723,74 @@ impl fmt::Display for FieldPattern {
     }
 }
 
+impl Expression {
+    pub(crate) fn precedence(&self) -> u8 {
+        match self {
+            Expression::Assignment(_) => ASSIGNMENT,
+            Expression::Range { .. } => RANGE,
+            Expression::BinaryOp(bin) => bin.op.precedence(),
+            Expression::Cast { .. } => CAST,
+            Expression::UnaryOp(_) => UNARY,
+            _ => POSTFIX,
+        }
+    }
+}
+
+impl BinaryOperator {
+    pub(crate) fn precedence(&self) -> u8 {
+        match self {
+            BinaryOperator::Or => OR,
+            BinaryOperator::And => AND,
+            BinaryOperator::Eq | BinaryOperator::Neq | BinaryOperator::Equals | BinaryOperator::NotEquals => EQUALITY,
+            BinaryOperator::Lt
+            | BinaryOperator::Lte
+            | BinaryOperator::Gt
+            | BinaryOperator::Gte
+            | BinaryOperator::LessThan
+            | BinaryOperator::GreaterThan
+            | BinaryOperator::LessThanEquals
+            | BinaryOperator::GreaterThanEquals => COMPARISON,
+            BinaryOperator::Add | BinaryOperator::Sub => TERM,
+            BinaryOperator::Mul
+            | BinaryOperator::Div
+            | BinaryOperator::Mod
+            | BinaryOperator::Divide
+            | BinaryOperator::Modulo => FACTOR,
+        }
+    }
+}
+
+// 結合が `min_precedence` より弱い式は括弧で囲んで書く
+fn write_operand(f: &mut fmt::Formatter<'_>, expr: &Expression, min_precedence: u8) -> fmt::Result {
+    if expr.precedence() < min_precedence {
+        write!(f, "({})", expr)
+    } else {
+        write!(f, "{}", expr)
+    }
+}
+
+// if/while/for/match の条件。構造体リテラルの `{` がブロックの始まりと読まれないよう、含む場合は全体を括弧で囲む
+struct Condition<'a>(&'a Expression);
+
+impl fmt::Display for Condition<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        struct FindStructLiteral(bool);
+        impl Visitor for FindStructLiteral {
+            fn visit_expression(&mut self, expression: &Expression) {
+                self.0 |= matches!(expression, Expression::StructLiteral { .. });
+                walk_expression(self, expression);
+            }
+        }
+        let mut finder = FindStructLiteral(false);
+        finder.visit_expression(self.0);
+        if finder.0 {
+            write!(f, "({})", self.0)
+        } else {
+            write!(f, "{}", self.0)
+        }
+    }
+}
+
 impl fmt::Display for Expression {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -485,19 +800,92 @@ impl fmt::Display for Expression {
             Expression::UnaryOp(expr) => write!(f, "{}", expr),
             Expression::Call(expr) => write!(f, "{}", expr),
             Expression::Assignment(expr) => write!(f, "{}", expr),
+            Expression::Array(elements) => {
+                write!(f, "[")?;
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", element)?;
+                }
+                write!(f, "]")
+            }
+            Expression::Tuple(elements) => {
+                write!(f, "(")?;
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", element)?;
+                }
+                if elements.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            }
+            Expression::Index { array, index } => {
+                write_operand(f, array, POSTFIX)?;
+                write!(f, "[{}]", index)
+            }
+            Expression::Range { start, end, inclusive } => {
+                write_operand(f, start, RANGE + 1)?;
+                write!(f, "{}", if *inclusive { "..=" } else { ".." })?;
+                write_operand(f, end, RANGE + 1)
+            }
+            Expression::FieldAccess { base, field } => {
+                write_operand(f, base, POSTFIX)?;
+                write!(f, ".{}", field)
+            }
+            Expression::Cast { expr, target } => {
+                write_operand(f, expr, CAST)?;
+                write!(f, " as {}", target)
+            }
+            Expression::Lambda { params, return_type, body } => {
+                write!(f, "fn({})", params.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", "))?;
+                if let Some(return_type) = return_type {
+                    write!(f, " -> {}", return_type)?;
+                }
+                write!(f, " {}", body)
+            }
+            Expression::StructLiteral { name, fields } => {
+                write!(f, "{} {{ ", name)?;
+                for (i, (field, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", field, value)?;
+                }
+                write!(f, " }}")
+            }
+            Expression::MethodCall { receiver, method, arguments } => {
+                write_operand(f, receiver, POSTFIX)?;
+                write!(f, ".{}(", method)?;
+                for (i, arg) in arguments.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ")")
+            }
         }
     }
 }
 
+// 二項演算は左結合なので、右辺は同じ強さの演算でも括弧で囲む
 impl fmt::Display for BinaryOpExpression {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{} {} {}", self.left, self.op, self.right)
+        let precedence = self.op.precedence();
+        write_operand(f, &self.left, precedence)?;
+        write!(f, " {} ", self.op)?;
+        write_operand(f, &self.right, precedence + 1)
     }
 }
 
 impl fmt::Display for UnaryOpExpression {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}{}", self.op, self.right)
+        write!(f, "{}", self.op)?;
+        write_operand(f, &self.expr, UNARY)
     }
 }
 
@@ -524,9 +912,16 @@ impl fmt::Display for Literal {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Literal::Int(i) => write!(f, "{}", i),
+            // `1.0` を `1` と書くと整数として読み直されるので小数点を残す
+            Literal::Float(fl) if fl.is_finite() && fl.fract() == 0.0 => write!(f, "{:.1}", fl),
             Literal::Float(fl) => write!(f, "{}", fl),
             Literal::Bool(b) => write!(f, "{}", b),
-            Literal::String(s) => write!(f, "\"{}\"", s),
+            Literal::Char(c) => write!(f, "'{}'", escape(&c.to_string(), '\'')),
+            // 波括弧は文字列補間と区別するため二重にする
+            Literal::String(s) => write!(f, "\"{}\"", escape(&s.replace('{', "{{").replace('}', "}}"), '"')),
+            // 虚数リテラルは `3i` / `1.5i`。実部のある値は加算の形で書く
+            Literal::Complex(re, im) if *re == 0.0 => write!(f, "{}i", im),
+            Literal::Complex(re, im) => write!(f, "({} + {}i)", re, im),
             Literal::Null => write!(f, "null"),
         }
     }
@@ -572,9 +967,9 @@ impl fmt::Display for UnaryOperator {
 
 impl fmt::Display for Block {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{{\n")?;
+        writeln!(f, "{{")?;
         for stmt in &self.statements {
-            writeln!(f, "  {}", stmt)?;
+            write!(f, "  {}", stmt)?;
         }
         write!(f, "}}")
     }
@@ -582,27 +977,36 @@ impl fmt::Display for Block {
 
 impl fmt::Display for Struct {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "struct {} {{", self.name)?;
+        write!(f, "struct {}{} {{", self.name, format_type_params(&self.type_params))?;
         for (i, field) in self.fields.iter().enumerate() {
             if i > 0 {
                 write!(f, ", ")?;
             }
-            write!(f, "{}: {}", field.name, field.type_)?;
+            write!(f, "{}", field)?;
         }
         write!(f, "}}")
     }
 }
 
+// 多段階の優先所有格は `,` がフィールドの区切りと紛れないように `[..]` で囲む
+impl fmt::Display for StructField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.type_)?;
+        match &self.priority {
+            Some(priority @ MemoryPriority::MultiLevel(_)) => write!(f, " @[{}]", priority),
+            Some(priority) => write!(f, " @{}", priority),
+            None => Ok(()),
+        }
+    }
+}
+
 impl fmt::Display for Trait {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "trait {} {{", self.name)?;
-        for (i, method) in self.methods.iter().enumerate() {
-            if i > 0 {
-                write!(f, ", ")?;
-            }
-            write!(f, "{}", method)?;
+        for method in &self.methods {
+            write!(f, " {};", method)?;
         }
-        write!(f, "}}")
+        write!(f, " }}")
     }
 }
 
@@ -621,8 +1025,8 @@ impl fmt::Display for Module {
 
 impl fmt::Display for Macro {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "macro {} {{", self.name)?;
-        write!(f, "}}")
+        let patterns: Vec<String> = self.patterns.iter().map(|pattern| pattern.pattern.to_string()).collect();
+        write!(f, "macro {}({}) {}", self.name, patterns.join(", "), self.body)
     }
 }
 
@@ -0,0 +1,34 @@
+use std::ops::Range;
+
+// パーサが記録したソース上の範囲 (バイト位置)。AST 自体には位置を持たせず、番号で対応させる
+// - `functions[i]` は `AST::functions[i]` の範囲。先頭の `Function:type:priority:` も含む
+// - `statements[i]` は `Visitor` が i 番目に訪れる文の範囲 (関数の順、ラムダの本体の中の文も含む)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SpanMap {
+    pub functions: Vec<Range<usize>>,
+    pub statements: Vec<Range<usize>>,
+}
+
+impl SpanMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn function(&self, index: usize) -> Option<Range<usize>> {
+        self.functions.get(index).cloned()
+    }
+
+    pub fn statement(&self, index: usize) -> Option<Range<usize>> {
+        self.statements.get(index).cloned()
+    }
+
+    // `offset` を含む関数の番号
+    pub fn function_at(&self, offset: usize) -> Option<usize> {
+        self.functions.iter().position(|span| span.contains(&offset))
+    }
+
+    // `offset` を含むもっとも内側の文の番号。文は外側から順に並んでいるので最後に見つかったものが内側
+    pub fn statement_at(&self, offset: usize) -> Option<usize> {
+        self.statements.iter().rposition(|span| span.contains(&offset))
+    }
+}
@@ -0,0 +1,307 @@
+use super::*;
+
+// AST を走査するためのトレイト。既定の実装は対応する `walk_*` を呼んで子を順に訪れるので、
+// 必要なメソッドだけ上書きすればよい。上書きしたメソッドから `walk_*` を呼べば子の走査も続く
+pub trait Visitor {
+    fn visit_function(&mut self, function: &Function) {
+        walk_function(self, function);
+    }
+
+    fn visit_block(&mut self, block: &Block) {
+        walk_block(self, block);
+    }
+
+    fn visit_statement(&mut self, statement: &Statement) {
+        walk_statement(self, statement);
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        walk_expression(self, expression);
+    }
+}
+
+// その場で書き換えるための `Visitor`
+pub trait VisitorMut {
+    fn visit_function_mut(&mut self, function: &mut Function) {
+        walk_function_mut(self, function);
+    }
+
+    fn visit_block_mut(&mut self, block: &mut Block) {
+        walk_block_mut(self, block);
+    }
+
+    fn visit_statement_mut(&mut self, statement: &mut Statement) {
+        walk_statement_mut(self, statement);
+    }
+
+    fn visit_expression_mut(&mut self, expression: &mut Expression) {
+        walk_expression_mut(self, expression);
+    }
+}
+
+pub fn walk_ast<V: Visitor + ?Sized>(visitor: &mut V, ast: &AST) {
+    for function in &ast.functions {
+        visitor.visit_function(function);
+    }
+}
+
+pub fn walk_function<V: Visitor + ?Sized>(visitor: &mut V, function: &Function) {
+    visitor.visit_block(&function.body);
+}
+
+pub fn walk_block<V: Visitor + ?Sized>(visitor: &mut V, block: &Block) {
+    for statement in &block.statements {
+        visitor.visit_statement(statement);
+    }
+}
+
+pub fn walk_statement<V: Visitor + ?Sized>(visitor: &mut V, statement: &Statement) {
+    match statement {
+        Statement::Let(stmt) => visitor.visit_expression(&stmt.value),
+        Statement::Return(stmt) => {
+            if let Some(value) = &stmt.value {
+                visitor.visit_expression(value);
+            }
+        }
+        Statement::If(stmt) => {
+            visitor.visit_expression(&stmt.condition);
+            visitor.visit_block(&stmt.then_block);
+            if let Some(else_block) = &stmt.else_block {
+                visitor.visit_block(else_block);
+            }
+        }
+        Statement::IfLet(stmt) => {
+            visitor.visit_expression(&stmt.expression);
+            visitor.visit_block(&stmt.then_block);
+            if let Some(else_block) = &stmt.else_block {
+                visitor.visit_block(else_block);
+            }
+        }
+        Statement::While(stmt) => {
+            visitor.visit_expression(&stmt.condition);
+            visitor.visit_block(&stmt.body);
+        }
+        Statement::WhileLet(stmt) => {
+            visitor.visit_expression(&stmt.expression);
+            visitor.visit_block(&stmt.body);
+        }
+        Statement::For(stmt) => {
+            visitor.visit_expression(&stmt.iterator);
+            visitor.visit_block(&stmt.body);
+        }
+        Statement::Match(stmt) => {
+            visitor.visit_expression(&stmt.expression);
+            for arm in &stmt.arms {
+                visitor.visit_block(&arm.body);
+            }
+        }
+        Statement::Break | Statement::Continue | Statement::Transfer(_) => {}
+        Statement::Expression(expr) => visitor.visit_expression(expr),
+    }
+}
+
+pub fn walk_expression<V: Visitor + ?Sized>(visitor: &mut V, expression: &Expression) {
+    match expression {
+        Expression::Literal(_) | Expression::Identifier(_) => {}
+        Expression::BinaryOp(expr) => {
+            visitor.visit_expression(&expr.left);
+            visitor.visit_expression(&expr.right);
+        }
+        Expression::UnaryOp(expr) => visitor.visit_expression(&expr.expr),
+        Expression::Call(expr) => {
+            for argument in &expr.arguments {
+                visitor.visit_expression(argument);
+            }
+        }
+        Expression::Assignment(expr) => visitor.visit_expression(&expr.value),
+        Expression::Array(elements) | Expression::Tuple(elements) => {
+            for element in elements {
+                visitor.visit_expression(element);
+            }
+        }
+        Expression::Index { array, index } => {
+            visitor.visit_expression(array);
+            visitor.visit_expression(index);
+        }
+        Expression::Range { start, end, .. } => {
+            visitor.visit_expression(start);
+            visitor.visit_expression(end);
+        }
+        Expression::FieldAccess { base, .. } | Expression::Cast { expr: base, .. } => visitor.visit_expression(base),
+        Expression::Lambda { body, .. } => visitor.visit_block(body),
+        Expression::StructLiteral { fields, .. } => {
+            for (_, value) in fields {
+                visitor.visit_expression(value);
+            }
+        }
+        Expression::MethodCall { receiver, arguments, .. } => {
+            visitor.visit_expression(receiver);
+            for argument in arguments {
+                visitor.visit_expression(argument);
+            }
+        }
+    }
+}
+
+pub fn walk_ast_mut<V: VisitorMut + ?Sized>(visitor: &mut V, ast: &mut AST) {
+    for function in &mut ast.functions {
+        visitor.visit_function_mut(function);
+    }
+}
+
+pub fn walk_function_mut<V: VisitorMut + ?Sized>(visitor: &mut V, function: &mut Function) {
+    visitor.visit_block_mut(&mut function.body);
+}
+
+pub fn walk_block_mut<V: VisitorMut + ?Sized>(visitor: &mut V, block: &mut Block) {
+    for statement in &mut block.statements {
+        visitor.visit_statement_mut(statement);
+    }
+}
+
+pub fn walk_statement_mut<V: VisitorMut + ?Sized>(visitor: &mut V, statement: &mut Statement) {
+    match statement {
+        Statement::Let(stmt) => visitor.visit_expression_mut(&mut stmt.value),
+        Statement::Return(stmt) => {
+            if let Some(value) = &mut stmt.value {
+                visitor.visit_expression_mut(value);
+            }
+        }
+        Statement::If(stmt) => {
+            visitor.visit_expression_mut(&mut stmt.condition);
+            visitor.visit_block_mut(&mut stmt.then_block);
+            if let Some(else_block) = &mut stmt.else_block {
+                visitor.visit_block_mut(else_block);
+            }
+        }
+        Statement::IfLet(stmt) => {
+            visitor.visit_expression_mut(&mut stmt.expression);
+            visitor.visit_block_mut(&mut stmt.then_block);
+            if let Some(else_block) = &mut stmt.else_block {
+                visitor.visit_block_mut(else_block);
+            }
+        }
+        Statement::While(stmt) => {
+            visitor.visit_expression_mut(&mut stmt.condition);
+            visitor.visit_block_mut(&mut stmt.body);
+        }
+        Statement::WhileLet(stmt) => {
+            visitor.visit_expression_mut(&mut stmt.expression);
+            visitor.visit_block_mut(&mut stmt.body);
+        }
+        Statement::For(stmt) => {
+            visitor.visit_expression_mut(&mut stmt.iterator);
+            visitor.visit_block_mut(&mut stmt.body);
+        }
+        Statement::Match(stmt) => {
+            visitor.visit_expression_mut(&mut stmt.expression);
+            for arm in &mut stmt.arms {
+                visitor.visit_block_mut(&mut arm.body);
+            }
+        }
+        Statement::Break | Statement::Continue | Statement::Transfer(_) => {}
+        Statement::Expression(expr) => visitor.visit_expression_mut(expr),
+    }
+}
+
+pub fn walk_expression_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expression: &mut Expression) {
+    match expression {
+        Expression::Literal(_) | Expression::Identifier(_) => {}
+        Expression::BinaryOp(expr) => {
+            visitor.visit_expression_mut(&mut expr.left);
+            visitor.visit_expression_mut(&mut expr.right);
+        }
+        Expression::UnaryOp(expr) => visitor.visit_expression_mut(&mut expr.expr),
+        Expression::Call(expr) => {
+            for argument in &mut expr.arguments {
+                visitor.visit_expression_mut(argument);
+            }
+        }
+        Expression::Assignment(expr) => visitor.visit_expression_mut(&mut expr.value),
+        Expression::Array(elements) | Expression::Tuple(elements) => {
+            for element in elements {
+                visitor.visit_expression_mut(element);
+            }
+        }
+        Expression::Index { array, index } => {
+            visitor.visit_expression_mut(array);
+            visitor.visit_expression_mut(index);
+        }
+        Expression::Range { start, end, .. } => {
+            visitor.visit_expression_mut(start);
+            visitor.visit_expression_mut(end);
+        }
+        Expression::FieldAccess { base, .. } | Expression::Cast { expr: base, .. } => visitor.visit_expression_mut(base),
+        Expression::Lambda { body, .. } => visitor.visit_block_mut(body),
+        Expression::StructLiteral { fields, .. } => {
+            for (_, value) in fields {
+                visitor.visit_expression_mut(value);
+            }
+        }
+        Expression::MethodCall { receiver, arguments, .. } => {
+            visitor.visit_expression_mut(receiver);
+            for argument in arguments {
+                visitor.visit_expression_mut(argument);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn parse(source: &str) -> AST {
+        Parser::new(Lexer::new(source)).parse().unwrap()
+    }
+
+    struct CallCounter {
+        calls: usize,
+    }
+
+    impl Visitor for CallCounter {
+        fn visit_expression(&mut self, expression: &Expression) {
+            if let Expression::Call(_) = expression {
+                self.calls += 1;
+            }
+            walk_expression(self, expression);
+        }
+    }
+
+    #[test]
+    fn test_count_calls() {
+        let ast = parse(
+            "fn f(x: int) -> int { return g(x) + 1; } \
+             fn main() -> void { \
+                 let a = f(f(1)); \
+                 if a > h() { print(a); } else { while false { print([f(2)]); } } \
+                 for i in xs() { print(p.m(i)); } \
+             }",
+        );
+        let mut counter = CallCounter { calls: 0 };
+        walk_ast(&mut counter, &ast);
+        assert_eq!(counter.calls, 9);
+    }
+
+    struct Rename;
+
+    impl VisitorMut for Rename {
+        fn visit_expression_mut(&mut self, expression: &mut Expression) {
+            if let Expression::Identifier(name) = expression {
+                if name == "x" {
+                    *name = "y".to_string();
+                }
+            }
+            walk_expression_mut(self, expression);
+        }
+    }
+
+    #[test]
+    fn test_rewrite_identifiers() {
+        let mut ast = parse("fn f(x: int) -> int { return x * (x + 1); }");
+        walk_ast_mut(&mut Rename, &mut ast);
+        assert_eq!(ast, parse("fn f(x: int) -> int { return y * (y + 1); }"));
+    }
+}
@@ -1,178 +1,729 @@
 use crate::ir::*;
-use crate::error::Result;
-use std::collections::HashMap;
+use crate::error::{Result, SlangError};
+use crate::type_system::Type;
+use std::collections::{HashMap, HashSet};
 
+mod wasm;
+pub use wasm::WasmGenerator;
+
+// IR を LLVM のテキスト形式に変換する。
+// 変数はすべて関数の先頭で `alloca` した領域に置き、読み書きのたびに `load`/`store` する
+// (SSA への変換は LLVM の mem2reg に任せる)
 pub struct CodeGenerator {
-    module: Module,
-    current_function: Option<String>,
-    current_block: Option<String>,
-    value_map: HashMap<String, String>,
-    block_map: HashMap<String, String>,
+    // 関数内の変数の型。`%名前.addr` に領域を確保済み
+    locals: HashMap<String, Type>,
+    // 関数の先頭ブロックに置く `alloca` と引数の `store`
+    prologue: Vec<String>,
+    // 式の途中の値に付ける `%.tN` の通し番号
+    temps: usize,
+    return_type: Type,
+    // `%名前 = type { ... }` として定義済みの型
+    struct_names: HashSet<String>,
+    // 関数名ごとの引数と戻り値の型。呼び出しの型の決定に使う
+    signatures: HashMap<String, (Vec<Type>, Type)>,
 }
 
 impl CodeGenerator {
-    pub fn new(module: Module) -> Self {
+    pub fn new() -> Self {
         Self {
-            module,
-            current_function: None,
-            current_block: None,
-            value_map: HashMap::new(),
-            block_map: HashMap::new(),
+            locals: HashMap::new(),
+            prologue: Vec::new(),
+            temps: 0,
+            return_type: Type::Void,
+            struct_names: HashSet::new(),
+            signatures: HashMap::new(),
         }
     }
 
-    pub fn generate(&mut self) -> Result<String> {
+    pub fn generate(&mut self, ir: &IR) -> Result<String> {
         let mut output = String::new();
-        
+
+        // 型定義の生成。フィールドで互いを参照できるよう、先に名前をすべて登録する
+        self.struct_names = ir.type_definitions.iter().map(|def| def.name.clone()).collect();
+        for type_def in &ir.type_definitions {
+            let fields = type_def.fields
+                .iter()
+                .map(|field| self.generate_type(&field.type_annotation))
+                .collect::<Result<Vec<_>>>()?;
+            output.push_str(&format!("%{} = type {{ {} }}\n", type_def.name, fields.join(", ")));
+        }
+        if !ir.type_definitions.is_empty() {
+            output.push('\n');
+        }
+
+        self.signatures = ir.functions
+            .iter()
+            .map(|function| {
+                let params = function.parameters.iter().map(|param| param.type_annotation.clone()).collect();
+                (function.name.clone(), (params, function.return_type.clone()))
+            })
+            .collect();
+
         // グローバル変数の生成
-        for global in &self.module.globals {
+        for global in &ir.globals {
             output.push_str(&self.generate_global(global)?);
         }
+        if !ir.globals.is_empty() {
+            output.push('\n');
+        }
 
         // 関数の生成
-        for function in &self.module.functions {
+        for function in &ir.functions {
             output.push_str(&self.generate_function(function)?);
         }
 
         Ok(output)
     }
 
-    fn generate_global(&self, global: &Global) -> Result<String> {
-        let mut output = format!("@{} = ", global.name);
-        
-        // 優先所有格の処理
-        if let Some(priority) = &global.priority {
-            output.push_str(&format!("priority({:?}) ", priority));
-        }
-
-        // 型の生成
-        output.push_str(&self.generate_type(&global.type_)?);
-        output.push_str(" ");
-
-        // 値の生成
-        output.push_str(&self.generate_constant(&global.value)?);
-        output.push_str("\n");
-
-        Ok(output)
+    fn generate_global(&self, global: &IRGlobal) -> Result<String> {
+        let type_ = self.generate_type(&global.type_annotation)?;
+        let value = self.generate_constant(&global.value)?;
+        Ok(format!("@{} = global {} {}\n", global.name, type_, value))
     }
 
-    fn generate_function(&mut self, function: &Function) -> Result<String> {
-        self.current_function = Some(function.name.clone());
-        let mut output = format!("define {} @{}(", 
+    fn generate_function(&mut self, function: &IRFunction) -> Result<String> {
+        self.locals.clear();
+        self.prologue.clear();
+        self.temps = 0;
+        self.return_type = function.return_type.clone();
+
+        let mut output = format!(
+            "define {} @{}(",
             self.generate_type(&function.return_type)?,
             function.name
         );
 
-        // パラメータの生成
-        let params: Vec<String> = function.params.iter()
-            .map(|param| format!("{} %{}", 
-                self.generate_type(&param.type_)?,
-                param.name
-            ))
-            .collect();
+        // パラメータの生成。受け取った値はすぐに変数の領域に移す
+        let mut params = Vec::new();
+        for param in &function.parameters {
+            let type_ = self.generate_type(&param.type_annotation)?;
+            params.push(format!("{} %{}", type_, param.name));
+            self.declare_local(&param.name, &param.type_annotation)?;
+            self.prologue.push(format!("  store {} %{}, {}* %{}.addr", type_, param.name, type_, param.name));
+        }
         output.push_str(&params.join(", "));
         output.push_str(") {\n");
 
-        // ブロックの生成
+        // ブロックの生成。`alloca` は本体をすべて生成してから先頭ブロックに入れる
+        let mut blocks = Vec::new();
         for block in &function.blocks {
-            output.push_str(&self.generate_block(block)?);
+            blocks.push(self.generate_block(block)?);
+        }
+        for (index, (label, body)) in blocks.iter().enumerate() {
+            output.push_str(&format!("{}:\n", label));
+            if index == 0 {
+                for line in &self.prologue {
+                    output.push_str(line);
+                    output.push('\n');
+                }
+            }
+            output.push_str(body);
         }
 
         output.push_str("}\n\n");
-        self.current_function = None;
         Ok(output)
     }
 
-    fn generate_block(&mut self, block: &Block) -> Result<String> {
-        self.current_block = Some(block.name.clone());
-        let mut output = format!("{}:\n", block.name);
+    // 分岐もリターンもせずに終わるブロックは、実行時と同じく値を返さずに関数を抜ける
+    fn generate_block(&mut self, block: &IRBlock) -> Result<(String, String)> {
+        let mut output = String::new();
 
-        // 命令の生成
+        // 命令の生成。終端命令より後ろは実行されないので出力しない
         for instruction in &block.instructions {
-            output.push_str(&self.generate_instruction(instruction)?);
+            let terminated = self.generate_instruction(instruction, &mut output)?;
+            if terminated {
+                return Ok((block.label.clone(), output));
+            }
         }
 
-        // 終端命令の生成
-        output.push_str(&self.generate_terminator(&block.terminator)?);
-        output.push_str("\n");
-
-        self.current_block = None;
-        Ok(output)
+        if self.return_type == Type::Void || self.return_type == Type::Unit {
+            output.push_str("  ret void\n");
+        } else {
+            output.push_str("  unreachable\n");
+        }
+        Ok((block.label.clone(), output))
     }
 
-    fn generate_instruction(&mut self, instruction: &Instruction) -> Result<String> {
+    // 終端命令 (`ret`/`br`) を出力した場合は true
+    fn generate_instruction(&mut self, instruction: &IRInstruction, output: &mut String) -> Result<bool> {
         match instruction {
-            Instruction::Alloca(inst) => {
-                let name = format!("%{}", inst.name);
-                self.value_map.insert(inst.name.clone(), name.clone());
-                Ok(format!("  {} = alloca {}\n", 
-                    name,
-                    self.generate_type(&inst.type_)?))
+            IRInstruction::Alloca { name, type_annotation } => {
+                self.declare_local(name, type_annotation)?;
+            }
+            IRInstruction::Let { name, value } | IRInstruction::Assignment { target: name, value } => {
+                let (operand, type_) = self.generate_value(value, output)?;
+                self.store(name, &operand, &type_, output)?;
+            }
+            // 変数が定義済みかの確認だけなので LLVM では何もしない
+            IRInstruction::Load { .. } => {}
+            // 優先所有格は実行時だけの情報なので出力しない
+            IRInstruction::Transfer { .. } | IRInstruction::Priority { .. } => {}
+            IRInstruction::Expression(value) => {
+                self.generate_value(value, output)?;
+            }
+            // 値を返す関数の値のない `return` には (型検査を通っていれば) 到達しない
+            IRInstruction::Return(None) => {
+                if self.return_type == Type::Void || self.return_type == Type::Unit {
+                    output.push_str("  ret void\n");
+                } else {
+                    output.push_str("  unreachable\n");
+                }
+                return Ok(true);
+            }
+            IRInstruction::Return(Some(value)) => {
+                let (operand, type_) = self.generate_value(value, output)?;
+                output.push_str(&format!("  ret {} {}\n", self.generate_type(&type_)?, operand));
+                return Ok(true);
+            }
+            IRInstruction::Branch { label } => {
+                output.push_str(&format!("  br label %{}\n", label));
+                return Ok(true);
+            }
+            IRInstruction::ConditionalBranch { condition, then_label, else_label } => {
+                let (operand, type_) = self.generate_value(condition, output)?;
+                if type_ != Type::Bool {
+                    return Err(SlangError::Compilation(format!("Branch condition must be bool, got {}", type_)));
+                }
+                output.push_str(&format!("  br i1 {}, label %{}, label %{}\n", operand, then_label, else_label));
+                return Ok(true);
+            }
+            IRInstruction::Store { name, value } => {
+                let (operand, type_) = self.generate_value(value, output)?;
+                self.store(name, &operand, &type_, output)?;
+            }
+            IRInstruction::BinaryOp { dest, op, left, right } => {
+                let (operand, type_) = self.generate_binary(op, left, right, output)?;
+                self.store(dest, &operand, &type_, output)?;
             }
-            Instruction::Load(inst) => {
-                let name = format!("%{}", inst.name);
-                self.value_map.insert(inst.name.clone(), name.clone());
-                Ok(format!("  {} = load {}, {}* {}\n",
-                    name,
-                    self.generate_type(&inst.type_)?,
-                    self.generate_type(&inst.type_)?,
-                    self.generate_value(&inst.pointer)?))
+            IRInstruction::UnaryOp { dest, op, expr } => {
+                let (operand, type_) = self.generate_unary(op, expr, output)?;
+                self.store(dest, &operand, &type_, output)?;
             }
-            // 他の命令の生成も同様に実装...
-            _ => unimplemented!(),
+            // 値を返さない関数の結果は変数に入れない
+            IRInstruction::Call { dest, function, arguments } => {
+                let (operand, type_) = self.generate_call(function, arguments, output)?;
+                if type_ != Type::Void && type_ != Type::Unit {
+                    self.store(dest, &operand, &type_, output)?;
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    // 値を計算する命令を `output` に書き、その結果のオペランドと型を返す
+    fn generate_value(&mut self, value: &IRValue, output: &mut String) -> Result<(String, Type)> {
+        match value {
+            IRValue::Int(i) => Ok((i.to_string(), Type::Int)),
+            IRValue::Float(f) => Ok((float_literal(*f), Type::Float)),
+            IRValue::Bool(b) => Ok((b.to_string(), Type::Bool)),
+            IRValue::Char(c) => Ok(((*c as u32).to_string(), Type::Char)),
+            IRValue::Constant(inner) => self.generate_value(inner, output),
+            IRValue::Variable(name) | IRValue::Identifier(name) => {
+                let type_ = self.locals.get(name).cloned().ok_or_else(|| {
+                    SlangError::Compilation(format!("Undefined variable: {}", name))
+                })?;
+                let llvm_type = self.generate_type(&type_)?;
+                let temp = self.new_temp();
+                output.push_str(&format!("  {} = load {}, {}* %{}.addr\n", temp, llvm_type, llvm_type, name));
+                Ok((temp, type_))
+            }
+            IRValue::BinaryOp { left, op, right } => self.generate_binary(op, left, right, output),
+            IRValue::UnaryOp { op, expr } => self.generate_unary(op, expr, output),
+            IRValue::Cast { value, target } => self.generate_cast(value, target, output),
+            IRValue::Call { function, arguments } => self.generate_call(function, arguments, output),
+            IRValue::Assignment { name, value } => {
+                let (operand, type_) = self.generate_value(value, output)?;
+                self.store(name, &operand, &type_, output)?;
+                Ok((operand, type_))
+            }
+            _ => Err(SlangError::Compilation(format!(
+                "LLVM backend does not support {:?} yet",
+                value
+            ))),
+        }
+    }
+
+    // 命令は左辺の型で選ぶ (`add i64` と `fadd double` など)。型検査と同じく、int と float が
+    // 混ざるときだけ int 側を `sitofp` で float にしてから計算する
+    fn generate_binary(
+        &mut self,
+        op: &IRBinaryOperator,
+        left: &IRValue,
+        right: &IRValue,
+        output: &mut String,
+    ) -> Result<(String, Type)> {
+        let (mut left, mut left_type) = self.generate_value(left, output)?;
+        let (mut right, mut right_type) = self.generate_value(right, output)?;
+        match (&left_type, &right_type) {
+            (Type::Int, Type::Float) => (left, left_type) = self.int_to_float(&left, output),
+            (Type::Float, Type::Int) => (right, right_type) = self.int_to_float(&right, output),
+            _ => {}
         }
+        if left_type != right_type {
+            return Err(SlangError::Compilation(format!(
+                "Operands of {:?} have different types: {} and {}",
+                op, left_type, right_type
+            )));
+        }
+        let (instruction, result_type) = binary_instruction(op, &left_type)?;
+        let temp = self.new_temp();
+        output.push_str(&format!(
+            "  {} = {} {} {}, {}\n",
+            temp,
+            instruction,
+            self.generate_type(&left_type)?,
+            left,
+            right
+        ));
+        Ok((temp, result_type))
+    }
+
+    fn int_to_float(&mut self, operand: &str, output: &mut String) -> (String, Type) {
+        let temp = self.new_temp();
+        output.push_str(&format!("  {} = sitofp i64 {} to double\n", temp, operand));
+        (temp, Type::Float)
+    }
+
+    fn generate_unary(&mut self, op: &IRUnaryOperator, expr: &IRValue, output: &mut String) -> Result<(String, Type)> {
+        let (operand, type_) = self.generate_value(expr, output)?;
+        let instruction = match (op, &type_) {
+            (IRUnaryOperator::Neg | IRUnaryOperator::Negate, Type::Int) => format!("sub i64 0, {}", operand),
+            (IRUnaryOperator::Neg | IRUnaryOperator::Negate, Type::Float) => format!("fneg double {}", operand),
+            (IRUnaryOperator::Not, Type::Bool) => format!("xor i1 {}, true", operand),
+            _ => {
+                return Err(SlangError::Compilation(format!(
+                    "LLVM backend does not support {:?} on {}",
+                    op, type_
+                )))
+            }
+        };
+        let temp = self.new_temp();
+        output.push_str(&format!("  {} = {}\n", temp, instruction));
+        Ok((temp, type_))
     }
 
+    // 数値どうしの変換だけを扱う。文字列との変換には実行時ライブラリが要る
+    fn generate_cast(&mut self, value: &IRValue, target: &Type, output: &mut String) -> Result<(String, Type)> {
+        let (operand, type_) = self.generate_value(value, output)?;
+        let instruction = match (&type_, target) {
+            (from, to) if from == to => return Ok((operand, type_)),
+            (Type::Int, Type::Float) => format!("sitofp i64 {} to double", operand),
+            (Type::Float, Type::Int) => format!("fptosi double {} to i64", operand),
+            _ => {
+                return Err(SlangError::Compilation(format!(
+                    "LLVM backend does not support casting {} to {}",
+                    type_, target
+                )))
+            }
+        };
+        let temp = self.new_temp();
+        output.push_str(&format!("  {} = {}\n", temp, instruction));
+        Ok((temp, target.clone()))
+    }
+
+    // 呼び出せるのは同じ IR にある関数だけ。引数の型は宣言と一致しなければならない
+    fn generate_call(&mut self, function: &str, arguments: &[IRValue], output: &mut String) -> Result<(String, Type)> {
+        let (params, return_type) = self.signatures.get(function).cloned().ok_or_else(|| {
+            SlangError::Compilation(format!("Undefined function: {}", function))
+        })?;
+        if params.len() != arguments.len() {
+            return Err(SlangError::Compilation(format!(
+                "Function {} expects {} arguments, got {}",
+                function,
+                params.len(),
+                arguments.len()
+            )));
+        }
+
+        let mut args = Vec::new();
+        for (argument, param_type) in arguments.iter().zip(&params) {
+            let (operand, type_) = self.generate_value(argument, output)?;
+            if &type_ != param_type {
+                return Err(SlangError::Compilation(format!(
+                    "Argument of {} must be {}, got {}",
+                    function, param_type, type_
+                )));
+            }
+            args.push(format!("{} {}", self.generate_type(&type_)?, operand));
+        }
+
+        let llvm_type = self.generate_type(&return_type)?;
+        let call = format!("call {} @{}({})", llvm_type, function, args.join(", "));
+        if return_type == Type::Void || return_type == Type::Unit {
+            output.push_str(&format!("  {}\n", call));
+            return Ok((String::new(), return_type));
+        }
+        let temp = self.new_temp();
+        output.push_str(&format!("  {} = {}\n", temp, call));
+        Ok((temp, return_type))
+    }
+
+    // 最初の代入で変数の型が決まる。以後は同じ型の値しか代入できない
+    fn store(&mut self, name: &str, operand: &str, type_: &Type, output: &mut String) -> Result<()> {
+        if *type_ == Type::Void || *type_ == Type::Unit {
+            return Err(SlangError::Compilation(format!("Cannot assign a void value to {}", name)));
+        }
+        self.declare_local(name, type_)?;
+        let llvm_type = self.generate_type(type_)?;
+        output.push_str(&format!("  store {} {}, {}* %{}.addr\n", llvm_type, operand, llvm_type, name));
+        Ok(())
+    }
+
+    fn declare_local(&mut self, name: &str, type_: &Type) -> Result<()> {
+        match self.locals.get(name) {
+            Some(existing) if existing == type_ => Ok(()),
+            Some(existing) => Err(SlangError::Compilation(format!(
+                "Variable {} is used as both {} and {}",
+                name, existing, type_
+            ))),
+            None => {
+                let llvm_type = self.generate_type(type_)?;
+                self.prologue.push(format!("  %{}.addr = alloca {}", name, llvm_type));
+                self.locals.insert(name.to_string(), type_.clone());
+                Ok(())
+            }
+        }
+    }
+
+    fn new_temp(&mut self) -> String {
+        let name = format!("%.t{}", self.temps);
+        self.temps += 1;
+        name
+    }
+
+    // 型の対応:
+    // - `[T]` は長さの決まらない配列なので、先頭要素へのポインタと要素数の組 `{ T*, i64 }` で渡す。
+    //   `[T; N]` は LLVM の配列型 `[N x T]`
+    // - タプルと複素数・四元数は構造体 (`{ i64, double }`)。複素数は実部・虚部、四元数は w, x, y, z の順
+    // - `vecN<T>` は LLVM のベクトル型 `<N x T>`、行列とテンソルは行優先の入れ子の配列
+    // - `type` で定義した型 (`Type::Struct`) は `%名前` の構造体。関数型は関数ポインタ
+    // - 構造体に解決されていない `Type::Named` は型パラメータか未定義の名前なのでエラー
+    // 集成体はすべて値渡しで、呼び出し規約の調整 (sret など) はしない
     fn generate_type(&self, type_: &Type) -> Result<String> {
         match type_ {
             Type::Int => Ok("i64".to_string()),
             Type::Float => Ok("double".to_string()),
             Type::Bool => Ok("i1".to_string()),
-            Type::Char => Ok("i8".to_string()),
+            // Unicode のスカラー値をそのまま入れる
+            Type::Char => Ok("i32".to_string()),
             Type::String => Ok("i8*".to_string()),
-            Type::Void => Ok("void".to_string()),
-            // 他の型の生成も同様に実装...
-            _ => unimplemented!(),
+            Type::Void | Type::Unit => Ok("void".to_string()),
+            Type::Array(element) => Ok(format!("{{ {}*, i64 }}", self.generate_pointee_type(element)?)),
+            Type::FixedArray(element, len) => Ok(format!("[{} x {}]", len, self.generate_type(element)?)),
+            Type::Tuple(types) if types.is_empty() => Ok("{}".to_string()),
+            Type::Tuple(types) => {
+                let fields = types.iter().map(|t| self.generate_type(t)).collect::<Result<Vec<_>>>()?;
+                Ok(format!("{{ {} }}", fields.join(", ")))
+            }
+            Type::Vector(size, element) => Ok(format!("<{} x {}>", size, self.generate_type(element)?)),
+            Type::Matrix(rows, cols, element) => {
+                Ok(format!("[{} x [{} x {}]]", rows, cols, self.generate_type(element)?))
+            }
+            Type::Tensor(dims, element) => {
+                let mut llvm_type = self.generate_type(element)?;
+                for dim in dims.iter().rev() {
+                    llvm_type = format!("[{} x {}]", dim, llvm_type);
+                }
+                Ok(llvm_type)
+            }
+            Type::Complex(element) => {
+                let element = self.generate_type(element)?;
+                Ok(format!("{{ {}, {} }}", element, element))
+            }
+            Type::Quaternion(element) => {
+                let element = self.generate_type(element)?;
+                Ok(format!("{{ {}, {}, {}, {} }}", element, element, element, element))
+            }
+            Type::Pointer(inner) => Ok(format!("{}*", self.generate_pointee_type(inner)?)),
+            Type::Function { params, return_type, .. } => {
+                let params = params.iter().map(|t| self.generate_type(t)).collect::<Result<Vec<_>>>()?;
+                Ok(format!("{} ({})*", self.generate_type(return_type)?, params.join(", ")))
+            }
+            Type::Struct(name) if self.struct_names.contains(name) => Ok(format!("%{}", name)),
+            Type::Struct(name) | Type::Named(name) => {
+                Err(SlangError::Compilation(format!("Unknown type: {}", name)))
+            }
+            Type::Optional(_) => Err(SlangError::Compilation(format!(
+                "Optional type {} is not supported by the LLVM backend",
+                type_
+            ))),
+            Type::Var(_) => Err(SlangError::Compilation(format!(
+                "Type {} must be resolved before code generation",
+                type_
+            ))),
+        }
+    }
+
+    // LLVM には `void*` がないので、`void` を指すポインタは `i8*` にする
+    fn generate_pointee_type(&self, type_: &Type) -> Result<String> {
+        match type_ {
+            Type::Void | Type::Unit => Ok("i8".to_string()),
+            _ => self.generate_type(type_),
         }
     }
 
-    fn generate_value(&self, value: &Value) -> Result<String> {
+    fn generate_constant(&self, value: &IRValue) -> Result<String> {
         match value {
-            Value::Constant(constant) => self.generate_constant(constant),
-            Value::Instruction(name) => Ok(self.value_map.get(name)
-                .cloned()
-                .unwrap_or_else(|| format!("%{}", name))),
-            Value::Parameter(name) => Ok(format!("%{}", name)),
-            Value::Global(name) => Ok(format!("@{}", name)),
-        }
-    }
-
-    fn generate_constant(&self, constant: &Constant) -> Result<String> {
-        match constant {
-            Constant::Int(value) => Ok(value.to_string()),
-            Constant::Float(value) => Ok(value.to_string()),
-            Constant::Bool(value) => Ok(if *value { "1" } else { "0" }.to_string()),
-            Constant::Char(value) => Ok(format!("{}", *value as u8)),
-            Constant::String(value) => Ok(format!("c\"{}\"", value)),
-            // 他の定数の生成も同様に実装...
-            _ => unimplemented!(),
-        }
-    }
-
-    fn generate_terminator(&self, terminator: &Terminator) -> Result<String> {
-        match terminator {
-            Terminator::Ret(None) => Ok("  ret void\n".to_string()),
-            Terminator::Ret(Some(value)) => Ok(format!("  ret {}\n",
-                self.generate_value(value)?)),
-            Terminator::Br(label) => Ok(format!("  br label %{}\n", label)),
-            Terminator::CondBr(cond, true_label, false_label) => Ok(format!(
-                "  br {}, label %{}, label %{}\n",
-                self.generate_value(cond)?,
-                true_label,
-                false_label
-            )),
-            // 他の終端命令の生成も同様に実装...
-            _ => unimplemented!(),
-        }
-    }
-} 
\ No newline at end of file
+            IRValue::Int(value) => Ok(value.to_string()),
+            IRValue::Float(value) => Ok(float_literal(*value)),
+            IRValue::Bool(value) => Ok(value.to_string()),
+            IRValue::Char(value) => Ok((*value as u32).to_string()),
+            IRValue::Constant(inner) => self.generate_constant(inner),
+            _ => Err(SlangError::Compilation(format!(
+                "Global initializer must be a scalar constant, got {:?}",
+                value
+            ))),
+        }
+    }
+}
+
+impl Default for CodeGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// LLVM の `double` 定数は 16 進のビット列で書くと丸めずに表せる
+fn float_literal(value: f64) -> String {
+    format!("0x{:016X}", value.to_bits())
+}
+
+fn binary_instruction(op: &IRBinaryOperator, operand_type: &Type) -> Result<(&'static str, Type)> {
+    use IRBinaryOperator::*;
+    let instruction = match (operand_type, op) {
+        (Type::Int, Add) => ("add", Type::Int),
+        (Type::Int, Sub | Subtract) => ("sub", Type::Int),
+        (Type::Int, Mul | Multiply) => ("mul", Type::Int),
+        (Type::Int, Div | Divide) => ("sdiv", Type::Int),
+        (Type::Int, Mod | Modulo) => ("srem", Type::Int),
+        (Type::Int | Type::Char | Type::Bool, Eq | Equals) => ("icmp eq", Type::Bool),
+        (Type::Int | Type::Char | Type::Bool, Neq | NotEquals) => ("icmp ne", Type::Bool),
+        (Type::Int, Lt | LessThan) => ("icmp slt", Type::Bool),
+        (Type::Int, Lte | LessThanEquals) => ("icmp sle", Type::Bool),
+        (Type::Int, Gt | GreaterThan) => ("icmp sgt", Type::Bool),
+        (Type::Int, Gte | GreaterThanEquals) => ("icmp sge", Type::Bool),
+        (Type::Char, Lt | LessThan) => ("icmp ult", Type::Bool),
+        (Type::Char, Lte | LessThanEquals) => ("icmp ule", Type::Bool),
+        (Type::Char, Gt | GreaterThan) => ("icmp ugt", Type::Bool),
+        (Type::Char, Gte | GreaterThanEquals) => ("icmp uge", Type::Bool),
+        (Type::Float, Add) => ("fadd", Type::Float),
+        (Type::Float, Sub | Subtract) => ("fsub", Type::Float),
+        (Type::Float, Mul | Multiply) => ("fmul", Type::Float),
+        (Type::Float, Div | Divide) => ("fdiv", Type::Float),
+        (Type::Float, Mod | Modulo) => ("frem", Type::Float),
+        // NaN との比較は `!=` だけが真になる
+        (Type::Float, Eq | Equals) => ("fcmp oeq", Type::Bool),
+        (Type::Float, Neq | NotEquals) => ("fcmp une", Type::Bool),
+        (Type::Float, Lt | LessThan) => ("fcmp olt", Type::Bool),
+        (Type::Float, Lte | LessThanEquals) => ("fcmp ole", Type::Bool),
+        (Type::Float, Gt | GreaterThan) => ("fcmp ogt", Type::Bool),
+        (Type::Float, Gte | GreaterThanEquals) => ("fcmp oge", Type::Bool),
+        (Type::Bool, And) => ("and", Type::Bool),
+        (Type::Bool, Or) => ("or", Type::Bool),
+        _ => {
+            return Err(SlangError::Compilation(format!(
+                "LLVM backend does not support {:?} on {}",
+                op, operand_type
+            )))
+        }
+    };
+    Ok(instruction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+
+    fn generate(source: &str) -> Result<String> {
+        let mut compiler = Compiler::new();
+        compiler.parse(source)?;
+        CodeGenerator::new().generate(&compiler.compile_program()?)
+    }
+
+    #[test]
+    fn test_add_function() {
+        assert_eq!(
+            generate("fn add(a: int, b: int) -> int { return a + b; }").unwrap(),
+            "define i64 @add(i64 %a, i64 %b) {\n\
+             entry:\n  \
+               %a.addr = alloca i64\n  \
+               store i64 %a, i64* %a.addr\n  \
+               %b.addr = alloca i64\n  \
+               store i64 %b, i64* %b.addr\n  \
+               %.t0 = load i64, i64* %a.addr\n  \
+               %.t1 = load i64, i64* %b.addr\n  \
+               %.t2 = add i64 %.t0, %.t1\n  \
+               ret i64 %.t2\n\
+             }\n\n"
+        );
+    }
+
+    #[test]
+    fn test_branches_and_locals() {
+        let output = generate(
+            "fn sign(n: int) -> int { let zero = 0; if n < zero { return 0 - 1; } return 1; }",
+        )
+        .unwrap();
+        assert!(output.contains("  %zero.addr = alloca i64\n"));
+        assert!(output.contains("  store i64 0, i64* %zero.addr\n"));
+        assert!(output.contains("icmp slt i64"));
+        assert!(output.contains("  br i1 %.t2, label %"));
+        // 各ブロックは必ず終端命令で終わる
+        for block in output.split("\n\n").next().unwrap().split(":\n").skip(1) {
+            let last = block.lines().rev().find(|line| line.starts_with("  ")).unwrap();
+            assert!(last.starts_with("  br ") || last.starts_with("  ret "), "{}", last);
+        }
+    }
+
+    #[test]
+    fn test_tuple_return() {
+        assert_eq!(
+            generate("fn id(p: (int, float)) -> (int, float) { return p; }").unwrap(),
+            "define { i64, double } @id({ i64, double } %p) {\n\
+             entry:\n  \
+               %p.addr = alloca { i64, double }\n  \
+               store { i64, double } %p, { i64, double }* %p.addr\n  \
+               %.t0 = load { i64, double }, { i64, double }* %p.addr\n  \
+               ret { i64, double } %.t0\n\
+             }\n\n"
+        );
+    }
+
+    #[test]
+    fn test_numeric_cast() {
+        let output = generate("fn half(n: int) -> int { return (n as float / 2.0) as int; }").unwrap();
+        assert!(output.contains("  %.t1 = sitofp i64 %.t0 to double\n"), "{}", output);
+        assert!(output.contains("  %.t3 = fptosi double %.t2 to i64\n"), "{}", output);
+        assert!(generate("fn f(n: int) -> string { return n as string; }").is_err());
+    }
+
+    #[test]
+    fn test_array_parameter() {
+        let output = generate("fn len(xs: [float], m: [[int]]) -> int { return 0; }").unwrap();
+        assert!(output.starts_with("define i64 @len({ double*, i64 } %xs, { { i64*, i64 }*, i64 } %m) {\n"));
+    }
+
+    #[test]
+    fn test_composite_types() {
+        let generator = CodeGenerator::new();
+        let cases = [
+            (Type::Vector(3, Box::new(Type::Float)), "<3 x double>"),
+            (Type::Matrix(2, 3, Box::new(Type::Int)), "[2 x [3 x i64]]"),
+            (Type::Tensor(vec![2, 3, 4], Box::new(Type::Float)), "[2 x [3 x [4 x double]]]"),
+            (Type::Complex(Box::new(Type::Float)), "{ double, double }"),
+            (Type::Pointer(Box::new(Type::Void)), "i8*"),
+            (Type::Pointer(Box::new(Type::Int)), "i64*"),
+            (Type::FixedArray(Box::new(Type::Int), 3), "[3 x i64]"),
+        ];
+        for (type_, expected) in cases {
+            assert_eq!(generator.generate_type(&type_).unwrap(), expected);
+        }
+        assert!(generator.generate_type(&Type::Named("Point".to_string())).is_err());
+        assert!(generator.generate_type(&Type::Struct("Point".to_string())).is_err());
+        assert!(generator.generate_type(&Type::Var(0)).is_err());
+    }
+
+    #[test]
+    fn test_type_definitions() {
+        let mut compiler = Compiler::new();
+        compiler
+            .parse("struct Point { x: float, y: float } struct Segment { ends: [Point; 2] } fn f(p: Point) -> void { return; }")
+            .unwrap();
+        let ir = compiler.compile_program().unwrap();
+        assert_eq!(ir.functions[0].parameters[0].type_annotation, Type::Struct("Point".to_string()));
+
+        let output = CodeGenerator::new().generate(&ir).unwrap();
+        assert!(output.starts_with(
+            "%Point = type { double, double }\n%Segment = type { [2 x %Point] }\n\ndefine void @f(%Point %p) {\n"
+        ));
+    }
+
+    #[test]
+    fn test_float_add() {
+        assert_eq!(
+            generate("fn add(a: float, b: float) -> float { return a + b; }").unwrap(),
+            "define double @add(double %a, double %b) {\n\
+             entry:\n  \
+               %a.addr = alloca double\n  \
+               store double %a, double* %a.addr\n  \
+               %b.addr = alloca double\n  \
+               store double %b, double* %b.addr\n  \
+               %.t0 = load double, double* %a.addr\n  \
+               %.t1 = load double, double* %b.addr\n  \
+               %.t2 = fadd double %.t0, %.t1\n  \
+               ret double %.t2\n\
+             }\n\n"
+        );
+    }
+
+    #[test]
+    fn test_mixed_int_and_float_operands() {
+        assert_eq!(
+            generate("fn f(a: int) -> float { return a + 1.5; }").unwrap(),
+            "define double @f(i64 %a) {\n\
+             entry:\n  \
+               %a.addr = alloca i64\n  \
+               store i64 %a, i64* %a.addr\n  \
+               %.t0 = load i64, i64* %a.addr\n  \
+               %.t1 = sitofp i64 %.t0 to double\n  \
+               %.t2 = fadd double %.t1, 0x3FF8000000000000\n  \
+               ret double %.t2\n\
+             }\n\n"
+        );
+        let output = generate("fn g(x: float, n: int) -> bool { return x < n; }").unwrap();
+        assert!(output.contains("  %.t2 = sitofp i64 %.t1 to double\n  %.t3 = fcmp olt double %.t0, %.t2\n"), "{}", output);
+        assert!(generate("fn h(b: bool, n: int) -> int { return n + b; }").is_err());
+    }
+
+    #[test]
+    fn test_calls_and_unary() {
+        let output = generate(
+            "fn neg(x: float) -> float { return -x; } \
+             fn log(x: float) -> void { return; } \
+             fn main() -> void { log(neg(1.5)); }",
+        )
+        .unwrap();
+        assert!(output.contains("  %.t1 = fneg double %.t0\n"));
+        assert!(output.contains("  %.t0 = call double @neg(double 0x3FF8000000000000)\n  call void @log(double %.t0)\n"));
+        assert!(generate("fn main() -> void { missing(); }").is_err());
+        assert!(generate("fn f(x: int) -> void { return; } fn main() -> void { f(1.0); }").is_err());
+    }
+
+    #[test]
+    fn test_instruction_forms() {
+        let function = IRFunction {
+            name: "f".to_string(),
+            parameters: vec![],
+            return_type: Type::Int,
+            priorities: vec![],
+            blocks: vec![IRBlock {
+                label: "entry".to_string(),
+                instructions: vec![
+                    IRInstruction::Store { name: "x".to_string(), value: IRValue::Int(2) },
+                    IRInstruction::BinaryOp {
+                        dest: "y".to_string(),
+                        op: IRBinaryOperator::Mul,
+                        left: IRValue::Variable("x".to_string()),
+                        right: IRValue::Int(3),
+                    },
+                    IRInstruction::UnaryOp {
+                        dest: "z".to_string(),
+                        op: IRUnaryOperator::Neg,
+                        expr: IRValue::Variable("y".to_string()),
+                    },
+                    IRInstruction::Call { dest: "w".to_string(), function: "f".to_string(), arguments: vec![] },
+                    IRInstruction::Return(Some(IRValue::Variable("z".to_string()))),
+                ],
+            }],
+        };
+        let mut ir = IR::new();
+        ir.add_function(function);
+
+        let output = CodeGenerator::new().generate(&ir).unwrap();
+        assert!(output.contains("  store i64 2, i64* %x.addr\n"));
+        assert!(output.contains("  %.t1 = mul i64 %.t0, 3\n  store i64 %.t1, i64* %y.addr\n"));
+        assert!(output.contains("  %.t3 = sub i64 0, %.t2\n  store i64 %.t3, i64* %z.addr\n"));
+        assert!(output.contains("  %.t4 = call i64 @f()\n  store i64 %.t4, i64* %w.addr\n"));
+    }
+}
@@ -0,0 +1,507 @@
+use crate::ir::*;
+use crate::error::{Result, SlangError};
+use crate::type_system::Type;
+use std::collections::HashMap;
+
+// IR を WebAssembly のテキスト形式 (WAT) に変換する。
+// WebAssembly には任意のラベルへの分岐がないため、ブロックが 2 つ以上ある関数は
+// 次に実行するブロックの番号を `$.block` に入れ、`loop` と `br_table` で振り分ける
+pub struct WasmGenerator {
+    // 関数内の変数の型 (引数を含む)
+    locals: HashMap<String, Type>,
+    // 関数の先頭で宣言する `(local ...)`
+    declarations: Vec<String>,
+    // ブロックのラベルから番号への対応
+    block_indices: HashMap<String, usize>,
+    return_type: Type,
+    // 関数名ごとの引数と戻り値の型
+    signatures: HashMap<String, (Vec<Type>, Type)>,
+}
+
+const DISPATCH: &str = "$.dispatch";
+const BLOCK_INDEX: &str = "$.block";
+
+impl WasmGenerator {
+    pub fn new() -> Self {
+        Self {
+            locals: HashMap::new(),
+            declarations: Vec::new(),
+            block_indices: HashMap::new(),
+            return_type: Type::Void,
+            signatures: HashMap::new(),
+        }
+    }
+
+    pub fn generate(&mut self, ir: &IR) -> Result<String> {
+        self.signatures = ir.functions
+            .iter()
+            .map(|function| {
+                let params = function.parameters.iter().map(|param| param.type_annotation.clone()).collect();
+                (function.name.clone(), (params, function.return_type.clone()))
+            })
+            .collect();
+
+        let mut output = String::from("(module\n");
+        for global in &ir.globals {
+            output.push_str(&self.generate_global(global)?);
+        }
+        for function in &ir.functions {
+            output.push_str(&self.generate_function(function)?);
+        }
+        output.push_str(")\n");
+        Ok(output)
+    }
+
+    fn generate_global(&self, global: &IRGlobal) -> Result<String> {
+        let type_ = self.generate_type(&global.type_annotation)?;
+        let value = match &global.value {
+            IRValue::Constant(inner) => inner,
+            value => value,
+        };
+        let value = match value {
+            IRValue::Int(_) | IRValue::Float(_) | IRValue::Bool(_) | IRValue::Char(_) => {
+                self.generate_literal(value)?.0
+            }
+            _ => {
+                return Err(SlangError::Compilation(format!(
+                    "Global initializer must be a scalar constant, got {:?}",
+                    global.value
+                )))
+            }
+        };
+        Ok(format!("  (global ${} (mut {}) {})\n", global.name, type_, value))
+    }
+
+    // 関数は同じ名前でエクスポートする
+    fn generate_function(&mut self, function: &IRFunction) -> Result<String> {
+        self.locals.clear();
+        self.declarations.clear();
+        self.return_type = function.return_type.clone();
+        self.block_indices = function.blocks
+            .iter()
+            .enumerate()
+            .map(|(index, block)| (block.label.clone(), index))
+            .collect();
+
+        let mut output = format!("  (func ${} (export \"{}\")", function.name, function.name);
+        for param in &function.parameters {
+            output.push_str(&format!(" (param ${} {})", param.name, self.generate_type(&param.type_annotation)?));
+            self.locals.insert(param.name.clone(), param.type_annotation.clone());
+        }
+        if !is_void(&function.return_type) {
+            output.push_str(&format!(" (result {})", self.generate_type(&function.return_type)?));
+        }
+        output.push('\n');
+
+        let mut body = Vec::new();
+        if let [block] = function.blocks.as_slice() {
+            self.generate_block(block, 2, &mut body)?;
+        } else if !function.blocks.is_empty() {
+            self.declarations.push(format!("    (local {} i32)", BLOCK_INDEX));
+            self.generate_dispatch(&function.blocks, &mut body)?;
+            // `loop` を抜けるのは分岐先のないときだけ
+            body.push("    unreachable".to_string());
+        }
+
+        for line in self.declarations.iter().chain(&body) {
+            output.push_str(line);
+            output.push('\n');
+        }
+        output.push_str("  )\n");
+        Ok(output)
+    }
+
+    // ブロック i の本体は `$.bi` の直後に置く。`br_table` で `$.bi` を抜けると本体 i から実行される:
+    //   (loop $.dispatch
+    //     (block $.b1
+    //       (block $.b0
+    //         (br_table $.b0 $.b1 (local.get $.block)))
+    //       本体 0)
+    //     本体 1)
+    fn generate_dispatch(&mut self, blocks: &[IRBlock], body: &mut Vec<String>) -> Result<()> {
+        let depth = blocks.len();
+        body.push(format!("    (loop {}", DISPATCH));
+        for index in (0..depth).rev() {
+            body.push(format!("{}(block $.b{}", indent(depth - index + 2), index));
+        }
+        let targets: Vec<String> = (0..depth).map(|index| format!("$.b{}", index)).collect();
+        body.push(format!(
+            "{}(br_table {} (local.get {})))",
+            indent(depth + 3),
+            targets.join(" "),
+            BLOCK_INDEX
+        ));
+        for (index, block) in blocks.iter().enumerate() {
+            let level = depth - index + 1;
+            self.generate_block(block, level + 1, body)?;
+            let last = body.last_mut().unwrap();
+            last.push(')');
+        }
+        Ok(())
+    }
+
+    // 分岐もリターンもせずに終わるブロックは、実行時と同じく値を返さずに関数を抜ける
+    fn generate_block(&mut self, block: &IRBlock, level: usize, body: &mut Vec<String>) -> Result<()> {
+        for instruction in &block.instructions {
+            let terminated = self.generate_instruction(instruction, level, body)?;
+            if terminated {
+                return Ok(());
+            }
+        }
+        let end = if is_void(&self.return_type) { "return" } else { "unreachable" };
+        body.push(format!("{}{}", indent(level), end));
+        Ok(())
+    }
+
+    // 終端命令 (`return`/分岐) を出力した場合は true
+    fn generate_instruction(&mut self, instruction: &IRInstruction, level: usize, body: &mut Vec<String>) -> Result<bool> {
+        let pad = indent(level);
+        match instruction {
+            IRInstruction::Alloca { name, type_annotation } => {
+                self.declare_local(name, type_annotation)?;
+            }
+            IRInstruction::Let { name, value }
+            | IRInstruction::Store { name, value }
+            | IRInstruction::Assignment { target: name, value } => {
+                let (expr, type_) = self.generate_value(value)?;
+                body.push(format!("{}{}", pad, self.set_local(name, expr, &type_)?));
+            }
+            IRInstruction::BinaryOp { dest, op, left, right } => {
+                let (expr, type_) = self.generate_binary(op, left, right)?;
+                body.push(format!("{}{}", pad, self.set_local(dest, expr, &type_)?));
+            }
+            IRInstruction::UnaryOp { dest, op, expr } => {
+                let (expr, type_) = self.generate_unary(op, expr)?;
+                body.push(format!("{}{}", pad, self.set_local(dest, expr, &type_)?));
+            }
+            IRInstruction::Call { dest, function, arguments } => {
+                let (expr, type_) = self.generate_call(function, arguments)?;
+                if is_void(&type_) {
+                    body.push(format!("{}{}", pad, expr));
+                } else {
+                    body.push(format!("{}{}", pad, self.set_local(dest, expr, &type_)?));
+                }
+            }
+            // 変数が定義済みかの確認だけなので何もしない
+            IRInstruction::Load { .. } => {}
+            // 優先所有格は実行時だけの情報なので出力しない
+            IRInstruction::Transfer { .. } | IRInstruction::Priority { .. } => {}
+            IRInstruction::Expression(value) => {
+                let (expr, type_) = self.generate_value(value)?;
+                if is_void(&type_) {
+                    body.push(format!("{}{}", pad, expr));
+                } else {
+                    body.push(format!("{}(drop {})", pad, expr));
+                }
+            }
+            // 値を返す関数の値のない `return` には (型検査を通っていれば) 到達しない
+            IRInstruction::Return(None) => {
+                let end = if is_void(&self.return_type) { "return" } else { "unreachable" };
+                body.push(format!("{}({})", pad, end));
+                return Ok(true);
+            }
+            IRInstruction::Return(Some(value)) => {
+                let (expr, _) = self.generate_value(value)?;
+                body.push(format!("{}(return {})", pad, expr));
+                return Ok(true);
+            }
+            IRInstruction::Branch { label } => {
+                body.push(format!("{}{}", pad, self.set_block(label)?));
+                body.push(format!("{}(br {})", pad, DISPATCH));
+                return Ok(true);
+            }
+            IRInstruction::ConditionalBranch { condition, then_label, else_label } => {
+                let (condition, type_) = self.generate_value(condition)?;
+                if type_ != Type::Bool {
+                    return Err(SlangError::Compilation(format!("Branch condition must be bool, got {}", type_)));
+                }
+                body.push(format!("{}{}", pad, self.set_block(then_label)?));
+                body.push(format!("{}(br_if {} {})", pad, DISPATCH, condition));
+                body.push(format!("{}{}", pad, self.set_block(else_label)?));
+                body.push(format!("{}(br {})", pad, DISPATCH));
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    // 値を計算する式 (入れ子の S 式) とその型を返す
+    fn generate_value(&mut self, value: &IRValue) -> Result<(String, Type)> {
+        match value {
+            IRValue::Int(_) | IRValue::Float(_) | IRValue::Bool(_) | IRValue::Char(_) => self.generate_literal(value),
+            IRValue::Constant(inner) => self.generate_value(inner),
+            IRValue::Variable(name) | IRValue::Identifier(name) => {
+                let type_ = self.locals.get(name).cloned().ok_or_else(|| {
+                    SlangError::Compilation(format!("Undefined variable: {}", name))
+                })?;
+                Ok((format!("(local.get ${})", name), type_))
+            }
+            IRValue::BinaryOp { left, op, right } => self.generate_binary(op, left, right),
+            IRValue::UnaryOp { op, expr } => self.generate_unary(op, expr),
+            IRValue::Call { function, arguments } => self.generate_call(function, arguments),
+            IRValue::Assignment { name, value } => {
+                let (expr, type_) = self.generate_value(value)?;
+                self.declare_local(name, &type_)?;
+                Ok((format!("(local.tee ${} {})", name, expr), type_))
+            }
+            _ => Err(SlangError::Compilation(format!(
+                "WebAssembly backend does not support {:?} yet",
+                value
+            ))),
+        }
+    }
+
+    fn generate_literal(&self, value: &IRValue) -> Result<(String, Type)> {
+        match value {
+            IRValue::Int(i) => Ok((format!("(i64.const {})", i), Type::Int)),
+            IRValue::Float(f) => Ok((format!("(f64.const {})", float_literal(*f)), Type::Float)),
+            IRValue::Bool(b) => Ok((format!("(i32.const {})", *b as i32), Type::Bool)),
+            IRValue::Char(c) => Ok((format!("(i32.const {})", *c as u32), Type::Char)),
+            _ => Err(SlangError::Compilation(format!("Not a literal: {:?}", value))),
+        }
+    }
+
+    // int と float が混ざるときは LLVM バックエンドと同じく int 側を `f64.convert_i64_s` で float にする
+    fn generate_binary(&mut self, op: &IRBinaryOperator, left: &IRValue, right: &IRValue) -> Result<(String, Type)> {
+        let (mut left, mut left_type) = self.generate_value(left)?;
+        let (mut right, mut right_type) = self.generate_value(right)?;
+        match (&left_type, &right_type) {
+            (Type::Int, Type::Float) => (left, left_type) = int_to_float(&left),
+            (Type::Float, Type::Int) => (right, right_type) = int_to_float(&right),
+            _ => {}
+        }
+        if left_type != right_type {
+            return Err(SlangError::Compilation(format!(
+                "Operands of {:?} have different types: {} and {}",
+                op, left_type, right_type
+            )));
+        }
+        let (instruction, result_type) = binary_instruction(op, &left_type)?;
+        Ok((format!("({} {} {})", instruction, left, right), result_type))
+    }
+
+    fn generate_unary(&mut self, op: &IRUnaryOperator, expr: &IRValue) -> Result<(String, Type)> {
+        let (expr, type_) = self.generate_value(expr)?;
+        let result = match (op, &type_) {
+            (IRUnaryOperator::Neg | IRUnaryOperator::Negate, Type::Int) => format!("(i64.sub (i64.const 0) {})", expr),
+            (IRUnaryOperator::Neg | IRUnaryOperator::Negate, Type::Float) => format!("(f64.neg {})", expr),
+            (IRUnaryOperator::Not, Type::Bool) => format!("(i32.eqz {})", expr),
+            _ => {
+                return Err(SlangError::Compilation(format!(
+                    "WebAssembly backend does not support {:?} on {}",
+                    op, type_
+                )))
+            }
+        };
+        Ok((result, type_))
+    }
+
+    // 呼び出せるのは同じ IR にある関数だけ。引数の型は宣言と一致しなければならない
+    fn generate_call(&mut self, function: &str, arguments: &[IRValue]) -> Result<(String, Type)> {
+        let (params, return_type) = self.signatures.get(function).cloned().ok_or_else(|| {
+            SlangError::Compilation(format!("Undefined function: {}", function))
+        })?;
+        if params.len() != arguments.len() {
+            return Err(SlangError::Compilation(format!(
+                "Function {} expects {} arguments, got {}",
+                function,
+                params.len(),
+                arguments.len()
+            )));
+        }
+
+        let mut call = format!("(call ${}", function);
+        for (argument, param_type) in arguments.iter().zip(&params) {
+            let (expr, type_) = self.generate_value(argument)?;
+            if &type_ != param_type {
+                return Err(SlangError::Compilation(format!(
+                    "Argument of {} must be {}, got {}",
+                    function, param_type, type_
+                )));
+            }
+            call.push(' ');
+            call.push_str(&expr);
+        }
+        call.push(')');
+        Ok((call, return_type))
+    }
+
+    // 最初の代入で変数の型が決まる。以後は同じ型の値しか代入できない
+    fn set_local(&mut self, name: &str, expr: String, type_: &Type) -> Result<String> {
+        if is_void(type_) {
+            return Err(SlangError::Compilation(format!("Cannot assign a void value to {}", name)));
+        }
+        self.declare_local(name, type_)?;
+        Ok(format!("(local.set ${} {})", name, expr))
+    }
+
+    fn declare_local(&mut self, name: &str, type_: &Type) -> Result<()> {
+        match self.locals.get(name) {
+            Some(existing) if existing == type_ => Ok(()),
+            Some(existing) => Err(SlangError::Compilation(format!(
+                "Variable {} is used as both {} and {}",
+                name, existing, type_
+            ))),
+            None => {
+                let wasm_type = self.generate_type(type_)?;
+                self.declarations.push(format!("    (local ${} {})", name, wasm_type));
+                self.locals.insert(name.to_string(), type_.clone());
+                Ok(())
+            }
+        }
+    }
+
+    fn set_block(&self, label: &str) -> Result<String> {
+        let index = self.block_indices.get(label).ok_or_else(|| {
+            SlangError::Compilation(format!("Unknown block: {}", label))
+        })?;
+        Ok(format!("(local.set {} (i32.const {}))", BLOCK_INDEX, index))
+    }
+
+    // bool と char は i32 (char は Unicode のスカラー値)。文字列や集成体はまだ扱わない
+    fn generate_type(&self, type_: &Type) -> Result<&'static str> {
+        match type_ {
+            Type::Int => Ok("i64"),
+            Type::Float => Ok("f64"),
+            Type::Bool | Type::Char => Ok("i32"),
+            _ => Err(SlangError::Compilation(format!(
+                "WebAssembly backend does not support type {} yet",
+                type_
+            ))),
+        }
+    }
+}
+
+impl Default for WasmGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn is_void(type_: &Type) -> bool {
+    matches!(type_, Type::Void | Type::Unit)
+}
+
+fn indent(level: usize) -> String {
+    "  ".repeat(level)
+}
+
+fn float_literal(value: f64) -> String {
+    if value.is_nan() {
+        "nan".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "inf" } else { "-inf" }.to_string()
+    } else {
+        format!("{:?}", value)
+    }
+}
+
+fn binary_instruction(op: &IRBinaryOperator, operand_type: &Type) -> Result<(&'static str, Type)> {
+    use IRBinaryOperator::*;
+    let instruction = match (operand_type, op) {
+        (Type::Int, Add) => ("i64.add", Type::Int),
+        (Type::Int, Sub | Subtract) => ("i64.sub", Type::Int),
+        (Type::Int, Mul | Multiply) => ("i64.mul", Type::Int),
+        (Type::Int, Div | Divide) => ("i64.div_s", Type::Int),
+        (Type::Int, Mod | Modulo) => ("i64.rem_s", Type::Int),
+        (Type::Int, Eq | Equals) => ("i64.eq", Type::Bool),
+        (Type::Int, Neq | NotEquals) => ("i64.ne", Type::Bool),
+        (Type::Int, Lt | LessThan) => ("i64.lt_s", Type::Bool),
+        (Type::Int, Lte | LessThanEquals) => ("i64.le_s", Type::Bool),
+        (Type::Int, Gt | GreaterThan) => ("i64.gt_s", Type::Bool),
+        (Type::Int, Gte | GreaterThanEquals) => ("i64.ge_s", Type::Bool),
+        (Type::Float, Add) => ("f64.add", Type::Float),
+        (Type::Float, Sub | Subtract) => ("f64.sub", Type::Float),
+        (Type::Float, Mul | Multiply) => ("f64.mul", Type::Float),
+        (Type::Float, Div | Divide) => ("f64.div", Type::Float),
+        (Type::Float, Eq | Equals) => ("f64.eq", Type::Bool),
+        (Type::Float, Neq | NotEquals) => ("f64.ne", Type::Bool),
+        (Type::Float, Lt | LessThan) => ("f64.lt", Type::Bool),
+        (Type::Float, Lte | LessThanEquals) => ("f64.le", Type::Bool),
+        (Type::Float, Gt | GreaterThan) => ("f64.gt", Type::Bool),
+        (Type::Float, Gte | GreaterThanEquals) => ("f64.ge", Type::Bool),
+        (Type::Bool | Type::Char, Eq | Equals) => ("i32.eq", Type::Bool),
+        (Type::Bool | Type::Char, Neq | NotEquals) => ("i32.ne", Type::Bool),
+        (Type::Char, Lt | LessThan) => ("i32.lt_u", Type::Bool),
+        (Type::Char, Lte | LessThanEquals) => ("i32.le_u", Type::Bool),
+        (Type::Char, Gt | GreaterThan) => ("i32.gt_u", Type::Bool),
+        (Type::Char, Gte | GreaterThanEquals) => ("i32.ge_u", Type::Bool),
+        (Type::Bool, And) => ("i32.and", Type::Bool),
+        (Type::Bool, Or) => ("i32.or", Type::Bool),
+        _ => {
+            return Err(SlangError::Compilation(format!(
+                "WebAssembly backend does not support {:?} on {}",
+                op, operand_type
+            )))
+        }
+    };
+    Ok(instruction)
+}
+
+fn int_to_float(expr: &str) -> (String, Type) {
+    (format!("(f64.convert_i64_s {})", expr), Type::Float)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+
+    fn generate(source: &str) -> Result<String> {
+        let mut compiler = Compiler::new();
+        compiler.parse(source)?;
+        WasmGenerator::new().generate(&compiler.compile_program()?)
+    }
+
+    #[test]
+    fn test_add_function() {
+        assert_eq!(
+            generate("fn add(a: int, b: int) -> int { return a + b; }").unwrap(),
+            "(module\n\
+             \x20 (func $add (export \"add\") (param $a i64) (param $b i64) (result i64)\n\
+             \x20   (return (i64.add (local.get $a) (local.get $b)))\n\
+             \x20 )\n\
+             )\n"
+        );
+    }
+
+    #[test]
+    fn test_branches_use_dispatch_loop() {
+        let output = generate(
+            "fn max(a: float, b: float) -> float { let m = b; if b > a { return m; } return a; }",
+        )
+        .unwrap();
+        assert!(output.contains("    (local $.block i32)\n    (local $m f64)\n"));
+        assert!(output.contains("(br_table $.b0 $.b1 $.b2 $.b3 (local.get $.block)))"));
+        assert!(output.contains("(br_if $.dispatch (f64.gt (local.get $b) (local.get $a)))"));
+        assert!(output.contains("(local.set $m (local.get $b))"));
+        assert!(output.contains("(return (local.get $m))"));
+        assert!(output.ends_with("    unreachable\n  )\n)\n"));
+
+        // 括弧の対応が取れている
+        let depth = output.chars().try_fold(0i32, |depth, c| {
+            let depth = depth + (c == '(') as i32 - (c == ')') as i32;
+            (depth >= 0).then_some(depth)
+        });
+        assert_eq!(depth, Some(0));
+    }
+
+    #[test]
+    fn test_calls() {
+        let output = generate(
+            "fn neg(x: int) -> int { return -x; } fn main() -> void { neg(1); }",
+        )
+        .unwrap();
+        assert!(output.contains("(return (i64.sub (i64.const 0) (local.get $x)))"));
+        assert!(output.contains("(drop (call $neg (i64.const 1)))"));
+        assert!(generate("fn f(s: string) -> void { return; }").is_err());
+    }
+
+    #[test]
+    fn test_mixed_int_and_float_operands() {
+        let output = generate("fn f() -> float { let x = 1; return x + 2.5; }").unwrap();
+        assert!(output.contains("(return (f64.add (f64.convert_i64_s (local.get $x)) (f64.const 2.5)))"), "{}", output);
+        let output = generate("fn g(a: float, n: int) -> bool { return a < n; }").unwrap();
+        assert!(output.contains("(return (f64.lt (local.get $a) (f64.convert_i64_s (local.get $n))))"), "{}", output);
+    }
+}
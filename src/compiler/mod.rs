@@ -3,6 +3,13 @@ use crate::error::{Result, SlangError};
 use crate::ir::*;
 use crate::lexer::Lexer;
 use crate::parser::Parser;
+use crate::type_system::{is_builtin, Type};
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+mod pipeline;
+pub use pipeline::{
+    CodegenPass, LowerPass, MacroExpandPass, OptimizePass, Pass, Pipeline, PipelineContext, TypeCheckPass,
+};
 
 pub struct Compiler {
     ast: AST,
@@ -15,77 +22,454 @@ impl Compiler {
         }
     }
 
-    pub fn compile(&mut self, source: &str) -> Result<IRFunction> {
+    pub fn with_ast(ast: AST) -> Self {
+        Self { ast }
+    }
+
+    pub fn parse(&mut self, source: &str) -> Result<()> {
         let lexer = Lexer::new(source);
         let mut parser = Parser::new(lexer);
-        let ast = parser.parse()?;
-        self.ast = ast;
+        self.ast = parser.parse()?;
+        Ok(())
+    }
 
-        // 最初の関数をコンパイル
-        if let Some(function) = self.ast.functions.first() {
-            self.compile_function(function)
-        } else {
-            Err(SlangError::Compilation("No functions found".to_string()))
+    // ソースを解析して最初の関数だけを返す
+    pub fn compile(&mut self, source: &str) -> Result<IRFunction> {
+        self.parse(source)?;
+        self.compile_program()?
+            .functions
+            .into_iter()
+            .next()
+            .ok_or_else(|| SlangError::Compilation("No functions found".to_string()))
+    }
+
+    // 解析済みの AST 全体を IR に変換する
+    pub fn compile_program(&mut self) -> Result<IR> {
+        let mut ir = IR::new();
+        for type_def in &self.ast.type_definitions {
+            ir.add_type_definition(IRTypeDefinition {
+                name: type_def.name.clone(),
+                fields: type_def.fields
+                    .iter()
+                    .map(|field| IRParameter {
+                        name: field.name.clone(),
+                        type_annotation: self.resolve_type(&field.type_annotation, &[]),
+                    })
+                    .collect(),
+            });
+        }
+        for function in &self.ast.functions {
+            let (function, lifted) = self.compile_function(function)?;
+            ir.add_function(function);
+            for lambda in lifted {
+                ir.add_function(lambda);
+            }
         }
+        Ok(ir)
     }
 
-    fn compile_function(&self, function: &Function) -> Result<IRFunction> {
-        let mut ir_function = IRFunction {
+    // 分岐を作らずに式を 1 つの値に変換する (デバッガの条件式など)。
+    // `&&` と `||` は二項演算のまま残す。実行時の評価でも右辺は短絡される
+    pub fn compile_value(&self, expression: &Expression) -> Result<IRValue> {
+        let mut builder = FunctionBuilder::new();
+        builder.short_circuit_as_value = true;
+        self.compile_expression(&mut builder, expression)
+    }
+
+    // IR の型では構造体の名前を `Type::Struct` にしておく (型検査の `resolve_type` と同じ規則)
+    fn resolve_type(&self, type_: &Type, type_params: &[String]) -> Type {
+        type_.resolve_structs(&|name| {
+            self.ast.type_definitions.iter().any(|definition| definition.name == name)
+                && !type_params.iter().any(|param| param == name)
+        })
+    }
+
+    // 本体に含まれるラムダを持ち上げた関数も一緒に返す
+    fn compile_function(&self, function: &Function) -> Result<(IRFunction, Vec<IRFunction>)> {
+        // 関数本体をコンパイル
+        let mut builder = FunctionBuilder::new();
+        builder.name = function.name.clone();
+        builder.type_params = function.type_params.clone();
+        builder.functions = self.ast.functions.iter().map(|f| f.name.clone()).collect();
+        // 仮引数と本体の変数は同じブロックに置く
+        for param in &function.parameters {
+            builder.declare(&param.name);
+        }
+        self.compile_statements(&mut builder, &function.body)?;
+        builder.terminate();
+
+        let function = IRFunction {
             name: function.name.clone(),
             parameters: function.parameters
                 .iter()
                 .map(|p| IRParameter {
                     name: p.name.clone(),
-                    type_annotation: p.type_annotation.clone(),
+                    type_annotation: self.resolve_type(&p.type_annotation, &function.type_params),
                 })
                 .collect(),
-            return_type: function.return_type.clone(),
-            blocks: vec![IRBlock {
-                label: "entry".to_string(),
-                instructions: Vec::new(),
-            }],
-            priority: function.priority,
+            return_type: self.resolve_type(&function.return_type, &function.type_params),
+            blocks: builder.blocks,
+            priorities: function.priorities.clone(),
         };
+        Ok((function, builder.lifted))
+    }
 
-        // 関数本体をコンパイル
-        for statement in &function.body.statements {
-            match statement {
-                Statement::Let(LetStatement { name, value, type_annotation }) => {
-                    let value = self.compile_expression(value)?;
-                    ir_function.blocks[0].instructions.push(IRInstruction::Let {
-                        name: name.clone(),
-                        value,
+    // ラムダの本体を `外側の関数名$lambdaN` という関数に持ち上げ、その関数を指すクロージャを返す。
+    // `$` はソースの識別子に使えないので名前は衝突しない
+    fn compile_lambda(&self, builder: &mut FunctionBuilder, params: &[Parameter], return_type: Option<&Type>, body: &Block) -> Result<IRValue> {
+        let name = format!("{}$lambda{}", builder.name, builder.lambdas);
+        builder.lambdas += 1;
+
+        let mut free = FreeVariables::default();
+        free.visit_block(body);
+        for param in params {
+            free.names.remove(&param.name);
+        }
+        // 捕まえる変数は外側と同じ IR の名前で参照する。外側の変数でない名前 (関数名など) はそのまま
+        let captures: Vec<String> = free.names.iter().map(|name| builder.resolve(name)).collect();
+
+        let mut lambda_builder = FunctionBuilder::new();
+        lambda_builder.name = name.clone();
+        lambda_builder.type_params = builder.type_params.clone();
+        lambda_builder.functions = builder.functions.clone();
+        for (name, ir_name) in free.names.iter().zip(&captures) {
+            lambda_builder.scopes[0].insert(name.clone(), ir_name.clone());
+        }
+        // 仮引数は捕まえる変数に含まれないので、捕まえた変数と同じブロックに置いて名前を変えずに済ませる
+        for param in params {
+            lambda_builder.declare(&param.name);
+        }
+        self.compile_statements(&mut lambda_builder, body)?;
+        lambda_builder.terminate();
+        builder.lifted.append(&mut lambda_builder.lifted);
+        builder.lifted.push(IRFunction {
+            name: name.clone(),
+            parameters: params
+                .iter()
+                .map(|p| IRParameter {
+                    name: p.name.clone(),
+                    type_annotation: self.resolve_type(&p.type_annotation, &builder.type_params),
+                })
+                .collect(),
+            // 省略された戻り値の型は型検査でしか分からない。実行時には使わない
+            return_type: return_type.map_or(Type::Unit, |t| self.resolve_type(t, &builder.type_params)),
+            blocks: lambda_builder.blocks,
+            priorities: Vec::new(),
+        });
+
+        Ok(IRValue::Closure { function: name, captures })
+    }
+
+    // ブロックの中で作った変数はブロックを抜けると見えなくなる
+    fn compile_block(&self, builder: &mut FunctionBuilder, block: &Block) -> Result<()> {
+        builder.push_scope();
+        let result = self.compile_statements(builder, block);
+        builder.pop_scope();
+        result
+    }
+
+    fn compile_statements(&self, builder: &mut FunctionBuilder, block: &Block) -> Result<()> {
+        for statement in &block.statements {
+            self.compile_statement(builder, statement)?;
+        }
+        Ok(())
+    }
+
+    fn compile_statement(&self, builder: &mut FunctionBuilder, statement: &Statement) -> Result<()> {
+        match statement {
+            Statement::Let(LetStatement { name, value, priority, .. }) => {
+                let value = self.compile_expression(builder, value)?;
+                let name = builder.declare(name);
+                builder.push(IRInstruction::Let {
+                    name: name.clone(),
+                    value,
+                });
+                for level in priority.iter().flat_map(|priority| priority.levels()) {
+                    builder.push(IRInstruction::Priority { name: name.clone(), priority: level });
+                }
+            }
+            Statement::Return(ReturnStatement { value }) => {
+                if let Some(value) = value {
+                    let value = self.compile_expression(builder, value)?;
+                    builder.push(IRInstruction::Return(Some(value)));
+                } else {
+                    builder.push(IRInstruction::Return(None));
+                }
+            }
+            Statement::If(IfStatement { condition, then_block, else_block }) => {
+                let condition = self.compile_expression(builder, condition)?;
+                let then_label = builder.new_block("if_then");
+                let else_label = builder.new_block("if_else");
+                let end_label = builder.new_block("if_end");
+                builder.push(IRInstruction::ConditionalBranch {
+                    condition,
+                    then_label: then_label.clone(),
+                    else_label: else_label.clone(),
+                });
+                builder.switch_to(&then_label);
+                self.compile_block(builder, then_block)?;
+                builder.push(IRInstruction::Branch { label: end_label.clone() });
+                builder.switch_to(&else_label);
+                if let Some(else_block) = else_block {
+                    self.compile_block(builder, else_block)?;
+                }
+                builder.push(IRInstruction::Branch { label: end_label.clone() });
+                builder.switch_to(&end_label);
+            }
+            Statement::While(WhileStatement { condition, body }) => {
+                let header_label = builder.new_block("while_header");
+                let body_label = builder.new_block("while_body");
+                let exit_label = builder.new_block("while_exit");
+                builder.push(IRInstruction::Branch { label: header_label.clone() });
+                builder.switch_to(&header_label);
+                let condition = self.compile_expression(builder, condition)?;
+                builder.push(IRInstruction::ConditionalBranch {
+                    condition,
+                    then_label: body_label.clone(),
+                    else_label: exit_label.clone(),
+                });
+                builder.switch_to(&body_label);
+                builder.loops.push((header_label.clone(), exit_label.clone()));
+                self.compile_block(builder, body)?;
+                builder.loops.pop();
+                builder.push(IRInstruction::Branch { label: header_label });
+                builder.switch_to(&exit_label);
+            }
+            Statement::For(ForStatement { variable, iterator, body }) => {
+                // `for x in xs { .. }` をインデックスを進める while に脱糖する
+                //   let iter = xs; let index = 0;
+                //   while index < iter.len() { let x = iter[index]; ..; index = index + 1; }
+                // 範囲 `for i in a..b { .. }` は配列を作らずに数える
+                //   let index = a; let end = b;
+                //   while index < end { let i = index; ..; index = index + 1; }
+                // `..=` は `<=` で比べ、`end` が `i64::MAX` でも溢れないよう `index == end` なら進める前に抜ける
+                let index = builder.new_temp("index");
+                let mut inclusive_end = None;
+                let (condition, element) = if let Expression::Range { start, end, inclusive } = iterator.as_ref() {
+                    let end_temp = builder.new_temp("end");
+                    let start = self.compile_expression(builder, start)?;
+                    builder.push(IRInstruction::Let { name: index.clone(), value: start });
+                    let end = self.compile_expression(builder, end)?;
+                    builder.push(IRInstruction::Let { name: end_temp.clone(), value: end });
+                    let op = if *inclusive { IRBinaryOperator::LessThanEquals } else { IRBinaryOperator::LessThan };
+                    let condition = IRValue::BinaryOp {
+                        left: Box::new(IRValue::Variable(index.clone())),
+                        op,
+                        right: Box::new(IRValue::Variable(end_temp.clone())),
+                    };
+                    if *inclusive {
+                        inclusive_end = Some(end_temp);
+                    }
+                    (condition, IRValue::Variable(index.clone()))
+                } else {
+                    let iter = builder.new_temp("iter");
+                    let iterator = self.compile_expression(builder, iterator)?;
+                    builder.push(IRInstruction::Let { name: iter.clone(), value: iterator });
+                    builder.push(IRInstruction::Let { name: index.clone(), value: IRValue::Int(0) });
+                    let condition = IRValue::BinaryOp {
+                        left: Box::new(IRValue::Variable(index.clone())),
+                        op: IRBinaryOperator::LessThan,
+                        right: Box::new(IRValue::MethodCall {
+                            receiver: Box::new(IRValue::Variable(iter.clone())),
+                            method: "len".to_string(),
+                            arguments: Vec::new(),
+                        }),
+                    };
+                    let element = IRValue::Index {
+                        array: Box::new(IRValue::Variable(iter)),
+                        index: Box::new(IRValue::Variable(index.clone())),
+                    };
+                    (condition, element)
+                };
+
+                let header_label = builder.new_block("for_header");
+                let body_label = builder.new_block("for_body");
+                let step_label = builder.new_block("for_step");
+                let exit_label = builder.new_block("for_exit");
+                builder.push(IRInstruction::Branch { label: header_label.clone() });
+
+                builder.switch_to(&header_label);
+                builder.push(IRInstruction::ConditionalBranch {
+                    condition,
+                    then_label: body_label.clone(),
+                    else_label: exit_label.clone(),
+                });
+
+                builder.switch_to(&body_label);
+                // ループ変数は本体の中だけで見える。外側の同名の変数は書き換えない
+                builder.push_scope();
+                let variable = builder.declare(variable);
+                builder.push(IRInstruction::Let { name: variable, value: element });
+                // `continue` はインデックスを進めるブロックに飛ぶ
+                builder.loops.push((step_label.clone(), exit_label.clone()));
+                self.compile_block(builder, body)?;
+                builder.loops.pop();
+                builder.pop_scope();
+                builder.push(IRInstruction::Branch { label: step_label.clone() });
+
+                builder.switch_to(&step_label);
+                if let Some(end_temp) = inclusive_end {
+                    let increment_label = builder.new_block("for_increment");
+                    builder.push(IRInstruction::ConditionalBranch {
+                        condition: IRValue::BinaryOp {
+                            left: Box::new(IRValue::Variable(index.clone())),
+                            op: IRBinaryOperator::Equals,
+                            right: Box::new(IRValue::Variable(end_temp)),
+                        },
+                        then_label: exit_label.clone(),
+                        else_label: increment_label.clone(),
                     });
+                    builder.switch_to(&increment_label);
+                }
+                builder.push(IRInstruction::Assignment {
+                    target: index.clone(),
+                    value: IRValue::BinaryOp {
+                        left: Box::new(IRValue::Variable(index)),
+                        op: IRBinaryOperator::Add,
+                        right: Box::new(IRValue::Int(1)),
+                    },
+                });
+                builder.push(IRInstruction::Branch { label: header_label });
+                builder.switch_to(&exit_label);
+            }
+            Statement::IfLet(IfLetStatement { pattern, expression, then_block, else_block }) => {
+                // let s = 式; br_if (パターンに合う) then else
+                // then: let 束縛..; ..; br end
+                let (condition, bindings) = self.compile_pattern_match(builder, pattern, expression)?;
+                let then_label = builder.new_block("if_let_then");
+                let else_label = builder.new_block("if_let_else");
+                let end_label = builder.new_block("if_let_end");
+                builder.push(IRInstruction::ConditionalBranch {
+                    condition,
+                    then_label: then_label.clone(),
+                    else_label: else_label.clone(),
+                });
+                builder.switch_to(&then_label);
+                builder.push_scope();
+                for (name, value) in bindings {
+                    let name = builder.declare(&name);
+                    builder.push(IRInstruction::Let { name, value });
+                }
+                self.compile_block(builder, then_block)?;
+                builder.pop_scope();
+                builder.push(IRInstruction::Branch { label: end_label.clone() });
+                builder.switch_to(&else_label);
+                if let Some(else_block) = else_block {
+                    self.compile_block(builder, else_block)?;
                 }
-                Statement::Return(ReturnStatement { value }) => {
-                    if let Some(value) = value {
-                        let value = self.compile_expression(value)?;
-                        ir_function.blocks[0].instructions.push(IRInstruction::Return(Some(value)));
-                    } else {
-                        ir_function.blocks[0].instructions.push(IRInstruction::Return(None));
+                builder.push(IRInstruction::Branch { label: end_label.clone() });
+                builder.switch_to(&end_label);
+            }
+            Statement::WhileLet(WhileLetStatement { pattern, expression, body }) => {
+                // 式は周回ごとにヘッダで評価し直す
+                let header_label = builder.new_block("while_let_header");
+                let body_label = builder.new_block("while_let_body");
+                let exit_label = builder.new_block("while_let_exit");
+                builder.push(IRInstruction::Branch { label: header_label.clone() });
+                builder.switch_to(&header_label);
+                let (condition, bindings) = self.compile_pattern_match(builder, pattern, expression)?;
+                builder.push(IRInstruction::ConditionalBranch {
+                    condition,
+                    then_label: body_label.clone(),
+                    else_label: exit_label.clone(),
+                });
+                builder.switch_to(&body_label);
+                builder.push_scope();
+                for (name, value) in bindings {
+                    let name = builder.declare(&name);
+                    builder.push(IRInstruction::Let { name, value });
+                }
+                builder.loops.push((header_label.clone(), exit_label.clone()));
+                self.compile_block(builder, body)?;
+                builder.loops.pop();
+                builder.pop_scope();
+                builder.push(IRInstruction::Branch { label: header_label });
+                builder.switch_to(&exit_label);
+            }
+            Statement::Match(MatchStatement { expression, arms }) => {
+                // let s = 式; 腕を上から順に試す
+                // match_arm: br_if (パターンに合う) match_body match_next
+                // match_body: let 束縛..; ..; br end
+                let scrutinee = builder.new_temp("scrutinee");
+                let value = self.compile_expression(builder, expression)?;
+                builder.push(IRInstruction::Let { name: scrutinee.clone(), value });
+                let end_label = builder.new_block("match_end");
+                for arm in arms {
+                    let (condition, bindings) = pattern_condition(&arm.pattern, IRValue::Variable(scrutinee.clone()))?;
+                    let body_label = builder.new_block("match_body");
+                    let next_label = builder.new_block("match_next");
+                    builder.push(IRInstruction::ConditionalBranch {
+                        condition,
+                        then_label: body_label.clone(),
+                        else_label: next_label.clone(),
+                    });
+                    builder.switch_to(&body_label);
+                    builder.push_scope();
+                    for (name, value) in bindings {
+                        let name = builder.declare(&name);
+                        builder.push(IRInstruction::Let { name, value });
                     }
+                    self.compile_block(builder, &arm.body)?;
+                    builder.pop_scope();
+                    builder.push(IRInstruction::Branch { label: end_label.clone() });
+                    builder.switch_to(&next_label);
                 }
-                _ => return Err(SlangError::Compilation("Unsupported statement".to_string())),
+                // 網羅性は型検査で確かめているので、どの腕にも合わずにここへ来ることはない
+                builder.push(IRInstruction::Branch { label: end_label.clone() });
+                builder.switch_to(&end_label);
+            }
+            Statement::Break => {
+                let (_, exit_label) = builder.loops.last()
+                    .cloned()
+                    .ok_or_else(|| SlangError::Compilation("`break` outside of a loop".to_string()))?;
+                builder.push(IRInstruction::Branch { label: exit_label });
+                // 後続の文は到達しないが、分岐の後に命令を積まないよう新しいブロックに移る
+                let unreachable = builder.new_block("after_break");
+                builder.switch_to(&unreachable);
+            }
+            Statement::Continue => {
+                let (header_label, _) = builder.loops.last()
+                    .cloned()
+                    .ok_or_else(|| SlangError::Compilation("`continue` outside of a loop".to_string()))?;
+                builder.push(IRInstruction::Branch { label: header_label });
+                let unreachable = builder.new_block("after_continue");
+                builder.switch_to(&unreachable);
+            }
+            Statement::Transfer(stmt) => {
+                builder.push(IRInstruction::Transfer { from: builder.resolve(&stmt.from), to: builder.resolve(&stmt.to) });
+            }
+            Statement::Expression(expr) => {
+                let value = self.compile_expression(builder, expr)?;
+                builder.push(IRInstruction::Expression(value));
             }
         }
+        Ok(())
+    }
 
-        Ok(ir_function)
+    // `式` を一時変数に入れ、`pattern` に合うかの条件と、合ったときに束縛する変数を返す
+    fn compile_pattern_match(
+        &self,
+        builder: &mut FunctionBuilder,
+        pattern: &Pattern,
+        expression: &Expression,
+    ) -> Result<(IRValue, Vec<(String, IRValue)>)> {
+        let scrutinee = builder.new_temp("scrutinee");
+        let value = self.compile_expression(builder, expression)?;
+        builder.push(IRInstruction::Let { name: scrutinee.clone(), value });
+        pattern_condition(pattern, IRValue::Variable(scrutinee))
     }
 
-    fn compile_expression(&self, expression: &Expression) -> Result<IRValue> {
+    fn compile_expression(&self, builder: &mut FunctionBuilder, expression: &Expression) -> Result<IRValue> {
         match expression {
-            Expression::Literal(lit) => {
-                match lit {
-                    Literal::Int(i) => Ok(IRValue::Int(*i)),
-                    Literal::Float(f) => Ok(IRValue::Float(*f)),
-                    Literal::String(s) => Ok(IRValue::String(s.clone())),
-                    Literal::Bool(b) => Ok(IRValue::Bool(*b)),
-                    Literal::Null => Ok(IRValue::Null),
-                }
+            Expression::Literal(lit) => Ok(literal_value(lit)),
+            Expression::BinaryOp(expr)
+                if matches!(expr.op, BinaryOperator::And | BinaryOperator::Or) && !builder.short_circuit_as_value =>
+            {
+                self.compile_short_circuit(builder, expr)
             }
             Expression::BinaryOp(expr) => {
-                let left_value = self.compile_expression(&expr.left)?;
-                let right_value = self.compile_expression(&expr.right)?;
+                let left_value = self.compile_expression(builder, &expr.left)?;
+                let right_value = self.compile_expression(builder, &expr.right)?;
                 Ok(IRValue::BinaryOp {
                     left: Box::new(left_value),
                     op: match expr.op {
@@ -100,8 +484,6 @@ impl Compiler {
                         BinaryOperator::Lte => IRBinaryOperator::Lte,
                         BinaryOperator::Gt => IRBinaryOperator::Gt,
                         BinaryOperator::Gte => IRBinaryOperator::Gte,
-                        BinaryOperator::And => IRBinaryOperator::And,
-                        BinaryOperator::Or => IRBinaryOperator::Or,
                         BinaryOperator::Equals => IRBinaryOperator::Equals,
                         BinaryOperator::NotEquals => IRBinaryOperator::NotEquals,
                         BinaryOperator::LessThan => IRBinaryOperator::LessThan,
@@ -110,12 +492,14 @@ impl Compiler {
                         BinaryOperator::GreaterThanEquals => IRBinaryOperator::GreaterThanEquals,
                         BinaryOperator::Divide => IRBinaryOperator::Divide,
                         BinaryOperator::Modulo => IRBinaryOperator::Modulo,
+                        BinaryOperator::And => IRBinaryOperator::And,
+                        BinaryOperator::Or => IRBinaryOperator::Or,
                     },
                     right: Box::new(right_value),
                 })
             }
             Expression::UnaryOp(expr) => {
-                let expr_value = self.compile_expression(&expr.right)?;
+                let expr_value = self.compile_expression(builder, &expr.expr)?;
                 Ok(IRValue::UnaryOp {
                     op: match expr.op {
                         UnaryOperator::Neg => IRUnaryOperator::Neg,
@@ -127,23 +511,432 @@ impl Compiler {
             }
             Expression::Call(expr) => {
                 let arg_values = expr.arguments.iter()
-                    .map(|arg| self.compile_expression(arg))
+                    .map(|arg| self.compile_expression(builder, arg))
                     .collect::<Result<Vec<_>>>()?;
+                // クロージャを入れた変数の呼び出しなら、その変数の IR での名前
                 Ok(IRValue::Call {
-                    function: expr.function.clone(),
+                    function: builder.resolve(&expr.function),
                     arguments: arg_values,
                 })
             }
             Expression::Assignment(expr) => {
-                let value = self.compile_expression(&expr.value)?;
+                let value = self.compile_expression(builder, &expr.value)?;
                 Ok(IRValue::Assignment {
-                    name: expr.target.clone(),
+                    name: builder.resolve(&expr.target),
                     value: Box::new(value),
                 })
             }
             Expression::Identifier(name) => {
-                Ok(IRValue::Variable(name.clone()))
+                Ok(IRValue::Variable(builder.resolve(name)))
+            }
+            Expression::Array(elements) => {
+                let elements = elements.iter()
+                    .map(|element| self.compile_expression(builder, element))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(IRValue::Array(elements))
+            }
+            Expression::Index { array, index } => {
+                Ok(IRValue::Index {
+                    array: Box::new(self.compile_expression(builder, array)?),
+                    index: Box::new(self.compile_expression(builder, index)?),
+                })
+            }
+            Expression::Range { .. } => Err(SlangError::Compilation(
+                "Ranges can only be used as the iterator of a `for` loop".to_string(),
+            )),
+            Expression::Cast { expr, target } => Ok(IRValue::Cast {
+                value: Box::new(self.compile_expression(builder, expr)?),
+                target: target.clone(),
+            }),
+            Expression::Lambda { params, return_type, body } => self.compile_lambda(builder, params, return_type.as_ref(), body),
+            // 実行時のタプルは要素を並べた配列と同じ値で表す。要素の型の区別は型検査で済んでいる
+            Expression::Tuple(elements) => {
+                let elements = elements.iter()
+                    .map(|element| self.compile_expression(builder, element))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(IRValue::Array(elements))
+            }
+            Expression::MethodCall { receiver, method, arguments } => {
+                let receiver = self.compile_expression(builder, receiver)?;
+                let arguments = arguments.iter()
+                    .map(|arg| self.compile_expression(builder, arg))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(IRValue::MethodCall {
+                    receiver: Box::new(receiver),
+                    method: method.clone(),
+                    arguments,
+                })
+            }
+            Expression::FieldAccess { .. } | Expression::StructLiteral { .. } => Err(SlangError::Compilation(
+                "Struct values cannot be lowered to IR yet".to_string(),
+            )),
+        }
+    }
+
+    // `a && b` / `a || b` は結果が決まった時点で右辺を評価しないよう分岐に変換する
+    //   let t = a; br_if t rhs end (`||` なら br_if t end rhs)
+    //   rhs: t = b; br end
+    //   end: (式の値は t)
+    fn compile_short_circuit(&self, builder: &mut FunctionBuilder, expr: &BinaryOpExpression) -> Result<IRValue> {
+        let is_and = expr.op == BinaryOperator::And;
+        let prefix = if is_and { "and" } else { "or" };
+        let result = builder.new_temp(prefix);
+        let left = self.compile_expression(builder, &expr.left)?;
+        builder.push(IRInstruction::Let { name: result.clone(), value: left });
+
+        let rhs_label = builder.new_block(&format!("{}_rhs", prefix));
+        let end_label = builder.new_block(&format!("{}_end", prefix));
+        let (then_label, else_label) = if is_and {
+            (rhs_label.clone(), end_label.clone())
+        } else {
+            (end_label.clone(), rhs_label.clone())
+        };
+        builder.push(IRInstruction::ConditionalBranch {
+            condition: IRValue::Variable(result.clone()),
+            then_label,
+            else_label,
+        });
+
+        builder.switch_to(&rhs_label);
+        let right = self.compile_expression(builder, &expr.right)?;
+        builder.push(IRInstruction::Assignment { target: result.clone(), value: right });
+        builder.push(IRInstruction::Branch { label: end_label.clone() });
+
+        builder.switch_to(&end_label);
+        Ok(IRValue::Variable(result))
+    }
+}
+
+// 関数本体を基本ブロックに分けながら組み立てる
+struct FunctionBuilder {
+    blocks: Vec<IRBlock>,
+    current: usize,
+    // ループごとの (`continue` の飛び先, `break` の飛び先)
+    loops: Vec<(String, String)>,
+    // 脱糖で導入する一時変数の通し番号
+    temps: usize,
+    // `&&`/`||` を分岐に変換せず値のまま残す (`compile_value`)
+    short_circuit_as_value: bool,
+    // 持ち上げたラムダの名前の接頭辞になる関数名
+    name: String,
+    // ラムダの通し番号
+    lambdas: usize,
+    // この関数の中のラムダを持ち上げた関数 (入れ子のラムダも含む)
+    lifted: Vec<IRFunction>,
+    // ブロックごとの、ソース上の変数名から IR の変数名への対応。外側のブロックの変数を
+    // 隠す `let` には別の名前を付け、ブロックを抜けると外側の変数がまた見えるようにする
+    scopes: Vec<HashMap<String, String>>,
+    // プログラムで定義された関数の名前
+    functions: HashSet<String>,
+    // 関数の型パラメータ。ラムダは外側の関数のものを引き継ぐ
+    type_params: Vec<String>,
+}
+
+// ラムダの本体が参照する名前。呼び出す関数の名前も、クロージャを入れた変数かもしれないので含める
+#[derive(Default)]
+struct FreeVariables {
+    names: BTreeSet<String>,
+}
+
+impl Visitor for FreeVariables {
+    fn visit_expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::Identifier(name) => {
+                self.names.insert(name.clone());
+            }
+            Expression::Call(call) => {
+                self.names.insert(call.function.clone());
+            }
+            Expression::Assignment(assign) => {
+                self.names.insert(assign.target.clone());
+            }
+            _ => {}
+        }
+        walk_expression(self, expression);
+    }
+}
+
+fn literal_value(literal: &Literal) -> IRValue {
+    match literal {
+        Literal::Int(i) => IRValue::Int(*i),
+        Literal::Float(f) => IRValue::Float(*f),
+        Literal::Complex(re, im) => IRValue::Complex(*re, *im),
+        Literal::String(s) => IRValue::String(s.clone()),
+        Literal::Bool(b) => IRValue::Bool(*b),
+        Literal::Char(c) => IRValue::Char(*c),
+        Literal::Null => IRValue::Null,
+    }
+}
+
+// `value` が `pattern` に合うかの条件と、合ったときに束縛する変数を返す
+fn pattern_condition(pattern: &Pattern, value: IRValue) -> Result<(IRValue, Vec<(String, IRValue)>)> {
+    let mut conditions = Vec::new();
+    let mut bindings = Vec::new();
+    compile_pattern(pattern, value, &mut conditions, &mut bindings)?;
+    let condition = conditions
+        .into_iter()
+        .reduce(|left, right| IRValue::BinaryOp {
+            left: Box::new(left),
+            op: IRBinaryOperator::And,
+            right: Box::new(right),
+        })
+        .unwrap_or(IRValue::Bool(true));
+    Ok((condition, bindings))
+}
+
+// `value` がパターンに合う条件を `conditions` に、束縛する変数と値を `bindings` に加える。
+// タプルの要素は添字で取り出す (要素数は型検査で一致している)
+fn compile_pattern(
+    pattern: &Pattern,
+    value: IRValue,
+    conditions: &mut Vec<IRValue>,
+    bindings: &mut Vec<(String, IRValue)>,
+) -> Result<()> {
+    match pattern {
+        Pattern::Identifier(name) => bindings.push((name.clone(), value)),
+        Pattern::Wildcard => {}
+        Pattern::Literal(literal) => conditions.push(IRValue::BinaryOp {
+            left: Box::new(value),
+            op: IRBinaryOperator::Equals,
+            right: Box::new(literal_value(literal)),
+        }),
+        Pattern::Tuple(patterns) => {
+            for (index, pattern) in patterns.iter().enumerate() {
+                let element = IRValue::Index {
+                    array: Box::new(value.clone()),
+                    index: Box::new(IRValue::Int(index as i64)),
+                };
+                compile_pattern(pattern, element, conditions, bindings)?;
+            }
+        }
+        Pattern::Struct { .. } => {
+            return Err(SlangError::Compilation("Struct patterns cannot be lowered to IR yet".to_string()));
+        }
+    }
+    Ok(())
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FunctionBuilder {
+    fn new() -> Self {
+        Self {
+            blocks: vec![IRBlock {
+                label: "entry".to_string(),
+                instructions: Vec::new(),
+            }],
+            current: 0,
+            loops: Vec::new(),
+            temps: 0,
+            short_circuit_as_value: false,
+            name: String::new(),
+            lambdas: 0,
+            lifted: Vec::new(),
+            scopes: vec![HashMap::new()],
+            functions: HashSet::new(),
+            type_params: Vec::new(),
+        }
+    }
+
+    fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn pop_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    // 現在のブロックに変数を作り、その IR での名前を返す。外側のブロックの同名の変数を隠す場合は
+    // `名前$N` にする (`$` はソースの識別子に使えない)。同じブロックでの `let` のやり直しは同じ名前のまま
+    fn declare(&mut self, name: &str) -> String {
+        let (current, enclosing) = self.scopes.split_last().expect("the function scope is never popped");
+        let ir_name = match current.get(name) {
+            Some(ir_name) => ir_name.clone(),
+            // 入れ子のブロックの変数はブロックを抜けてもフレームに残る。関数と同じ名前のものは、
+            // ブロックの外の呼び出しがそのクロージャを呼ばないように別の名前にする
+            None if enclosing.iter().any(|scope| scope.contains_key(name))
+                || (!enclosing.is_empty() && (self.functions.contains(name) || is_builtin(name))) =>
+            {
+                self.temps += 1;
+                format!("{}${}", name, self.temps - 1)
             }
+            None => name.to_string(),
+        };
+        self.scopes.last_mut().expect("checked above").insert(name.to_string(), ir_name.clone());
+        ir_name
+    }
+
+    // 見えている変数の IR での名前。変数でなければ (関数名など) そのまま返す
+    fn resolve(&self, name: &str) -> String {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .cloned()
+            .unwrap_or_else(|| name.to_string())
+    }
+
+    fn new_block(&mut self, prefix: &str) -> String {
+        let label = format!("{}{}", prefix, self.blocks.len());
+        self.blocks.push(IRBlock {
+            label: label.clone(),
+            instructions: Vec::new(),
+        });
+        label
+    }
+
+    // ソース上の識別子と衝突しない名前を返す
+    fn new_temp(&mut self, prefix: &str) -> String {
+        let name = format!("${}{}", prefix, self.temps);
+        self.temps += 1;
+        name
+    }
+
+    fn switch_to(&mut self, label: &str) {
+        if let Some(index) = self.blocks.iter().position(|block| block.label == label) {
+            self.current = index;
+        }
+    }
+
+    fn push(&mut self, instruction: IRInstruction) {
+        self.blocks[self.current].instructions.push(instruction);
+    }
+
+    // 本体の末尾に達した場合は値を返さずに抜ける。そのブロックも終端命令で閉じる
+    fn terminate(&mut self) {
+        if !self.blocks[self.current].instructions.last().is_some_and(IRInstruction::is_terminator) {
+            self.push(IRInstruction::Return(None));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block<'a>(function: &'a IRFunction, prefix: &str) -> &'a IRBlock {
+        function.blocks.iter().find(|block| block.label.starts_with(prefix)).unwrap()
+    }
+
+    #[test]
+    fn test_break_and_continue_branch_to_loop_exit_and_header() {
+        let function = Compiler::new()
+            .compile("fn f() -> int { while true { if false { continue; } break; } return 1; }")
+            .unwrap();
+        let header = block(&function, "while_header").label.clone();
+        let exit = block(&function, "while_exit").label.clone();
+
+        assert_eq!(
+            block(&function, "entry").instructions,
+            vec![IRInstruction::Branch { label: header.clone() }]
+        );
+        assert_eq!(
+            block(&function, "if_then").instructions,
+            vec![IRInstruction::Branch { label: header.clone() }]
+        );
+        assert_eq!(
+            block(&function, "if_end").instructions,
+            vec![IRInstruction::Branch { label: exit.clone() }]
+        );
+        assert_eq!(
+            block(&function, "while_exit").instructions,
+            vec![IRInstruction::Return(Some(IRValue::Int(1)))]
+        );
+    }
+
+    #[test]
+    fn test_if_else_branches_to_merge_block() {
+        let function = Compiler::new()
+            .compile("fn f(x: int) -> int { if x < 0 { return 0; } else { return 1; } return 2; }")
+            .unwrap();
+        let then_label = block(&function, "if_then").label.clone();
+        let else_label = block(&function, "if_else").label.clone();
+        let end_label = block(&function, "if_end").label.clone();
+
+        assert_eq!(
+            block(&function, "entry").instructions,
+            vec![IRInstruction::ConditionalBranch {
+                condition: IRValue::BinaryOp {
+                    left: Box::new(IRValue::Variable("x".to_string())),
+                    op: IRBinaryOperator::LessThan,
+                    right: Box::new(IRValue::Int(0)),
+                },
+                then_label,
+                else_label,
+            }]
+        );
+        for (prefix, value) in [("if_then", 0), ("if_else", 1)] {
+            assert_eq!(
+                block(&function, prefix).instructions,
+                vec![
+                    IRInstruction::Return(Some(IRValue::Int(value))),
+                    IRInstruction::Branch { label: end_label.clone() },
+                ]
+            );
         }
+        assert_eq!(
+            block(&function, "if_end").instructions,
+            vec![IRInstruction::Return(Some(IRValue::Int(2)))]
+        );
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_for_loop_continue_advances_index() {
+        let function = Compiler::new()
+            .compile("fn f() -> int { for x in [1, 2] { continue; } return 0; }")
+            .unwrap();
+        let step = block(&function, "for_step").label.clone();
+        let body = block(&function, "for_body");
+        assert_eq!(body.instructions.last(), Some(&IRInstruction::Branch { label: step }));
+        assert_eq!(
+            block(&function, "for_step").instructions.last(),
+            Some(&IRInstruction::Branch { label: block(&function, "for_header").label.clone() })
+        );
+    }
+
+    #[test]
+    fn test_compile_program_lowers_every_function() {
+        let mut compiler = Compiler::new();
+        compiler
+            .parse("fn helper(n: int) -> int priority 3, 1 { return n; } fn main() -> int { return helper(1); }")
+            .unwrap();
+        let ir = compiler.compile_program().unwrap();
+        let functions: Vec<_> = ir.functions.iter().map(|f| (f.name.as_str(), f.priorities.clone())).collect();
+        assert_eq!(functions, vec![("helper", vec![3, 1]), ("main", vec![])]);
+    }
+
+    #[test]
+    fn test_logical_operators_lower_to_branches() {
+        let function = Compiler::new()
+            .compile("fn f() -> int { if false && missing() { return 1; } return 0; }")
+            .unwrap();
+        let rhs = block(&function, "and_rhs").label.clone();
+        let end = block(&function, "and_end").label.clone();
+        assert_eq!(
+            block(&function, "entry").instructions,
+            vec![
+                IRInstruction::Let { name: "$and0".to_string(), value: IRValue::Bool(false) },
+                IRInstruction::ConditionalBranch {
+                    condition: IRValue::Variable("$and0".to_string()),
+                    then_label: rhs,
+                    else_label: end,
+                },
+            ]
+        );
+
+        let function = Compiler::new().compile("fn f() -> int { return true || missing(); }").unwrap();
+        let IRInstruction::ConditionalBranch { then_label, .. } = &block(&function, "entry").instructions[1] else {
+            panic!("expected a conditional branch");
+        };
+        assert!(then_label.starts_with("or_end"));
+    }
+
+    #[test]
+    fn test_break_outside_loop_is_rejected() {
+        assert!(Compiler::new().compile("fn f() -> int { break; }").is_err());
+    }
+}
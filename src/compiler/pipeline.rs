@@ -0,0 +1,270 @@
+use crate::ast::{expand_macros, Program, AST};
+use crate::codegen::CodeGenerator;
+use crate::error::{Result, SlangError};
+use crate::ir::IR;
+use crate::lexer::Lexer;
+use crate::optimizer::Optimizer;
+use crate::parser::Parser;
+use crate::type_system::TypeChecker;
+
+use super::Compiler;
+
+// パスの間で受け渡す状態。構文解析の結果から始まり、各パスが読み書きする
+#[derive(Debug)]
+pub struct PipelineContext {
+    pub program: Program,
+    // `LowerPass` が作る IR
+    pub ir: Option<IR>,
+    // `CodegenPass` が出力した LLVM IR
+    pub output: Option<String>,
+    // 型検査の警告 (到達しない `match` の腕など)
+    pub warnings: Vec<String>,
+}
+
+impl PipelineContext {
+    pub fn new(program: Program) -> Self {
+        Self {
+            program,
+            ir: None,
+            output: None,
+            warnings: Vec::new(),
+        }
+    }
+
+    fn ir_mut(&mut self, pass: &str) -> Result<&mut IR> {
+        self.ir
+            .as_mut()
+            .ok_or_else(|| SlangError::Compilation(format!("Pass `{}` needs IR; add `{}` before it", pass, LowerPass::NAME)))
+    }
+}
+
+// パイプラインの 1 段。名前は `Pipeline::without` などで段を指定するのに使う
+pub trait Pass {
+    fn name(&self) -> &'static str;
+    fn run(&mut self, context: &mut PipelineContext) -> Result<()>;
+}
+
+// `名前!(..)` の呼び出しをマクロの本体に置き換える。型検査より前に置く
+pub struct MacroExpandPass;
+
+impl MacroExpandPass {
+    pub const NAME: &'static str = "expand-macros";
+}
+
+impl Pass for MacroExpandPass {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn run(&mut self, context: &mut PipelineContext) -> Result<()> {
+        expand_macros(&mut context.program)
+    }
+}
+
+pub struct TypeCheckPass;
+
+impl TypeCheckPass {
+    pub const NAME: &'static str = "type-check";
+}
+
+impl Pass for TypeCheckPass {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn run(&mut self, context: &mut PipelineContext) -> Result<()> {
+        let mut checker = TypeChecker::new();
+        checker.check_program(&context.program)?;
+        context.warnings.extend_from_slice(checker.warnings());
+        Ok(())
+    }
+}
+
+// プログラムを平らにした AST を IR に変換する
+pub struct LowerPass;
+
+impl LowerPass {
+    pub const NAME: &'static str = "lower";
+}
+
+impl Pass for LowerPass {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn run(&mut self, context: &mut PipelineContext) -> Result<()> {
+        context.ir = Some(Compiler::with_ast(AST::from(context.program.clone())).compile_program()?);
+        Ok(())
+    }
+}
+
+pub struct OptimizePass;
+
+impl OptimizePass {
+    pub const NAME: &'static str = "optimize";
+}
+
+impl Pass for OptimizePass {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn run(&mut self, context: &mut PipelineContext) -> Result<()> {
+        Optimizer::new().optimize(context.ir_mut(Self::NAME)?);
+        Ok(())
+    }
+}
+
+pub struct CodegenPass;
+
+impl CodegenPass {
+    pub const NAME: &'static str = "codegen";
+}
+
+impl Pass for CodegenPass {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn run(&mut self, context: &mut PipelineContext) -> Result<()> {
+        let output = CodeGenerator::new().generate(context.ir_mut(Self::NAME)?)?;
+        context.output = Some(output);
+        Ok(())
+    }
+}
+
+// 構文解析のあとに並べたパスを順に実行する。既定はマクロの展開・型検査・IR への変換・最適化・コード生成
+//
+//     let context = Pipeline::default().without_optimizer().run(source)?;
+pub struct Pipeline {
+    passes: Vec<Box<dyn Pass>>,
+}
+
+impl Pipeline {
+    // パスを 1 つも持たない (構文解析だけを行う) パイプライン
+    pub fn new() -> Self {
+        Self { passes: Vec::new() }
+    }
+
+    pub fn with_pass(mut self, pass: impl Pass + 'static) -> Self {
+        self.passes.push(Box::new(pass));
+        self
+    }
+
+    // `name` のパスの直前に挿入する。見つからなければ末尾に追加する
+    pub fn insert_before(mut self, name: &str, pass: impl Pass + 'static) -> Self {
+        let index = self.position(name).unwrap_or(self.passes.len());
+        self.passes.insert(index, Box::new(pass));
+        self
+    }
+
+    // `name` のパスの直後に挿入する。見つからなければ末尾に追加する
+    pub fn insert_after(mut self, name: &str, pass: impl Pass + 'static) -> Self {
+        let index = self.position(name).map_or(self.passes.len(), |index| index + 1);
+        self.passes.insert(index, Box::new(pass));
+        self
+    }
+
+    pub fn without(mut self, name: &str) -> Self {
+        self.passes.retain(|pass| pass.name() != name);
+        self
+    }
+
+    pub fn without_optimizer(self) -> Self {
+        self.without(OptimizePass::NAME)
+    }
+
+    pub fn pass_names(&self) -> Vec<&'static str> {
+        self.passes.iter().map(|pass| pass.name()).collect()
+    }
+
+    pub fn run(&mut self, source: &str) -> Result<PipelineContext> {
+        let program = Parser::new(Lexer::new(source)).parse_program()?;
+        let mut context = PipelineContext::new(program);
+        for pass in &mut self.passes {
+            pass.run(&mut context)?;
+        }
+        Ok(context)
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.passes.iter().position(|pass| pass.name() == name)
+    }
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Self::new()
+            .with_pass(MacroExpandPass)
+            .with_pass(TypeCheckPass)
+            .with_pass(LowerPass)
+            .with_pass(OptimizePass)
+            .with_pass(CodegenPass)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{IRBinaryOperator, IRInstruction, IRValue};
+
+    const SOURCE: &str = "fn main() -> int { return 1 + 2; }";
+
+    fn returned_value(context: &PipelineContext) -> &IRInstruction {
+        &context.ir.as_ref().unwrap().functions[0].blocks[0].instructions[0]
+    }
+
+    #[test]
+    fn test_pipeline_without_optimizer_keeps_unfolded_ir() {
+        let pipeline = Pipeline::default().without_optimizer();
+        assert_eq!(pipeline.pass_names(), vec!["expand-macros", "type-check", "lower", "codegen"]);
+
+        let context = Pipeline::default().without_optimizer().run(SOURCE).unwrap();
+        assert_eq!(
+            returned_value(&context),
+            &IRInstruction::Return(Some(IRValue::BinaryOp {
+                left: Box::new(IRValue::Int(1)),
+                op: IRBinaryOperator::Add,
+                right: Box::new(IRValue::Int(2)),
+            }))
+        );
+        assert!(context.output.unwrap().contains("add"));
+
+        let context = Pipeline::default().run(SOURCE).unwrap();
+        assert_eq!(returned_value(&context), &IRInstruction::Return(Some(IRValue::Int(3))));
+    }
+
+    #[test]
+    fn test_pipeline_custom_passes() {
+        struct CountFunctions;
+
+        impl Pass for CountFunctions {
+            fn name(&self) -> &'static str {
+                "count-functions"
+            }
+
+            fn run(&mut self, context: &mut PipelineContext) -> Result<()> {
+                let count = context.ir.as_ref().map_or(0, |ir| ir.functions.len());
+                context.warnings.push(format!("{} functions", count));
+                Ok(())
+            }
+        }
+
+        let mut pipeline = Pipeline::default()
+            .without(CodegenPass::NAME)
+            .insert_after(LowerPass::NAME, CountFunctions);
+        assert_eq!(pipeline.pass_names(), vec!["expand-macros", "type-check", "lower", "count-functions", "optimize"]);
+        let context = pipeline.run("fn f() -> int { return 1; } fn main() -> int { return f(); }").unwrap();
+        assert_eq!(context.warnings, vec!["2 functions".to_string()]);
+        assert_eq!(context.output, None);
+
+        // 型検査を外すと型の誤りがあっても IR まで進む
+        let source = "fn main() -> int { return 1.5; }";
+        assert!(Pipeline::default().run(source).is_err());
+        assert!(Pipeline::default().without(TypeCheckPass::NAME).without(CodegenPass::NAME).run(source).is_ok());
+
+        // IR を作る前に最適化するとエラーになる
+        let error = Pipeline::new().with_pass(OptimizePass).run(SOURCE).unwrap_err();
+        assert!(error.to_string().contains("lower"), "{}", error);
+    }
+}
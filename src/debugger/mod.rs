@@ -1,48 +1,78 @@
 use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex};
-use crate::runtime::Runtime;
+use crate::compiler::Compiler;
+use crate::ir::IRValue;
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::runtime::{ExecutionObserver, Location, Runtime, StepAction, Value};
 
+// ブレークポイントで止まったときに呼ぶ関数。止まった関数のフレームが残っているうちに呼ばれるので、
+// `Runtime::evaluate` でローカル変数を読める。返した `StepAction` で続けるか打ち切るかを決める
+pub type PauseHandler = Box<dyn FnMut(&Location, &mut Runtime) -> StepAction>;
+
+// `Runtime::set_observer` に渡すと、ブレークポイントの位置で一時停止する。
+// `on_pause` で関数を登録していなければ、そこで実行を打ち切る (`StepAction::Abort`)
 pub struct Debugger {
-    runtime: Arc<Runtime>,
     breakpoints: Arc<Mutex<HashSet<Breakpoint>>>,
     watched_variables: Arc<Mutex<HashMap<String, WatchConfig>>>,
     priority_watches: Arc<Mutex<HashSet<String>>>,
+    aborted_at: Arc<Mutex<Option<Location>>>,
+    // 条件式の文字列ごとに変換済みの値を覚えておく。変換できなかった条件は `None`
+    conditions: Arc<Mutex<HashMap<String, Option<IRValue>>>>,
+    on_pause: Mutex<Option<PauseHandler>>,
 }
 
-#[derive(Clone, Hash, Eq, PartialEq)]
+// IR には行番号がないため、ブレークポイントは IR の命令の位置で指定する
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
 pub struct Breakpoint {
-    file: String,
-    line: u32,
+    location: Location,
     condition: Option<String>,
 }
 
+// 値は表示用の文字列で比べる。実行時の値 (`Box<dyn Any>`) は複製も比較もできないため
+#[derive(Debug, Clone, PartialEq)]
 pub struct WatchConfig {
     last_value: String,
     break_on_change: bool,
 }
 
+impl WatchConfig {
+    pub fn last_value(&self) -> &str {
+        &self.last_value
+    }
+
+    pub fn break_on_change(&self) -> bool {
+        self.break_on_change
+    }
+}
+
 impl Debugger {
-    pub fn new(runtime: Arc<Runtime>) -> Self {
+    pub fn new() -> Self {
         Self {
-            runtime,
             breakpoints: Arc::new(Mutex::new(HashSet::new())),
             watched_variables: Arc::new(Mutex::new(HashMap::new())),
             priority_watches: Arc::new(Mutex::new(HashSet::new())),
+            aborted_at: Arc::new(Mutex::new(None)),
+            conditions: Arc::new(Mutex::new(HashMap::new())),
+            on_pause: Mutex::new(None),
         }
     }
 
-    pub fn add_breakpoint(&self, file: String, line: u32, condition: Option<String>) {
+    pub fn on_pause(&self, handler: impl FnMut(&Location, &mut Runtime) -> StepAction + 'static) {
+        *self.on_pause.lock().unwrap() = Some(Box::new(handler));
+    }
+
+    pub fn add_breakpoint(&self, location: Location, condition: Option<String>) {
         let mut breakpoints = self.breakpoints.lock().unwrap();
         breakpoints.insert(Breakpoint {
-            file,
-            line,
+            location,
             condition,
         });
     }
 
-    pub fn remove_breakpoint(&self, file: &str, line: u32) {
+    pub fn remove_breakpoint(&self, location: &Location) {
         let mut breakpoints = self.breakpoints.lock().unwrap();
-        breakpoints.retain(|bp| !(bp.file == file && bp.line == line));
+        breakpoints.retain(|bp| &bp.location != location);
     }
 
     pub fn watch_variable(&self, name: String, break_on_change: bool) {
@@ -68,19 +98,37 @@ impl Debugger {
         priority_watches.remove(name);
     }
 
-    pub fn check_breakpoint(&self, file: &str, line: u32) -> bool {
-        let breakpoints = self.breakpoints.lock().unwrap();
-        breakpoints.iter().any(|bp| {
-            bp.file == file && bp.line == line && {
-                if let Some(condition) = &bp.condition {
-                    self.evaluate_condition(condition)
-                } else {
-                    true
-                }
-            }
+    // 条件付きのブレークポイントは、その時点のスコープで条件が `true` になる場合だけ止まる
+    pub fn check_breakpoint(&self, location: &Location, runtime: &mut Runtime) -> bool {
+        let conditions: Vec<Option<String>> = {
+            let breakpoints = self.breakpoints.lock().unwrap();
+            breakpoints
+                .iter()
+                .filter(|bp| &bp.location == location)
+                .map(|bp| bp.condition.clone())
+                .collect()
+        };
+        conditions.iter().any(|condition| match condition {
+            Some(condition) => self.evaluate_condition(condition, runtime),
+            None => true,
         })
     }
 
+    // 最後に実行を打ち切ったブレークポイントの位置
+    pub fn aborted_at(&self) -> Option<Location> {
+        self.aborted_at.lock().unwrap().clone()
+    }
+
+    // 監視している変数のうち、現在のスコープにあるものの値を読み直す
+    pub fn refresh_watched_variables(&self, runtime: &mut Runtime) {
+        let names: Vec<String> = self.watched_variables.lock().unwrap().keys().cloned().collect();
+        for name in names {
+            if let Ok(value) = runtime.evaluate(&IRValue::Variable(name.clone())) {
+                self.check_variable_change(&name, &value.to_string());
+            }
+        }
+    }
+
     pub fn check_variable_change(&self, name: &str, new_value: &str) -> bool {
         let mut watched = self.watched_variables.lock().unwrap();
         if let Some(config) = watched.get_mut(name) {
@@ -94,20 +142,29 @@ impl Debugger {
         false
     }
 
-    pub fn check_priority_change(&self, name: &str, new_priority: u32) -> bool {
+    pub fn check_priority_change(&self, runtime: &Runtime, name: &str, new_priority: i32) -> bool {
         let priority_watches = self.priority_watches.lock().unwrap();
         if priority_watches.contains(name) {
-            if let Some(old_priority) = self.runtime.get_priority(name) {
+            if let Some(old_priority) = runtime.get_priority(name) {
                 return old_priority != new_priority;
             }
         }
         false
     }
 
-    fn evaluate_condition(&self, condition: &str) -> bool {
-        // Simple condition evaluation for now
-        // In a real implementation, this would parse and evaluate the condition
-        true
+    // 解析できない条件や、評価に失敗した条件 (未定義の変数など) では止まらない
+    fn evaluate_condition(&self, condition: &str, runtime: &mut Runtime) -> bool {
+        let compiled = self
+            .conditions
+            .lock()
+            .unwrap()
+            .entry(condition.to_string())
+            .or_insert_with(|| compile_condition(condition))
+            .clone();
+        let Some(value) = compiled else {
+            return false;
+        };
+        matches!(runtime.evaluate(&value), Ok(Value::Bool(true)))
     }
 
     pub fn get_variable_value(&self, name: &str) -> Option<String> {
@@ -115,8 +172,8 @@ impl Debugger {
         watched.get(name).map(|config| config.last_value.clone())
     }
 
-    pub fn get_priority_value(&self, name: &str) -> Option<u32> {
-        self.runtime.get_priority(name)
+    pub fn get_priority_value(&self, runtime: &Runtime, name: &str) -> Option<i32> {
+        runtime.get_priority(name)
     }
 
     pub fn list_breakpoints(&self) -> Vec<Breakpoint> {
@@ -124,13 +181,203 @@ impl Debugger {
         breakpoints.iter().cloned().collect()
     }
 
+    // 名前順に並べて返す
     pub fn list_watched_variables(&self) -> Vec<(String, WatchConfig)> {
         let watched = self.watched_variables.lock().unwrap();
-        watched.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+        let mut list: Vec<_> = watched.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+        list.sort_by(|(a, _), (b, _)| a.cmp(b));
+        list
     }
 
     pub fn list_priority_watches(&self) -> Vec<String> {
         let priority_watches = self.priority_watches.lock().unwrap();
         priority_watches.iter().cloned().collect()
     }
-} 
\ No newline at end of file
+}
+
+fn compile_condition(condition: &str) -> Option<IRValue> {
+    let expression = Parser::new(Lexer::new(condition)).parse_standalone_expression().ok()?;
+    Compiler::new().compile_value(&expression).ok()
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ExecutionObserver for Debugger {
+    fn on_instruction(&self, loc: Location, runtime: &mut Runtime) -> StepAction {
+        if !self.check_breakpoint(&loc, runtime) {
+            return StepAction::Continue;
+        }
+        self.refresh_watched_variables(runtime);
+        // ロックを持ったまま呼ばないよう取り出しておく。呼び出し中に `on_pause` で差し替えられたら新しい方を残す
+        let handler = self.on_pause.lock().unwrap().take();
+        let action = match handler {
+            Some(mut handler) => {
+                let action = handler(&loc, runtime);
+                self.on_pause.lock().unwrap().get_or_insert(handler);
+                action
+            }
+            None => StepAction::Abort,
+        };
+        if action == StepAction::Abort {
+            *self.aborted_at.lock().unwrap() = Some(loc);
+        }
+        action
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::IRInstruction;
+    use crate::type_system::Type;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    fn location(function: &str, block: &str, instruction: usize) -> Location {
+        Location {
+            function: function.to_string(),
+            block: block.to_string(),
+            instruction,
+        }
+    }
+
+    #[test]
+    fn test_list_watched_variables() {
+        let debugger = Debugger::new();
+        debugger.watch_variable("y".to_string(), false);
+        debugger.watch_variable("x".to_string(), true);
+        assert!(debugger.check_variable_change("x", "1"));
+        assert!(!debugger.check_variable_change("y", "2"));
+
+        let watched = debugger.list_watched_variables();
+        let summary: Vec<_> = watched
+            .iter()
+            .map(|(name, config)| (name.as_str(), config.last_value(), config.break_on_change()))
+            .collect();
+        assert_eq!(summary, vec![("x", "1", true), ("y", "2", false)]);
+    }
+
+    #[test]
+    fn test_priority_watch() {
+        let mut runtime = Runtime::new();
+        runtime.set_priority("x", 2);
+        let debugger = Debugger::new();
+        assert!(!debugger.check_priority_change(&runtime, "x", 3));
+        debugger.watch_priority("x".to_string());
+        assert!(debugger.check_priority_change(&runtime, "x", 3));
+        assert!(!debugger.check_priority_change(&runtime, "x", 2));
+        assert_eq!(debugger.get_priority_value(&runtime, "x"), Some(2));
+    }
+
+    #[test]
+    fn test_conditional_breakpoint() {
+        let mut compiler = Compiler::new();
+        compiler.parse("fn main() -> void { for i in [1, 2, 3, 4, 5, 6, 7] { seen(i); } }").unwrap();
+        let ir = compiler.compile_program().unwrap();
+        let body = ir.functions[0].blocks.iter().find(|block| block.label.starts_with("for_body")).unwrap();
+        let call = body.instructions.iter()
+            .position(|instruction| matches!(instruction, IRInstruction::Expression(_)))
+            .unwrap();
+
+        let seen = Rc::new(std::cell::RefCell::new(Vec::new()));
+        let mut runtime = Runtime::new();
+        let record = seen.clone();
+        runtime.register_function("seen", vec![Type::Int], Type::Unit, move |args: &[Value]| {
+            record.borrow_mut().push(i64::try_from(args[0].clone())?);
+            Ok(Value::Null)
+        });
+        let debugger = Rc::new(Debugger::new());
+        let breakpoint = location("main", &body.label, call);
+        debugger.add_breakpoint(breakpoint.clone(), Some("i == 5 && !(i < 0)".to_string()));
+        runtime.set_observer(debugger.clone());
+
+        runtime.execute(&ir).unwrap();
+        assert_eq!(*seen.borrow(), vec![1, 2, 3, 4]);
+        assert_eq!(runtime.aborted_at(), Some(&breakpoint));
+        assert_eq!(debugger.conditions.lock().unwrap().len(), 1);
+
+        // 解析できない条件や未定義の変数を参照する条件では止まらない
+        for condition in ["i ==", "missing == 1"] {
+            debugger.remove_breakpoint(&breakpoint);
+            debugger.add_breakpoint(breakpoint.clone(), Some(condition.to_string()));
+            seen.borrow_mut().clear();
+            runtime.execute(&ir).unwrap();
+            assert_eq!(seen.borrow().len(), 7);
+        }
+    }
+
+    #[test]
+    fn test_breakpoint_aborts_before_instruction() {
+        let mut compiler = Compiler::new();
+        compiler.parse("fn main() -> void { tick(); tick(); tick(); }").unwrap();
+        let ir = compiler.compile_program().unwrap();
+
+        let ticks = Rc::new(Cell::new(0));
+        let mut runtime = Runtime::new();
+        let counter = ticks.clone();
+        runtime.register_function("tick", vec![], Type::Unit, move |_: &[Value]| {
+            counter.set(counter.get() + 1);
+            Ok(Value::Null)
+        });
+        let debugger = Rc::new(Debugger::new());
+        debugger.add_breakpoint(location("main", "entry", 2), None);
+        runtime.set_observer(debugger.clone());
+
+        runtime.execute(&ir).unwrap();
+        assert_eq!(ticks.get(), 2);
+        assert_eq!(debugger.aborted_at(), Some(location("main", "entry", 2)));
+        assert_eq!(runtime.aborted_at(), Some(&location("main", "entry", 2)));
+
+        // 打ち切った位置からは再開せず、最初から実行し直す
+        debugger.remove_breakpoint(&location("main", "entry", 2));
+        runtime.execute(&ir).unwrap();
+        assert_eq!(ticks.get(), 5);
+        assert_eq!(runtime.aborted_at(), None);
+    }
+
+    #[test]
+    fn test_pause_reads_locals_before_continuing() {
+        let source = "fn helper(n: int) -> int { let doubled = n * 2; return doubled; } \
+                      fn main() -> void { let r = helper(21); tick(); }";
+        let mut compiler = Compiler::new();
+        compiler.parse(source).unwrap();
+        let ir = compiler.compile_program().unwrap();
+
+        let ticks = Rc::new(Cell::new(0));
+        let mut runtime = Runtime::new();
+        let counter = ticks.clone();
+        runtime.register_function("tick", vec![], Type::Unit, move |_: &[Value]| {
+            counter.set(counter.get() + 1);
+            Ok(Value::Null)
+        });
+        let debugger = Rc::new(Debugger::new());
+        debugger.add_breakpoint(location("helper", "entry", 1), None);
+        debugger.watch_variable("doubled".to_string(), false);
+        let paused = Rc::new(std::cell::RefCell::new(Vec::new()));
+        let record = paused.clone();
+        debugger.on_pause(move |loc, runtime| {
+            let value = runtime.evaluate(&IRValue::Variable("doubled".to_string()));
+            record.borrow_mut().push((loc.clone(), value.ok()));
+            StepAction::Continue
+        });
+        runtime.set_observer(debugger.clone());
+
+        runtime.execute(&ir).unwrap();
+        assert_eq!(*paused.borrow(), vec![(location("helper", "entry", 1), Some(Value::Int(42)))]);
+        assert_eq!(debugger.get_variable_value("doubled"), Some("42".to_string()));
+        // 一時停止のあとも続きを実行する
+        assert_eq!(ticks.get(), 1);
+        assert_eq!(debugger.aborted_at(), None);
+        assert_eq!(runtime.aborted_at(), None);
+
+        // `StepAction::Abort` を返せばそこで打ち切る
+        debugger.on_pause(|_, _| StepAction::Abort);
+        runtime.execute(&ir).unwrap();
+        assert_eq!(ticks.get(), 1);
+        assert_eq!(runtime.aborted_at(), Some(&location("helper", "entry", 1)));
+    }
+}
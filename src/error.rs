@@ -1,12 +1,107 @@
 use std::fmt;
+use std::ops::Range;
 
 #[derive(Debug)]
 pub enum SlangError {
     Syntax(String),
     Type(String),
     Compilation(String),
-    Runtime(String),
+    // `kind` で種類を判別できる。表示は `message` だけを使う
+    Runtime { kind: RuntimeErrorKind, message: String },
     IO(String),
+    // 元のエラーにソース上の位置 (バイト範囲) を付けたもの
+    Spanned { error: Box<SlangError>, span: Range<usize> },
+}
+
+// 実行時エラーの種類。呼び出し側で `SlangError::runtime_kind` を使って判別する
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuntimeErrorKind {
+    // 整数・複素数の 0 による除算と剰余
+    DivByZero,
+    UndefinedVariable,
+    // 未定義の関数・メソッドの呼び出し
+    UndefinedFunction,
+    // 演算や組み込み関数に合わない型の値を渡した
+    TypeMismatch,
+    IndexOutOfBounds,
+    // 関数に渡した引数の数が合わない
+    ArgumentCount,
+    Overflow,
+    // `as` で値を変換できない (`"abc" as int` など)
+    InvalidCast,
+    // `assert`/`assert_eq` の条件が成り立たない
+    AssertionFailed,
+    // `panic(msg)` で止めた
+    Panic,
+    Other,
+}
+
+impl SlangError {
+    pub fn runtime(kind: RuntimeErrorKind, message: impl Into<String>) -> Self {
+        SlangError::Runtime { kind, message: message.into() }
+    }
+
+    // 実行時エラーの種類。位置が付いている場合は内側のエラーを見る
+    pub fn runtime_kind(&self) -> Option<RuntimeErrorKind> {
+        match self {
+            SlangError::Runtime { kind, .. } => Some(*kind),
+            SlangError::Spanned { error, .. } => error.runtime_kind(),
+            _ => None,
+        }
+    }
+
+    // 既に位置が付いている場合は内側の (より正確な) 位置を残す
+    pub fn with_span(self, span: Range<usize>) -> Self {
+        match self {
+            SlangError::Spanned { .. } => self,
+            error => SlangError::Spanned { error: Box::new(error), span },
+        }
+    }
+
+    pub fn span(&self) -> Option<Range<usize>> {
+        match self {
+            SlangError::Spanned { span, .. } => Some(span.clone()),
+            _ => None,
+        }
+    }
+
+    // rustc と同じ形式でエラーの行と位置を表示する
+    //   Syntax error: ...
+    //    --> 2:15
+    //     |
+    //   2 |     return 1 +;
+    //     |               ^
+    // 範囲が複数行にまたがる場合は最初の行の末尾まで下線を引く
+    pub fn render(&self, source: &str) -> String {
+        let Some(span) = self.span() else {
+            return self.to_string();
+        };
+        let mut start = span.start.min(source.len());
+        // 末尾の改行の後 (EOF) を指す場合は最後の行の末尾に寄せる
+        if start == source.len() && source.ends_with('\n') {
+            start -= 1;
+        }
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..].find('\n').map_or(source.len(), |i| start + i);
+        let line_number = source[..line_start].matches('\n').count() + 1;
+        let column = source[line_start..start].chars().count();
+        let width = source[start..span.end.clamp(start, line_end)].chars().count().max(1);
+
+        let gutter = " ".repeat(line_number.to_string().len());
+        format!(
+            "{}\n{} --> {}:{}\n{} |\n{} | {}\n{} | {}{}",
+            self,
+            gutter,
+            line_number,
+            column + 1,
+            gutter,
+            line_number,
+            &source[line_start..line_end],
+            gutter,
+            " ".repeat(column),
+            "^".repeat(width)
+        )
+    }
 }
 
 impl fmt::Display for SlangError {
@@ -15,8 +110,9 @@ impl fmt::Display for SlangError {
             SlangError::Syntax(msg) => write!(f, "Syntax error: {}", msg),
             SlangError::Type(msg) => write!(f, "Type error: {}", msg),
             SlangError::Compilation(msg) => write!(f, "Compilation error: {}", msg),
-            SlangError::Runtime(msg) => write!(f, "Runtime error: {}", msg),
+            SlangError::Runtime { message, .. } => write!(f, "Runtime error: {}", message),
             SlangError::IO(msg) => write!(f, "IO error: {}", msg),
+            SlangError::Spanned { error, .. } => write!(f, "{}", error),
         }
     }
 }
@@ -35,4 +131,49 @@ impl From<std::io::Error> for SlangError {
     fn from(err: std::io::Error) -> Self {
         SlangError::IO(err.to_string())
     }
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_underlines_span() {
+        let source = "fn main() -> int {\n    return 1 +;\n}";
+        let start = source.find(';').unwrap();
+        let error = SlangError::Syntax("Unexpected token".to_string()).with_span(start..start + 1);
+        assert_eq!(
+            error.render(source),
+            "Syntax error: Unexpected token\n  --> 2:15\n  |\n2 |     return 1 +;\n  |               ^"
+        );
+        assert_eq!(error.to_string(), "Syntax error: Unexpected token");
+        assert_eq!(SlangError::Type("x".to_string()).render(source), "Type error: x");
+    }
+
+    #[test]
+    fn test_render_multi_line_span_stops_at_line_end() {
+        let source = "let x = foo(\n  1);";
+        let start = source.find("foo").unwrap();
+        let error = SlangError::Syntax("bad call".to_string()).with_span(start..source.len());
+        assert_eq!(
+            error.render(source),
+            "Syntax error: bad call\n  --> 1:9\n  |\n1 | let x = foo(\n  |         ^^^^"
+        );
+    }
+
+    #[test]
+    fn test_render_span_at_end_of_file() {
+        let error = |source: &str| SlangError::Syntax("Expected RBrace, got EOF".to_string())
+            .with_span(source.len()..source.len())
+            .render(source);
+        assert_eq!(
+            error("fn f() {"),
+            "Syntax error: Expected RBrace, got EOF\n  --> 1:9\n  |\n1 | fn f() {\n  |         ^"
+        );
+        assert_eq!(
+            error("fn f() {\n"),
+            "Syntax error: Expected RBrace, got EOF\n  --> 1:9\n  |\n1 | fn f() {\n  |         ^"
+        );
+        assert!(error("").ends_with("1 | \n  | ^"));
+    }
+}
@@ -1,6 +1,6 @@
 use crate::ast::*;
-use crate::error::Result;
-use std::fmt::Write;
+use crate::error::{Result, SlangError};
+use std::fmt::{self, Write};
 
 pub struct Formatter {
     indent_level: usize,
@@ -17,203 +17,581 @@ impl Formatter {
         }
     }
 
-    pub fn format(&mut self, program: &Program) -> Result<String> {
+    // 1 段の字下げ幅と、1 行の最大幅 (これを超える呼び出しや二項演算の列は折り返す)
+    pub fn with_config(indent_size: usize, line_length: usize) -> Self {
+        Self {
+            indent_level: 0,
+            indent_size,
+            line_length,
+        }
+    }
+
+    // 出力は再びパースすると同じ AST になる
+    pub fn format(&mut self, ast: &AST) -> Result<String> {
         let mut output = String::new();
-        self.format_program(program, &mut output)?;
+        self.format_ast(ast, &mut output)
+            .map_err(|e| SlangError::IO(e.to_string()))?;
         Ok(output)
     }
 
-    fn format_program(&mut self, program: &Program, output: &mut String) -> Result<()> {
-        for (i, item) in program.items.iter().enumerate() {
+    // `format` と同じだが、モジュールなどの項目の並びをそのまま保つ
+    pub fn format_program(&mut self, program: &Program) -> Result<String> {
+        let mut output = String::new();
+        self.format_items(&program.items, &mut output)
+            .map_err(|e| SlangError::IO(e.to_string()))?;
+        Ok(output)
+    }
+
+    fn format_items(&mut self, items: &[Item], output: &mut String) -> fmt::Result {
+        for (i, item) in items.iter().enumerate() {
             if i > 0 {
                 writeln!(output)?;
             }
-            self.format_item(item, output)?;
+            match item {
+                Item::Function(func) => self.format_function(func, output)?,
+                Item::Struct(struct_) => self.format_struct(struct_, output)?,
+                Item::Module(module) => {
+                    self.write_indent(output)?;
+                    writeln!(output, "module {} {{", module.name)?;
+                    self.indent_level += 1;
+                    self.format_items(&module.items, output)?;
+                    self.indent_level -= 1;
+                    self.write_indent(output)?;
+                    writeln!(output, "}}")?;
+                }
+                Item::Trait(trait_) => self.format_trait(trait_, output)?,
+                Item::Macro(macro_) => {
+                    let patterns: Vec<String> = macro_.patterns.iter().map(|pattern| pattern.pattern.to_string()).collect();
+                    self.write_indent(output)?;
+                    write!(output, "macro {}({}) ", macro_.name, patterns.join(", "))?;
+                    self.format_block(&macro_.body, output)?;
+                    writeln!(output)?;
+                }
+            }
         }
         Ok(())
     }
 
-    fn format_item(&mut self, item: &Item, output: &mut String) -> Result<()> {
-        match item {
-            Item::Function(func) => self.format_function(func, output)?,
-            Item::Struct(struct_) => self.format_struct(struct_, output)?,
-            Item::Trait(trait_) => self.format_trait(trait_, output)?,
-            Item::Module(module) => self.format_module(module, output)?,
-            Item::Macro(macro_) => self.format_macro(macro_, output)?,
+    fn format_ast(&mut self, ast: &AST, output: &mut String) -> fmt::Result {
+        for (i, type_def) in ast.type_definitions.iter().enumerate() {
+            if i > 0 {
+                writeln!(output)?;
+            }
+            self.format_type_definition(type_def, output)?;
+        }
+        for (i, func) in ast.functions.iter().enumerate() {
+            if i > 0 || !ast.type_definitions.is_empty() {
+                writeln!(output)?;
+            }
+            self.format_function(func, output)?;
         }
         Ok(())
     }
 
-    fn format_function(&mut self, func: &Function, output: &mut String) -> Result<()> {
+    fn format_type_definition(&mut self, type_def: &TypeDefinition, output: &mut String) -> fmt::Result {
+        self.write_indent(output)?;
+        writeln!(output, "type {} {{", type_def.name)?;
+        self.indent_level += 1;
+        for field in &type_def.fields {
+            self.write_indent(output)?;
+            writeln!(output, "{}: {},", field.name, field.type_annotation)?;
+        }
+        self.indent_level -= 1;
+        self.write_indent(output)?;
+        writeln!(output, "}}")
+    }
+
+    fn format_struct(&mut self, struct_: &Struct, output: &mut String) -> fmt::Result {
+        self.write_indent(output)?;
+        write!(output, "struct {}", struct_.name)?;
+        if !struct_.type_params.is_empty() {
+            write!(output, "<{}>", struct_.type_params.join(", "))?;
+        }
+        writeln!(output, " {{")?;
+        self.indent_level += 1;
+        for field in &struct_.fields {
+            self.write_indent(output)?;
+            writeln!(output, "{},", field)?;
+        }
+        self.indent_level -= 1;
+        self.write_indent(output)?;
+        writeln!(output, "}}")
+    }
+
+    fn format_trait(&mut self, trait_: &Trait, output: &mut String) -> fmt::Result {
+        self.write_indent(output)?;
+        writeln!(output, "trait {} {{", trait_.name)?;
+        self.indent_level += 1;
+        for method in &trait_.methods {
+            self.write_indent(output)?;
+            writeln!(output, "{};", method)?;
+        }
+        self.indent_level -= 1;
+        self.write_indent(output)?;
+        writeln!(output, "}}")
+    }
+
+    fn format_function(&mut self, func: &Function, output: &mut String) -> fmt::Result {
         // 関数の優先所有格
-        if let Some(priority) = &func.priority {
-            writeln!(output, "Function:type:priority:{:?}", priority)?;
+        if !func.priorities.is_empty() {
+            let priorities = func.priorities.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+            self.write_indent(output)?;
+            writeln!(output, "Function:type:priority: {}", priorities.join(", "))?;
         }
 
         // 関数の定義
-        write!(output, "fn {}(", func.name)?;
-        self.format_parameters(&func.params, output)?;
-        write!(output, ") -> {}", func.return_type)?;
-        writeln!(output)?;
+        self.write_indent(output)?;
+        write!(output, "fn {}", func.name)?;
+        if !func.type_params.is_empty() {
+            write!(output, "<{}>", func.type_params.join(", "))?;
+        }
+        write!(output, "(")?;
+        self.format_parameters(&func.parameters, output)?;
+        write!(output, ") -> {} ", func.return_type)?;
 
         // 関数の本体
         self.format_block(&func.body, output)?;
-        Ok(())
+        writeln!(output)
     }
 
-    fn format_parameters(&self, params: &[Parameter], output: &mut String) -> Result<()> {
+    fn format_parameters(&self, params: &[Parameter], output: &mut String) -> fmt::Result {
         for (i, param) in params.iter().enumerate() {
             if i > 0 {
                 write!(output, ", ")?;
             }
-            write!(output, "{}: {}", param.name, param.type_)?;
+            if param.mutable {
+                write!(output, "mut ")?;
+            }
+            write!(output, "{}: {}", param.name, param.type_annotation)?;
         }
         Ok(())
     }
 
-    fn format_block(&mut self, block: &Block, output: &mut String) -> Result<()> {
+    // `{` から `}` まで。閉じ括弧の後の改行は呼び出し側が書く
+    fn format_block(&mut self, block: &Block, output: &mut String) -> fmt::Result {
         writeln!(output, "{{")?;
         self.indent_level += 1;
 
         for statement in &block.statements {
+            self.write_indent(output)?;
             self.format_statement(statement, output)?;
         }
 
         self.indent_level -= 1;
-        writeln!(output, "}}")?;
-        Ok(())
+        self.write_indent(output)?;
+        write!(output, "}}")
     }
 
-    fn format_statement(&mut self, statement: &Statement, output: &mut String) -> Result<()> {
-        self.write_indent(output)?;
+    fn format_statement(&mut self, statement: &Statement, output: &mut String) -> fmt::Result {
         match statement {
             Statement::Let(let_stmt) => self.format_let_statement(let_stmt, output)?,
             Statement::Expression(expr) => {
-                self.format_expression(expr, output)?;
+                self.format_expression(expr, output, false)?;
                 writeln!(output, ";")?;
             }
-            Statement::Return(expr) => {
+            Statement::Return(return_stmt) => {
                 write!(output, "return")?;
-                if let Some(expr) = expr {
+                if let Some(expr) = &return_stmt.value {
                     write!(output, " ")?;
-                    self.format_expression(expr, output)?;
+                    self.format_expression(expr, output, false)?;
                 }
                 writeln!(output, ";")?;
             }
-            Statement::If(if_stmt) => self.format_if_statement(if_stmt, output)?,
+            Statement::If(if_stmt) => {
+                self.format_if_statement(if_stmt, output)?;
+                writeln!(output)?;
+            }
+            Statement::IfLet(if_let) => {
+                self.format_if_let_statement(if_let, output)?;
+                writeln!(output)?;
+            }
+            Statement::While(while_stmt) => {
+                write!(output, "while ")?;
+                self.format_expression(&while_stmt.condition, output, true)?;
+                write!(output, " ")?;
+                self.format_block(&while_stmt.body, output)?;
+                writeln!(output)?;
+            }
+            Statement::WhileLet(while_let) => {
+                write!(output, "while let {} = ", while_let.pattern)?;
+                self.format_expression(&while_let.expression, output, true)?;
+                write!(output, " ")?;
+                self.format_block(&while_let.body, output)?;
+                writeln!(output)?;
+            }
+            Statement::For(for_stmt) => {
+                write!(output, "for {} in ", for_stmt.variable)?;
+                self.format_expression(&for_stmt.iterator, output, true)?;
+                write!(output, " ")?;
+                self.format_block(&for_stmt.body, output)?;
+                writeln!(output)?;
+            }
             Statement::Match(match_stmt) => self.format_match_statement(match_stmt, output)?,
-            Statement::While(while_stmt) => self.format_while_statement(while_stmt, output)?,
-            Statement::For(for_stmt) => self.format_for_statement(for_stmt, output)?,
-            Statement::Priority(priority_stmt) => self.format_priority_statement(priority_stmt, output)?,
+            Statement::Break => writeln!(output, "break;")?,
+            Statement::Continue => writeln!(output, "continue;")?,
+            Statement::Transfer(stmt) => writeln!(output, "transfer {} -> {};", stmt.from, stmt.to)?,
         }
         Ok(())
     }
 
-    fn format_let_statement(&mut self, let_stmt: &LetStatement, output: &mut String) -> Result<()> {
+    fn format_let_statement(&mut self, let_stmt: &LetStatement, output: &mut String) -> fmt::Result {
         // 変数の優先所有格
         if let Some(priority) = &let_stmt.priority {
-            writeln!(output, "Var:type:priority:{:?}", priority)?;
+            writeln!(output, "Var:type:priority: {}", priority)?;
             self.write_indent(output)?;
         }
 
-        write!(output, "let {}", let_stmt.name)?;
-        if let Some(type_) = &let_stmt.type_ {
+        write!(output, "let {}{}", if let_stmt.mutable { "mut " } else { "" }, let_stmt.name)?;
+        if let Some(type_) = &let_stmt.type_annotation {
             write!(output, ": {}", type_)?;
         }
         write!(output, " = ")?;
-        self.format_expression(&let_stmt.value, output)?;
-        writeln!(output, ";")?;
+        self.format_expression(&let_stmt.value, output, false)?;
+        writeln!(output, ";")
+    }
+
+    // `else` の中が `if` 文だけなら `else if` として書く (パーサはそう読み込む)
+    fn format_if_statement(&mut self, if_stmt: &IfStatement, output: &mut String) -> fmt::Result {
+        write!(output, "if ")?;
+        self.format_expression(&if_stmt.condition, output, true)?;
+        write!(output, " ")?;
+        self.format_block(&if_stmt.then_block, output)?;
+        self.format_else(if_stmt.else_block.as_ref(), output)
+    }
+
+    fn format_if_let_statement(&mut self, if_let: &IfLetStatement, output: &mut String) -> fmt::Result {
+        write!(output, "if let {} = ", if_let.pattern)?;
+        self.format_expression(&if_let.expression, output, true)?;
+        write!(output, " ")?;
+        self.format_block(&if_let.then_block, output)?;
+        self.format_else(if_let.else_block.as_ref(), output)
+    }
+
+    fn format_else(&mut self, else_block: Option<&Block>, output: &mut String) -> fmt::Result {
+        let Some(else_block) = else_block else {
+            return Ok(());
+        };
+        write!(output, " else ")?;
+        match else_block.statements.as_slice() {
+            [Statement::If(else_if)] => self.format_if_statement(else_if, output),
+            [Statement::IfLet(else_if)] => self.format_if_let_statement(else_if, output),
+            _ => self.format_block(else_block, output),
+        }
+    }
+
+    fn format_match_statement(&mut self, match_stmt: &MatchStatement, output: &mut String) -> fmt::Result {
+        write!(output, "match ")?;
+        self.format_expression(&match_stmt.expression, output, true)?;
+        writeln!(output, " {{")?;
+        self.indent_level += 1;
+        for arm in &match_stmt.arms {
+            self.write_indent(output)?;
+            write!(output, "{} => ", arm.pattern)?;
+            self.format_block(&arm.body, output)?;
+            writeln!(output)?;
+        }
+        self.indent_level -= 1;
+        self.write_indent(output)?;
+        writeln!(output, "}}")
+    }
+
+    // 1 行に収まらなければ、呼び出しは引数を 1 つずつ別の行に、二項演算の列は演算子の後ろで改行する。
+    // 折り返した行は 1 段深く字下げする
+    fn format_expression(&mut self, expr: &Expression, output: &mut String, in_condition: bool) -> fmt::Result {
+        if self.line_length == usize::MAX {
+            return self.write_expression(expr, output, in_condition);
+        }
+        let flat = self.flat(expr, in_condition)?;
+        // ラムダの本体は折り返さずに複数行になるので、幅は最初の行で測る
+        let width = flat.lines().next().map_or(0, |line| line.chars().count());
+        if column(output) + width <= self.line_length {
+            return output.write_str(&flat);
+        }
+        match expr {
+            Expression::Call(call) => {
+                write!(output, "{}", call.function)?;
+                self.format_wrapped_list(&call.arguments, output)
+            }
+            Expression::MethodCall { receiver, method, arguments } => {
+                self.format_operand(receiver, POSTFIX, output, in_condition)?;
+                write!(output, ".{}", method)?;
+                self.format_wrapped_list(arguments, output)
+            }
+            Expression::BinaryOp(_) => self.format_wrapped_chain(expr, output, in_condition),
+            _ => self.write_expression(expr, output, in_condition),
+        }
+    }
+
+    // 折り返しをせずに 1 行で書いた場合の文字列
+    fn flat(&self, expr: &Expression, in_condition: bool) -> std::result::Result<String, fmt::Error> {
+        let mut formatter = Formatter::with_config(self.indent_size, usize::MAX);
+        formatter.indent_level = self.indent_level;
+        let mut output = String::new();
+        formatter.write_expression(expr, &mut output, in_condition)?;
+        Ok(output)
+    }
+
+    fn format_wrapped_list(&mut self, elements: &[Expression], output: &mut String) -> fmt::Result {
+        writeln!(output, "(")?;
+        self.indent_level += 1;
+        for element in elements {
+            self.write_indent(output)?;
+            self.format_expression(element, output, false)?;
+            writeln!(output, ",")?;
+        }
+        self.indent_level -= 1;
+        self.write_indent(output)?;
+        write!(output, ")")
+    }
+
+    // `a + b + c` のように同じ優先順位で左に連なる演算をまとめて折り返す
+    fn format_wrapped_chain(&mut self, expr: &Expression, output: &mut String, in_condition: bool) -> fmt::Result {
+        let chain_precedence = expr.precedence();
+        let mut rest = Vec::new();
+        let mut first = expr;
+        while let Expression::BinaryOp(bin) = first {
+            if first.precedence() != chain_precedence {
+                break;
+            }
+            rest.push((&bin.op, bin.right.as_ref()));
+            first = &bin.left;
+        }
+        self.format_operand(first, chain_precedence, output, in_condition)?;
+        self.indent_level += 1;
+        for (op, right) in rest.into_iter().rev() {
+            writeln!(output, " {}", op)?;
+            self.write_indent(output)?;
+            self.format_operand(right, chain_precedence + 1, output, in_condition)?;
+        }
+        self.indent_level -= 1;
         Ok(())
     }
 
-    fn format_expression(&self, expr: &Expression, output: &mut String) -> Result<()> {
+    // `in_condition` は if/while/for の条件の中かどうか。そこでは構造体リテラルを括弧で囲む
+    fn write_expression(&mut self, expr: &Expression, output: &mut String, in_condition: bool) -> fmt::Result {
         match expr {
-            Expression::Literal(lit) => self.format_literal(lit, output)?,
+            Expression::Literal(lit) => write!(output, "{}", lit)?,
             Expression::Identifier(name) => write!(output, "{}", name)?,
-            Expression::Binary(bin) => {
-                self.format_expression(&bin.left, output)?;
-                write!(output, " {} ", self.format_binary_operator(&bin.operator))?;
-                self.format_expression(&bin.right, output)?;
+            Expression::BinaryOp(bin) => {
+                let precedence = expr.precedence();
+                self.format_operand(&bin.left, precedence, output, in_condition)?;
+                write!(output, " {} ", bin.op)?;
+                self.format_operand(&bin.right, precedence + 1, output, in_condition)?;
             }
-            Expression::Unary(unary) => {
-                write!(output, "{}", self.format_unary_operator(&unary.operator))?;
-                self.format_expression(&unary.expression, output)?;
+            Expression::UnaryOp(unary) => {
+                write!(output, "{}", unary.op)?;
+                self.format_operand(&unary.expr, UNARY, output, in_condition)?;
             }
             Expression::Call(call) => {
-                self.format_expression(&call.callee, output)?;
+                write!(output, "{}(", call.function)?;
+                self.format_expression_list(&call.arguments, output)?;
+                write!(output, ")")?;
+            }
+            Expression::Assignment(assignment) => {
+                write!(output, "{} = ", assignment.target)?;
+                self.format_operand(&assignment.value, ASSIGNMENT, output, in_condition)?;
+            }
+            Expression::Array(elements) => {
+                write!(output, "[")?;
+                self.format_expression_list(elements, output)?;
+                write!(output, "]")?;
+            }
+            Expression::Tuple(elements) => {
                 write!(output, "(")?;
-                for (i, arg) in call.arguments.iter().enumerate() {
-                    if i > 0 {
-                        write!(output, ", ")?;
-                    }
-                    self.format_expression(arg, output)?;
+                self.format_expression_list(elements, output)?;
+                if elements.len() == 1 {
+                    write!(output, ",")?;
                 }
                 write!(output, ")")?;
             }
-            // 他の式のフォーマットも同様に実装...
-            _ => unimplemented!(),
-        }
-        Ok(())
-    }
-
-    fn format_literal(&self, lit: &Literal, output: &mut String) -> Result<()> {
-        match lit {
-            Literal::Integer(value) => write!(output, "{}", value)?,
-            Literal::Float(value) => write!(output, "{}", value)?,
-            Literal::Boolean(value) => write!(output, "{}", value)?,
-            Literal::Character(value) => write!(output, "'{}'", value)?,
-            Literal::String(value) => write!(output, "\"{}\"", value)?,
-            Literal::Array(elements) => {
+            Expression::Range { start, end, inclusive } => {
+                self.format_operand(start, RANGE + 1, output, in_condition)?;
+                write!(output, "{}", if *inclusive { "..=" } else { ".." })?;
+                self.format_operand(end, RANGE + 1, output, in_condition)?;
+            }
+            Expression::Index { array, index } => {
+                self.format_operand(array, POSTFIX, output, in_condition)?;
                 write!(output, "[")?;
-                for (i, element) in elements.iter().enumerate() {
-                    if i > 0 {
-                        write!(output, ", ")?;
-                    }
-                    self.format_expression(element, output)?;
-                }
+                self.format_expression(index, output, in_condition)?;
                 write!(output, "]")?;
             }
-            Literal::Tuple(elements) => {
-                write!(output, "(")?;
-                for (i, element) in elements.iter().enumerate() {
+            Expression::FieldAccess { base, field } => {
+                self.format_operand(base, POSTFIX, output, in_condition)?;
+                write!(output, ".{}", field)?;
+            }
+            Expression::Cast { expr, target } => {
+                self.format_operand(expr, CAST, output, in_condition)?;
+                write!(output, " as {}", target)?;
+            }
+            Expression::Lambda { params, return_type, body } => {
+                write!(output, "fn(")?;
+                self.format_parameters(params, output)?;
+                write!(output, ")")?;
+                if let Some(return_type) = return_type {
+                    write!(output, " -> {}", return_type)?;
+                }
+                write!(output, " ")?;
+                self.format_block(body, output)?;
+            }
+            Expression::StructLiteral { name, fields } => {
+                if in_condition {
+                    write!(output, "(")?;
+                }
+                write!(output, "{} {{ ", name)?;
+                for (i, (field, value)) in fields.iter().enumerate() {
                     if i > 0 {
                         write!(output, ", ")?;
                     }
-                    self.format_expression(element, output)?;
+                    write!(output, "{}: ", field)?;
+                    self.format_expression(value, output, false)?;
+                }
+                write!(output, " }}")?;
+                if in_condition {
+                    write!(output, ")")?;
                 }
+            }
+            Expression::MethodCall { receiver, method, arguments } => {
+                self.format_operand(receiver, POSTFIX, output, in_condition)?;
+                write!(output, ".{}(", method)?;
+                self.format_expression_list(arguments, output)?;
                 write!(output, ")")?;
             }
         }
         Ok(())
     }
 
-    fn format_binary_operator(&self, op: &BinaryOperator) -> &'static str {
-        match op {
-            BinaryOperator::Add => "+",
-            BinaryOperator::Subtract => "-",
-            BinaryOperator::Multiply => "*",
-            BinaryOperator::Divide => "/",
-            BinaryOperator::Modulo => "%",
-            BinaryOperator::Equal => "==",
-            BinaryOperator::NotEqual => "!=",
-            BinaryOperator::LessThan => "<",
-            BinaryOperator::GreaterThan => ">",
-            BinaryOperator::LessEqual => "<=",
-            BinaryOperator::GreaterEqual => ">=",
-            BinaryOperator::And => "&&",
-            BinaryOperator::Or => "||",
+    // 結合が `min_precedence` より弱い式は括弧で囲む。括弧の内側は条件の外と同じ扱い
+    fn format_operand(&mut self, expr: &Expression, min_precedence: u8, output: &mut String, in_condition: bool) -> fmt::Result {
+        if expr.precedence() < min_precedence {
+            write!(output, "(")?;
+            self.format_expression(expr, output, false)?;
+            write!(output, ")")
+        } else {
+            self.format_expression(expr, output, in_condition)
         }
     }
 
-    fn format_unary_operator(&self, op: &UnaryOperator) -> &'static str {
-        match op {
-            UnaryOperator::Negate => "-",
-            UnaryOperator::Not => "!",
+    fn format_expression_list(&mut self, elements: &[Expression], output: &mut String) -> fmt::Result {
+        for (i, element) in elements.iter().enumerate() {
+            if i > 0 {
+                write!(output, ", ")?;
+            }
+            self.format_expression(element, output, false)?;
         }
+        Ok(())
     }
 
-    fn write_indent(&self, output: &mut String) -> Result<()> {
-        write!(output, "{}", " ".repeat(self.indent_level * self.indent_size))?;
-        Ok(())
+    fn write_indent(&self, output: &mut String) -> fmt::Result {
+        write!(output, "{}", " ".repeat(self.indent_level * self.indent_size))
+    }
+}
+
+impl Default for Formatter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// 出力の最後の行に既に書かれている文字数
+fn column(output: &str) -> usize {
+    let start = output.rfind('\n').map_or(0, |i| i + 1);
+    output[start..].chars().count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn parse(source: &str) -> AST {
+        Parser::new(Lexer::new(source)).parse().unwrap()
+    }
+
+    fn format(source: &str) -> String {
+        Formatter::new().format(&parse(source)).unwrap()
+    }
+
+    #[test]
+    fn test_format_function() {
+        let formatted = format("fn add(a:int,b:int)->int{let c=a+b;if c>0{return c;}else{return 0;}}");
+        assert_eq!(
+            formatted,
+            "fn add(a: int, b: int) -> int {\n    let c = a + b;\n    if c > 0 {\n        return c;\n    } else {\n        return 0;\n    }\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_long_call_wraps_at_configured_width() {
+        let ast = parse("fn main() -> void { print_all(first_argument, second_argument, 3 * third); }");
+        let formatted = Formatter::with_config(2, 40).format(&ast).unwrap();
+        assert_eq!(
+            formatted,
+            "fn main() -> void {\n  print_all(\n    first_argument,\n    second_argument,\n    3 * third,\n  );\n}\n"
+        );
+        assert_eq!(parse(&formatted), ast);
+        assert!(Formatter::with_config(2, 80).format(&ast).unwrap().contains("print_all(first_argument, "));
+    }
+
+    #[test]
+    fn test_long_binary_chain_wraps_after_operators() {
+        let ast = parse("fn f() -> int { return alpha_value + beta_value * 2 - (gamma_value + delta_value); }");
+        let formatted = Formatter::with_config(4, 40).format(&ast).unwrap();
+        assert_eq!(
+            formatted,
+            "fn f() -> int {\n    return alpha_value +\n        beta_value * 2 -\n        (gamma_value + delta_value);\n}\n"
+        );
+        assert_eq!(parse(&formatted), ast);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let source = r#"
+            Function:type:priority: 2, 1
+            fn f<T>(xs: [int], t: T) -> float {
+                Var:type:priority: most_high let total: float = 0.0;
+                for x in xs { if x % 2 == 0 && !(x < 0) { continue; } else if x > 10 { break; } }
+                while (Point { x: 1, y: 2 }).x < 3 { print("{{braces}} {total}"); }
+                let mut v = (1 + 2) * -(3 - 4) - (5 - 6);
+                match v { -1 => { v = 0; } Point { x, y: 2 } => {}, _ => {} }
+                if let (a, null) = pair { v = a; } else if let (0, b) = pair { v = 1; } else { v = 2; }
+                while let (x, _) = next(v) { v = x; }
+                let p = (a, (b,), [1.5, 2.0]);
+                let s = ("a\tb\n\"q\" \\ \u{7f}", '\'', '\n');
+                let add = fn(x: int) -> int { return x + v; };
+                apply(fn() { let p = P { x: 1 }; }, add);
+                return xs[0].len() * 1.0;
+            }
+
+            fn g() -> void { return; }
+        "#;
+        let ast = parse(source);
+        let formatted = Formatter::new().format(&ast).unwrap();
+        assert_eq!(parse(&formatted), ast);
+        assert_eq!(format(&formatted), formatted);
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_format_program_indents_modules() {
+        let source = "fn main() -> int { return 1; } module util { Function:type:priority: 2 fn helper() -> void { let x = 1; } }";
+        let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+        let formatted = Formatter::new().format_program(&program).unwrap();
+        assert_eq!(
+            formatted,
+            "fn main() -> int {\n    return 1;\n}\n\nmodule util {\n    Function:type:priority: 2\n    fn helper() -> void {\n        let x = 1;\n    }\n}\n"
+        );
+        assert_eq!(Parser::new(Lexer::new(&formatted)).parse_program().unwrap(), program);
+    }
+
+    #[test]
+    fn test_format_structs_traits_and_macros() {
+        let source = "struct Pair<T>{first:T@[2,1],second:T @most_low,count:int} trait Sum{fn sum(p:Pair)->int;} macro first(p){p.first;}";
+        let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+        let formatted = Formatter::new().format_program(&program).unwrap();
+        assert_eq!(
+            formatted,
+            "struct Pair<T> {\n    first: T @[2, 1],\n    second: T @most_low,\n    count: int,\n}\n\ntrait Sum {\n    fn sum(p: Pair) -> int;\n}\n\nmacro first(p) {\n    p.first;\n}\n"
+        );
+        assert_eq!(Parser::new(Lexer::new(&formatted)).parse_program().unwrap(), program);
+    }
+}
@@ -1,8 +1,7 @@
-use crate::lexer::{Lexer, Token};
+use crate::lexer::{tokenize_with_trivia, Token};
 use crate::error::Result;
-use std::fmt::Write;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum TokenKind {
     Keyword,
     Identifier,
@@ -13,6 +12,32 @@ pub enum TokenKind {
     Priority,
 }
 
+impl TokenKind {
+    fn ansi_color(self) -> &'static str {
+        match self {
+            TokenKind::Keyword => "\x1b[1;34m",   // 青
+            TokenKind::Identifier => "\x1b[1;36m", // シアン
+            TokenKind::Literal => "\x1b[1;33m",   // 黄
+            TokenKind::Operator => "\x1b[1;35m",  // マゼンタ
+            TokenKind::Delimiter => "\x1b[1;37m", // 白
+            TokenKind::Comment => "\x1b[1;32m",   // 緑
+            TokenKind::Priority => "\x1b[1;31m",  // 赤
+        }
+    }
+
+    fn css_class(self) -> &'static str {
+        match self {
+            TokenKind::Keyword => "tok-keyword",
+            TokenKind::Identifier => "tok-identifier",
+            TokenKind::Literal => "tok-literal",
+            TokenKind::Operator => "tok-operator",
+            TokenKind::Delimiter => "tok-delimiter",
+            TokenKind::Comment => "tok-comment",
+            TokenKind::Priority => "tok-priority",
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct HighlightedToken {
     pub kind: TokenKind,
@@ -31,110 +56,128 @@ impl Highlighter {
         }
     }
 
+    // ANSI エスケープで色付けする
     pub fn highlight(&mut self, source: &str) -> Result<String> {
-        let lexer = Lexer::new(source)?;
-        self.tokens.clear();
+        self.tokenize(source);
+        Ok(self.generate_highlighted_output(
+            source,
+            |kind, text| format!("{}{}\x1b[0m", kind.ansi_color(), text),
+            str::to_string,
+        ))
+    }
 
-        for token in lexer.tokens {
-            let (token, span) = token;
-            let kind = self.get_token_kind(&token);
-            let text = self.get_token_text(&token);
+    // 各トークンを `<span class="tok-keyword">` などで囲んだ HTML を返す。
+    // クラス名は `tok-` に続けて TokenKind の名前を小文字にしたもの
+    pub fn highlight_html(&mut self, source: &str) -> Result<String> {
+        self.tokenize(source);
+        Ok(self.generate_highlighted_output(
+            source,
+            |kind, text| format!("<span class=\"{}\">{}</span>", kind.css_class(), escape_html(text)),
+            escape_html,
+        ))
+    }
+
+    // パーサ用の Lexer と違ってコメントも残す。字句エラーの部分は色を付けずにそのまま出す
+    fn tokenize(&mut self, source: &str) {
+        self.tokens.clear();
+        for (token, span) in tokenize_with_trivia(source) {
+            let Some(kind) = self.get_token_kind(&token) else { continue };
             self.tokens.push(HighlightedToken {
                 kind,
-                text,
-                span,
+                text: source[span.clone()].to_string(),
+                span: (span.start, span.end),
             });
         }
-
-        self.generate_highlighted_output()
-    }
-
-    fn get_token_kind(&self, token: &Token) -> TokenKind {
-        match token {
-            Token::Fn | Token::Let | Token::If | Token::Else | Token::Match |
-            Token::While | Token::For | Token::Return => TokenKind::Keyword,
-            Token::Identifier(_) => TokenKind::Identifier,
-            Token::IntegerLiteral(_) | Token::FloatLiteral(_) | Token::StringLiteral(_) |
-            Token::CharLiteral(_) => TokenKind::Literal,
-            Token::Plus | Token::Minus | Token::Star | Token::Slash | Token::Equals |
-            Token::EqualsEquals | Token::NotEquals | Token::LessThan | Token::GreaterThan |
-            Token::LessEquals | Token::GreaterEquals => TokenKind::Operator,
-            Token::LeftParen | Token::RightParen | Token::LeftBrace | Token::RightBrace |
-            Token::LeftBracket | Token::RightBracket | Token::Semicolon | Token::Comma |
-            Token::Dot | Token::Arrow => TokenKind::Delimiter,
-            Token::VarTypePriority | Token::FunctionTypePriority | Token::MacroType => TokenKind::Priority,
-            _ => TokenKind::Identifier,
-        }
     }
 
-    fn get_token_text(&self, token: &Token) -> String {
-        match token {
-            Token::Identifier(name) => name.clone(),
-            Token::IntegerLiteral(value) => value.to_string(),
-            Token::FloatLiteral(value) => value.to_string(),
-            Token::StringLiteral(value) => format!("\"{}\"", value),
-            Token::CharLiteral(value) => format!("'{}'", value),
-            Token::Fn => "fn".to_string(),
-            Token::Let => "let".to_string(),
-            Token::If => "if".to_string(),
-            Token::Else => "else".to_string(),
-            Token::Match => "match".to_string(),
-            Token::While => "while".to_string(),
-            Token::For => "for".to_string(),
-            Token::Return => "return".to_string(),
-            Token::Plus => "+".to_string(),
-            Token::Minus => "-".to_string(),
-            Token::Star => "*".to_string(),
-            Token::Slash => "/".to_string(),
-            Token::Equals => "=".to_string(),
-            Token::EqualsEquals => "==".to_string(),
-            Token::NotEquals => "!=".to_string(),
-            Token::LessThan => "<".to_string(),
-            Token::GreaterThan => ">".to_string(),
-            Token::LessEquals => "<=".to_string(),
-            Token::GreaterEquals => ">=".to_string(),
-            Token::LeftParen => "(".to_string(),
-            Token::RightParen => ")".to_string(),
-            Token::LeftBrace => "{".to_string(),
-            Token::RightBrace => "}".to_string(),
-            Token::LeftBracket => "[".to_string(),
-            Token::RightBracket => "]".to_string(),
-            Token::Semicolon => ";".to_string(),
-            Token::Comma => ",".to_string(),
-            Token::Dot => ".".to_string(),
-            Token::Arrow => "->".to_string(),
-            Token::VarTypePriority => "Var:type:priority:".to_string(),
-            Token::FunctionTypePriority => "Function:type:priority:".to_string(),
-            Token::MacroType => "Macro:type:".to_string(),
-            Token::Error => "".to_string(),
-        }
+    fn get_token_kind(&self, token: &Token) -> Option<TokenKind> {
+        let kind = match token {
+            Token::Function | Token::Let | Token::Mut | Token::If | Token::Else | Token::Match | Token::While |
+            Token::For | Token::In | Token::Return | Token::Break | Token::Continue | Token::As | Token::Transfer | Token::Type | Token::Module |
+            Token::Struct | Token::Trait | Token::Macro => TokenKind::Keyword,
+            Token::Identifier(_) | Token::Underscore => TokenKind::Identifier,
+            Token::IntegerLiteral(_) | Token::FloatLiteral(_) | Token::ImaginaryLiteral(_) | Token::StringLiteral(_) |
+            Token::InterpolatedString(_) | Token::CharLiteral(_) | Token::True | Token::False |
+            Token::Null => TokenKind::Literal,
+            Token::Plus | Token::Minus | Token::Star | Token::Slash | Token::Percent | Token::Assign |
+            Token::Equals | Token::NotEquals | Token::LessThan | Token::GreaterThan |
+            Token::LessThanEquals | Token::GreaterThanEquals | Token::And | Token::Or | Token::Not |
+            Token::DotDot | Token::DotDotEq | Token::Question => TokenKind::Operator,
+            Token::LParen | Token::RParen | Token::LBrace | Token::RBrace | Token::LBracket |
+            Token::RBracket | Token::Colon | Token::Semicolon | Token::Comma | Token::Dot |
+            Token::Arrow | Token::FatArrow => TokenKind::Delimiter,
+            Token::Comment | Token::BlockComment => TokenKind::Comment,
+            Token::VarTypePriority | Token::FunctionTypePriority | Token::MacroType | Token::Priority |
+            Token::MostHigh | Token::MostLow | Token::At => TokenKind::Priority,
+            Token::Whitespace => return None,
+        };
+        Some(kind)
     }
 
-    fn generate_highlighted_output(&self) -> Result<String> {
+    // トークンの間 (空白や字句エラーの部分) は元のソースをそのまま写す
+    fn generate_highlighted_output(
+        &self,
+        source: &str,
+        style: impl Fn(TokenKind, &str) -> String,
+        plain: impl Fn(&str) -> String,
+    ) -> String {
         let mut output = String::new();
         let mut current_pos = 0;
 
         for token in &self.tokens {
-            // 空白を追加
-            if token.span.0 > current_pos {
-                output.push_str(&" ".repeat(token.span.0 - current_pos));
-            }
-
-            // トークンの種類に応じて色付け
-            let colored_text = match token.kind {
-                TokenKind::Keyword => format!("\x1b[1;34m{}\x1b[0m", token.text), // 青
-                TokenKind::Identifier => format!("\x1b[1;36m{}\x1b[0m", token.text), // シアン
-                TokenKind::Literal => format!("\x1b[1;33m{}\x1b[0m", token.text), // 黄
-                TokenKind::Operator => format!("\x1b[1;35m{}\x1b[0m", token.text), // マゼンタ
-                TokenKind::Delimiter => format!("\x1b[1;37m{}\x1b[0m", token.text), // 白
-                TokenKind::Comment => format!("\x1b[1;32m{}\x1b[0m", token.text), // 緑
-                TokenKind::Priority => format!("\x1b[1;31m{}\x1b[0m", token.text), // 赤
-            };
-
-            output.push_str(&colored_text);
+            output.push_str(&plain(&source[current_pos..token.span.0]));
+            output.push_str(&style(token.kind, &token.text));
             current_pos = token.span.1;
         }
+        output.push_str(&plain(&source[current_pos..]));
 
-        Ok(output)
+        output
     }
-} 
\ No newline at end of file
+}
+
+impl Default for Highlighter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_highlight_html() {
+        let html = Highlighter::new().highlight_html("fn f() -> bool { return a < b && c; } // x<y").unwrap();
+        assert!(html.starts_with("<span class=\"tok-keyword\">fn</span> <span class=\"tok-identifier\">f</span>"));
+        assert!(html.contains("<span class=\"tok-operator\">&lt;</span>"));
+        assert!(html.contains("<span class=\"tok-operator\">&amp;&amp;</span>"));
+        assert!(html.contains("<span class=\"tok-delimiter\">-&gt;</span>"));
+        assert!(html.ends_with("<span class=\"tok-comment\">// x&lt;y</span>"));
+    }
+
+    #[test]
+    fn test_highlight_main() {
+        let ansi = Highlighter::new().highlight("fn main").unwrap();
+        assert_eq!(ansi, "\x1b[1;34mfn\x1b[0m \x1b[1;36mmain\x1b[0m");
+    }
+
+    #[test]
+    fn test_comments_are_green() {
+        let ansi = Highlighter::new().highlight("// comment\nx /* block\ncomment */").unwrap();
+        assert!(ansi.starts_with("\x1b[1;32m// comment\x1b[0m\n"));
+        assert!(ansi.ends_with("\x1b[1;32m/* block\ncomment */\x1b[0m"));
+    }
+
+    #[test]
+    fn test_highlight_keeps_whitespace() {
+        let ansi = Highlighter::new().highlight("let x = 1;\n\tx").unwrap();
+        assert_eq!(
+            ansi,
+            "\x1b[1;34mlet\x1b[0m \x1b[1;36mx\x1b[0m \x1b[1;35m=\x1b[0m \x1b[1;33m1\x1b[0m\x1b[1;37m;\x1b[0m\n\t\x1b[1;36mx\x1b[0m"
+        );
+    }
+}
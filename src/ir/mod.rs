@@ -1,10 +1,13 @@
 use crate::type_system::Type;
 use std::fmt;
 
+mod verify;
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct IR {
     pub functions: Vec<IRFunction>,
     pub globals: Vec<IRGlobal>,
+    pub type_definitions: Vec<IRTypeDefinition>,
 }
 
 impl IR {
@@ -12,6 +15,7 @@ impl IR {
         Self {
             functions: Vec::new(),
             globals: Vec::new(),
+            type_definitions: Vec::new(),
         }
     }
 
@@ -22,6 +26,16 @@ impl IR {
     pub fn add_global(&mut self, global: IRGlobal) {
         self.globals.push(global);
     }
+
+    pub fn add_type_definition(&mut self, type_def: IRTypeDefinition) {
+        self.type_definitions.push(type_def);
+    }
+}
+
+impl Default for IR {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -29,10 +43,17 @@ pub struct IRFunction {
     pub name: String,
     pub parameters: Vec<IRParameter>,
     pub return_type: Type,
-    pub priority: i32,
+    pub priorities: Vec<i32>,
     pub blocks: Vec<IRBlock>,
 }
 
+impl IRFunction {
+    // `Compiler` がラムダの本体を持ち上げた関数 (`外側の関数名$lambdaN`)
+    pub fn is_lambda(&self) -> bool {
+        self.name.contains("$lambda")
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct IRParameter {
     pub name: String,
@@ -46,6 +67,12 @@ pub struct IRGlobal {
     pub value: IRValue,
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub struct IRTypeDefinition {
+    pub name: String,
+    pub fields: Vec<IRParameter>,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct IRBlock {
     pub label: String,
@@ -66,6 +93,17 @@ pub enum IRInstruction {
     Assignment { target: String, value: IRValue },
     Expression(IRValue),
     Let { name: String, value: IRValue },
+    // `from` の優先所有格を `to` に移す
+    Transfer { from: String, to: String },
+    // `Var:type:priority:` で宣言した優先所有格を `name` に記録する。複数段なら順に並ぶ
+    Priority { name: String, priority: i32 },
+}
+
+impl IRInstruction {
+    // ブロックを終える命令。後ろに続く命令は実行されない
+    pub fn is_terminator(&self) -> bool {
+        matches!(self, IRInstruction::Return(_) | IRInstruction::Branch { .. } | IRInstruction::ConditionalBranch { .. })
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -106,7 +144,10 @@ pub enum IRUnaryOperator {
 pub enum IRValue {
     Int(i64),
     Float(f64),
+    // 実部と虚部
+    Complex(f64, f64),
     Bool(bool),
+    Char(char),
     String(String),
     Null,
     Identifier(String),
@@ -116,6 +157,12 @@ pub enum IRValue {
     UnaryOp { op: IRUnaryOperator, expr: Box<IRValue> },
     Call { function: String, arguments: Vec<IRValue> },
     Assignment { name: String, value: Box<IRValue> },
+    Array(Vec<IRValue>),
+    Index { array: Box<IRValue>, index: Box<IRValue> },
+    MethodCall { receiver: Box<IRValue>, method: String, arguments: Vec<IRValue> },
+    Cast { value: Box<IRValue>, target: Type },
+    // ラムダを持ち上げた関数と、作った時点で値を捕まえる外側の変数
+    Closure { function: String, captures: Vec<String> },
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -124,11 +171,15 @@ pub enum IRConstant {
     Float(f64),
     String(String),
     Boolean(bool),
+    Char(char),
     Unit,
 }
 
 impl fmt::Display for IR {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for type_def in &self.type_definitions {
+            writeln!(f, "{}", type_def)?;
+        }
         for func in &self.functions {
             writeln!(f, "{}", func)?;
         }
@@ -141,14 +192,28 @@ impl fmt::Display for IR {
 
 impl fmt::Display for IRFunction {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "fn {}({}) -> {} [priority: {}] {{\n", self.name, self.parameters.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", "), self.return_type, self.priority)?;
+        writeln!(f, "fn {}({}) -> {}{} {{", self.name, self.parameters.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", "), self.return_type, format_priorities(&self.priorities))?;
         for block in &self.blocks {
-            writeln!(f, "  {}", block)?;
+            write!(f, "  {}", block)?;
         }
         write!(f, "}}")
     }
 }
 
+fn format_priorities(priorities: &[i32]) -> String {
+    if priorities.is_empty() {
+        String::new()
+    } else {
+        format!(" [priority: {}]", priorities.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", "))
+    }
+}
+
+impl fmt::Display for IRTypeDefinition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "type {} {{ {} }}", self.name, self.fields.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", "))
+    }
+}
+
 impl fmt::Display for IRParameter {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}: {}", self.name, self.type_annotation)
@@ -157,7 +222,7 @@ impl fmt::Display for IRParameter {
 
 impl fmt::Display for IRBlock {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}: ", self.label)?;
+        writeln!(f, "{}:", self.label)?;
         for instr in &self.instructions {
             writeln!(f, "    {}", instr)?;
         }
@@ -181,6 +246,8 @@ impl fmt::Display for IRInstruction {
             IRInstruction::Assignment { target, value } => write!(f, "{} = {}", target, value),
             IRInstruction::Expression(value) => write!(f, "expr {}", value),
             IRInstruction::Let { name, value } => write!(f, "let {} = {}", name, value),
+            IRInstruction::Transfer { from, to } => write!(f, "transfer {} -> {}", from, to),
+            IRInstruction::Priority { name, priority } => write!(f, "priority {} = {}", name, priority),
         }
     }
 }
@@ -190,7 +257,9 @@ impl fmt::Display for IRValue {
         match self {
             IRValue::Int(i) => write!(f, "{}", i),
             IRValue::Float(fl) => write!(f, "{}", fl),
+            IRValue::Complex(re, im) => write!(f, "({} + {}i)", re, im),
             IRValue::Bool(b) => write!(f, "{}", b),
+            IRValue::Char(c) => write!(f, "{:?}", c),
             IRValue::String(s) => write!(f, "\"{}\"", s),
             IRValue::Null => write!(f, "null"),
             IRValue::Identifier(name) => write!(f, "{}", name),
@@ -198,8 +267,13 @@ impl fmt::Display for IRValue {
             IRValue::Variable(v) => write!(f, "{}", v),
             IRValue::BinaryOp { left, op, right } => write!(f, "{} {} {}", left, op, right),
             IRValue::UnaryOp { op, expr } => write!(f, "{} {}", op, expr),
-            IRValue::Call { function, arguments } => write!(f, "{} {}({})", function, function, arguments.iter().map(|a| a.to_string()).collect::<Vec<_>>().join(", ")),
+            IRValue::Call { function, arguments } => write!(f, "{}({})", function, arguments.iter().map(|a| a.to_string()).collect::<Vec<_>>().join(", ")),
             IRValue::Assignment { name, value } => write!(f, "{} = {}", name, value),
+            IRValue::Array(elements) => write!(f, "[{}]", elements.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(", ")),
+            IRValue::Index { array, index } => write!(f, "{}[{}]", array, index),
+            IRValue::MethodCall { receiver, method, arguments } => write!(f, "{}.{}({})", receiver, method, arguments.iter().map(|a| a.to_string()).collect::<Vec<_>>().join(", ")),
+            IRValue::Cast { value, target } => write!(f, "{} as {}", value, target),
+            IRValue::Closure { function, captures } => write!(f, "closure {}[{}]", function, captures.join(", ")),
         }
     }
 }
@@ -250,4 +324,36 @@ impl fmt::Display for IRGlobal {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "global {}: {} = {}", self.name, self.type_annotation, self.value)
     }
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_function_display() {
+        let function = IRFunction {
+            name: "main".to_string(),
+            parameters: vec![IRParameter { name: "n".to_string(), type_annotation: Type::Int }],
+            return_type: Type::Int,
+            priorities: vec![2],
+            blocks: vec![IRBlock {
+                label: "entry".to_string(),
+                instructions: vec![
+                    IRInstruction::Let {
+                        name: "x".to_string(),
+                        value: IRValue::Call {
+                            function: "f".to_string(),
+                            arguments: vec![IRValue::Variable("n".to_string()), IRValue::Int(1)],
+                        },
+                    },
+                    IRInstruction::Return(Some(IRValue::Variable("x".to_string()))),
+                ],
+            }],
+        };
+        assert_eq!(
+            function.to_string(),
+            "fn main(n: int) -> int [priority: 2] {\n  entry:\n    let x = f(n, 1)\n    return x\n}"
+        );
+    }
+}
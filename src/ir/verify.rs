@@ -0,0 +1,329 @@
+use super::*;
+use crate::error::{Result, SlangError};
+use std::collections::{HashMap, HashSet};
+
+impl IR {
+    // 実行や最適化の前に IR が整っているかを調べる。コンパイラの変換の誤りを早く見つけるためのもので、
+    // - どのブロックも終端命令 (`return`/分岐) で終わる
+    // - 分岐先のラベルが関数の中にある
+    // - 読み出す変数 (`Load`/`Variable`) は、そこに至るいずれかの経路で先に定義されている
+    // を確かめる。到達しないブロックの中の読み出しは調べない
+    pub fn verify(&self) -> Result<()> {
+        // 持ち上げたラムダの本体では、クロージャが捕まえた変数も定義済みとして扱う
+        let mut captures: HashMap<&str, HashSet<String>> = HashMap::new();
+        for function in &self.functions {
+            for block in &function.blocks {
+                for inst in &block.instructions {
+                    for_each_value(inst, &mut |value| collect_captures(value, &mut captures));
+                }
+            }
+        }
+        let globals: HashSet<String> = self.globals.iter().map(|global| global.name.clone()).collect();
+        for function in &self.functions {
+            let mut defined = globals.clone();
+            defined.extend(function.parameters.iter().map(|param| param.name.clone()));
+            if let Some(captured) = captures.get(function.name.as_str()) {
+                defined.extend(captured.iter().cloned());
+            }
+            verify_function(function, defined)
+                .map_err(|message| SlangError::Compilation(format!("Invalid IR in function `{}`: {}", function.name, message)))?;
+        }
+        Ok(())
+    }
+}
+
+fn verify_function(function: &IRFunction, entry: HashSet<String>) -> std::result::Result<(), String> {
+    let mut labels = HashMap::new();
+    for (index, block) in function.blocks.iter().enumerate() {
+        if labels.insert(block.label.as_str(), index).is_some() {
+            return Err(format!("duplicate block label `{}`", block.label));
+        }
+    }
+    if function.blocks.is_empty() {
+        return Err("function has no blocks".to_string());
+    }
+    for block in &function.blocks {
+        if !block.instructions.last().is_some_and(IRInstruction::is_terminator) {
+            return Err(format!("block `{}` does not end in a terminator", block.label));
+        }
+        for inst in &block.instructions {
+            for label in branch_targets(inst) {
+                if !labels.contains_key(label) {
+                    return Err(format!("block `{}` branches to unknown label `{}`", block.label, label));
+                }
+            }
+        }
+    }
+
+    // 各ブロックの入口で定義済みの名前。合流点では和集合をとり、変化がなくなるまで繰り返す
+    let mut defined_at: Vec<Option<HashSet<String>>> = vec![None; function.blocks.len()];
+    defined_at[0] = Some(entry);
+    let mut pending = vec![0];
+    while let Some(index) = pending.pop() {
+        let mut defined = defined_at[index].clone().unwrap_or_default();
+        let successors = walk_block(&function.blocks[index], &mut defined, &mut Vec::new());
+        for label in successors {
+            let target = labels[label];
+            let first_visit = defined_at[target].is_none();
+            let merged = defined_at[target].get_or_insert_with(HashSet::new);
+            let before = merged.len();
+            merged.extend(defined.iter().cloned());
+            if first_visit || merged.len() != before {
+                pending.push(target);
+            }
+        }
+    }
+
+    for (block, defined) in function.blocks.iter().zip(defined_at) {
+        let Some(mut defined) = defined else { continue };
+        let mut undefined = Vec::new();
+        walk_block(block, &mut defined, &mut undefined);
+        if let Some(name) = undefined.first() {
+            return Err(format!("`{}` is used in block `{}` before it is defined", name, block.label));
+        }
+    }
+    Ok(())
+}
+
+// 最初の終端命令までを順にたどって定義を `defined` に加え、未定義の読み出しを `undefined` に集める。
+// 終端命令の分岐先を返す
+fn walk_block<'a>(block: &'a IRBlock, defined: &mut HashSet<String>, undefined: &mut Vec<String>) -> Vec<&'a str> {
+    for inst in &block.instructions {
+        walk_instruction(inst, defined, undefined);
+        if inst.is_terminator() {
+            return branch_targets(inst);
+        }
+    }
+    Vec::new()
+}
+
+fn walk_instruction(inst: &IRInstruction, defined: &mut HashSet<String>, undefined: &mut Vec<String>) {
+    let mut read = |name: &str, defined: &HashSet<String>| {
+        if !defined.contains(name) {
+            undefined.push(name.to_string());
+        }
+    };
+    match inst {
+        IRInstruction::Load { name } => read(name, defined),
+        IRInstruction::Transfer { from, to } => {
+            read(from, defined);
+            read(to, defined);
+        }
+        IRInstruction::Priority { name, .. } => read(name, defined),
+        _ => {}
+    }
+    // 値を読んでから書き込む (`let x = x + 1` の右辺の `x` は前の定義)
+    for_each_value(inst, &mut |value| walk_value(value, defined, undefined));
+    match inst {
+        IRInstruction::Alloca { name, .. }
+        | IRInstruction::Store { name, .. }
+        | IRInstruction::Let { name, .. }
+        | IRInstruction::Assignment { target: name, .. }
+        | IRInstruction::BinaryOp { dest: name, .. }
+        | IRInstruction::UnaryOp { dest: name, .. }
+        | IRInstruction::Call { dest: name, .. } => {
+            defined.insert(name.clone());
+        }
+        IRInstruction::Load { .. }
+        | IRInstruction::Return(_)
+        | IRInstruction::Branch { .. }
+        | IRInstruction::ConditionalBranch { .. }
+        | IRInstruction::Expression(_)
+        | IRInstruction::Transfer { .. }
+        | IRInstruction::Priority { .. } => {}
+    }
+}
+
+fn walk_value(value: &IRValue, defined: &mut HashSet<String>, undefined: &mut Vec<String>) {
+    match value {
+        IRValue::Variable(name) | IRValue::Identifier(name) => {
+            if !defined.contains(name) {
+                undefined.push(name.clone());
+            }
+        }
+        IRValue::Assignment { name, value } => {
+            walk_value(value, defined, undefined);
+            defined.insert(name.clone());
+        }
+        IRValue::Constant(inner) | IRValue::UnaryOp { expr: inner, .. } | IRValue::Cast { value: inner, .. } => {
+            walk_value(inner, defined, undefined)
+        }
+        IRValue::BinaryOp { left, right, .. } => {
+            walk_value(left, defined, undefined);
+            walk_value(right, defined, undefined);
+        }
+        IRValue::Call { arguments, .. } | IRValue::Array(arguments) => {
+            for argument in arguments {
+                walk_value(argument, defined, undefined);
+            }
+        }
+        IRValue::Index { array, index } => {
+            walk_value(array, defined, undefined);
+            walk_value(index, defined, undefined);
+        }
+        IRValue::MethodCall { receiver, arguments, .. } => {
+            walk_value(receiver, defined, undefined);
+            for argument in arguments {
+                walk_value(argument, defined, undefined);
+            }
+        }
+        // 捕まえる変数はスコープにあるものだけが捕まえられるので、未定義でもよい
+        IRValue::Closure { .. }
+        | IRValue::Int(_)
+        | IRValue::Float(_)
+        | IRValue::Complex(_, _)
+        | IRValue::Bool(_)
+        | IRValue::Char(_)
+        | IRValue::String(_)
+        | IRValue::Null => {}
+    }
+}
+
+// 命令が直接持つ値
+fn for_each_value<'a>(inst: &'a IRInstruction, f: &mut impl FnMut(&'a IRValue)) {
+    match inst {
+        IRInstruction::Store { value, .. }
+        | IRInstruction::Let { value, .. }
+        | IRInstruction::Assignment { value, .. }
+        | IRInstruction::Expression(value)
+        | IRInstruction::Return(Some(value))
+        | IRInstruction::UnaryOp { expr: value, .. }
+        | IRInstruction::ConditionalBranch { condition: value, .. } => f(value),
+        IRInstruction::BinaryOp { left, right, .. } => {
+            f(left);
+            f(right);
+        }
+        IRInstruction::Call { arguments, .. } => arguments.iter().for_each(f),
+        IRInstruction::Alloca { .. }
+        | IRInstruction::Load { .. }
+        | IRInstruction::Return(None)
+        | IRInstruction::Branch { .. }
+        | IRInstruction::Transfer { .. }
+        | IRInstruction::Priority { .. } => {}
+    }
+}
+
+fn collect_captures<'a>(value: &'a IRValue, captures: &mut HashMap<&'a str, HashSet<String>>) {
+    match value {
+        IRValue::Closure { function, captures: names } => {
+            captures.entry(function.as_str()).or_default().extend(names.iter().cloned());
+        }
+        IRValue::Assignment { value: inner, .. }
+        | IRValue::Constant(inner)
+        | IRValue::UnaryOp { expr: inner, .. }
+        | IRValue::Cast { value: inner, .. } => collect_captures(inner, captures),
+        IRValue::BinaryOp { left, right, .. } | IRValue::Index { array: left, index: right } => {
+            collect_captures(left, captures);
+            collect_captures(right, captures);
+        }
+        IRValue::Call { arguments, .. } | IRValue::Array(arguments) => {
+            for argument in arguments {
+                collect_captures(argument, captures);
+            }
+        }
+        IRValue::MethodCall { receiver, arguments, .. } => {
+            collect_captures(receiver, captures);
+            for argument in arguments {
+                collect_captures(argument, captures);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn branch_targets(inst: &IRInstruction) -> Vec<&str> {
+    match inst {
+        IRInstruction::Branch { label } => vec![label.as_str()],
+        IRInstruction::ConditionalBranch { then_label, else_label, .. } => vec![then_label.as_str(), else_label.as_str()],
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::optimizer::Optimizer;
+
+    fn function(blocks: Vec<IRBlock>) -> IR {
+        let mut ir = IR::new();
+        ir.add_function(IRFunction {
+            name: "main".to_string(),
+            parameters: vec![IRParameter { name: "n".to_string(), type_annotation: Type::Int }],
+            return_type: Type::Int,
+            priorities: Vec::new(),
+            blocks,
+        });
+        ir
+    }
+
+    fn block(label: &str, instructions: Vec<IRInstruction>) -> IRBlock {
+        IRBlock { label: label.to_string(), instructions }
+    }
+
+    fn variable(name: &str) -> IRValue {
+        IRValue::Variable(name.to_string())
+    }
+
+    #[test]
+    fn test_dangling_branch_target() {
+        let ir = function(vec![block("entry", vec![IRInstruction::Branch { label: "missing".to_string() }])]);
+        let error = ir.verify().unwrap_err();
+        assert!(matches!(error, SlangError::Compilation(_)));
+        assert!(
+            error.to_string().contains("Invalid IR in function `main`: block `entry` branches to unknown label `missing`"),
+            "{}",
+            error
+        );
+
+        let ir = function(vec![block("entry", vec![IRInstruction::Let { name: "x".to_string(), value: IRValue::Int(1) }])]);
+        let error = ir.verify().unwrap_err();
+        assert!(error.to_string().contains("block `entry` does not end in a terminator"), "{}", error);
+    }
+
+    #[test]
+    fn test_use_before_definition() {
+        let ir = function(vec![block("entry", vec![
+            IRInstruction::Let { name: "y".to_string(), value: variable("x") },
+            IRInstruction::Let { name: "x".to_string(), value: IRValue::Int(1) },
+            IRInstruction::Return(Some(variable("y"))),
+        ])]);
+        let error = ir.verify().unwrap_err();
+        assert!(error.to_string().contains("`x` is used in block `entry` before it is defined"), "{}", error);
+
+        // どちらかの経路で定義されていればよい。パラメータは最初から定義済み
+        let ir = function(vec![
+            block("entry", vec![IRInstruction::ConditionalBranch {
+                condition: IRValue::Bool(true),
+                then_label: "then".to_string(),
+                else_label: "end".to_string(),
+            }]),
+            block("then", vec![
+                IRInstruction::Let { name: "x".to_string(), value: variable("n") },
+                IRInstruction::Branch { label: "end".to_string() },
+            ]),
+            block("end", vec![IRInstruction::Load { name: "x".to_string() }, IRInstruction::Return(Some(variable("x")))]),
+        ]);
+        assert!(ir.verify().is_ok());
+    }
+
+    #[test]
+    fn test_compiled_programs_verify() {
+        let sources = [
+            "fn f() -> void { }",
+            "fn f(n: int) -> int { let mut i = 0; while i < n { if i == 3 { break; } i = i + 1; } return i; }",
+            "fn f(xs: [int]) -> int { let mut total = 0; for x in xs { total = total + x; } return total; }",
+            "fn f(n: int) -> int { if n > 0 { return 1; } else { return 2; } }",
+            "fn f(n: int) -> bool { let b = n > 0 && n < 5; return b || n == 9; }",
+            "fn f(k: int) -> int { let g = fn(x: int) -> int { let h = fn() { return x + k; }; return h(); }; return g(1); }",
+        ];
+        for source in sources {
+            let mut compiler = Compiler::new();
+            compiler.parse(source).unwrap();
+            let mut ir = compiler.compile_program().unwrap();
+            ir.verify().unwrap_or_else(|error| panic!("{}\n{}", source, error));
+            Optimizer::new().optimize(&mut ir);
+            ir.verify().unwrap_or_else(|error| panic!("{} (optimized)\n{}", source, error));
+        }
+    }
+}
@@ -1,25 +1,102 @@
 pub mod ast;
+pub mod codegen;
 pub mod compiler;
+pub mod debugger;
 pub mod error;
+pub mod formatter;
+pub mod highlighter;
 pub mod ir;
 pub mod lexer;
+pub mod optimizer;
 pub mod parser;
 pub mod runtime;
 pub mod type_system;
 
 pub use ast::*;
+pub use codegen::*;
 pub use compiler::*;
+pub use debugger::*;
 pub use error::*;
+pub use formatter::*;
+pub use highlighter::*;
 pub use ir::*;
 pub use lexer::*;
+pub use optimizer::*;
 pub use parser::*;
 pub use runtime::*;
 pub use type_system::*;
 
+/// ソースを字句解析・構文解析・型検査して IR に変換する。最初に見つかったエラーを返す。
+///
+/// ```
+/// let ir = slang::compile("fn main() -> int { return 1+1; }").unwrap();
+/// assert_eq!(ir.functions[0].name, "main");
+/// ```
+pub fn compile(source: &str) -> error::Result<IR> {
+    compile_with(source, TypeChecker::new())
+}
+
+// `checker` にあらかじめ宣言した関数 (`Runtime::register_function` の関数など) も使えるものとして [`compile`] する
+pub(crate) fn compile_with(source: &str, mut checker: TypeChecker) -> error::Result<IR> {
+    let mut program = Parser::new(Lexer::new(source)).parse_program()?;
+    expand_macros(&mut program)?;
+    checker.check_program(&program)?;
+    Compiler::with_ast(program.into()).compile_program()
+}
+
+/// [`compile`] した IR を実行する。`main` があればそれを、なければ各関数を順に実行する。
+///
+/// ```
+/// slang::run("fn main() -> int { return 1+1; }").unwrap();
+/// assert!(slang::run("fn main() -> int { return 1.5; }").is_err());
+/// ```
+pub fn run(source: &str) -> error::Result<()> {
+    let ir = compile(source)?;
+    Runtime::new().execute(&ir)
+}
+
 #[cfg(test)]
 mod tests {
+    use super::*;
+
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
+
+    // 字句解析・構文解析・ハイライトが同じトークン名 (`Token::Function`, `LParen`, `LBrace`, `LBracket`) を使っている
+    #[test]
+    fn test_lex_parse_and_highlight_full_function() {
+        let source = "fn sum(xs: [int]) -> int {\n    let mut total = 0;\n    for x in xs { total = total + x; }\n    return total;\n}";
+
+        let tokens: Vec<Token> = tokenize_with_trivia(source)
+            .into_iter()
+            .map(|(token, _)| token)
+            .filter(|token| *token != Token::Whitespace)
+            .collect();
+        assert_eq!(
+            &tokens[..8],
+            &[
+                Token::Function,
+                Token::Identifier("sum".to_string()),
+                Token::LParen,
+                Token::Identifier("xs".to_string()),
+                Token::Colon,
+                Token::LBracket,
+                Token::Identifier("int".to_string()),
+                Token::RBracket,
+            ]
+        );
+        assert_eq!(tokens.iter().filter(|token| **token == Token::LBrace).count(), 2);
+
+        let ast = Parser::new(Lexer::new(source)).parse().unwrap();
+        let function = &ast.functions[0];
+        assert_eq!(function.name, "sum");
+        assert_eq!(function.parameters[0].type_annotation, Type::Array(Box::new(Type::Int)));
+        assert_eq!(function.body.statements.len(), 3);
+        compile(source).unwrap();
+
+        let html = Highlighter::new().highlight_html(source).unwrap();
+        assert!(html.starts_with("<span class=\"tok-keyword\">fn</span> <span class=\"tok-identifier\">sum</span><span class=\"tok-delimiter\">(</span>"), "{}", html);
+    }
 } 
\ No newline at end of file
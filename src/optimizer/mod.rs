@@ -1,157 +1,891 @@
-use crate::ir::{IRNode, IRFunction, IRBlock, IRInstruction, IRValue, IRType};
+use crate::ast::Literal;
+use crate::ir::{IRBinaryOperator, IRBlock, IRFunction, IRInstruction, IRUnaryOperator, IRValue, IR};
+use crate::type_system::{Type, TypeCast};
 use std::collections::{HashMap, HashSet};
 
 pub struct Optimizer {
     constant_values: HashMap<String, IRValue>,
-    priority_ownership: HashMap<String, u32>,
 }
 
 impl Optimizer {
     pub fn new() -> Self {
         Self {
             constant_values: HashMap::new(),
-            priority_ownership: HashMap::new(),
         }
     }
 
-    pub fn optimize(&mut self, ir: &mut IRNode) {
-        self.constant_folding(ir);
-        self.dead_code_elimination(ir);
-        self.optimize_priority_ownership(ir);
+    pub fn optimize(&mut self, ir: &mut IR) {
+        for function in &mut ir.functions {
+            self.constant_folding(function);
+            self.dead_code_elimination(function);
+            self.common_subexpression_elimination(function);
+        }
     }
 
-    fn constant_folding(&mut self, ir: &mut IRNode) {
-        match ir {
-            IRNode::Function(func) => {
-                for block in &mut func.blocks {
-                    self.fold_constants_in_block(block);
-                }
-            }
-            _ => {}
+    fn constant_folding(&mut self, function: &mut IRFunction) {
+        for block in &mut function.blocks {
+            self.fold_constants_in_block(block);
         }
     }
 
+    // 定数の伝播はブロック内に限る。ブロックの入口ではどの経路から来たか分からないため
     fn fold_constants_in_block(&mut self, block: &mut IRBlock) {
-        let mut i = 0;
-        while i < block.instructions.len() {
-            if let Some(folded) = self.try_fold_instruction(&block.instructions[i]) {
-                block.instructions[i] = folded;
+        self.constant_values.clear();
+        for instruction in &mut block.instructions {
+            if let Some(folded) = self.try_fold_instruction(instruction) {
+                *instruction = folded;
             }
-            i += 1;
         }
     }
 
-    fn try_fold_instruction(&self, inst: &IRInstruction) -> Option<IRInstruction> {
+    fn try_fold_instruction(&mut self, inst: &mut IRInstruction) -> Option<IRInstruction> {
         match inst {
-            IRInstruction::BinaryOp { op, left, right, result } => {
-                if let (Some(l), Some(r)) = (self.evaluate_constant(left), self.evaluate_constant(right)) {
-                    if let (IRValue::Integer(l_val), IRValue::Integer(r_val)) = (l, r) {
-                        let result_val = match op.as_str() {
-                            "add" => l_val + r_val,
-                            "sub" => l_val - r_val,
-                            "mul" => l_val * r_val,
-                            "div" => l_val / r_val,
-                            _ => return None,
-                        };
-                        return Some(IRInstruction::Assign {
-                            value: IRValue::Integer(result_val),
-                            target: result.clone(),
-                        });
+            IRInstruction::BinaryOp { dest, op, left, right } => {
+                let value = self.fold_value(&IRValue::BinaryOp {
+                    left: Box::new(left.clone()),
+                    op: op.clone(),
+                    right: Box::new(right.clone()),
+                });
+                self.record(dest, &value);
+                if is_literal(&value) {
+                    return Some(IRInstruction::Store { name: dest.clone(), value });
+                }
+            }
+            IRInstruction::UnaryOp { dest, op, expr } => {
+                let value = self.fold_value(&IRValue::UnaryOp {
+                    op: op.clone(),
+                    expr: Box::new(expr.clone()),
+                });
+                self.record(dest, &value);
+                if is_literal(&value) {
+                    return Some(IRInstruction::Store { name: dest.clone(), value });
+                }
+            }
+            IRInstruction::Store { name, value } | IRInstruction::Let { name, value } => {
+                *value = self.fold_value(value);
+                self.record(name, value);
+            }
+            IRInstruction::Assignment { target, value } => {
+                *value = self.fold_value(value);
+                self.record(target, value);
+            }
+            IRInstruction::Call { dest, arguments, .. } => {
+                for argument in arguments.iter_mut() {
+                    *argument = self.fold_value(argument);
+                }
+                // push/pop などは変数を直接書き換えるため、引数に渡した変数は定数として扱わない
+                for argument in arguments.iter() {
+                    if let IRValue::Variable(name) | IRValue::Identifier(name) = argument {
+                        self.constant_values.remove(name);
                     }
                 }
+                self.constant_values.remove(dest);
+            }
+            IRInstruction::Alloca { name, .. } => {
+                self.constant_values.remove(name);
+            }
+            IRInstruction::Return(Some(value)) | IRInstruction::Expression(value) => {
+                *value = self.fold_value(value);
             }
-            _ => {}
+            IRInstruction::ConditionalBranch { condition, then_label, else_label } => {
+                *condition = self.fold_value(condition);
+                if let IRValue::Bool(b) = condition {
+                    let label = if *b { then_label } else { else_label };
+                    return Some(IRInstruction::Branch { label: label.clone() });
+                }
+            }
+            IRInstruction::Load { .. }
+            | IRInstruction::Return(None)
+            | IRInstruction::Branch { .. }
+            | IRInstruction::Transfer { .. }
+            | IRInstruction::Priority { .. } => {}
         }
         None
     }
 
+    fn record(&mut self, name: &str, value: &IRValue) {
+        if is_scalar(value) {
+            self.constant_values.insert(name.to_string(), value.clone());
+        } else {
+            self.constant_values.remove(name);
+        }
+    }
+
+    // 子から順に畳み込む。評価順は実行時と同じく左から右
+    fn fold_value(&mut self, value: &IRValue) -> IRValue {
+        match value {
+            IRValue::Constant(inner) => self.fold_value(inner),
+            IRValue::Variable(_) | IRValue::Identifier(_) => {
+                self.evaluate_constant(value).unwrap_or_else(|| value.clone())
+            }
+            IRValue::BinaryOp { left, op, right } => {
+                let left = self.fold_value(left);
+                // 短絡評価で右辺が実行されない場合は右辺ごと消してよい
+                match (op, &left) {
+                    (IRBinaryOperator::And, IRValue::Bool(false)) => return IRValue::Bool(false),
+                    (IRBinaryOperator::Or, IRValue::Bool(true)) => return IRValue::Bool(true),
+                    _ => {}
+                }
+                let right = self.fold_value(right);
+                fold_binary(op, &left, &right).unwrap_or_else(|| IRValue::BinaryOp {
+                    left: Box::new(left),
+                    op: op.clone(),
+                    right: Box::new(right),
+                })
+            }
+            IRValue::UnaryOp { op, expr } => {
+                let expr = self.fold_value(expr);
+                fold_unary(op, &expr).unwrap_or_else(|| IRValue::UnaryOp {
+                    op: op.clone(),
+                    expr: Box::new(expr),
+                })
+            }
+            IRValue::Call { function, arguments } => {
+                let arguments = arguments.iter().map(|argument| self.fold_value(argument)).collect::<Vec<_>>();
+                for argument in &arguments {
+                    if let IRValue::Variable(name) | IRValue::Identifier(name) = argument {
+                        self.constant_values.remove(name);
+                    }
+                }
+                IRValue::Call { function: function.clone(), arguments }
+            }
+            IRValue::Assignment { name, value } => {
+                let value = self.fold_value(value);
+                self.record(name, &value);
+                IRValue::Assignment { name: name.clone(), value: Box::new(value) }
+            }
+            IRValue::Array(elements) => {
+                IRValue::Array(elements.iter().map(|element| self.fold_value(element)).collect())
+            }
+            IRValue::Index { array, index } => IRValue::Index {
+                array: Box::new(self.fold_value(array)),
+                index: Box::new(self.fold_value(index)),
+            },
+            IRValue::MethodCall { receiver, method, arguments } => IRValue::MethodCall {
+                receiver: Box::new(self.fold_value(receiver)),
+                method: method.clone(),
+                arguments: arguments.iter().map(|argument| self.fold_value(argument)).collect(),
+            },
+            IRValue::Cast { value, target } => {
+                let value = self.fold_value(value);
+                fold_cast(&value, target).unwrap_or_else(|| IRValue::Cast {
+                    value: Box::new(value),
+                    target: target.clone(),
+                })
+            }
+            IRValue::Int(_)
+            | IRValue::Float(_)
+            | IRValue::Complex(_, _)
+            | IRValue::Bool(_)
+            | IRValue::Char(_)
+            | IRValue::String(_)
+            | IRValue::Null
+            | IRValue::Closure { .. } => value.clone(),
+        }
+    }
+
     fn evaluate_constant(&self, value: &IRValue) -> Option<IRValue> {
         match value {
-            IRValue::Integer(_) | IRValue::Float(_) | IRValue::Boolean(_) => Some(value.clone()),
-            IRValue::Variable(name) => self.constant_values.get(name).cloned(),
+            IRValue::Int(_) | IRValue::Float(_) | IRValue::Bool(_) => Some(value.clone()),
+            IRValue::Variable(name) | IRValue::Identifier(name) => self.constant_values.get(name).cloned(),
             _ => None,
         }
     }
 
-    fn dead_code_elimination(&mut self, ir: &mut IRNode) {
+    fn dead_code_elimination(&mut self, function: &mut IRFunction) {
+        for block in &mut function.blocks {
+            truncate_after_terminator(block);
+        }
+        self.remove_unreachable_blocks(function);
         let mut live_vars = HashSet::new();
-        self.mark_live_variables(ir, &mut live_vars);
-        self.remove_dead_code(ir, &live_vars);
-    }
-
-    fn mark_live_variables(&self, ir: &IRNode, live_vars: &mut HashSet<String>) {
-        match ir {
-            IRNode::Function(func) => {
-                for block in &func.blocks {
-                    for inst in &block.instructions {
-                        match inst {
-                            IRInstruction::Assign { target, .. } => {
-                                live_vars.insert(target.clone());
-                            }
-                            IRInstruction::BinaryOp { result, .. } => {
-                                live_vars.insert(result.clone());
-                            }
-                            _ => {}
-                        }
-                    }
+        self.mark_live_variables(function, &mut live_vars);
+        self.remove_dead_code(function, &live_vars);
+    }
+
+    // 最初のブロックから分岐をたどって到達できないブロックを消す。
+    // 存在しないラベルへの分岐がある場合は実行時エラーを残すため何もしない
+    fn remove_unreachable_blocks(&self, function: &mut IRFunction) {
+        let labels: HashMap<&str, usize> = function.blocks.iter()
+            .enumerate()
+            .map(|(index, block)| (block.label.as_str(), index))
+            .collect();
+        let mut reachable = HashSet::new();
+        let mut pending = vec![0];
+        while let Some(index) = pending.pop() {
+            let Some(block) = function.blocks.get(index) else { continue };
+            if !reachable.insert(index) {
+                continue;
+            }
+            for label in branch_targets(block) {
+                match labels.get(label) {
+                    Some(target) => pending.push(*target),
+                    None => return,
                 }
             }
-            _ => {}
         }
+        let mut index = 0;
+        function.blocks.retain(|_| {
+            index += 1;
+            reachable.contains(&(index - 1))
+        });
     }
 
-    fn remove_dead_code(&self, ir: &mut IRNode, live_vars: &HashSet<String>) {
-        match ir {
-            IRNode::Function(func) => {
-                for block in &mut func.blocks {
-                    block.instructions.retain(|inst| {
-                        match inst {
-                            IRInstruction::Assign { target, .. } => live_vars.contains(target),
-                            IRInstruction::BinaryOp { result, .. } => live_vars.contains(result),
-                            _ => true,
+    fn mark_live_variables(&self, function: &IRFunction, live_vars: &mut HashSet<String>) {
+        for block in &function.blocks {
+            for inst in &block.instructions {
+                match inst {
+                    IRInstruction::Store { value, .. } | IRInstruction::Let { value, .. } => {
+                        collect_reads(value, live_vars);
+                    }
+                    IRInstruction::Assignment { target, value } => {
+                        live_vars.insert(target.clone());
+                        collect_reads(value, live_vars);
+                    }
+                    IRInstruction::Load { name } => {
+                        live_vars.insert(name.clone());
+                    }
+                    // 優先所有格は変数名で管理するので、移動元と移動先の定義を消さない
+                    IRInstruction::Transfer { from, to } => {
+                        live_vars.insert(from.clone());
+                        live_vars.insert(to.clone());
+                    }
+                    IRInstruction::Priority { name, .. } => {
+                        live_vars.insert(name.clone());
+                    }
+                    IRInstruction::BinaryOp { left, right, .. } => {
+                        collect_reads(left, live_vars);
+                        collect_reads(right, live_vars);
+                    }
+                    IRInstruction::UnaryOp { expr, .. } => collect_reads(expr, live_vars),
+                    IRInstruction::Call { arguments, .. } => {
+                        for argument in arguments {
+                            collect_reads(argument, live_vars);
                         }
-                    });
+                    }
+                    IRInstruction::Return(Some(value))
+                    | IRInstruction::Expression(value)
+                    | IRInstruction::ConditionalBranch { condition: value, .. } => {
+                        collect_reads(value, live_vars);
+                    }
+                    IRInstruction::Alloca { .. } | IRInstruction::Return(None) | IRInstruction::Branch { .. } => {}
                 }
             }
-            _ => {}
         }
     }
 
-    fn optimize_priority_ownership(&mut self, ir: &mut IRNode) {
-        self.analyze_priority_ownership(ir);
-        self.apply_priority_optimizations(ir);
+    // 一度も読まれない変数への定数の書き込みだけを消す。式の評価にはエラーや副作用があり得るため残す
+    fn remove_dead_code(&self, function: &mut IRFunction, live_vars: &HashSet<String>) {
+        for block in &mut function.blocks {
+            block.instructions.retain(|inst| match inst {
+                IRInstruction::Store { name, value } | IRInstruction::Let { name, value } => {
+                    live_vars.contains(name) || !is_literal(value)
+                }
+                IRInstruction::Expression(value) => !is_literal(value),
+                _ => true,
+            });
+        }
     }
 
-    fn analyze_priority_ownership(&mut self, ir: &IRNode) {
-        match ir {
-            IRNode::Function(func) => {
-                for block in &func.blocks {
-                    for inst in &block.instructions {
-                        if let IRInstruction::PriorityOwnership { variable, priority } = inst {
-                            self.priority_ownership.insert(variable.clone(), *priority);
-                        }
-                    }
+    fn common_subexpression_elimination(&mut self, function: &mut IRFunction) {
+        for block in &mut function.blocks {
+            self.eliminate_common_subexpressions_in_block(block);
+        }
+    }
+
+    // 同じブロック内で同じ純粋な計算が再び現れたら、最初の結果を参照する命令に置き換える。
+    // 以降の命令の読み出しも最初の結果の名前に書き換える
+    fn eliminate_common_subexpressions_in_block(&self, block: &mut IRBlock) {
+        let mut available: Vec<(IRValue, String)> = Vec::new();
+        let mut aliases: HashMap<String, String> = HashMap::new();
+        for inst in &mut block.instructions {
+            rename_instruction_reads(inst, &aliases);
+            let candidate = match inst {
+                IRInstruction::BinaryOp { dest, op, left, right } => Some((
+                    dest.clone(),
+                    IRValue::BinaryOp { left: Box::new(left.clone()), op: op.clone(), right: Box::new(right.clone()) },
+                )),
+                IRInstruction::UnaryOp { dest, op, expr } => Some((
+                    dest.clone(),
+                    IRValue::UnaryOp { op: op.clone(), expr: Box::new(expr.clone()) },
+                )),
+                IRInstruction::Store { name, value } | IRInstruction::Let { name, value }
+                    if matches!(value, IRValue::BinaryOp { .. } | IRValue::UnaryOp { .. }) =>
+                {
+                    Some((name.clone(), value.clone()))
                 }
+                _ => None,
+            };
+
+            let mut written = Vec::new();
+            collect_instruction_writes(inst, &mut written);
+            for name in &written {
+                available.retain(|(value, dest)| dest != name && !reads_variable(value, name));
+                aliases.retain(|alias, target| alias != name && target != name);
+            }
+
+            let Some((dest, value)) = candidate else { continue };
+            if !is_pure(&value) || reads_variable(&value, &dest) {
+                continue;
+            }
+            match available.iter().find(|(computed, _)| *computed == value) {
+                Some((_, first)) => {
+                    let reference = IRValue::Variable(first.clone());
+                    aliases.insert(dest.clone(), first.clone());
+                    *inst = match inst {
+                        IRInstruction::Let { .. } => IRInstruction::Let { name: dest, value: reference },
+                        _ => IRInstruction::Store { name: dest, value: reference },
+                    };
+                }
+                None => available.push((value, dest)),
             }
-            _ => {}
         }
     }
+}
 
-    fn apply_priority_optimizations(&self, ir: &mut IRNode) {
-        match ir {
-            IRNode::Function(func) => {
-                for block in &mut func.blocks {
-                    block.instructions.retain(|inst| {
-                        if let IRInstruction::PriorityOwnership { variable, priority } = inst {
-                            if let Some(current_priority) = self.priority_ownership.get(variable) {
-                                return current_priority != priority;
-                            }
-                        }
-                        true
-                    });
-                }
+impl Default for Optimizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn is_scalar(value: &IRValue) -> bool {
+    matches!(value, IRValue::Int(_) | IRValue::Float(_) | IRValue::Bool(_))
+}
+
+fn is_literal(value: &IRValue) -> bool {
+    match value {
+        IRValue::Int(_) | IRValue::Float(_) | IRValue::Complex(_, _) | IRValue::Bool(_) | IRValue::Char(_) | IRValue::String(_) | IRValue::Null => true,
+        IRValue::Constant(inner) => is_literal(inner),
+        IRValue::Array(elements) => elements.iter().all(is_literal),
+        _ => false,
+    }
+}
+
+// ゼロ除算やオーバーフローは実行時エラーとして残すため畳み込まない
+fn fold_binary(op: &IRBinaryOperator, left: &IRValue, right: &IRValue) -> Option<IRValue> {
+    use IRBinaryOperator::*;
+    match (left, right) {
+        (IRValue::Int(l), IRValue::Int(r)) => match op {
+            Add => l.checked_add(*r).map(IRValue::Int),
+            Sub | Subtract => l.checked_sub(*r).map(IRValue::Int),
+            Mul | Multiply => l.checked_mul(*r).map(IRValue::Int),
+            Div | Divide => l.checked_div(*r).map(IRValue::Int),
+            Mod | Modulo => l.checked_rem(*r).map(IRValue::Int),
+            Eq | Equals => Some(IRValue::Bool(l == r)),
+            Neq | NotEquals => Some(IRValue::Bool(l != r)),
+            Lt | LessThan => Some(IRValue::Bool(l < r)),
+            Lte | LessThanEquals => Some(IRValue::Bool(l <= r)),
+            Gt | GreaterThan => Some(IRValue::Bool(l > r)),
+            Gte | GreaterThanEquals => Some(IRValue::Bool(l >= r)),
+            And | Or => None,
+        },
+        (IRValue::Float(l), IRValue::Float(r)) => match op {
+            Add => Some(IRValue::Float(l + r)),
+            Sub | Subtract => Some(IRValue::Float(l - r)),
+            Mul | Multiply => Some(IRValue::Float(l * r)),
+            Div | Divide if *r != 0.0 => Some(IRValue::Float(l / r)),
+            Eq | Equals => Some(IRValue::Bool(l == r)),
+            Neq | NotEquals => Some(IRValue::Bool(l != r)),
+            Lt | LessThan => Some(IRValue::Bool(l < r)),
+            Lte | LessThanEquals => Some(IRValue::Bool(l <= r)),
+            Gt | GreaterThan => Some(IRValue::Bool(l > r)),
+            Gte | GreaterThanEquals => Some(IRValue::Bool(l >= r)),
+            _ => None,
+        },
+        (IRValue::Char(l), IRValue::Char(r)) => match op {
+            Eq | Equals => Some(IRValue::Bool(l == r)),
+            Neq | NotEquals => Some(IRValue::Bool(l != r)),
+            _ => None,
+        },
+        (IRValue::String(l), IRValue::String(r)) => match op {
+            Eq | Equals => Some(IRValue::Bool(l == r)),
+            Neq | NotEquals => Some(IRValue::Bool(l != r)),
+            _ => None,
+        },
+        (IRValue::Bool(l), IRValue::Bool(r)) => match op {
+            And => Some(IRValue::Bool(*l && *r)),
+            Or => Some(IRValue::Bool(*l || *r)),
+            Eq | Equals => Some(IRValue::Bool(l == r)),
+            Neq | NotEquals => Some(IRValue::Bool(l != r)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn fold_unary(op: &IRUnaryOperator, expr: &IRValue) -> Option<IRValue> {
+    match (op, expr) {
+        (IRUnaryOperator::Neg | IRUnaryOperator::Negate, IRValue::Int(i)) => i.checked_neg().map(IRValue::Int),
+        (IRUnaryOperator::Neg | IRUnaryOperator::Negate, IRValue::Float(f)) => Some(IRValue::Float(-f)),
+        (IRUnaryOperator::Not, IRValue::Bool(b)) => Some(IRValue::Bool(!b)),
+        _ => None,
+    }
+}
+
+// 分岐・リターンより後ろの命令は実行されない
+fn truncate_after_terminator(block: &mut IRBlock) {
+    let terminator = block.instructions.iter().position(IRInstruction::is_terminator);
+    if let Some(index) = terminator {
+        block.instructions.truncate(index + 1);
+    }
+}
+
+fn branch_targets(block: &IRBlock) -> Vec<&str> {
+    match block.instructions.last() {
+        Some(IRInstruction::Branch { label }) => vec![label.as_str()],
+        Some(IRInstruction::ConditionalBranch { then_label, else_label, .. }) => {
+            vec![then_label.as_str(), else_label.as_str()]
+        }
+        _ => Vec::new(),
+    }
+}
+
+// 定数の変換は実行時と同じく `TypeCast` で行う。失敗する変換 (`"abc" as int`) は畳み込まない
+fn fold_cast(value: &IRValue, target: &Type) -> Option<IRValue> {
+    let type_cast = TypeCast::new();
+    if let IRValue::Array(elements) = value {
+        let literals = elements.iter().map(literal_of).collect::<Option<Vec<_>>>()?;
+        let elements = type_cast.cast_literals(&literals, target).ok()?;
+        return elements.into_iter().map(ir_value_of).collect::<Option<Vec<_>>>().map(IRValue::Array);
+    }
+    ir_value_of(type_cast.cast_literal(&literal_of(value)?, target).ok()?)
+}
+
+fn literal_of(value: &IRValue) -> Option<Literal> {
+    match value {
+        IRValue::Int(i) => Some(Literal::Int(*i)),
+        IRValue::Float(f) => Some(Literal::Float(*f)),
+        IRValue::Bool(b) => Some(Literal::Bool(*b)),
+        IRValue::Char(c) => Some(Literal::Char(*c)),
+        IRValue::String(s) => Some(Literal::String(s.clone())),
+        _ => None,
+    }
+}
+
+fn ir_value_of(literal: Literal) -> Option<IRValue> {
+    match literal {
+        Literal::Int(i) => Some(IRValue::Int(i)),
+        Literal::Float(f) => Some(IRValue::Float(f)),
+        Literal::Bool(b) => Some(IRValue::Bool(b)),
+        Literal::Char(c) => Some(IRValue::Char(c)),
+        Literal::String(s) => Some(IRValue::String(s)),
+        _ => None,
+    }
+}
+
+// 呼び出しも代入も含まない値だけを純粋とみなす
+fn is_pure(value: &IRValue) -> bool {
+    match value {
+        IRValue::Call { .. } | IRValue::MethodCall { .. } | IRValue::Assignment { .. } => false,
+        IRValue::Constant(inner) | IRValue::UnaryOp { expr: inner, .. } | IRValue::Cast { value: inner, .. } => {
+            is_pure(inner)
+        }
+        IRValue::BinaryOp { left, right, .. } => is_pure(left) && is_pure(right),
+        IRValue::Index { array, index } => is_pure(array) && is_pure(index),
+        IRValue::Array(elements) => elements.iter().all(is_pure),
+        IRValue::Int(_)
+        | IRValue::Float(_)
+        | IRValue::Complex(_, _)
+        | IRValue::Bool(_)
+        | IRValue::Char(_)
+        | IRValue::String(_)
+        | IRValue::Null
+        | IRValue::Identifier(_)
+        | IRValue::Variable(_)
+        | IRValue::Closure { .. } => true,
+    }
+}
+
+fn reads_variable(value: &IRValue, name: &str) -> bool {
+    let mut reads = HashSet::new();
+    collect_reads(value, &mut reads);
+    reads.contains(name)
+}
+
+fn rename_instruction_reads(inst: &mut IRInstruction, aliases: &HashMap<String, String>) {
+    if aliases.is_empty() {
+        return;
+    }
+    match inst {
+        IRInstruction::Store { value, .. }
+        | IRInstruction::Let { value, .. }
+        | IRInstruction::Assignment { value, .. }
+        | IRInstruction::UnaryOp { expr: value, .. }
+        | IRInstruction::Return(Some(value))
+        | IRInstruction::Expression(value)
+        | IRInstruction::ConditionalBranch { condition: value, .. } => rename_reads(value, aliases),
+        IRInstruction::BinaryOp { left, right, .. } => {
+            rename_reads(left, aliases);
+            rename_reads(right, aliases);
+        }
+        IRInstruction::Call { arguments, .. } => rename_arguments(arguments, aliases),
+        IRInstruction::Load { name } => {
+            if let Some(target) = aliases.get(name) {
+                *name = target.clone();
+            }
+        }
+        // 優先所有格は変数名に結び付いているので別名に置き換えない
+        IRInstruction::Alloca { .. }
+        | IRInstruction::Return(None)
+        | IRInstruction::Branch { .. }
+        | IRInstruction::Transfer { .. }
+        | IRInstruction::Priority { .. } => {}
+    }
+}
+
+fn rename_reads(value: &mut IRValue, aliases: &HashMap<String, String>) {
+    match value {
+        IRValue::Variable(name) | IRValue::Identifier(name) => {
+            if let Some(target) = aliases.get(name) {
+                *name = target.clone();
+            }
+        }
+        IRValue::Constant(inner)
+        | IRValue::UnaryOp { expr: inner, .. }
+        | IRValue::Assignment { value: inner, .. }
+        | IRValue::Cast { value: inner, .. } => rename_reads(inner, aliases),
+        IRValue::BinaryOp { left, right, .. } => {
+            rename_reads(left, aliases);
+            rename_reads(right, aliases);
+        }
+        IRValue::Call { arguments, .. } => rename_arguments(arguments, aliases),
+        IRValue::Array(elements) => {
+            for element in elements {
+                rename_reads(element, aliases);
+            }
+        }
+        IRValue::Index { array, index } => {
+            rename_reads(array, aliases);
+            rename_reads(index, aliases);
+        }
+        IRValue::MethodCall { receiver, arguments, .. } => {
+            rename_reads(receiver, aliases);
+            for argument in arguments {
+                rename_reads(argument, aliases);
+            }
+        }
+        // 捕まえる名前はラムダの本体の中の名前でもあるので書き換えない
+        IRValue::Closure { .. } => {}
+        IRValue::Int(_) | IRValue::Float(_) | IRValue::Complex(_, _) | IRValue::Bool(_) | IRValue::Char(_) | IRValue::String(_) | IRValue::Null => {}
+    }
+}
+
+// 変数をそのまま渡した引数は push/pop で書き換えられ得るので名前を変えない
+fn rename_arguments(arguments: &mut [IRValue], aliases: &HashMap<String, String>) {
+    for argument in arguments {
+        if !matches!(argument, IRValue::Variable(_) | IRValue::Identifier(_)) {
+            rename_reads(argument, aliases);
+        }
+    }
+}
+
+fn collect_instruction_writes(inst: &IRInstruction, written: &mut Vec<String>) {
+    match inst {
+        IRInstruction::Store { name, value } | IRInstruction::Let { name, value } | IRInstruction::Assignment { target: name, value } => {
+            collect_writes(value, written);
+            written.push(name.clone());
+        }
+        IRInstruction::BinaryOp { dest, left, right, .. } => {
+            collect_writes(left, written);
+            collect_writes(right, written);
+            written.push(dest.clone());
+        }
+        IRInstruction::UnaryOp { dest, expr, .. } => {
+            collect_writes(expr, written);
+            written.push(dest.clone());
+        }
+        IRInstruction::Call { dest, arguments, .. } => {
+            collect_argument_writes(arguments, written);
+            written.push(dest.clone());
+        }
+        IRInstruction::Alloca { name, .. } => written.push(name.clone()),
+        IRInstruction::Return(Some(value)) | IRInstruction::Expression(value) | IRInstruction::ConditionalBranch { condition: value, .. } => {
+            collect_writes(value, written)
+        }
+        IRInstruction::Load { .. }
+        | IRInstruction::Return(None)
+        | IRInstruction::Branch { .. }
+        | IRInstruction::Transfer { .. }
+        | IRInstruction::Priority { .. } => {}
+    }
+}
+
+fn collect_writes(value: &IRValue, written: &mut Vec<String>) {
+    match value {
+        IRValue::Assignment { name, value } => {
+            collect_writes(value, written);
+            written.push(name.clone());
+        }
+        IRValue::Call { arguments, .. } => collect_argument_writes(arguments, written),
+        IRValue::Constant(inner) | IRValue::UnaryOp { expr: inner, .. } | IRValue::Cast { value: inner, .. } => {
+            collect_writes(inner, written)
+        }
+        IRValue::BinaryOp { left, right, .. } => {
+            collect_writes(left, written);
+            collect_writes(right, written);
+        }
+        IRValue::Array(elements) => {
+            for element in elements {
+                collect_writes(element, written);
+            }
+        }
+        IRValue::Index { array, index } => {
+            collect_writes(array, written);
+            collect_writes(index, written);
+        }
+        IRValue::MethodCall { receiver, arguments, .. } => {
+            collect_writes(receiver, written);
+            for argument in arguments {
+                collect_writes(argument, written);
             }
-            _ => {}
         }
+        IRValue::Int(_)
+        | IRValue::Float(_)
+        | IRValue::Complex(_, _)
+        | IRValue::Bool(_)
+        | IRValue::Char(_)
+        | IRValue::String(_)
+        | IRValue::Null
+        | IRValue::Identifier(_)
+        | IRValue::Variable(_)
+        | IRValue::Closure { .. } => {}
     }
-} 
\ No newline at end of file
+}
+
+fn collect_argument_writes(arguments: &[IRValue], written: &mut Vec<String>) {
+    for argument in arguments {
+        match argument {
+            IRValue::Variable(name) | IRValue::Identifier(name) => written.push(name.clone()),
+            _ => collect_writes(argument, written),
+        }
+    }
+}
+
+fn collect_reads(value: &IRValue, live_vars: &mut HashSet<String>) {
+    match value {
+        IRValue::Variable(name) | IRValue::Identifier(name) => {
+            live_vars.insert(name.clone());
+        }
+        IRValue::Assignment { name, value } => {
+            live_vars.insert(name.clone());
+            collect_reads(value, live_vars);
+        }
+        IRValue::Constant(inner) => collect_reads(inner, live_vars),
+        IRValue::BinaryOp { left, right, .. } => {
+            collect_reads(left, live_vars);
+            collect_reads(right, live_vars);
+        }
+        IRValue::UnaryOp { expr, .. } | IRValue::Cast { value: expr, .. } => collect_reads(expr, live_vars),
+        IRValue::Call { arguments, .. } | IRValue::Array(arguments) => {
+            for argument in arguments {
+                collect_reads(argument, live_vars);
+            }
+        }
+        IRValue::Index { array, index } => {
+            collect_reads(array, live_vars);
+            collect_reads(index, live_vars);
+        }
+        IRValue::MethodCall { receiver, arguments, .. } => {
+            collect_reads(receiver, live_vars);
+            for argument in arguments {
+                collect_reads(argument, live_vars);
+            }
+        }
+        // 捕まえる変数はクロージャを作った時点で読まれる
+        IRValue::Closure { captures, .. } => live_vars.extend(captures.iter().cloned()),
+        IRValue::Int(_) | IRValue::Float(_) | IRValue::Complex(_, _) | IRValue::Bool(_) | IRValue::Char(_) | IRValue::String(_) | IRValue::Null => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+
+    fn optimize(source: &str) -> IR {
+        let mut compiler = Compiler::new();
+        compiler.parse(source).unwrap();
+        let mut ir = compiler.compile_program().unwrap();
+        Optimizer::new().optimize(&mut ir);
+        ir
+    }
+
+    #[test]
+    fn test_constant_folding() {
+        let ir = optimize("fn main() -> int { let x = 2 * 3 + 4; let y = x * 10; return y - 1; }");
+        let entry = &ir.functions[0].blocks[0];
+        assert_eq!(
+            entry.instructions,
+            vec![IRInstruction::Return(Some(IRValue::Int(99)))]
+        );
+    }
+
+    #[test]
+    fn test_constant_branch_removes_unreachable_block() {
+        let ir = optimize("fn main() -> int { if 1 < 2 { return 1; } else { return 2; } }");
+        let function = &ir.functions[0];
+        assert!(function.blocks.iter().all(|block| block.instructions != vec![IRInstruction::Return(Some(IRValue::Int(2)))]));
+        assert!(matches!(function.blocks[0].instructions.last(), Some(IRInstruction::Branch { .. })));
+    }
+
+    fn folded_return(source: &str) -> IRInstruction {
+        optimize(source).functions[0].blocks[0].instructions[0].clone()
+    }
+
+    #[test]
+    fn test_float_and_comparison_folding() {
+        assert_eq!(
+            folded_return("fn main() -> float { return 2.0 * 3.0; }"),
+            IRInstruction::Return(Some(IRValue::Float(6.0)))
+        );
+        assert_eq!(
+            folded_return("fn main() -> bool { return 3 < 5; }"),
+            IRInstruction::Return(Some(IRValue::Bool(true)))
+        );
+        assert_eq!(
+            folded_return("fn main() -> bool { return 1.5 >= 2.5; }"),
+            IRInstruction::Return(Some(IRValue::Bool(false)))
+        );
+        assert_eq!(
+            folded_return("fn main() -> bool { return 'a' != 'b'; }"),
+            IRInstruction::Return(Some(IRValue::Bool(true)))
+        );
+    }
+
+    #[test]
+    fn test_cast_folding() {
+        assert_eq!(
+            folded_return("fn main() -> float { return 5 as float * 2.0; }"),
+            IRInstruction::Return(Some(IRValue::Float(10.0)))
+        );
+        assert_eq!(
+            folded_return("fn main() -> int { return \"42\" as int; }"),
+            IRInstruction::Return(Some(IRValue::Int(42)))
+        );
+        assert_eq!(
+            folded_return("fn main() -> [float] { return [1, 2] as [float]; }"),
+            IRInstruction::Return(Some(IRValue::Array(vec![IRValue::Float(1.0), IRValue::Float(2.0)])))
+        );
+        // 失敗する変換は実行時にエラーを出せるよう残す
+        assert_eq!(
+            folded_return("fn main() -> int { return \"abc\" as int; }"),
+            IRInstruction::Return(Some(IRValue::Cast { value: Box::new(IRValue::String("abc".to_string())), target: Type::Int }))
+        );
+    }
+
+    #[test]
+    fn test_boolean_folding() {
+        let binary = |left, op, right| IRValue::BinaryOp { left: Box::new(left), op, right: Box::new(right) };
+        let mut optimizer = Optimizer::new();
+        assert_eq!(
+            optimizer.fold_value(&binary(IRValue::Bool(true), IRBinaryOperator::And, IRValue::Bool(true))),
+            IRValue::Bool(true)
+        );
+        assert_eq!(
+            optimizer.fold_value(&binary(IRValue::Bool(false), IRBinaryOperator::Or, IRValue::Bool(false))),
+            IRValue::Bool(false)
+        );
+        // 右辺が定数でなくても左辺で結果が決まれば畳み込む
+        let call = IRValue::Call { function: "f".to_string(), arguments: Vec::new() };
+        assert_eq!(
+            optimizer.fold_value(&binary(IRValue::Bool(false), IRBinaryOperator::And, call.clone())),
+            IRValue::Bool(false)
+        );
+        assert_eq!(
+            optimizer.fold_value(&binary(IRValue::Bool(true), IRBinaryOperator::And, call.clone())),
+            binary(IRValue::Bool(true), IRBinaryOperator::And, call)
+        );
+    }
+
+    fn add(left: &str, right: &str) -> IRValue {
+        IRValue::BinaryOp {
+            left: Box::new(IRValue::Variable(left.to_string())),
+            op: IRBinaryOperator::Add,
+            right: Box::new(IRValue::Variable(right.to_string())),
+        }
+    }
+
+    #[test]
+    fn test_common_subexpression_elimination() {
+        let ir = optimize("fn f(a: int, b: int) -> int { let x = a + b; let y = a + b; return x * y; }");
+        assert_eq!(
+            ir.functions[0].blocks[0].instructions,
+            vec![
+                IRInstruction::Let { name: "x".to_string(), value: add("a", "b") },
+                IRInstruction::Let { name: "y".to_string(), value: IRValue::Variable("x".to_string()) },
+                IRInstruction::Return(Some(IRValue::BinaryOp {
+                    left: Box::new(IRValue::Variable("x".to_string())),
+                    op: IRBinaryOperator::Mul,
+                    right: Box::new(IRValue::Variable("x".to_string())),
+                })),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_common_subexpression_is_recomputed_after_operand_changes() {
+        let mut compiler = Compiler::new();
+        compiler.parse("fn f(a: int, b: int) -> int { let x = a + b; let y = a + b; return y; }").unwrap();
+        let mut ir = compiler.compile_program().unwrap();
+        ir.functions[0].blocks[0].instructions.insert(
+            1,
+            IRInstruction::Assignment { target: "a".to_string(), value: IRValue::Int(1) },
+        );
+        Optimizer::new().optimize(&mut ir);
+        assert_eq!(
+            ir.functions[0].blocks[0].instructions[2],
+            IRInstruction::Let { name: "y".to_string(), value: IRValue::BinaryOp {
+                left: Box::new(IRValue::Int(1)),
+                op: IRBinaryOperator::Add,
+                right: Box::new(IRValue::Variable("b".to_string())),
+            } }
+        );
+    }
+
+    #[test]
+    fn test_calls_are_not_eliminated() {
+        let ir = optimize("fn f(a: int) -> int { let x = a + g(); let y = a + g(); return x + y; }");
+        assert!(matches!(
+            &ir.functions[0].blocks[0].instructions[1],
+            IRInstruction::Let { value: IRValue::BinaryOp { .. }, .. }
+        ));
+    }
+
+    #[test]
+    fn test_division_by_zero_is_not_folded() {
+        let ir = optimize("fn main() -> int { return 1 / 0; }");
+        assert!(matches!(
+            &ir.functions[0].blocks[0].instructions[0],
+            IRInstruction::Return(Some(IRValue::BinaryOp { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_binary_op_instruction_is_folded() {
+        let mut ir = IR::new();
+        ir.add_function(IRFunction {
+            name: "main".to_string(),
+            parameters: Vec::new(),
+            return_type: crate::type_system::Type::Int,
+            priorities: Vec::new(),
+            blocks: vec![IRBlock {
+                label: "entry".to_string(),
+                instructions: vec![
+                    IRInstruction::Store { name: "a".to_string(), value: IRValue::Int(6) },
+                    IRInstruction::BinaryOp {
+                        dest: "b".to_string(),
+                        op: IRBinaryOperator::Mul,
+                        left: IRValue::Variable("a".to_string()),
+                        right: IRValue::Int(7),
+                    },
+                    IRInstruction::Return(Some(IRValue::Variable("b".to_string()))),
+                ],
+            }],
+        });
+        Optimizer::new().optimize(&mut ir);
+        assert_eq!(
+            ir.functions[0].blocks[0].instructions,
+            vec![IRInstruction::Return(Some(IRValue::Int(42)))]
+        );
+    }
+}
@@ -1,50 +1,273 @@
 use crate::ast::*;
 use crate::error::{Result, SlangError};
-use crate::lexer::{Lexer, Token};
+use crate::lexer::{Lexer, StringSegment, Token};
 use crate::type_system::Type;
-use std::ops::Range;
 
 pub struct Parser<'a> {
     lexer: Lexer<'a>,
+    // `if`/`while` の条件式では `Name {` をブロックの開始として扱う
+    allow_struct_literal: bool,
+    // 解析中の構文の名前。入力が途中で終わったときのエラーで、何を読んでいたかを示す
+    context: Vec<&'static str>,
+    // 関数と文のソース上の範囲。エディタの定義へのジャンプなどに使う
+    spans: SpanMap,
 }
 
 impl<'a> Parser<'a> {
     pub fn new(lexer: Lexer<'a>) -> Self {
-        Self { lexer }
+        Self {
+            lexer,
+            allow_struct_literal: true,
+            context: Vec::new(),
+            spans: SpanMap::new(),
+        }
     }
 
+    // 関数だけを並べた `AST` として解析する。モジュールの中身は展開され、トレイトなどは含まれない
     pub fn parse(&mut self) -> Result<AST> {
-        let mut ast = AST::new();
+        self.parse_program().map(AST::from)
+    }
+
+    // ソース全体を項目 (関数・型定義・モジュール) の列として解析する
+    pub fn parse_program(&mut self) -> Result<Program> {
+        if let Some((error, span)) = self.lexer.errors().first() {
+            return Err(SlangError::Syntax(error.to_string()).with_span(span.clone()));
+        }
+        let items = self.parse_items(false)?;
+        Ok(Program { items })
+    }
+
+    // `in_module` なら `}` の手前まで、そうでなければ入力の終わりまで項目を読む
+    fn parse_items(&mut self, in_module: bool) -> Result<Vec<Item>> {
+        let mut items = Vec::new();
         while let Some(token) = self.lexer.peek() {
             match token {
-                Token::Function => {
-                    let function = self.parse_function()?;
-                    ast.add_function(function);
+                Token::RBrace if in_module => break,
+                Token::Function | Token::FunctionTypePriority => {
+                    let start = self.lexer.current_span().start;
+                    let function = self.parse_prioritized_function().map_err(|e| self.attach_span(e))?;
+                    self.spans.functions.push(start..self.lexer.previous_span().end);
+                    items.push(Item::Function(function));
                 }
                 Token::Type => {
-                    let type_def = self.parse_type_definition()?;
-                    ast.add_type_definition(type_def);
+                    let type_def = self
+                        .in_context("type definition", Self::parse_type_definition)
+                        .map_err(|e| self.attach_span(e))?;
+                    items.push(Item::from(type_def));
+                }
+                Token::Struct => {
+                    let struct_ = self
+                        .in_context("struct definition", Self::parse_struct)
+                        .map_err(|e| self.attach_span(e))?;
+                    items.push(Item::Struct(struct_));
+                }
+                Token::Trait => {
+                    let trait_ = self
+                        .in_context("trait definition", Self::parse_trait)
+                        .map_err(|e| self.attach_span(e))?;
+                    items.push(Item::Trait(trait_));
+                }
+                Token::Macro => {
+                    let macro_ = self.in_context("macro definition", Self::parse_macro).map_err(|e| self.attach_span(e))?;
+                    items.push(Item::Macro(macro_));
+                }
+                Token::Module => {
+                    let module = self.in_context("module", Self::parse_module).map_err(|e| self.attach_span(e))?;
+                    items.push(Item::Module(module));
+                }
+                _ => {
+                    let error = SlangError::Syntax(format!("Unexpected token: {:?}", token));
+                    return Err(self.attach_span(error));
                 }
-                _ => return Err(SlangError::Syntax(format!("Unexpected token: {:?}", token))),
             }
         }
-        Ok(ast)
+        Ok(items)
     }
 
-    fn parse_function(&mut self) -> Result<Function> {
-        self.expect(Token::Function)?;
+    // `struct 名前<T> { フィールド: 型 @優先所有格, ... }`。優先所有格は省略できる
+    fn parse_struct(&mut self) -> Result<Struct> {
+        self.expect(Token::Struct)?;
+        let name = self.parse_identifier()?;
+        let type_params = self.parse_type_params()?;
+        self.expect(Token::LBrace)?;
+        let mut fields = Vec::new();
+        while self.lexer.peek() != Some(&Token::RBrace) {
+            let name = self.parse_identifier()?;
+            self.expect(Token::Colon)?;
+            let type_ = self.parse_type()?;
+            let priority = self.parse_field_priority()?;
+            fields.push(StructField { name, type_, priority });
+            match self.lexer.peek() {
+                Some(Token::Comma) => {
+                    self.lexer.next();
+                }
+                Some(Token::RBrace) => break,
+                Some(token) => return Err(SlangError::Syntax(format!("Expected ',' or '}}', got {:?}", token))),
+                None => return Err(self.unexpected_eof("`,` or `}`")),
+            }
+        }
+        self.expect(Token::RBrace)?;
+        Ok(Struct { name, type_params, fields })
+    }
+
+    // `@3` / `@most_high` / `@[3, 1]` (多段階)。省略された場合は `None`
+    fn parse_field_priority(&mut self) -> Result<Option<MemoryPriority>> {
+        if self.lexer.peek() != Some(&Token::At) {
+            return Ok(None);
+        }
+        self.lexer.next();
+        let priority = match self.lexer.peek() {
+            Some(Token::LBracket) => {
+                self.lexer.next();
+                let priority = self.parse_memory_priority()?;
+                self.expect(Token::RBracket)?;
+                priority
+            }
+            Some(Token::MostHigh | Token::MostLow) => self.parse_memory_priority()?,
+            _ => MemoryPriority::Level(self.parse_priority_level()?),
+        };
+        Ok(Some(priority))
+    }
+
+    // `trait 名前 { fn メソッド(仮引数) -> 型; ... }`。メソッドは本体を持たない
+    fn parse_trait(&mut self) -> Result<Trait> {
+        self.expect(Token::Trait)?;
+        let name = self.parse_identifier()?;
+        self.expect(Token::LBrace)?;
+        let mut methods = Vec::new();
+        while self.lexer.peek() != Some(&Token::RBrace) {
+            self.expect(Token::Function)?;
+            let name = self.parse_identifier()?;
+            let params = self.parse_parameters()?;
+            self.expect(Token::Arrow)?;
+            let return_type = self.parse_type()?;
+            self.expect(Token::Semicolon)?;
+            methods.push(TraitMethod { name, params, return_type });
+        }
+        self.expect(Token::RBrace)?;
+        Ok(Trait { name, methods })
+    }
+
+    // `macro 名前(パターン, ...) { 本体 }`。呼び出し側の `名前!(引数, ...)` は `expand_macros` が展開する
+    fn parse_macro(&mut self) -> Result<Macro> {
+        self.expect(Token::Macro)?;
+        let name = self.parse_identifier()?;
+        self.expect(Token::LParen)?;
+        let mut patterns = Vec::new();
+        while self.lexer.peek() != Some(&Token::RParen) {
+            patterns.push(MacroPattern { pattern: self.parse_pattern()?, guard: None });
+            match self.lexer.peek() {
+                Some(Token::Comma) => {
+                    self.lexer.next();
+                }
+                Some(Token::RParen) => break,
+                Some(token) => return Err(SlangError::Syntax(format!("Expected ',' or ')', got {:?}", token))),
+                None => return Err(self.unexpected_eof("`,` or `)`")),
+            }
+        }
+        self.expect(Token::RParen)?;
+        // 本体は展開後の関数の中にしか現れず、`AST` の文として `Visitor` が訪れないので範囲を記録しない
+        let statement_count = self.spans.statements.len();
+        let body = self.parse_block()?;
+        self.spans.statements.truncate(statement_count);
+        Ok(Macro { name, patterns, body })
+    }
+
+    // `module 名前 { 項目 ... }`
+    fn parse_module(&mut self) -> Result<Module> {
+        self.expect(Token::Module)?;
         let name = self.parse_identifier()?;
+        self.expect(Token::LBrace)?;
+        let items = self.parse_items(true)?;
+        self.expect(Token::RBrace)?;
+        Ok(Module { name, items })
+    }
+
+    // `parse` で読んだ関数と文の範囲
+    pub fn spans(&self) -> &SpanMap {
+        &self.spans
+    }
+
+    // 関数の外にある式 1 つだけのソースを解析する (デバッガの条件式など)
+    pub fn parse_standalone_expression(&mut self) -> Result<Expression> {
+        if let Some((error, span)) = self.lexer.errors().first() {
+            return Err(SlangError::Syntax(error.to_string()).with_span(span.clone()));
+        }
+        let expression = self
+            .in_context("expression", Self::parse_expression)
+            .map_err(|e| self.attach_span(e))?;
+        if let Some(token) = self.lexer.peek() {
+            let error = SlangError::Syntax(format!("Unexpected token after expression: {:?}", token));
+            return Err(self.attach_span(error));
+        }
+        Ok(expression)
+    }
+
+    // 解析が止まった位置 (次に読むはずだったトークン) をエラーに付ける
+    fn attach_span(&self, error: SlangError) -> SlangError {
+        error.with_span(self.lexer.current_span())
+    }
+
+    // `parse` の中で失敗したときに、今の解析の文脈を `parse` の後に戻す
+    fn in_context<T>(&mut self, context: &'static str, parse: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        self.context.push(context);
+        let result = parse(self);
+        self.context.pop();
+        result
+    }
+
+    // 入力が途中で終わった場合のエラー。`Expected X, got None` の代わりに、解析中の構文と期待したものを示す
+    fn unexpected_eof(&self, expected: &str) -> SlangError {
+        let context = self.context.last().copied().unwrap_or("program");
+        SlangError::Syntax(format!("Unexpected end of input while parsing {}; expected {}", context, expected))
+    }
+
+    fn parse_function(&mut self) -> Result<Function> {
+        let (name, type_params, parameters, return_type, priorities) =
+            self.in_context("function signature", |parser| {
+                parser.expect(Token::Function)?;
+                let name = parser.parse_identifier()?;
+                let type_params = parser.parse_type_params()?;
+                let parameters = parser.parse_parameters()?;
+                // `-> type` を省略した関数は値を返さない
+                let return_type = if let Some(Token::Arrow) = parser.lexer.peek() {
+                    parser.lexer.next();
+                    parser.parse_type()?
+                } else {
+                    Type::Void
+                };
+                let priorities = parser.parse_priorities()?;
+                Ok((name, type_params, parameters, return_type, priorities))
+            })?;
+        let body = self.in_context("function body", Self::parse_block)?;
+        Ok(Function {
+            name,
+            type_params,
+            parameters,
+            return_type,
+            priorities,
+            body,
+        })
+    }
+
+    // `(name: type, mut name: type, ...)` の仮引数の並び
+    fn parse_parameters(&mut self) -> Result<Vec<Parameter>> {
         self.expect(Token::LParen)?;
         let mut parameters = Vec::new();
         if let Some(token) = self.lexer.peek() {
             if token != &Token::RParen {
                 loop {
+                    let mutable = self.lexer.peek() == Some(&Token::Mut);
+                    if mutable {
+                        self.lexer.next();
+                    }
                     let param_name = self.parse_identifier()?;
                     self.expect(Token::Colon)?;
                     let param_type = self.parse_type()?;
                     parameters.push(Parameter {
                         name: param_name,
                         type_annotation: param_type,
+                        mutable,
                     });
                     if let Some(token) = self.lexer.peek() {
                         if token == &Token::RParen {
@@ -52,84 +275,372 @@ impl<'a> Parser<'a> {
                         }
                         self.expect(Token::Comma)?;
                     } else {
-                        return Err(SlangError::Syntax("Expected ')' or ','".to_string()));
+                        return Err(self.unexpected_eof("`)` or `,`"));
                     }
                 }
             }
         }
         self.expect(Token::RParen)?;
-        self.expect(Token::Arrow)?;
-        let return_type = self.parse_type()?;
-        let priority = if let Some(Token::Priority) = self.lexer.peek() {
+        Ok(parameters)
+    }
+
+    // 式の位置の `fn(x: int) -> int { ... }`。`-> type` は省略できる
+    fn parse_lambda(&mut self) -> Result<Expression> {
+        self.expect(Token::Function)?;
+        let params = self.parse_parameters()?;
+        let return_type = if let Some(Token::Arrow) = self.lexer.peek() {
             self.lexer.next();
-            self.parse_integer()?
+            Some(self.parse_type()?)
         } else {
-            0
+            None
         };
-        let body = self.parse_block()?;
-        Ok(Function {
-            name,
-            parameters,
-            return_type,
-            priority,
-            body,
-        })
+        // 本体の中では外側の文脈 (`if` の条件など) の制限を受けない
+        let allow_struct_literal = std::mem::replace(&mut self.allow_struct_literal, true);
+        let body = self.parse_block();
+        self.allow_struct_literal = allow_struct_literal;
+        Ok(Expression::Lambda { params, return_type, body: body? })
+    }
+
+    // `Function:type:priority: 2, 1 fn ...` の形。優先度は整数の列だけを受け付け、
+    // 後ろの `priority` 節と同時には指定できない
+    fn parse_prioritized_function(&mut self) -> Result<Function> {
+        if self.lexer.peek() != Some(&Token::FunctionTypePriority) {
+            return self.parse_function();
+        }
+        self.lexer.next();
+        let priorities = match self.parse_memory_priority()? {
+            MemoryPriority::Level(level) => vec![level],
+            MemoryPriority::MultiLevel(levels) => levels,
+            priority => {
+                return Err(SlangError::Syntax(format!(
+                    "Function priority must be a list of integers, got {}",
+                    priority
+                )))
+            }
+        };
+        let mut function = self.parse_function()?;
+        if !function.priorities.is_empty() {
+            return Err(SlangError::Syntax(format!(
+                "Priority of function {} is specified twice",
+                function.name
+            )));
+        }
+        function.priorities = priorities;
+        Ok(function)
+    }
+
+    // 優先所有格の値: `3` / `3, 1` (多段階) / `most_high` / `most_low`
+    fn parse_memory_priority(&mut self) -> Result<MemoryPriority> {
+        match self.lexer.peek() {
+            Some(Token::MostHigh) => {
+                self.lexer.next();
+                Ok(MemoryPriority::MostHigh)
+            }
+            Some(Token::MostLow) => {
+                self.lexer.next();
+                Ok(MemoryPriority::MostLow)
+            }
+            _ => {
+                let mut levels = vec![self.parse_priority_level()?];
+                while let Some(Token::Comma) = self.lexer.peek() {
+                    self.lexer.next();
+                    levels.push(self.parse_priority_level()?);
+                }
+                if levels.len() == 1 {
+                    Ok(MemoryPriority::Level(levels[0]))
+                } else {
+                    Ok(MemoryPriority::MultiLevel(levels))
+                }
+            }
+        }
+    }
+
+    // `priority 2, 1` を読む。省略された場合は空
+    fn parse_priorities(&mut self) -> Result<Vec<i32>> {
+        let mut priorities = Vec::new();
+        if let Some(Token::Priority) = self.lexer.peek() {
+            self.lexer.next();
+            priorities.push(self.parse_priority_level()?);
+            while let Some(Token::Comma) = self.lexer.peek() {
+                self.lexer.next();
+                priorities.push(self.parse_priority_level()?);
+            }
+        }
+        Ok(priorities)
+    }
+
+    // `<T, U>` を読む。省略された場合は空
+    fn parse_type_params(&mut self) -> Result<Vec<String>> {
+        let mut type_params = Vec::new();
+        if let Some(Token::LessThan) = self.lexer.peek() {
+            self.lexer.next();
+            loop {
+                type_params.push(self.parse_identifier()?);
+                match self.lexer.peek() {
+                    Some(Token::Comma) => {
+                        self.lexer.next();
+                    }
+                    Some(Token::GreaterThan) => break,
+                    _ => return Err(SlangError::Syntax("Expected '>' or ','".to_string())),
+                }
+            }
+            self.expect(Token::GreaterThan)?;
+        }
+        Ok(type_params)
     }
 
+    // `Type` の `Display` が出力する形をそのまま読める。ただし推論中の型 (`?0`) は書けず、
+    // 構造体の名前は型検査で解決される前の `Type::Named` として読む。
+    // 後ろの `?` は直前の型を `Optional` にする。`*int?` は `*(int?)` と読む
     fn parse_type(&mut self) -> Result<Type> {
+        let mut type_ = self.parse_non_optional_type()?;
+        while self.lexer.peek() == Some(&Token::Question) {
+            self.lexer.next();
+            type_ = Type::Optional(Box::new(type_));
+        }
+        Ok(type_)
+    }
+
+    fn parse_non_optional_type(&mut self) -> Result<Type> {
         match self.lexer.peek() {
             Some(Token::Identifier(name)) => {
                 let name = name.clone();
                 self.lexer.next();
-                Ok(Type::Named(name))
+                // 組み込み型の名前は `Type` の `Display` と同じ綴り
+                let type_ = match name.as_str() {
+                    "int" => Type::Int,
+                    "float" => Type::Float,
+                    "bool" => Type::Bool,
+                    "string" => Type::String,
+                    "char" => Type::Char,
+                    "void" => Type::Void,
+                    _ => return self.parse_named_type(name),
+                };
+                Ok(type_)
             }
+            // `[T]` と長さの決まった `[T; N]`
             Some(Token::LBracket) => {
                 self.lexer.next();
                 let element_type = Box::new(self.parse_type()?);
+                if self.lexer.peek() == Some(&Token::Semicolon) {
+                    self.lexer.next();
+                    let len = self.parse_integer()?;
+                    let len = usize::try_from(len)
+                        .map_err(|_| SlangError::Syntax(format!("Array length must not be negative, got {}", len)))?;
+                    self.expect(Token::RBracket)?;
+                    return Ok(Type::FixedArray(element_type, len));
+                }
                 self.expect(Token::RBracket)?;
                 Ok(Type::Array(element_type))
             }
             Some(Token::LParen) => {
                 self.lexer.next();
-                let mut types = Vec::new();
-                if let Some(token) = self.lexer.peek() {
-                    if token != &Token::RParen {
-                        loop {
-                            types.push(self.parse_type()?);
-                            if let Some(token) = self.lexer.peek() {
-                                if token == &Token::RParen {
-                                    break;
-                                }
-                                self.expect(Token::Comma)?;
-                            } else {
-                                return Err(SlangError::Syntax("Expected ')' or ','".to_string()));
-                            }
+                let types = self.parse_type_list()?;
+                // `()` は空のタプルではなくユニット型
+                if types.is_empty() {
+                    Ok(Type::Unit)
+                } else {
+                    Ok(Type::Tuple(types))
+                }
+            }
+            Some(Token::Star) => {
+                self.lexer.next();
+                Ok(Type::Pointer(Box::new(self.parse_type()?)))
+            }
+            // `fn(int) -> int`。優先度は関数定義と同じ `Function:type:priority: 2 fn(...)` の形で書く
+            Some(Token::Function) => self.parse_function_type(None),
+            Some(Token::FunctionTypePriority) => {
+                self.lexer.next();
+                let priority = self.parse_integer()?;
+                let priority = u32::try_from(priority).map_err(|_| {
+                    SlangError::Syntax(format!("Function type priority {} is out of range", priority))
+                })?;
+                self.parse_function_type(Some(priority))
+            }
+            Some(token) => Err(SlangError::Syntax(format!("Unexpected token in type: {:?}", token))),
+            None => Err(self.unexpected_eof("type")),
+        }
+    }
+
+    // `vec3<float>` / `mat2x3<float>` / `tensor<2x3x4, float>` / `quat<float>` / `complex<float>`。
+    // 次元は 1 以上の整数。`tensor` などの名前も `<` が続かなければ利用者定義の型の名前として扱う
+    fn parse_named_type(&mut self, name: String) -> Result<Type> {
+        let has_argument = self.lexer.peek() == Some(&Token::LessThan);
+        if let Some(size) = name.strip_prefix("vec").filter(|rest| starts_with_digit(rest) || has_argument) {
+            let size = parse_dimension(size).ok_or_else(|| malformed_dimension(&name, "vec3<T>"))?;
+            return Ok(Type::Vector(size, Box::new(self.parse_type_argument()?)));
+        }
+        if let Some(dimensions) = name.strip_prefix("mat").filter(|rest| starts_with_digit(rest) || has_argument) {
+            let (rows, cols) = parse_matrix_dimensions(dimensions).ok_or_else(|| malformed_dimension(&name, "mat2x3<T>"))?;
+            return Ok(Type::Matrix(rows, cols, Box::new(self.parse_type_argument()?)));
+        }
+        if !has_argument {
+            return Ok(Type::Named(name));
+        }
+        match name.as_str() {
+            "quat" => Ok(Type::Quaternion(Box::new(self.parse_type_argument()?))),
+            "complex" => Ok(Type::Complex(Box::new(self.parse_type_argument()?))),
+            "tensor" => {
+                self.expect(Token::LessThan)?;
+                let dimensions = self.parse_tensor_dimensions()?;
+                self.expect(Token::Comma)?;
+                let element_type = self.parse_type()?;
+                self.expect(Token::GreaterThan)?;
+                Ok(Type::Tensor(dimensions, Box::new(element_type)))
+            }
+            _ => Err(SlangError::Syntax(format!("Type {} does not take type arguments", name))),
+        }
+    }
+
+    // `2x3x4` は字句解析で整数 `2` と識別子 `x3x4` に分かれる
+    fn parse_tensor_dimensions(&mut self) -> Result<Vec<usize>> {
+        let text = self.lexer.current_text();
+        let first = match (self.lexer.peek(), parse_dimension(text)) {
+            (Some(Token::IntegerLiteral(_)), Some(size)) => size,
+            _ => return Err(SlangError::Syntax(format!("Expected a positive tensor dimension, got {}", text))),
+        };
+        self.lexer.next();
+        let mut dimensions = vec![first];
+        if let Some(Token::Identifier(rest)) = self.lexer.peek() {
+            let rest = rest.clone();
+            self.lexer.next();
+            for dimension in rest.strip_prefix('x').unwrap_or("").split('x') {
+                dimensions.push(parse_dimension(dimension).ok_or_else(|| {
+                    SlangError::Syntax(format!("Malformed tensor dimensions: {}{}", first, rest))
+                })?);
+            }
+        }
+        Ok(dimensions)
+    }
+
+    fn parse_function_type(&mut self, priority: Option<u32>) -> Result<Type> {
+        self.expect(Token::Function)?;
+        self.expect(Token::LParen)?;
+        let params = self.parse_type_list()?;
+        self.expect(Token::Arrow)?;
+        let return_type = Box::new(self.parse_type()?);
+        Ok(Type::Function { params, return_type, priority })
+    }
+
+    // `(` の後ろから `)` までのカンマ区切りの型を読む
+    fn parse_type_list(&mut self) -> Result<Vec<Type>> {
+        let mut types = Vec::new();
+        if let Some(token) = self.lexer.peek() {
+            if token != &Token::RParen {
+                loop {
+                    types.push(self.parse_type()?);
+                    if let Some(token) = self.lexer.peek() {
+                        if token == &Token::RParen {
+                            break;
                         }
+                        self.expect(Token::Comma)?;
+                    } else {
+                        return Err(self.unexpected_eof("`)` or `,`"));
                     }
                 }
-                self.expect(Token::RParen)?;
-                Ok(Type::Tuple(types))
             }
-            _ => Err(SlangError::Syntax(format!("Unexpected token in type: {:?}", self.lexer.peek()))),
         }
+        self.expect(Token::RParen)?;
+        Ok(types)
+    }
+
+    // `vec3<float>` の `<float>`
+    fn parse_type_argument(&mut self) -> Result<Type> {
+        self.expect(Token::LessThan)?;
+        let type_ = self.parse_type()?;
+        self.expect(Token::GreaterThan)?;
+        Ok(type_)
     }
 
     fn parse_type_definition(&mut self) -> Result<TypeDefinition> {
         self.expect(Token::Type)?;
         let name = self.parse_identifier()?;
-        self.expect(Token::Equals)?;
+        self.expect(Token::Assign)?;
         let fields = vec![]; // TODO: parse fields properly
         self.expect(Token::Semicolon)?;
         Ok(TypeDefinition { name, fields })
     }
 
+    // `match 式 { パターン => { .. } ... }`。腕の間の `,` は省略できる
+    fn parse_match_statement(&mut self) -> Result<MatchStatement> {
+        self.expect(Token::Match)?;
+        let expression = Box::new(self.parse_condition()?);
+        self.expect(Token::LBrace)?;
+        let mut arms = Vec::new();
+        loop {
+            match self.lexer.peek() {
+                Some(Token::RBrace) => break,
+                Some(_) => {}
+                None => return Err(self.unexpected_eof("`}`")),
+            }
+            let pattern = self.parse_pattern()?;
+            self.expect(Token::FatArrow)?;
+            let body = self.parse_block()?;
+            arms.push(MatchArm { pattern, body });
+            if self.lexer.peek() == Some(&Token::Comma) {
+                self.lexer.next();
+            }
+        }
+        self.expect(Token::RBrace)?;
+        Ok(MatchStatement { expression, arms })
+    }
+
     fn parse_pattern(&mut self) -> Result<Pattern> {
         match self.lexer.peek() {
+            // `Point { x: 0, y }`。`y` は `y: y` の省略形
+            Some(Token::Identifier(_)) if self.lexer.peek_nth(1) == Some(&Token::LBrace) => {
+                let name = self.parse_identifier()?;
+                self.lexer.next();
+                let mut fields = Vec::new();
+                while self.lexer.peek() != Some(&Token::RBrace) {
+                    let field = self.parse_identifier()?;
+                    let pattern = if self.lexer.peek() == Some(&Token::Colon) {
+                        self.lexer.next();
+                        self.parse_pattern()?
+                    } else {
+                        Pattern::Identifier(field.clone())
+                    };
+                    fields.push(FieldPattern { name: field, pattern: Box::new(pattern) });
+                    if self.lexer.peek() != Some(&Token::RBrace) {
+                        self.expect(Token::Comma)?;
+                    }
+                }
+                self.expect(Token::RBrace)?;
+                Ok(Pattern::Struct { name, fields })
+            }
             Some(Token::Identifier(name)) => {
                 let name = name.clone();
                 self.lexer.next();
                 Ok(Pattern::Identifier(name))
             }
+            Some(Token::IntegerLiteral(value)) => {
+                let value = *value;
+                self.lexer.next();
+                Ok(Pattern::Literal(Literal::Int(value)))
+            }
+            Some(Token::Minus) if matches!(self.lexer.peek_nth(1), Some(Token::IntegerLiteral(_))) => {
+                self.lexer.next();
+                let Some(Token::IntegerLiteral(value)) = self.lexer.next() else { unreachable!() };
+                Ok(Pattern::Literal(Literal::Int(-value)))
+            }
+            Some(Token::StringLiteral(value)) => {
+                let value = value.clone();
+                self.lexer.next();
+                Ok(Pattern::Literal(Literal::String(value)))
+            }
+            Some(Token::CharLiteral(value)) => {
+                let value = *value;
+                self.lexer.next();
+                Ok(Pattern::Literal(Literal::Char(value)))
+            }
+            Some(Token::True) | Some(Token::False) => {
+                let value = self.lexer.next() == Some(&Token::True);
+                Ok(Pattern::Literal(Literal::Bool(value)))
+            }
+            Some(Token::Null) => {
+                self.lexer.next();
+                Ok(Pattern::Literal(Literal::Null))
+            }
             Some(Token::Underscore) => {
                 self.lexer.next();
                 Ok(Pattern::Wildcard)
@@ -147,7 +658,7 @@ impl<'a> Parser<'a> {
                                 }
                                 self.expect(Token::Comma)?;
                             } else {
-                                return Err(SlangError::Syntax("Expected ')' or ','".to_string()));
+                                return Err(self.unexpected_eof("`)` or `,`"));
                             }
                         }
                     }
@@ -155,7 +666,8 @@ impl<'a> Parser<'a> {
                 self.expect(Token::RParen)?;
                 Ok(Pattern::Tuple(patterns))
             }
-            _ => Err(SlangError::Syntax(format!("Unexpected token in pattern: {:?}", self.lexer.peek()))),
+            Some(token) => Err(SlangError::Syntax(format!("Unexpected token in pattern: {:?}", token))),
+            None => Err(self.unexpected_eof("pattern")),
         }
     }
 
@@ -166,41 +678,28 @@ impl<'a> Parser<'a> {
                 self.lexer.next();
                 Ok(name)
             }
-            _ => Err(SlangError::Syntax(format!("Expected identifier, got {:?}", self.lexer.peek()))),
-        }
-    }
-
-    fn parse_string(&mut self) -> Result<String> {
-        match self.lexer.peek() {
-            Some(Token::StringLiteral(value)) => {
-                let value = value.clone();
-                self.lexer.next();
-                Ok(value)
-            }
-            _ => Err(SlangError::Syntax(format!("Expected string literal, got {:?}", self.lexer.peek()))),
+            Some(token) => Err(SlangError::Syntax(format!("Expected identifier, got {:?}", token))),
+            None => Err(self.unexpected_eof("identifier")),
         }
     }
 
-    fn parse_integer(&mut self) -> Result<i32> {
+    // 整数リテラルの値をそのまま返す。`i32` などへの変換と範囲の検査は呼び出し側で行う
+    fn parse_integer(&mut self) -> Result<i64> {
         match self.lexer.peek() {
             Some(Token::IntegerLiteral(value)) => {
                 let value = *value;
                 self.lexer.next();
-                Ok(value as i32)
+                Ok(value)
             }
-            _ => Err(SlangError::Syntax(format!("Expected integer literal, got {:?}", self.lexer.peek()))),
+            Some(token) => Err(SlangError::Syntax(format!("Expected integer literal, got {:?}", token))),
+            None => Err(self.unexpected_eof("integer literal")),
         }
     }
 
-    fn parse_float(&mut self) -> Result<f64> {
-        match self.lexer.peek() {
-            Some(Token::FloatLiteral(value)) => {
-                let value = *value;
-                self.lexer.next();
-                Ok(value)
-            }
-            _ => Err(SlangError::Syntax(format!("Expected float literal, got {:?}", self.lexer.peek()))),
-        }
+    // 優先度の値。`i32` に収まらなければエラー
+    fn parse_priority_level(&mut self) -> Result<i32> {
+        let value = self.parse_integer()?;
+        i32::try_from(value).map_err(|_| SlangError::Syntax(format!("Priority {} is out of range", value)))
     }
 
     fn parse_block(&mut self) -> Result<Block> {
@@ -217,9 +716,101 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_statement(&mut self) -> Result<Statement> {
+        let context = match self.lexer.peek() {
+            Some(Token::If) if self.lexer.peek_nth(1) == Some(&Token::Let) => "`if let` statement",
+            Some(Token::If) => "`if` statement",
+            Some(Token::While) if self.lexer.peek_nth(1) == Some(&Token::Let) => "`while let` loop",
+            Some(Token::While) => "`while` loop",
+            Some(Token::For) => "`for` loop",
+            Some(Token::Let) | Some(Token::VarTypePriority) => "let statement",
+            Some(Token::Return) => "return statement",
+            Some(Token::Transfer) => "transfer statement",
+            Some(Token::Match) => "`match` statement",
+            _ => "statement",
+        };
+        // 中の文より先に番号を取り、`Visitor` が訪れる順 (外側が先) に並べる
+        let index = self.spans.statements.len();
+        let start = self.lexer.current_span().start;
+        self.spans.statements.push(start..start);
+        let statement = self.in_context(context, Self::parse_statement_kind)?;
+        self.spans.statements[index].end = self.lexer.previous_span().end;
+        Ok(statement)
+    }
+
+    fn parse_statement_kind(&mut self) -> Result<Statement> {
         match self.lexer.peek() {
+            Some(Token::If) => self.parse_if_statement(),
+            Some(Token::While) => {
+                self.lexer.next();
+                if let Some(pattern) = self.parse_let_pattern()? {
+                    let expression = Box::new(self.parse_condition()?);
+                    let body = self.parse_block()?;
+                    return Ok(Statement::WhileLet(WhileLetStatement { pattern, expression, body }));
+                }
+                let condition = Box::new(self.parse_condition()?);
+                let body = self.parse_block()?;
+                Ok(Statement::While(WhileStatement { condition, body }))
+            }
+            Some(Token::For) => {
+                self.lexer.next();
+                // `for (i in 0..5) { .. }` のように `変数 in 式` を括弧で囲んでもよい
+                let parenthesized = self.lexer.peek() == Some(&Token::LParen)
+                    && matches!(self.lexer.peek_nth(1), Some(Token::Identifier(_)))
+                    && self.lexer.peek_nth(2) == Some(&Token::In);
+                if parenthesized {
+                    self.lexer.next();
+                }
+                let variable = self.parse_identifier()?;
+                self.expect(Token::In)?;
+                let iterator = Box::new(self.parse_condition()?);
+                if parenthesized {
+                    self.expect(Token::RParen)?;
+                }
+                let body = self.parse_block()?;
+                Ok(Statement::For(ForStatement {
+                    variable,
+                    iterator,
+                    body,
+                }))
+            }
+            Some(Token::Match) => Ok(Statement::Match(self.parse_match_statement()?)),
+            Some(Token::Break) => {
+                self.lexer.next();
+                self.expect(Token::Semicolon)?;
+                Ok(Statement::Break)
+            }
+            Some(Token::Continue) => {
+                self.lexer.next();
+                self.expect(Token::Semicolon)?;
+                Ok(Statement::Continue)
+            }
+            Some(Token::Transfer) => {
+                self.lexer.next();
+                let from = self.parse_identifier()?;
+                self.expect(Token::Arrow)?;
+                let to = self.parse_identifier()?;
+                self.expect(Token::Semicolon)?;
+                Ok(Statement::Transfer(TransferStatement { from, to }))
+            }
+            Some(Token::VarTypePriority) => {
+                self.lexer.next();
+                let priority = self.parse_memory_priority()?;
+                match self.parse_statement_kind()? {
+                    Statement::Let(mut stmt) => {
+                        stmt.priority = Some(priority);
+                        Ok(Statement::Let(stmt))
+                    }
+                    _ => Err(SlangError::Syntax(
+                        "`Var:type:priority:` must be followed by a let statement".to_string(),
+                    )),
+                }
+            }
             Some(Token::Let) => {
                 self.lexer.next();
+                let mutable = self.lexer.peek() == Some(&Token::Mut);
+                if mutable {
+                    self.lexer.next();
+                }
                 let name = self.parse_identifier()?;
                 let type_annotation = if let Some(Token::Colon) = self.lexer.peek() {
                     self.lexer.next();
@@ -227,13 +818,15 @@ impl<'a> Parser<'a> {
                 } else {
                     None
                 };
-                self.expect(Token::Equals)?;
+                self.expect(Token::Assign)?;
                 let value = Box::new(self.parse_expression()?);
                 self.expect(Token::Semicolon)?;
                 Ok(Statement::Let(LetStatement {
                     name,
+                    mutable,
                     type_annotation,
                     value,
+                    priority: None,
                 }))
             }
             Some(Token::Return) => {
@@ -258,34 +851,276 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn parse_expression(&mut self) -> Result<Expression> {
-        match self.lexer.peek() {
+    // `if 条件 { .. }` か `if let パターン = 式 { .. }`。`else if` は else 側のブロックに入れ子にする
+    fn parse_if_statement(&mut self) -> Result<Statement> {
+        self.expect(Token::If)?;
+        let pattern = self.parse_let_pattern()?;
+        let condition = Box::new(self.parse_condition()?);
+        let then_block = self.parse_block()?;
+        let else_block = if let Some(Token::Else) = self.lexer.peek() {
+            self.lexer.next();
+            if let Some(Token::If) = self.lexer.peek() {
+                let else_if = self.parse_statement()?;
+                Some(Block {
+                    statements: vec![else_if],
+                })
+            } else {
+                Some(self.parse_block()?)
+            }
+        } else {
+            None
+        };
+        Ok(match pattern {
+            Some(pattern) => Statement::IfLet(IfLetStatement {
+                pattern,
+                expression: condition,
+                then_block,
+                else_block,
+            }),
+            None => Statement::If(IfStatement {
+                condition,
+                then_block,
+                else_block,
+            }),
+        })
+    }
+
+    // `if let` / `while let` の `let パターン =` の部分。`let` が続かなければ `None`
+    fn parse_let_pattern(&mut self) -> Result<Option<Pattern>> {
+        if self.lexer.peek() != Some(&Token::Let) {
+            return Ok(None);
+        }
+        self.lexer.next();
+        let pattern = self.parse_pattern()?;
+        self.expect(Token::Assign)?;
+        Ok(Some(pattern))
+    }
+
+    fn parse_condition(&mut self) -> Result<Expression> {
+        let allow_struct_literal = std::mem::replace(&mut self.allow_struct_literal, false);
+        let condition = self.parse_expression();
+        self.allow_struct_literal = allow_struct_literal;
+        condition
+    }
+
+    // 括弧の内側では条件式の中でも構造体リテラルを許可する
+    fn parse_nested_expression(&mut self) -> Result<Expression> {
+        let allow_struct_literal = std::mem::replace(&mut self.allow_struct_literal, true);
+        let expression = self.parse_expression();
+        self.allow_struct_literal = allow_struct_literal;
+        expression
+    }
+
+    fn parse_expression(&mut self) -> Result<Expression> {
+        self.parse_assignment()
+    }
+
+    // `x = value` は最も結合が弱く、右結合 (`a = b = 1` は `a = (b = 1)`)。
+    // 代入先は変数名だけなので、`=` (`Token::Assign`) が続く識別子だけを代入先として読む
+    fn parse_assignment(&mut self) -> Result<Expression> {
+        if let (Some(Token::Identifier(target)), Some(Token::Assign)) = (self.lexer.peek(), self.lexer.peek_nth(1)) {
+            let target = target.clone();
+            self.lexer.next();
+            self.lexer.next();
+            let value = Box::new(self.parse_assignment()?);
+            return Ok(Expression::Assignment(Box::new(AssignmentExpression { target, value })));
+        }
+        self.parse_range()
+    }
+
+    // 範囲 `a..b` は `||` より結合が弱く、連結できない
+    fn parse_range(&mut self) -> Result<Expression> {
+        let start = self.parse_logical_or()?;
+        let inclusive = match self.lexer.peek() {
+            Some(Token::DotDot) => false,
+            Some(Token::DotDotEq) => true,
+            _ => return Ok(start),
+        };
+        self.lexer.next();
+        let end = self.parse_logical_or()?;
+        Ok(Expression::Range {
+            start: Box::new(start),
+            end: Box::new(end),
+            inclusive,
+        })
+    }
+
+    fn parse_logical_or(&mut self) -> Result<Expression> {
+        let mut left = self.parse_logical_and()?;
+        while let Some(Token::Or) = self.lexer.peek() {
+            self.lexer.next();
+            let right = self.parse_logical_and()?;
+            left = Self::binary(left, BinaryOperator::Or, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_logical_and(&mut self) -> Result<Expression> {
+        let mut left = self.parse_equality()?;
+        while let Some(Token::And) = self.lexer.peek() {
+            self.lexer.next();
+            let right = self.parse_equality()?;
+            left = Self::binary(left, BinaryOperator::And, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_equality(&mut self) -> Result<Expression> {
+        let mut left = self.parse_comparison()?;
+        loop {
+            let op = match self.lexer.peek() {
+                Some(Token::Equals) => BinaryOperator::Equals,
+                Some(Token::NotEquals) => BinaryOperator::NotEquals,
+                _ => break,
+            };
+            self.lexer.next();
+            let right = self.parse_comparison()?;
+            left = Self::binary(left, op, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expression> {
+        let mut left = self.parse_term()?;
+        loop {
+            let op = match self.lexer.peek() {
+                Some(Token::LessThan) => BinaryOperator::LessThan,
+                Some(Token::GreaterThan) => BinaryOperator::GreaterThan,
+                Some(Token::LessThanEquals) => BinaryOperator::LessThanEquals,
+                Some(Token::GreaterThanEquals) => BinaryOperator::GreaterThanEquals,
+                _ => break,
+            };
+            self.lexer.next();
+            let right = self.parse_term()?;
+            left = Self::binary(left, op, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_term(&mut self) -> Result<Expression> {
+        let mut left = self.parse_factor()?;
+        loop {
+            let op = match self.lexer.peek() {
+                Some(Token::Plus) => BinaryOperator::Add,
+                Some(Token::Minus) => BinaryOperator::Sub,
+                _ => break,
+            };
+            self.lexer.next();
+            let right = self.parse_factor()?;
+            left = Self::binary(left, op, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_factor(&mut self) -> Result<Expression> {
+        let mut left = self.parse_cast()?;
+        loop {
+            let op = match self.lexer.peek() {
+                Some(Token::Star) => BinaryOperator::Mul,
+                Some(Token::Slash) => BinaryOperator::Div,
+                Some(Token::Percent) => BinaryOperator::Mod,
+                _ => break,
+            };
+            self.lexer.next();
+            let right = self.parse_cast()?;
+            left = Self::binary(left, op, right);
+        }
+        Ok(left)
+    }
+
+    // `-x as float` は `(-x) as float`、`a * b as float` は `a * (b as float)` と読む
+    fn parse_cast(&mut self) -> Result<Expression> {
+        let mut expr = self.parse_unary()?;
+        while self.lexer.peek() == Some(&Token::As) {
+            self.lexer.next();
+            let target = self.parse_type()?;
+            expr = Expression::Cast { expr: Box::new(expr), target };
+        }
+        Ok(expr)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expression> {
+        let op = match self.lexer.peek() {
+            Some(Token::Minus) => UnaryOperator::Negate,
+            Some(Token::Not) => UnaryOperator::Not,
+            _ => return self.parse_postfix(),
+        };
+        self.lexer.next();
+        let expr = self.parse_unary()?;
+        Ok(Expression::UnaryOp(Box::new(UnaryOpExpression {
+            op,
+            expr: Box::new(expr),
+        })))
+    }
+
+    fn parse_postfix(&mut self) -> Result<Expression> {
+        let mut expression = self.parse_primary()?;
+        loop {
+            match self.lexer.peek() {
+                Some(Token::LBracket) => {
+                    self.lexer.next();
+                    let index = self.parse_nested_expression()?;
+                    self.expect(Token::RBracket)?;
+                    expression = Expression::Index {
+                        array: Box::new(expression),
+                        index: Box::new(index),
+                    };
+                }
+                Some(Token::Dot) => {
+                    self.lexer.next();
+                    let field = self.parse_identifier()?;
+                    expression = if let Some(Token::LParen) = self.lexer.peek() {
+                        self.lexer.next();
+                        let arguments = self.parse_expression_list(Token::RParen)?;
+                        Expression::MethodCall {
+                            receiver: Box::new(expression),
+                            method: field,
+                            arguments,
+                        }
+                    } else {
+                        Expression::FieldAccess {
+                            base: Box::new(expression),
+                            field,
+                        }
+                    };
+                }
+                _ => break,
+            }
+        }
+        Ok(expression)
+    }
+
+    fn binary(left: Expression, op: BinaryOperator, right: Expression) -> Expression {
+        Expression::BinaryOp(Box::new(BinaryOpExpression {
+            left: Box::new(left),
+            op,
+            right: Box::new(right),
+        }))
+    }
+
+    fn parse_primary(&mut self) -> Result<Expression> {
+        match self.lexer.peek() {
             Some(Token::Identifier(name)) => {
                 let name = name.clone();
                 self.lexer.next();
                 if let Some(Token::LParen) = self.lexer.peek() {
                     self.lexer.next();
-                    let mut arguments = Vec::new();
-                    if let Some(token) = self.lexer.peek() {
-                        if token != &Token::RParen {
-                            loop {
-                                arguments.push(Box::new(self.parse_expression()?));
-                                if let Some(token) = self.lexer.peek() {
-                                    if token == &Token::RParen {
-                                        break;
-                                    }
-                                    self.expect(Token::Comma)?;
-                                } else {
-                                    return Err(SlangError::Syntax("Expected ')' or ','".to_string()));
-                                }
-                            }
-                        }
-                    }
-                    self.expect(Token::RParen)?;
+                    let arguments = self.parse_expression_list(Token::RParen)?;
                     Ok(Expression::Call(Box::new(CallExpression {
                         function: name,
                         arguments,
                     })))
+                } else if self.lexer.peek() == Some(&Token::Not) && self.lexer.peek_nth(1) == Some(&Token::LParen) {
+                    // マクロの呼び出しは名前の末尾に `!` を付けた関数呼び出しとして表す
+                    self.lexer.next();
+                    self.lexer.next();
+                    let arguments = self.parse_expression_list(Token::RParen)?;
+                    Ok(Expression::Call(Box::new(CallExpression {
+                        function: format!("{}!", name),
+                        arguments,
+                    })))
+                } else if self.allow_struct_literal && self.at_struct_literal() {
+                    self.in_context("struct literal", |parser| parser.parse_struct_literal(name))
                 } else {
                     Ok(Expression::Identifier(name))
                 }
@@ -295,6 +1130,11 @@ impl<'a> Parser<'a> {
                 self.lexer.next();
                 Ok(Expression::Literal(Literal::String(value)))
             }
+            Some(Token::InterpolatedString(segments)) => {
+                let segments = segments.clone();
+                self.lexer.next();
+                self.parse_interpolated_string(&segments)
+            }
             Some(Token::IntegerLiteral(value)) => {
                 let value = *value;
                 self.lexer.next();
@@ -305,6 +1145,16 @@ impl<'a> Parser<'a> {
                 self.lexer.next();
                 Ok(Expression::Literal(Literal::Float(value)))
             }
+            Some(Token::ImaginaryLiteral(value)) => {
+                let value = *value;
+                self.lexer.next();
+                Ok(Expression::Literal(Literal::Complex(0.0, value)))
+            }
+            Some(Token::CharLiteral(value)) => {
+                let value = *value;
+                self.lexer.next();
+                Ok(Expression::Literal(Literal::Char(value)))
+            }
             Some(Token::True) => {
                 self.lexer.next();
                 Ok(Expression::Literal(Literal::Bool(true)))
@@ -317,10 +1167,127 @@ impl<'a> Parser<'a> {
                 self.lexer.next();
                 Ok(Expression::Literal(Literal::Null))
             }
-            _ => Err(SlangError::Syntax(format!("Unexpected token in expression: {:?}", self.lexer.peek()))),
+            Some(Token::Function) => self.in_context("lambda", Self::parse_lambda),
+            Some(Token::LBracket) => {
+                self.lexer.next();
+                let elements = self.parse_expression_list(Token::RBracket)?;
+                Ok(Expression::Array(elements))
+            }
+            Some(Token::LParen) => {
+                // `(expr)` はグループ化、`(expr,)` や `(a, b)` はタプル
+                self.lexer.next();
+                if let Some(Token::RParen) = self.lexer.peek() {
+                    self.lexer.next();
+                    return Ok(Expression::Tuple(Vec::new()));
+                }
+                let first = self.parse_nested_expression()?;
+                if let Some(Token::RParen) = self.lexer.peek() {
+                    self.lexer.next();
+                    return Ok(first);
+                }
+                self.expect(Token::Comma)?;
+                let mut elements = vec![first];
+                elements.extend(self.parse_expression_list(Token::RParen)?);
+                Ok(Expression::Tuple(elements))
+            }
+            Some(token) => Err(SlangError::Syntax(format!("Unexpected token in expression: {:?}", token))),
+            None => Err(self.unexpected_eof("expression")),
         }
     }
 
+    // 構造体名の後の `{` が構造体リテラルの始まりかどうか。`{}` か `{ field:` の場合だけ構造体リテラルとみなす
+    fn at_struct_literal(&self) -> bool {
+        if self.lexer.peek() != Some(&Token::LBrace) {
+            return false;
+        }
+        match self.lexer.peek_nth(1) {
+            Some(Token::RBrace) => true,
+            Some(Token::Identifier(_)) => self.lexer.peek_nth(2) == Some(&Token::Colon),
+            _ => false,
+        }
+    }
+
+    fn parse_struct_literal(&mut self, name: String) -> Result<Expression> {
+        self.expect(Token::LBrace)?;
+        let mut fields = Vec::new();
+        while let Some(token) = self.lexer.peek() {
+            if token == &Token::RBrace {
+                break;
+            }
+            let field = self.parse_identifier()?;
+            self.expect(Token::Colon)?;
+            fields.push((field, self.parse_nested_expression()?));
+            if let Some(Token::Comma) = self.lexer.peek() {
+                self.lexer.next();
+            } else {
+                break;
+            }
+        }
+        self.expect(Token::RBrace)?;
+        Ok(Expression::StructLiteral { name, fields })
+    }
+
+    // 終端トークンまでのカンマ区切りの式を読む (末尾のカンマを許可)
+    fn parse_expression_list(&mut self, terminator: Token) -> Result<Vec<Expression>> {
+        let mut elements = Vec::new();
+        while let Some(token) = self.lexer.peek() {
+            if token == &terminator {
+                break;
+            }
+            elements.push(self.parse_nested_expression()?);
+            if let Some(Token::Comma) = self.lexer.peek() {
+                self.lexer.next();
+            } else {
+                break;
+            }
+        }
+        self.expect(terminator)?;
+        Ok(elements)
+    }
+
+    // "a {x} b" を "a " + to_string(x) + " b" に脱糖する
+    fn parse_interpolated_string(&mut self, segments: &[StringSegment]) -> Result<Expression> {
+        let mut parts = Vec::new();
+        for segment in segments {
+            match segment {
+                StringSegment::Literal(text) => {
+                    parts.push(Expression::Literal(Literal::String(text.clone())));
+                }
+                StringSegment::Expression(source) => {
+                    let mut parser = Parser::new(Lexer::new(source));
+                    let expression = parser.parse_expression();
+                    let trailing = parser.lexer.peek().cloned();
+                    // 読み飛ばした不正な文字は、構文エラーより先に字句エラーとして報告する
+                    if let Some((error, _)) = parser.lexer.errors().first() {
+                        return Err(SlangError::Syntax(format!("{} in interpolated expression", error)));
+                    }
+                    let expression = expression?;
+                    if let Some(token) = trailing {
+                        return Err(SlangError::Syntax(format!(
+                            "Unexpected token in interpolated expression: {:?}",
+                            token
+                        )));
+                    }
+                    parts.push(Expression::Call(Box::new(CallExpression {
+                        function: "to_string".to_string(),
+                        arguments: vec![expression],
+                    })));
+                }
+            }
+        }
+        let mut parts = parts.into_iter();
+        let first = parts
+            .next()
+            .unwrap_or_else(|| Expression::Literal(Literal::String(String::new())));
+        Ok(parts.fold(first, |left, right| {
+            Expression::BinaryOp(Box::new(BinaryOpExpression {
+                left: Box::new(left),
+                op: BinaryOperator::Add,
+                right: Box::new(right),
+            }))
+        }))
+    }
+
     fn expect(&mut self, expected: Token) -> Result<()> {
         match self.lexer.peek() {
             Some(token) if token == &expected => {
@@ -331,7 +1298,935 @@ impl<'a> Parser<'a> {
                 "Expected {:?}, got {:?}",
                 expected, token
             ))),
-            None => Err(SlangError::Syntax(format!("Expected {:?}, got EOF", expected))),
+            None => Err(self.unexpected_eof(&describe_token(&expected))),
         }
     }
-} 
\ No newline at end of file
+}
+
+// EOF のエラーで期待したトークンを示すための綴り。記号はソースと同じ綴りで示す
+fn describe_token(token: &Token) -> String {
+    let spelling = match token {
+        Token::LParen => "(",
+        Token::RParen => ")",
+        Token::LBrace => "{",
+        Token::RBrace => "}",
+        Token::LBracket => "[",
+        Token::RBracket => "]",
+        Token::Colon => ":",
+        Token::Semicolon => ";",
+        Token::Comma => ",",
+        Token::Dot => ".",
+        Token::Arrow => "->",
+        Token::FatArrow => "=>",
+        Token::Assign => "=",
+        Token::LessThan => "<",
+        Token::GreaterThan => ">",
+        Token::Function => "fn",
+        Token::In => "in",
+        _ => return format!("{:?}", token),
+    };
+    format!("`{}`", spelling)
+}
+
+// `vec3` の `3` のような次元。0 や先頭が 0 の数字は受け付けない
+fn parse_dimension(digits: &str) -> Option<usize> {
+    if digits.is_empty() || digits.starts_with('0') || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+fn starts_with_digit(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_ascii_digit())
+}
+
+fn malformed_dimension(name: &str, example: &str) -> SlangError {
+    SlangError::Syntax(format!("Malformed dimension in type {}: expected a form like {}", name, example))
+}
+
+// `mat2x3` の `2x3`
+fn parse_matrix_dimensions(dimensions: &str) -> Option<(usize, usize)> {
+    let (rows, cols) = dimensions.split_once('x')?;
+    Some((parse_dimension(rows)?, parse_dimension(cols)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_expression(source: &str) -> Expression {
+        Parser::new(Lexer::new(source)).parse_expression().unwrap()
+    }
+
+    fn string(value: &str) -> Box<Expression> {
+        Box::new(Expression::Literal(Literal::String(value.to_string())))
+    }
+
+    fn to_string(name: &str) -> Box<Expression> {
+        Box::new(Expression::Call(Box::new(CallExpression {
+            function: "to_string".to_string(),
+            arguments: vec![Expression::Identifier(name.to_string())],
+        })))
+    }
+
+    fn identifier(name: &str) -> Box<Expression> {
+        Box::new(Expression::Identifier(name.to_string()))
+    }
+
+    fn binary(left: Box<Expression>, op: BinaryOperator, right: Box<Expression>) -> Box<Expression> {
+        Box::new(Expression::BinaryOp(Box::new(BinaryOpExpression { left, op, right })))
+    }
+
+    fn add(left: Box<Expression>, right: Box<Expression>) -> Box<Expression> {
+        binary(left, BinaryOperator::Add, right)
+    }
+
+    #[test]
+    fn test_and_binds_tighter_than_or() {
+        let expected = binary(
+            binary(identifier("a"), BinaryOperator::And, identifier("b")),
+            BinaryOperator::Or,
+            identifier("c"),
+        );
+        assert_eq!(parse_expression("a && b || c"), *expected);
+
+        let expected = binary(
+            identifier("a"),
+            BinaryOperator::Or,
+            binary(identifier("b"), BinaryOperator::And, identifier("c")),
+        );
+        assert_eq!(parse_expression("a || b && c"), *expected);
+    }
+
+    #[test]
+    fn test_logical_operators_bind_looser_than_comparison() {
+        let expected = binary(
+            binary(identifier("x"), BinaryOperator::LessThan, identifier("y")),
+            BinaryOperator::And,
+            binary(identifier("y"), BinaryOperator::Equals, identifier("z")),
+        );
+        assert_eq!(parse_expression("x < y && y == z"), *expected);
+    }
+
+    fn int(value: i64) -> Expression {
+        Expression::Literal(Literal::Int(value))
+    }
+
+    #[test]
+    fn test_array_literals() {
+        assert_eq!(parse_expression("[1, 2, 3]"), Expression::Array(vec![int(1), int(2), int(3)]));
+        assert_eq!(parse_expression("[1, 2,]"), Expression::Array(vec![int(1), int(2)]));
+        assert_eq!(parse_expression("[]"), Expression::Array(vec![]));
+    }
+
+    #[test]
+    fn test_index_expressions() {
+        let expected = Expression::Index {
+            array: Box::new(Expression::Index {
+                array: identifier("grid"),
+                index: Box::new(int(0)),
+            }),
+            index: add(identifier("i"), Box::new(int(1))),
+        };
+        assert_eq!(parse_expression("grid[0][i + 1]"), expected);
+
+        let expected = Expression::UnaryOp(Box::new(UnaryOpExpression {
+            op: UnaryOperator::Negate,
+            expr: Box::new(Expression::Index {
+                array: Box::new(Expression::Array(vec![int(1)])),
+                index: Box::new(int(0)),
+            }),
+        }));
+        assert_eq!(parse_expression("-[1][0]"), expected);
+    }
+
+    fn field(base: Box<Expression>, field: &str) -> Box<Expression> {
+        Box::new(Expression::FieldAccess {
+            base,
+            field: field.to_string(),
+        })
+    }
+
+    #[test]
+    fn test_chained_field_access() {
+        assert_eq!(parse_expression("a.b.c"), *field(field(identifier("a"), "b"), "c"));
+
+        let expected = Expression::Index {
+            array: field(identifier("list"), "items"),
+            index: Box::new(int(0)),
+        };
+        assert_eq!(parse_expression("list.items[0]"), expected);
+        assert_eq!(
+            parse_expression("p.x + 1"),
+            *add(field(identifier("p"), "x"), Box::new(int(1)))
+        );
+        assert!(Parser::new(Lexer::new("a.")).parse_expression().is_err());
+    }
+
+    // `Display` の出力を読み直すと同じ AST になる
+    #[test]
+    fn test_display_round_trip() {
+        let corpus = [
+            "fn main() -> int { return 1 + 2 * 3; }",
+            "fn f(a: int, b: int) -> int { return (a + b) * (a - (b - 1)) / -(a % 2); }",
+            "fn f(x: bool, y: bool) -> bool { return !(x && y) || x == (y != x); }",
+            "fn f() -> float priority 2, 1 { let x: float = 1.0; let y = 2.5e3; return x * y; }",
+            r#"Function:type:priority: 3 fn f() -> void { print("tab\t \"quoted\" {{braces}} \\"); let c = '\''; }"#,
+            r#"fn greet(name: string) -> string { return "hi {name}!"; }"#,
+            "fn f<T>(xs: [T], t: T) -> T { return xs[0]; }",
+            "fn f(xs: [int]) -> int { let mut total = 0; for x in xs { total = total + x; } return total; }",
+            "fn f(n: int) -> int { let mut i = 0; while i < n { if i == 3 { break; } else { i = i + 1; continue; } } return i; }",
+            "fn f(n: int) -> int { if n < 0 { return -1; } else if n == 0 { return 0; } else { return 1; } }",
+            "fn f() -> void { for i in 0..10 { g(i); } for j in (1 + 1)..=(2 * 3) { g(j); } }",
+            "fn f() -> int { let p = Point { x: 1, y: 2 }; while (Point { x: 1, y: 2 }).x < p.y { g(p); } return p.x; }",
+            "fn f() -> int { let t = (1, (2,), [3, 4]); return (t.len() + 1).abs(); }",
+            "fn f(n: int) -> int { match n { 1 => { return 1; } -2 => {} _ => { return 0; } } return n; }",
+            "fn f(p: Point) -> int { match p { Point { x, y: 0 } => { return x; } _ => {} } return 0; }",
+            "fn f() -> void { Var:type:priority: most_high let x = 1; let y = 2; transfer x -> y; }",
+            "fn f() -> void { let z = (1 + 2i) * 3i; let v = vec(1.0, 2.0); let m = mat([1, 2], [3, 4]); }",
+            "fn f() -> void { let mut a = 0; let mut b = 0; a = b = 1; }",
+            "fn f() -> void { } fn g() -> void { return; }",
+            "fn main() { print(1); } fn g() priority 1 { }",
+            "fn f(x: int) -> float { return -x as float * (x + 1) as float / (x as float as int) as float; }",
+            "fn f(k: int) -> int { let g = fn(x: int) -> int { return x + k; }; if h(fn() { let p = P { x: 1 }; }) { } return g(1); }",
+            "fn f(t: (int, int?)) -> int { if let (a, null) = t { return a; } else if let (0, b) = t { return b; } while let (x, _) = g() { h(x); } return 1; }",
+        ];
+        for source in corpus {
+            let ast = Parser::new(Lexer::new(source)).parse().unwrap();
+            let printed = ast.to_string();
+            let reparsed = Parser::new(Lexer::new(&printed))
+                .parse()
+                .unwrap_or_else(|error| panic!("{}\n{}\n{}", source, printed, error));
+            assert_eq!(reparsed, ast, "{}\n{}", source, printed);
+        }
+    }
+
+    #[test]
+    fn test_spans_cover_functions_and_statements() {
+        let source = "// header\nfn f(x: int) -> int {\n    if x > 0 { return 1; } else if x < 0 { let g = fn() { h(); }; }\n    return 0;\n}\nFunction:type:priority: 2 fn g() -> void { }";
+        let mut parser = Parser::new(Lexer::new(source));
+        let ast = parser.parse().unwrap();
+        let spans = parser.spans().clone();
+
+        let text = |span: std::ops::Range<usize>| &source[span];
+        let function = text(spans.function(0).unwrap());
+        assert!(function.starts_with("fn f(") && function.ends_with("return 0;\n}"), "{}", function);
+        assert_eq!(text(spans.function(1).unwrap()), "Function:type:priority: 2 fn g() -> void { }");
+
+        // 文は `Visitor` が訪れる順に並ぶ
+        struct Statements(Vec<String>);
+        impl Visitor for Statements {
+            fn visit_statement(&mut self, statement: &Statement) {
+                self.0.push(statement.to_string());
+                walk_statement(self, statement);
+            }
+        }
+        let mut visited = Statements(Vec::new());
+        walk_ast(&mut visited, &ast);
+        assert_eq!(visited.0.len(), spans.statements.len());
+        let texts: Vec<&str> = spans.statements.iter().map(|span| text(span.clone())).collect();
+        assert_eq!(texts, vec![
+            "if x > 0 { return 1; } else if x < 0 { let g = fn() { h(); }; }",
+            "return 1;",
+            "if x < 0 { let g = fn() { h(); }; }",
+            "let g = fn() { h(); };",
+            "h();",
+            "return 0;",
+        ]);
+
+        let offset = source.find("h()").unwrap();
+        assert_eq!(spans.statement_at(offset), Some(4));
+        assert_eq!(spans.function_at(offset), Some(0));
+        assert_eq!(spans.function_at(0), None);
+    }
+
+    #[test]
+    fn test_spans_skip_macro_bodies() {
+        let source = "macro twice(a) { let t = a; print(t); } fn main() -> int { twice!(1); return 0; }";
+        let mut parser = Parser::new(Lexer::new(source));
+        parser.parse().unwrap();
+        let spans = parser.spans();
+        let texts: Vec<&str> = spans.statements.iter().map(|span| &source[span.clone()]).collect();
+        assert_eq!(texts, vec!["twice!(1);", "return 0;"]);
+        assert_eq!(&source[spans.function(0).unwrap()], "fn main() -> int { twice!(1); return 0; }");
+    }
+
+    #[test]
+    fn test_parse_program_keeps_items_and_modules() {
+        let source = "type Empty = ; fn main() -> int { return helper(); } module util { fn helper() -> int { return 1; } module inner { } }";
+        let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+        assert_eq!(program.items.len(), 3);
+        assert!(matches!(&program.items[0], Item::Struct(s) if s.name == "Empty" && s.fields.is_empty()));
+        assert!(matches!(&program.items[1], Item::Function(f) if f.name == "main"));
+        let Item::Module(util) = &program.items[2] else { panic!("expected a module: {}", program.items[2]) };
+        assert_eq!(util.name, "util");
+        assert!(matches!(&util.items[..], [Item::Function(f), Item::Module(inner)] if f.name == "helper" && inner.items.is_empty()));
+
+        // `parse` はモジュールの中の関数も出現順に並べた `AST` を返す
+        let ast = Parser::new(Lexer::new(source)).parse().unwrap();
+        let names: Vec<&str> = ast.functions.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["main", "helper"]);
+        assert_eq!(ast.type_definitions.len(), 1);
+        assert_eq!(AST::from(program), ast);
+
+        let error = Parser::new(Lexer::new("module util { fn f() -> void { }")).parse_program().unwrap_err();
+        assert!(error.to_string().contains("while parsing module"), "{}", error);
+    }
+
+    #[test]
+    fn test_struct_definitions() {
+        let parse = |source: &str| -> Struct {
+            match Parser::new(Lexer::new(source)).parse_program().unwrap().items.remove(0) {
+                Item::Struct(struct_) => struct_,
+                item => panic!("expected a struct: {}", item),
+            }
+        };
+        let field = |name: &str, type_: Type, priority: Option<MemoryPriority>| StructField { name: name.to_string(), type_, priority };
+
+        let point = parse("struct Point { x: int, y: int, }");
+        assert_eq!(point.name, "Point");
+        assert_eq!(point.fields, vec![field("x", Type::Int, None), field("y", Type::Int, None)]);
+        assert!(parse("struct Unit { }").fields.is_empty());
+
+        let node = parse("struct Node<T> { value: T @3, next: *Node? @[2, 1], cache: [int] @most_high, label: string }");
+        assert_eq!(node.type_params, vec!["T".to_string()]);
+        let priorities: Vec<Option<MemoryPriority>> = node.fields.iter().map(|field| field.priority.clone()).collect();
+        assert_eq!(priorities, vec![
+            Some(MemoryPriority::Level(3)),
+            Some(MemoryPriority::MultiLevel(vec![2, 1])),
+            Some(MemoryPriority::MostHigh),
+            None,
+        ]);
+        assert_eq!(node.fields[0].to_string(), "value: T @3");
+        assert_eq!(node.fields[1].to_string(), format!("next: {} @[2, 1]", node.fields[1].type_));
+
+        let error = |source: &str| Parser::new(Lexer::new(source)).parse_program().unwrap_err().to_string();
+        assert!(error("struct P { x: int y: int }").contains("Expected ',' or '}'"));
+        assert!(error("struct P { x: int @ }").contains("Expected integer literal"));
+        assert!(error("struct P { x: int").contains("while parsing struct definition"));
+    }
+
+    #[test]
+    fn test_trait_definitions() {
+        let source = "trait Shape { fn area(self: Self) -> float; fn scale(self: Self, by: int) -> Self; }";
+        let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+        let Item::Trait(shape) = &program.items[0] else { panic!("expected a trait: {}", program.items[0]) };
+        assert_eq!(shape.name, "Shape");
+        let names: Vec<&str> = shape.methods.iter().map(|method| method.name.as_str()).collect();
+        assert_eq!(names, vec!["area", "scale"]);
+        assert_eq!(shape.methods[1].params.len(), 2);
+        assert_eq!(shape.methods[1].return_type, Type::Named("Self".to_string()));
+        // `Display` の出力はそのまま読み直せる
+        assert_eq!(Parser::new(Lexer::new(&shape.to_string())).parse_program().unwrap(), program);
+
+        let error = |source: &str| Parser::new(Lexer::new(source)).parse_program().unwrap_err().to_string();
+        assert!(error("trait T { fn f() -> int { return 1; } }").contains("Expected Semicolon"));
+        assert!(error("trait T { fn f() -> int;").contains("while parsing trait definition"));
+    }
+
+    #[test]
+    fn test_function_priorities() {
+        let parse = |source: &str| Parser::new(Lexer::new(source)).parse().unwrap().functions.remove(0);
+        let function = parse("fn f() -> int priority 2, 1 { return 0; }");
+        assert_eq!(function.priorities, vec![2, 1]);
+        assert_eq!(function.primary_priority(), 2);
+        assert!(function.to_string().starts_with("fn f() -> int priority 2, 1 {"));
+
+        let function = parse("fn f() -> int { return 0; }");
+        assert!(function.priorities.is_empty());
+        assert_eq!(function.primary_priority(), 0);
+    }
+
+    #[test]
+    fn test_priority_annotations() {
+        for (source, priority) in [
+            ("Var:type:priority: 3 let x = 1;", MemoryPriority::Level(3)),
+            ("Var:type:priority: 3, 1 let x = 1;", MemoryPriority::MultiLevel(vec![3, 1])),
+            ("Var:type:priority: most_high let x = 1;", MemoryPriority::MostHigh),
+            ("Var:type:priority: most_low let x = 1;", MemoryPriority::MostLow),
+        ] {
+            let statement = parse_statement(source);
+            let Statement::Let(let_statement) = &statement else {
+                panic!("expected a let statement");
+            };
+            assert_eq!(let_statement.priority, Some(priority));
+            // 表示した結果を読み直しても同じ文になる
+            assert_eq!(statement.to_string(), format!("{}\n", source));
+            assert_eq!(parse_statement(&statement.to_string()), statement);
+        }
+        assert!(Parser::new(Lexer::new("Var:type:priority: 1 return 1;")).parse_statement().is_err());
+
+        let parse = |source: &str| Parser::new(Lexer::new(source)).parse();
+        let ast = parse("Function:type:priority: 2, 1 fn f() -> int { return 0; }").unwrap();
+        assert_eq!(ast.functions[0].priorities, vec![2, 1]);
+        assert!(parse("Function:type:priority: 2 fn f() -> int priority 1 { return 0; }").is_err());
+        assert!(parse("Function:type:priority: most_high fn f() -> int { return 0; }").is_err());
+    }
+
+    #[test]
+    fn test_generic_function_signature() {
+        let ast = Parser::new(Lexer::new("fn pair<T, U>(a: T, b: U) -> (T, U) { return (a, b); }")).parse().unwrap();
+        let function = &ast.functions[0];
+        assert_eq!(function.type_params, vec!["T".to_string(), "U".to_string()]);
+        assert_eq!(function.parameters[0].type_annotation, Type::Named("T".to_string()));
+        assert_eq!(
+            function.return_type,
+            Type::Tuple(vec![Type::Named("T".to_string()), Type::Named("U".to_string())])
+        );
+        assert!(function.to_string().starts_with("fn pair<T, U>(a: T, b: U)"));
+    }
+
+    fn parse_statement(source: &str) -> Statement {
+        Parser::new(Lexer::new(source)).parse_statement().unwrap()
+    }
+
+    fn point(x: Expression, y: Expression) -> Expression {
+        Expression::StructLiteral {
+            name: "Point".to_string(),
+            fields: vec![("x".to_string(), x), ("y".to_string(), y)],
+        }
+    }
+
+    #[test]
+    fn test_call_with_two_arguments() {
+        let expected = Expression::Call(Box::new(CallExpression {
+            function: "add".to_string(),
+            arguments: vec![*identifier("x"), Expression::Literal(Literal::Int(1))],
+        }));
+        assert_eq!(parse_expression("add(x, 1)"), expected);
+        assert_eq!(expected.to_string(), "add(x, 1)");
+    }
+
+    #[test]
+    fn test_method_call() {
+        let method_call = |receiver: Expression, method: &str, arguments: Vec<Expression>| Expression::MethodCall {
+            receiver: Box::new(receiver),
+            method: method.to_string(),
+            arguments,
+        };
+        assert_eq!(
+            parse_expression("list.push(x)"),
+            method_call(*identifier("list"), "push", vec![*identifier("x")])
+        );
+        assert_eq!(
+            parse_expression("a.b.len()"),
+            method_call(*field(identifier("a"), "b"), "len", vec![])
+        );
+        assert_eq!(
+            parse_expression("xs.len().y"),
+            *field(Box::new(method_call(*identifier("xs"), "len", vec![])), "y")
+        );
+        assert_eq!(
+            parse_expression("list.push(1, 2,)").to_string(),
+            "list.push(1, 2)"
+        );
+    }
+
+    #[test]
+    fn test_struct_literal() {
+        assert_eq!(parse_expression("Point { x: 1, y: 2 }"), point(int(1), int(2)));
+        assert_eq!(parse_expression("Point { x: 1, y: 2, }"), point(int(1), int(2)));
+        assert_eq!(
+            parse_expression("Point { x: Point { x: 1, y: 2 }.x, y: 3 }"),
+            point(*field(Box::new(point(int(1), int(2))), "x"), int(3))
+        );
+        assert_eq!(
+            parse_expression("Empty {}"),
+            Expression::StructLiteral {
+                name: "Empty".to_string(),
+                fields: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn test_struct_literal_needs_field_after_brace() {
+        // `{` の後がフィールド名と `:` でも `}` でもなければ構造体リテラルではない
+        for source in ["a { b }", "a { b; }", "a { 1 }", "a { { } }"] {
+            let mut parser = Parser::new(Lexer::new(source));
+            assert_eq!(parser.parse_expression().unwrap(), *identifier("a"), "{}", source);
+            assert_eq!(parser.lexer.peek(), Some(&Token::LBrace), "{}", source);
+        }
+        assert!(Parser::new(Lexer::new("a { b: }")).parse_expression().is_err());
+    }
+
+    #[test]
+    fn test_struct_literal_display_round_trips() {
+        let literal = point(int(1), *add(identifier("a"), Box::new(int(2))));
+        assert_eq!(literal.to_string(), "Point { x: 1, y: a + 2 }");
+        assert_eq!(parse_expression(&literal.to_string()), literal);
+    }
+
+    #[test]
+    fn test_identifier_before_block_in_condition_is_not_struct_literal() {
+        let expected = Statement::If(IfStatement {
+            condition: identifier("ready"),
+            then_block: Block {
+                statements: vec![Statement::Expression(identifier("x"))],
+            },
+            else_block: None,
+        });
+        assert_eq!(parse_statement("if ready { x; }"), expected);
+
+        let expected = Statement::While(WhileStatement {
+            condition: Box::new(Expression::BinaryOp(Box::new(BinaryOpExpression {
+                left: field(Box::new(point(int(1), int(2))), "x"),
+                op: BinaryOperator::LessThan,
+                right: identifier("n"),
+            }))),
+            body: Block { statements: vec![] },
+        });
+        assert_eq!(parse_statement("while (Point { x: 1, y: 2 }).x < n {}"), expected);
+    }
+
+    #[test]
+    fn test_loops_with_break_and_continue() {
+        let expected = Statement::For(ForStatement {
+            variable: "x".to_string(),
+            iterator: identifier("xs"),
+            body: Block {
+                statements: vec![Statement::Continue, Statement::Break],
+            },
+        });
+        assert_eq!(parse_statement("for x in xs { continue; break; }"), expected);
+        assert_eq!(parse_statement("break;").to_string(), "break;\n");
+        assert!(Parser::new(Lexer::new("break")).parse_statement().is_err());
+    }
+
+    #[test]
+    fn test_if_let_and_while_let() {
+        let tuple = Pattern::Tuple(vec![Pattern::Identifier("a".to_string()), Pattern::Identifier("b".to_string())]);
+        let Statement::IfLet(statement) = parse_statement("if let (a, b) = pair { f(a); } else if let _ = b { }") else {
+            panic!("expected an if let statement");
+        };
+        assert_eq!(statement.pattern, tuple);
+        assert_eq!(statement.expression, identifier("pair"));
+        assert!(matches!(
+            statement.else_block.unwrap().statements.as_slice(),
+            [Statement::IfLet(IfLetStatement { pattern: Pattern::Wildcard, .. })]
+        ));
+
+        let expected = Statement::WhileLet(WhileLetStatement {
+            pattern: tuple,
+            expression: identifier("pair"),
+            body: Block { statements: vec![Statement::Break] },
+        });
+        assert_eq!(parse_statement("while let (a, b) = pair { break; }"), expected);
+
+        let error = Parser::new(Lexer::new("if let (a, b) = ")).parse_statement().unwrap_err();
+        assert!(error.to_string().contains("while parsing `if let` statement"), "{}", error);
+        assert!(Parser::new(Lexer::new("while let (a, b) pair {}")).parse_statement().is_err());
+    }
+
+    #[test]
+    fn test_match_patterns() {
+        let Statement::Match(statement) = parse_statement(
+            r#"match n { 1 => { return 1; } -2 => {} "s" => {}, 'c' => {} true => {} null => {} (x, _) => {} }"#,
+        ) else {
+            panic!("expected a match statement");
+        };
+        let patterns: Vec<Pattern> = statement.arms.into_iter().map(|arm| arm.pattern).collect();
+        assert_eq!(
+            patterns,
+            vec![
+                Pattern::Literal(Literal::Int(1)),
+                Pattern::Literal(Literal::Int(-2)),
+                Pattern::Literal(Literal::String("s".to_string())),
+                Pattern::Literal(Literal::Char('c')),
+                Pattern::Literal(Literal::Bool(true)),
+                Pattern::Literal(Literal::Null),
+                Pattern::Tuple(vec![Pattern::Identifier("x".to_string()), Pattern::Wildcard]),
+            ]
+        );
+
+        let Statement::Match(statement) = parse_statement("match p { Point { x, y: 0 } => {} Point {} => {} }") else {
+            panic!("expected a match statement");
+        };
+        let field = |name: &str, pattern: Pattern| FieldPattern { name: name.to_string(), pattern: Box::new(pattern) };
+        assert_eq!(
+            statement.arms[0].pattern,
+            Pattern::Struct {
+                name: "Point".to_string(),
+                fields: vec![
+                    field("x", Pattern::Identifier("x".to_string())),
+                    field("y", Pattern::Literal(Literal::Int(0))),
+                ],
+            }
+        );
+        assert_eq!(statement.arms[0].pattern.to_string(), "Point { x: x, y: 0 }");
+        assert_eq!(statement.arms[1].pattern, Pattern::Struct { name: "Point".to_string(), fields: vec![] });
+
+        assert!(Parser::new(Lexer::new("match n { 1 {} }")).parse_statement().is_err());
+        assert!(Parser::new(Lexer::new("match n { + => {} }")).parse_statement().is_err());
+    }
+
+    #[test]
+    fn test_transfer_statement() {
+        let expected = Statement::Transfer(TransferStatement { from: "x".to_string(), to: "y".to_string() });
+        assert_eq!(parse_statement("transfer x -> y;"), expected);
+        assert_eq!(expected.to_string(), "transfer x -> y;\n");
+        assert!(Parser::new(Lexer::new("transfer x y;")).parse_statement().is_err());
+    }
+
+    #[test]
+    fn test_assignment_is_not_equality() {
+        let assignment = |target: &str, value| {
+            Expression::Assignment(Box::new(AssignmentExpression { target: target.to_string(), value }))
+        };
+        assert_eq!(parse_expression("x = 5"), assignment("x", Box::new(int(5))));
+        assert_eq!(parse_expression("x == 5"), *binary(identifier("x"), BinaryOperator::Equals, Box::new(int(5))));
+        assert_eq!(parse_expression("x != 5"), *binary(identifier("x"), BinaryOperator::NotEquals, Box::new(int(5))));
+        assert_eq!(
+            parse_expression("a = b = x + 1"),
+            assignment("a", Box::new(assignment("b", add(identifier("x"), Box::new(int(1))))))
+        );
+        assert_eq!(parse_statement("x = 5;"), Statement::Expression(Box::new(assignment("x", Box::new(int(5))))));
+        assert_eq!(parse_expression("x = 5").to_string(), "x = 5");
+        // 代入先は変数名だけ
+        assert!(Parser::new(Lexer::new("f() = 5;")).parse_statement().is_err());
+    }
+
+    #[test]
+    fn test_ranges() {
+        let range = |start, end, inclusive| Expression::Range { start, end, inclusive };
+        let int = |value| Box::new(int(value));
+        assert_eq!(parse_expression("0..10"), range(int(0), int(10), false));
+        assert_eq!(parse_expression("1..=n + 1"), range(int(1), add(identifier("n"), int(1)), true));
+        assert_eq!(parse_expression("a..=b").to_string(), "a..=b");
+
+        let expected = Statement::For(ForStatement {
+            variable: "i".to_string(),
+            iterator: Box::new(range(int(0), int(5), false)),
+            body: Block { statements: vec![] },
+        });
+        assert_eq!(parse_statement("for (i in 0..5) {}"), expected);
+        assert_eq!(parse_statement("for i in 0..5 {}"), expected);
+        assert!(Parser::new(Lexer::new("for (i in 0..5 {}")).parse_statement().is_err());
+    }
+
+    #[test]
+    fn test_let_mut() {
+        let Statement::Let(statement) = parse_statement("let mut x = 1;") else {
+            panic!("expected a let statement");
+        };
+        assert!(statement.mutable);
+        assert_eq!(statement.to_string(), "let mut x = 1;\n");
+    }
+
+    #[test]
+    fn test_let_with_type_annotation() {
+        let statement = parse_statement("let x: float = 1;");
+        assert_eq!(
+            statement,
+            Statement::Let(LetStatement {
+                name: "x".to_string(),
+                mutable: false,
+                type_annotation: Some(Type::Float),
+                value: Box::new(Expression::Literal(Literal::Int(1))),
+                priority: None,
+            })
+        );
+        assert_eq!(statement.to_string(), "let x: float = 1;\n");
+
+        let Statement::Let(statement) = parse_statement("let ys: [Point] = [];") else {
+            panic!("expected a let statement");
+        };
+        assert_eq!(statement.type_annotation, Some(Type::Array(Box::new(Type::Named("Point".to_string())))));
+        assert_eq!(parse_statement("let x = 1;").to_string(), "let x = 1;\n");
+    }
+
+    #[test]
+    fn test_syntax_errors_carry_the_offending_token_span() {
+        let source = "fn main() -> int { return 1 + ; }";
+        let error = Parser::new(Lexer::new(source)).parse().unwrap_err();
+        let offset = source.find(';').unwrap();
+        assert_eq!(error.span(), Some(offset..offset + 1));
+
+        let source = "fn main() -> int { return 1;";
+        let error = Parser::new(Lexer::new(source)).parse().unwrap_err();
+        assert_eq!(error.span(), Some(source.len()..source.len()));
+
+        let error = Parser::new(Lexer::new("fn main() -> int { return 1 @ 2; }")).parse().unwrap_err();
+        assert_eq!(error.span(), Some(28..29));
+    }
+
+    #[test]
+    fn test_unexpected_end_of_input() {
+        let error = |source: &str| Parser::new(Lexer::new(source)).parse().unwrap_err().to_string();
+        assert_eq!(
+            error("fn f("),
+            "Syntax error: Unexpected end of input while parsing function signature; expected `)`"
+        );
+        assert_eq!(
+            error("fn f(x: int"),
+            "Syntax error: Unexpected end of input while parsing function signature; expected `)` or `,`"
+        );
+        assert_eq!(
+            error("fn f() -> int { let x ="),
+            "Syntax error: Unexpected end of input while parsing let statement; expected expression"
+        );
+        assert_eq!(
+            error("fn f() -> int { let x = 1;"),
+            "Syntax error: Unexpected end of input while parsing function body; expected `}`"
+        );
+        assert_eq!(
+            error("fn f() -> int { while x { g(1);"),
+            "Syntax error: Unexpected end of input while parsing `while` loop; expected `}`"
+        );
+
+        let mut parser = Parser::new(Lexer::new("{ let x ="));
+        assert_eq!(
+            parser.parse_block().unwrap_err().to_string(),
+            "Syntax error: Unexpected end of input while parsing let statement; expected expression"
+        );
+        // 途中で終わっていなければ従来どおり次のトークンを示す
+        assert!(error("fn f) -> int {}").contains("Expected LParen, got RParen"));
+    }
+
+    #[test]
+    fn test_unary_negation() {
+        let unary = |op: UnaryOperator, expr: Expression| Expression::UnaryOp(Box::new(UnaryOpExpression {
+            op,
+            expr: Box::new(expr),
+        }));
+        assert_eq!(parse_expression("-x"), unary(UnaryOperator::Negate, *identifier("x")));
+        assert_eq!(
+            parse_expression("!-x"),
+            unary(UnaryOperator::Not, unary(UnaryOperator::Negate, *identifier("x")))
+        );
+        assert_eq!(parse_expression("-x").to_string(), "-x");
+    }
+
+    #[test]
+    fn test_cast_expression() {
+        let cast = |expr: Expression, target: Type| Expression::Cast { expr: Box::new(expr), target };
+        let negate_x = Expression::UnaryOp(Box::new(UnaryOpExpression {
+            op: UnaryOperator::Negate,
+            expr: identifier("x"),
+        }));
+        assert_eq!(parse_expression("-x as float"), cast(negate_x, Type::Float));
+        assert_eq!(
+            parse_expression("a * b as float"),
+            *binary(identifier("a"), BinaryOperator::Mul, Box::new(cast(*identifier("b"), Type::Float)))
+        );
+        assert_eq!(
+            parse_expression("x as float as string"),
+            cast(cast(*identifier("x"), Type::Float), Type::String)
+        );
+        assert_eq!(parse_expression("(a + b) as float").to_string(), "(a + b) as float");
+        assert!(Parser::new(Lexer::new("x as")).parse_expression().is_err());
+    }
+
+    #[test]
+    fn test_lambda_expression() {
+        let Expression::Lambda { params, return_type, body } = parse_expression("fn(x: int, y: int) -> int { return x + y; }") else {
+            panic!("expected a lambda");
+        };
+        assert_eq!(params.iter().map(|p| p.name.as_str()).collect::<Vec<_>>(), vec!["x", "y"]);
+        assert_eq!(return_type, Some(Type::Int));
+        assert_eq!(body.statements.len(), 1);
+
+        let Expression::Lambda { params, return_type, .. } = parse_expression("fn() { }") else {
+            panic!("expected a lambda");
+        };
+        assert!(params.is_empty());
+        assert_eq!(return_type, None);
+
+        let error = Parser::new(Lexer::new("fn(x: int")).parse_expression().unwrap_err();
+        assert!(error.to_string().contains("lambda"), "{}", error);
+    }
+
+    #[test]
+    fn test_bool_and_char_literals() {
+        assert_eq!(parse_expression("true"), Expression::Literal(Literal::Bool(true)));
+        assert_eq!(parse_expression("'a'"), Expression::Literal(Literal::Char('a')));
+        assert_eq!(Expression::Literal(Literal::Char('a')).to_string(), "'a'");
+    }
+
+    #[test]
+    fn test_else_if_chain() {
+        let statement = parse_statement("if a { } else if b { } else { c; }");
+        let Statement::If(outer) = statement else {
+            panic!("expected if statement");
+        };
+        let else_block = outer.else_block.unwrap();
+        let Statement::If(inner) = &else_block.statements[0] else {
+            panic!("expected nested if statement");
+        };
+        assert_eq!(inner.condition, identifier("b"));
+        assert_eq!(
+            inner.else_block,
+            Some(Block {
+                statements: vec![Statement::Expression(identifier("c"))],
+            })
+        );
+    }
+
+    #[test]
+    fn test_tuple_literals_and_grouping() {
+        assert_eq!(
+            parse_expression(r#"(1, "a")"#),
+            Expression::Tuple(vec![int(1), *string("a")])
+        );
+        assert_eq!(parse_expression("(1,)"), Expression::Tuple(vec![int(1)]));
+        assert_eq!(parse_expression("(1)"), int(1));
+        assert_eq!(parse_expression("()"), Expression::Tuple(vec![]));
+        assert!(Parser::new(Lexer::new("(1 2)")).parse_expression().is_err());
+    }
+
+    #[test]
+    fn test_grouping_overrides_precedence() {
+        let expected = binary(
+            identifier("a"),
+            BinaryOperator::And,
+            binary(identifier("b"), BinaryOperator::Or, identifier("c")),
+        );
+        assert_eq!(parse_expression("a && (b || c)"), *expected);
+
+        let expected = binary(
+            add(identifier("a"), identifier("b")),
+            BinaryOperator::Mul,
+            identifier("c"),
+        );
+        assert_eq!(parse_expression("(a + b) * c"), *expected);
+    }
+
+    #[test]
+    fn test_interpolated_string_desugars_to_concatenation() {
+        let expected = add(
+            add(add(string("hello "), to_string("name")), string(", you are ")),
+            to_string("age"),
+        );
+        assert_eq!(
+            parse_expression(r#""hello {name}, you are {age}""#),
+            *expected
+        );
+        assert_eq!(parse_expression(r#""{name}""#), *to_string("name"));
+    }
+
+    #[test]
+    fn test_interpolated_string_rejects_trailing_tokens() {
+        let result = Parser::new(Lexer::new(r#""{a b}""#)).parse_expression();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_interpolated_string_reports_invalid_characters() {
+        for source in [r#""a{x $}b""#, r#""{$}""#] {
+            let error = Parser::new(Lexer::new(source)).parse_expression().unwrap_err();
+            assert_eq!(error.to_string(), "Syntax error: Unexpected character in interpolated expression", "{}", source);
+        }
+        assert!(crate::compile(r#"fn main() -> void { let x = 1; print("a{x $}b"); }"#).is_err());
+    }
+
+    #[test]
+    fn test_type_display_round_trip() {
+        let function = |params: Vec<Type>, return_type: Type, priority| Type::Function {
+            params,
+            return_type: Box::new(return_type),
+            priority,
+        };
+        let types = vec![
+            Type::Unit,
+            Type::Int,
+            Type::Named("Point".to_string()),
+            Type::Array(Box::new(Type::Array(Box::new(Type::Char)))),
+            Type::Tuple(vec![Type::Int]),
+            Type::Tuple(vec![Type::Float, Type::String, Type::Unit]),
+            Type::Vector(3, Box::new(Type::Float)),
+            Type::Vector(4, Box::new(Type::Vector(2, Box::new(Type::Int)))),
+            Type::Matrix(2, 3, Box::new(Type::Float)),
+            Type::Pointer(Box::new(Type::Bool)),
+            Type::Optional(Box::new(Type::Int)),
+            Type::Optional(Box::new(Type::Optional(Box::new(Type::Array(Box::new(Type::String)))))),
+            Type::Pointer(Box::new(Type::Optional(Box::new(Type::Int)))),
+            function(vec![], Type::Void, None),
+            function(vec![Type::Int, Type::Float], Type::Bool, Some(2)),
+            function(vec![function(vec![Type::Int], Type::Int, Some(1))], function(vec![], Type::Int, None), None),
+        ];
+        for type_ in types {
+            let source = type_.to_string();
+            let mut parser = Parser::new(Lexer::new(&source));
+            assert_eq!(parser.parse_type().unwrap(), type_, "{}", source);
+            assert_eq!(parser.lexer.peek(), None, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_function_type_in_signature() {
+        let ast = Parser::new(Lexer::new("fn apply(f: fn(int) -> int, x: int) -> int { return f(x); }"))
+            .parse()
+            .unwrap();
+        assert_eq!(
+            ast.functions[0].parameters[0].type_annotation,
+            Type::Function { params: vec![Type::Int], return_type: Box::new(Type::Int), priority: None }
+        );
+
+        // `u32` に収まらない優先度は切り詰めずにエラーにする
+        let error = parse_type_source("Function:type:priority: 4294967297 fn(int) -> int").unwrap_err();
+        assert!(error.to_string().contains("Function type priority 4294967297 is out of range"), "{}", error);
+        let function_type = parse_type_source("Function:type:priority: 4294967295 fn(int) -> int").unwrap();
+        assert_eq!(function_type.get_priority(), Some(u32::MAX));
+    }
+
+    fn parse_type_source(source: &str) -> Result<Type> {
+        Parser::new(Lexer::new(source)).parse_type()
+    }
+
+    #[test]
+    fn test_parse_numeric_types() {
+        let float = || Box::new(Type::Float);
+        let cases = [
+            ("vec3<float>", Type::Vector(3, float())),
+            ("mat2x2<float>", Type::Matrix(2, 2, float())),
+            ("tensor<2x3x4, float>", Type::Tensor(vec![2, 3, 4], float())),
+            ("tensor<5, float>", Type::Tensor(vec![5], float())),
+            ("quat<float>", Type::Quaternion(float())),
+            ("complex<float>", Type::Complex(float())),
+            ("[float; 3]", Type::FixedArray(float(), 3)),
+            ("[[float; 2]; 2]", Type::FixedArray(Box::new(Type::FixedArray(float(), 2)), 2)),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(parse_type_source(source).unwrap(), expected, "{}", source);
+            assert_eq!(expected.to_string(), source);
+        }
+        // `<` が続かなければ利用者定義の型
+        assert_eq!(parse_type_source("complex").unwrap(), Type::Named("complex".to_string()));
+        assert_eq!(parse_type_source("vector").unwrap(), Type::Named("vector".to_string()));
+    }
+
+    #[test]
+    fn test_oversized_array_length_does_not_wrap() {
+        assert_eq!(
+            parse_type_source("[int; 4294967298]").unwrap(),
+            Type::FixedArray(Box::new(Type::Int), 4294967298)
+        );
+        assert!(crate::compile("fn main() -> int { let xs: [int; 4294967298] = [1, 2]; return 0; }").is_err());
+        let error = Parser::new(Lexer::new("Var:type:priority: 4294967297 let x = 1;")).parse_statement().unwrap_err();
+        assert!(error.to_string().contains("Priority 4294967297 is out of range"), "{}", error);
+    }
+
+    #[test]
+    fn test_malformed_dimensions() {
+        for source in [
+            "vec<float>",
+            "vec0<float>",
+            "vec03<float>",
+            "vec3x<float>",
+            "mat<float>",
+            "mat2<float>",
+            "mat2x<float>",
+            "mat0x2<float>",
+            "tensor<float>",
+            "tensor<0x2, float>",
+            "tensor<2x0, float>",
+            "tensor<2xy, float>",
+            "tensor<2x3>",
+            "quat<>",
+            "[float; -1]",
+            "[float; n]",
+        ] {
+            assert!(parse_type_source(source).is_err(), "{}", source);
+        }
+    }
+}
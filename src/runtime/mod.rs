@@ -1,12 +1,169 @@
-use crate::error::{Result, SlangError};
+use crate::error::{Result, RuntimeErrorKind, SlangError};
 use std::any::Any;
 use std::collections::HashMap;
 use std::fmt;
+use std::rc::Rc;
+
+mod profiler;
+mod value;
+pub use profiler::Profiler;
+pub use value::Value;
 
 pub struct Runtime {
     memory_manager: MemoryManager,
+    // 優先度付き所有権の管理。優先度は `Var:type:priority:` の宣言かホストの `set_priority` で
+    // 現在のフレームに記録し、フレームを解放すると消える
     priority_ownership_manager: PriorityOwnershipManager,
     standard_library: StandardLibrary,
+    // `register_function` で登録した関数の型。`compile` がソースの型検査に使う
+    host_signatures: HashMap<String, crate::type_system::Type>,
+    // `Call` から呼び出せるユーザー定義関数
+    functions: HashMap<String, Rc<crate::ir::IRFunction>>,
+    // 有効にすると `Return` のたびに戻り値を表示する
+    debug: bool,
+    // 命令を実行する前に呼ぶ。デバッガなど
+    observer: Option<Rc<dyn ExecutionObserver>>,
+    // 最後の実行が `StepAction::Abort` で打ち切られた位置
+    aborted_at: Option<Location>,
+    overflow_mode: OverflowMode,
+    // `enable_profiling` で有効にした場合だけ命令と関数の実行回数を数える
+    profiler: Option<Profiler>,
+}
+
+// 整数 (`int`) の演算結果が i64 に収まらない場合の扱い。既定は `Checked`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowMode {
+    // 2 の補数で折り返す
+    Wrapping,
+    // `RuntimeErrorKind::Overflow` のエラーにする
+    #[default]
+    Checked,
+    // `i64::MAX` / `i64::MIN` に張り付かせる
+    Saturating,
+}
+
+impl OverflowMode {
+    // 各モードで計算した結果から、このモードの結果を選ぶ。`checked` は溢れた場合 `None`
+    fn select(self, operation: &str, checked: Option<i64>, wrapping: i64, saturating: i64) -> Result<i64> {
+        match self {
+            OverflowMode::Wrapping => Ok(wrapping),
+            OverflowMode::Saturating => Ok(saturating),
+            OverflowMode::Checked => checked.ok_or_else(|| {
+                SlangError::runtime(RuntimeErrorKind::Overflow, format!("Integer overflow in {}", operation))
+            }),
+        }
+    }
+}
+
+// 実行中の命令の位置。IR には行番号がないため、関数名・ブロック名・ブロック内の番号で表す
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Location {
+    pub function: String,
+    pub block: String,
+    pub instruction: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.function, self.block, self.instruction)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepAction {
+    Continue,
+    // その命令を実行せずに実行全体を打ち切る。途中から再開はできず、続きが要るときは
+    // `Runtime::execute` で最初から実行し直す。打ち切った位置は `Runtime::aborted_at` で分かる
+    Abort,
+}
+
+// `complex<float>` の実行時の値
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ComplexValue {
+    pub re: f64,
+    pub im: f64,
+}
+
+// `quat<float>` の実行時の値 (`quat(w, x, y, z)` で作る)。`w` が実部
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuaternionValue {
+    pub w: f64,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl QuaternionValue {
+    // ハミルトン積。可換ではない
+    pub fn hamilton_product(&self, other: &Self) -> Self {
+        let (a, b) = (self, other);
+        Self {
+            w: a.w * b.w - a.x * b.x - a.y * b.y - a.z * b.z,
+            x: a.w * b.x + a.x * b.w + a.y * b.z - a.z * b.y,
+            y: a.w * b.y - a.x * b.z + a.y * b.w + a.z * b.x,
+            z: a.w * b.z + a.x * b.y - a.y * b.x + a.z * b.w,
+        }
+    }
+
+    pub fn conjugate(&self) -> Self {
+        Self { w: self.w, x: -self.x, y: -self.y, z: -self.z }
+    }
+
+    pub fn norm(&self) -> f64 {
+        (self.w * self.w + self.x * self.x + self.y * self.y + self.z * self.z).sqrt()
+    }
+
+    // 大きさ 1 の四元数にする。大きさ 0 なら `None`
+    pub fn normalize(&self) -> Option<Self> {
+        let norm = self.norm();
+        (norm != 0.0).then(|| Self { w: self.w / norm, x: self.x / norm, y: self.y / norm, z: self.z / norm })
+    }
+}
+
+// `vecN<T>` の実行時の値 (`vec(1, 2, 3)` で作る)。要素は Int で作っても Float として持つ
+#[derive(Debug, Clone, PartialEq)]
+pub struct VectorValue {
+    pub elements: Vec<f64>,
+}
+
+// `matRxC<T>` の実行時の値 (`mat([1, 2], [3, 4])` で作る)。要素は行優先で並べる
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatrixValue {
+    pub rows: usize,
+    pub cols: usize,
+    pub elements: Vec<f64>,
+}
+
+// `tensor<D1xD2x.., T>` の実行時の値 (`tensor([2, 3], [1, 2, 3, 4, 5, 6])` で作る)。
+// 要素は行優先で 1 列に並べ、`shape` の積と同じ数だけ持つ
+#[derive(Debug, Clone, PartialEq)]
+pub struct TensorValue {
+    pub shape: Vec<usize>,
+    pub elements: Vec<f64>,
+}
+
+// `fn(x: int) { ... }` を評価した値。本体は `function` という名前の持ち上げた関数で、
+// 作った時点の外側の変数の値を `captured` に持つ。複製しても捕まえた値は共有する
+#[derive(Debug, Clone)]
+struct ClosureValue {
+    function: String,
+    captured: Rc<Vec<(String, Box<dyn Any>)>>,
+}
+
+impl MatrixValue {
+    pub fn get(&self, row: usize, col: usize) -> f64 {
+        self.elements[row * self.cols + col]
+    }
+}
+
+// `Runtime::set_observer` で登録すると、各命令を実行する直前に呼ばれる。
+// `runtime` からはその時点のスコープで式を評価できる (`Runtime::evaluate`)
+pub trait ExecutionObserver {
+    fn on_instruction(&self, loc: Location, runtime: &mut Runtime) -> StepAction;
+
+    // 関数 (`main` を含む) を抜けてフレームを解放した直後に呼ばれる。`released` は解放した順の変数名で、
+    // 優先度の低い変数が先に来る (`MemoryManager::pop_frame`)
+    fn on_frame_released(&self, _function: &str, _released: &[String]) {}
 }
 
 impl Runtime {
@@ -15,137 +172,387 @@ impl Runtime {
             memory_manager: MemoryManager::new(),
             priority_ownership_manager: PriorityOwnershipManager::new(),
             standard_library: StandardLibrary::new(),
+            host_signatures: HashMap::new(),
+            functions: HashMap::new(),
+            debug: false,
+            observer: None,
+            aborted_at: None,
+            overflow_mode: OverflowMode::default(),
+            profiler: None,
+        }
+    }
+
+    // 整数演算の溢れを `overflow_mode` で扱う実行環境
+    pub fn with_overflow_mode(overflow_mode: OverflowMode) -> Self {
+        Self { overflow_mode, ..Self::new() }
+    }
+
+    pub fn set_observer(&mut self, observer: Rc<dyn ExecutionObserver>) {
+        self.observer = Some(observer);
+    }
+
+    pub fn aborted_at(&self) -> Option<&Location> {
+        self.aborted_at.as_ref()
+    }
+
+    // 現在のフレームの変数に優先度を設定する。
+    // 同じ名前に続けて設定した場合は履歴として残り、`get_priority` は最後の値を返す
+    pub fn set_priority(&mut self, name: &str, priority: i32) {
+        self.priority_ownership_manager.set_priority(name.to_string(), priority);
+        self.memory_manager.set_priority(name, priority);
+    }
+
+    // 現在のフレームの変数に最後に割り当てられた優先度
+    pub fn get_priority(&self, name: &str) -> Option<i32> {
+        self.priority_ownership_manager.get_priority(name)?.last().copied()
+    }
+
+    pub fn set_debug(&mut self, debug: bool) {
+        self.debug = debug;
+    }
+
+    pub fn enable_profiling(&mut self) {
+        self.profiler.get_or_insert_with(Profiler::new);
+    }
+
+    // これまでの実行回数を取り出す (キーは `Profiler` を参照)。計測は続き、次はまた 0 から数える。
+    // `enable_profiling` していなければ空
+    pub fn take_profile(&mut self) -> HashMap<String, u64> {
+        self.profiler.as_mut().map(Profiler::take).unwrap_or_default()
+    }
+
+    /// ホスト側の Rust 関数を `name` で呼び出せるようにする。同名の組み込み関数は置き換わる。
+    ///
+    /// 引数と戻り値は [`Value`] で受け渡す: `int` は `Value::Int`、`float` は `Value::Float`、
+    /// `bool` は `Value::Bool`、`string` と `char` は `Value::Str`、配列は `Value::Array`、
+    /// 値のない結果は `Value::Null`。引数は呼び出し側の値のコピーなので、書き換えても
+    /// 呼び出し元には反映されない。引数の数や型が合わない場合は `SlangError::runtime` で作った実行時エラーを返すこと
+    /// (`Value` から `i64` などへの `TryFrom` はそのエラーを返す)。
+    ///
+    /// `params` と `return_type` はソースから見た関数の型で、[`Runtime::compile`] と [`Runtime::run`] の型検査に使う。
+    ///
+    /// ```
+    /// use slang::{Runtime, RuntimeErrorKind, SlangError, Type, Value};
+    ///
+    /// let mut runtime = Runtime::new();
+    /// runtime.register_function("double", vec![Type::Int], Type::Int, |args: &[Value]| match args {
+    ///     [x] => Ok(Value::from(i64::try_from(x.clone())? * 2)),
+    ///     _ => Err(SlangError::runtime(RuntimeErrorKind::ArgumentCount, "double() takes one argument")),
+    /// });
+    /// runtime.run("fn main() { assert_eq(double(21), 42); }").unwrap();
+    /// assert!(runtime.compile("fn main() -> int { return double(1.5); }").is_err());
+    /// ```
+    pub fn register_function(
+        &mut self,
+        name: &str,
+        params: Vec<crate::type_system::Type>,
+        return_type: crate::type_system::Type,
+        f: impl Fn(&[Value]) -> Result<Value> + 'static,
+    ) {
+        let function = move |args: &[Box<dyn Any>]| {
+            let args = args.iter()
+                .map(|arg| Value::from_any(arg.as_ref()))
+                .collect::<Result<Vec<_>>>()?;
+            Ok(f(&args)?.into_any())
+        };
+        self.standard_library.functions.insert(name.to_string(), Box::new(function));
+        let signature = crate::type_system::Type::Function { params, return_type: Box::new(return_type), priority: None };
+        self.host_signatures.insert(name.to_string(), signature);
+    }
+
+    /// [`crate::compile`] と同じだが、`register_function` で登録した関数も呼び出せるものとして型検査する。
+    pub fn compile(&self, source: &str) -> Result<crate::ir::IR> {
+        let mut checker = crate::type_system::TypeChecker::new();
+        for (name, signature) in &self.host_signatures {
+            checker.declare_function(name, signature.clone());
         }
+        crate::compile_with(source, checker)
+    }
+
+    /// ソースを [`Runtime::compile`] して、この実行環境で [`Runtime::execute`] する。
+    pub fn run(&mut self, source: &str) -> Result<()> {
+        let ir = self.compile(source)?;
+        self.execute(&ir)
     }
 
+    // `main` があればそこから実行し、なければ関数を定義順にすべて実行する。
+    // 監視側が `StepAction::Abort` を返した場合はそこで打ち切り、エラーにはしない
     pub fn execute(&mut self, ir: &crate::ir::IR) -> Result<()> {
+        self.aborted_at = None;
+        match self.execute_program(ir) {
+            Err(_) if self.aborted_at.is_some() => Ok(()),
+            result => result,
+        }
+    }
+
+    fn execute_program(&mut self, ir: &crate::ir::IR) -> Result<()> {
         for function in &ir.functions {
-            self.execute_function(function)?;
+            self.functions.insert(function.name.clone(), Rc::new(function.clone()));
+        }
+        if let Some(main) = self.functions.get("main").cloned() {
+            self.execute_in_frame(&main, HashMap::new())?;
+            return Ok(());
+        }
+        // 持ち上げたラムダは呼び出されたときだけ実行する
+        for function in ir.functions.iter().filter(|function| !function.is_lambda()) {
+            self.execute_in_frame(function, HashMap::new())?;
         }
         Ok(())
     }
 
-    fn execute_function(&mut self, function: &crate::ir::IRFunction) -> Result<()> {
-        for block in &function.blocks {
-            for instruction in &block.instructions {
-                self.execute_instruction(instruction)?;
+    // ブロック単位で実行し、分岐命令に従って次のブロックへ移る。
+    // 分岐せずにブロックの末尾に達した場合は値を返さずに関数を抜ける
+    fn execute_function(&mut self, function: &crate::ir::IRFunction) -> Result<Option<Box<dyn Any>>> {
+        let labels: HashMap<&str, usize> = function.blocks.iter()
+            .enumerate()
+            .map(|(index, block)| (block.label.as_str(), index))
+            .collect();
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record_call(&function.name);
+        }
+        let mut current = 0;
+        while let Some(block) = function.blocks.get(current) {
+            let mut next = None;
+            for (index, instruction) in block.instructions.iter().enumerate() {
+                self.notify_observer(function, block, index)?;
+                if let Some(profiler) = &mut self.profiler {
+                    profiler.record_instruction(&function.name, &block.label, index);
+                }
+                match self.execute_instruction(instruction)? {
+                    ControlFlow::Next => {}
+                    ControlFlow::Jump(label) => {
+                        next = Some(*labels.get(label.as_str()).ok_or_else(|| {
+                            SlangError::runtime(RuntimeErrorKind::Other, format!("Unknown block label: {}", label))
+                        })?);
+                        break;
+                    }
+                    ControlFlow::Return(value) => return Ok(value),
+                }
+            }
+            match next {
+                Some(index) => current = index,
+                None => break,
             }
         }
+        Ok(None)
+    }
+
+    // 打ち切る場合は呼び出し元の関数まで戻れるようにエラーとして返す (`execute` で取り除く)
+    fn notify_observer(&mut self, function: &crate::ir::IRFunction, block: &crate::ir::IRBlock, index: usize) -> Result<()> {
+        let Some(observer) = self.observer.clone() else {
+            return Ok(());
+        };
+        let location = Location {
+            function: function.name.clone(),
+            block: block.label.clone(),
+            instruction: index,
+        };
+        if observer.on_instruction(location.clone(), self) == StepAction::Abort {
+            let error = SlangError::runtime(RuntimeErrorKind::Other, format!("Execution aborted at {}", location));
+            self.aborted_at = Some(location);
+            return Err(error);
+        }
         Ok(())
     }
 
-    fn execute_instruction(&mut self, instruction: &crate::ir::IRInstruction) -> Result<()> {
+    fn execute_instruction(&mut self, instruction: &crate::ir::IRInstruction) -> Result<ControlFlow> {
         match instruction {
             crate::ir::IRInstruction::Alloca { name, type_annotation: _ } => {
-                self.memory_manager.heap.insert(name.clone(), Box::new(()));
-                Ok(())
+                self.memory_manager.define(name.clone(), Box::new(()));
             }
             crate::ir::IRInstruction::Store { name, value } => {
                 let value = self.evaluate_value(value)?;
-                self.memory_manager.heap.insert(name.clone(), value);
-                Ok(())
+                self.memory_manager.define(name.clone(), value);
             }
             crate::ir::IRInstruction::Load { name } => {
-                if !self.memory_manager.heap.contains_key(name) {
-                    return Err(SlangError::Runtime(format!("Undefined variable: {}", name)));
+                if self.memory_manager.get_value(name).is_none() {
+                    return Err(SlangError::runtime(RuntimeErrorKind::UndefinedVariable, format!("Undefined variable: {}", name)));
                 }
-                Ok(())
             }
             crate::ir::IRInstruction::BinaryOp { dest, op, left, right } => {
-                let left_value = self.evaluate_value(left)?;
-                let right_value = self.evaluate_value(right)?;
-                let result = self.execute_binary_op(op, left_value, right_value)?;
-                self.memory_manager.heap.insert(dest.clone(), result);
-                Ok(())
+                let result = self.evaluate_binary_op(op, left, right)?;
+                self.memory_manager.define(dest.clone(), result);
             }
             crate::ir::IRInstruction::UnaryOp { dest, op, expr } => {
                 let value = self.evaluate_value(expr)?;
                 let result = self.execute_unary_op(op, value)?;
-                self.memory_manager.heap.insert(dest.clone(), result);
-                Ok(())
+                self.memory_manager.define(dest.clone(), result);
             }
             crate::ir::IRInstruction::Call { dest, function, arguments } => {
-                let func = self.evaluate_value(function)?;
                 let args = arguments.iter()
                     .map(|arg| self.evaluate_value(arg))
                     .collect::<Result<Vec<_>>>()?;
-
-                if let Some(f) = func.downcast_ref::<Box<dyn Fn(&[Box<dyn Any>]) -> Result<Box<dyn Any>>>>() {
-                    let result = f(&args)?;
-                    self.memory_manager.heap.insert(dest.clone(), result);
-                }
-                Ok(())
+                let result = self.execute_function_call(function, args)?;
+                self.memory_manager.define(dest.clone(), result);
             }
             crate::ir::IRInstruction::Return(value) => {
-                if let Some(value) = value {
-                    let value = self.evaluate_value(value)?;
-                    println!("Return value: {:?}", value);
-                }
-                Ok(())
+                let value = match value {
+                    Some(value) => {
+                        let value = self.evaluate_value(value)?;
+                        if self.debug {
+                            println!("Return value: {}", describe_value(value.as_ref()));
+                        }
+                        Some(value)
+                    }
+                    None => None,
+                };
+                return Ok(ControlFlow::Return(value));
             }
             crate::ir::IRInstruction::Branch { label } => {
-                // self.current_block = label.clone(); // Remove or comment out
-                Ok(())
+                return Ok(ControlFlow::Jump(label.clone()));
             }
             crate::ir::IRInstruction::ConditionalBranch { condition, then_label, else_label } => {
                 let cond = self.evaluate_value(condition)?;
-                if let Some(b) = cond.downcast_ref::<bool>() {
-                    // self.current_block = if *b { then_label.clone() } else { else_label.clone() };
-                } else {
-                    return Err(SlangError::Runtime("Condition must be boolean".to_string()));
-                }
-                Ok(())
+                let b = cond.downcast_ref::<bool>()
+                    .ok_or_else(|| SlangError::runtime(RuntimeErrorKind::TypeMismatch, "Condition must be boolean"))?;
+                let label = if *b { then_label } else { else_label };
+                return Ok(ControlFlow::Jump(label.clone()));
             }
             crate::ir::IRInstruction::Assignment { target, value } => {
                 let value = self.evaluate_value(value)?;
-                self.memory_manager.heap.insert(target.clone(), value);
-                Ok(())
+                self.memory_manager.assign(target.clone(), value);
             }
             crate::ir::IRInstruction::Expression(value) => {
                 self.evaluate_value(value)?;
-                Ok(())
             }
             crate::ir::IRInstruction::Let { name, value } => {
                 let value = self.evaluate_value(value)?;
-                self.memory_manager.heap.insert(name.clone(), value);
-                Ok(())
+                self.memory_manager.define(name.clone(), value);
+            }
+            crate::ir::IRInstruction::Transfer { from, to } => {
+                self.priority_ownership_manager.transfer_ownership(from, to)?;
+                if let Some(priority) = self.get_priority(to) {
+                    self.memory_manager.set_priority(to, priority);
+                }
+            }
+            crate::ir::IRInstruction::Priority { name, priority } => {
+                self.set_priority(name, *priority);
             }
         }
+        Ok(ControlFlow::Next)
+    }
+
+    // 現在のスコープで値を評価する。評価中に呼ばれた関数の命令は監視側に通知しない
+    pub fn evaluate(&mut self, value: &crate::ir::IRValue) -> Result<Value> {
+        let observer = self.observer.take();
+        let result = self.evaluate_value(value);
+        self.observer = observer;
+        Value::from_any(result?.as_ref())
     }
 
     fn evaluate_value(&mut self, value: &crate::ir::IRValue) -> Result<Box<dyn Any>> {
         match value {
             crate::ir::IRValue::Int(i) => Ok(Box::new(*i)),
             crate::ir::IRValue::Float(f) => Ok(Box::new(*f)),
+            crate::ir::IRValue::Complex(re, im) => Ok(Box::new(ComplexValue { re: *re, im: *im })),
             crate::ir::IRValue::Bool(b) => Ok(Box::new(*b)),
+            crate::ir::IRValue::Char(c) => Ok(Box::new(*c)),
             crate::ir::IRValue::String(s) => Ok(Box::new(s.clone())),
             crate::ir::IRValue::Null => Ok(Box::new(())),
-            crate::ir::IRValue::Variable(name) => {
-                self.memory_manager.get_value(name)
-                    .map(|v| Box::new(v.as_ref().clone()))
-                    .ok_or_else(|| SlangError::Runtime(format!("Variable not found: {}", name)))
-            }
-            crate::ir::IRValue::BinaryOp { left, op, right } => {
-                let left_value = self.evaluate_value(left)?;
-                let right_value = self.evaluate_value(right)?;
-                self.execute_binary_op(op, left_value, right_value)
+            crate::ir::IRValue::Constant(value) => self.evaluate_value(value),
+            crate::ir::IRValue::Identifier(name) | crate::ir::IRValue::Variable(name) => {
+                let value = self.memory_manager.get_value(name)
+                    .ok_or_else(|| SlangError::runtime(RuntimeErrorKind::UndefinedVariable, format!("Variable not found: {}", name)))?;
+                clone_value(value.as_ref())
             }
+            crate::ir::IRValue::BinaryOp { left, op, right } => self.evaluate_binary_op(op, left, right),
             crate::ir::IRValue::UnaryOp { op, expr } => {
                 let value = self.evaluate_value(expr)?;
                 self.execute_unary_op(op, value)
             }
             crate::ir::IRValue::Call { function, arguments } => {
+                // `push(xs, x)` のように第1引数を書き換える組み込み関数に変数を渡した場合は、その場で呼び出す
+                let shadowed = self.functions.contains_key(function) || self.memory_manager.get_value(function).is_some();
+                if !shadowed && self.standard_library.is_in_place(function) {
+                    if let Some((crate::ir::IRValue::Variable(name), rest)) = arguments.split_first() {
+                        let args = rest.iter()
+                            .map(|arg| self.evaluate_value(arg))
+                            .collect::<Result<Vec<_>>>()?;
+                        let target = self.memory_manager.get_value_mut(name)
+                            .ok_or_else(|| SlangError::runtime(RuntimeErrorKind::UndefinedVariable, format!("Variable not found: {}", name)))?;
+                        return self.standard_library.call_in_place(function, target.as_mut(), &args);
+                    }
+                }
                 let arg_values = arguments.iter()
                     .map(|arg| self.evaluate_value(arg))
                     .collect::<Result<Vec<_>>>()?;
-                self.execute_function(function, &arg_values)
+                self.execute_function_call(function, arg_values)
             }
             crate::ir::IRValue::Assignment { name, value } => {
                 let value = self.evaluate_value(value)?;
-                self.memory_manager.heap.insert(name.clone(), value.clone());
+                self.memory_manager.assign(name.clone(), clone_value(value.as_ref())?);
                 Ok(value)
             }
+            crate::ir::IRValue::Array(elements) => {
+                let elements = elements.iter()
+                    .map(|element| self.evaluate_value(element))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(Box::new(elements))
+            }
+            crate::ir::IRValue::Index { array, index } => {
+                let array = self.evaluate_value(array)?;
+                let index = self.evaluate_value(index)?;
+                let elements = array.downcast_ref::<Vec<Box<dyn Any>>>()
+                    .ok_or_else(|| SlangError::runtime(RuntimeErrorKind::TypeMismatch, "Cannot index into a non-array value"))?;
+                let index = index.downcast_ref::<i64>()
+                    .ok_or_else(|| SlangError::runtime(RuntimeErrorKind::TypeMismatch, "Array index must be an integer"))?;
+                let element = usize::try_from(*index).ok()
+                    .and_then(|i| elements.get(i))
+                    .ok_or_else(|| SlangError::runtime(RuntimeErrorKind::IndexOutOfBounds, format!(
+                        "Index {} out of bounds for array of length {}",
+                        index,
+                        elements.len()
+                    )))?;
+                clone_value(element.as_ref())
+            }
+            crate::ir::IRValue::MethodCall { receiver, method, arguments } => {
+                let args = arguments.iter()
+                    .map(|arg| self.evaluate_value(arg))
+                    .collect::<Result<Vec<_>>>()?;
+                // 変数がレシーバの場合は `list.push(x)` の変更が残るようにその場で呼び出す
+                if let crate::ir::IRValue::Variable(name) = receiver.as_ref() {
+                    let value = self.memory_manager.get_value_mut(name)
+                        .ok_or_else(|| SlangError::runtime(RuntimeErrorKind::UndefinedVariable, format!("Variable not found: {}", name)))?;
+                    return self.standard_library.call_method(value.as_mut(), method, &args);
+                }
+                let mut value = self.evaluate_value(receiver)?;
+                self.standard_library.call_method(value.as_mut(), method, &args)
+            }
+            crate::ir::IRValue::Cast { value, target } => {
+                let value = self.evaluate_value(value)?;
+                cast_value(value.as_ref(), target)
+            }
+            // 作った時点の値を捕まえる。スコープにない名前 (関数名など) は捕まえない
+            crate::ir::IRValue::Closure { function, captures } => {
+                let captured = captures.iter()
+                    .filter_map(|name| self.memory_manager.get_value(name).map(|value| (name, value)))
+                    .map(|(name, value)| Ok((name.clone(), clone_value(value.as_ref())?)))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(Box::new(ClosureValue { function: function.clone(), captured: Rc::new(captured) }))
+            }
+        }
+    }
+
+    // `&&` と `||` は左辺で結果が決まれば右辺を評価しない
+    fn evaluate_binary_op(
+        &mut self,
+        op: &crate::ir::IRBinaryOperator,
+        left: &crate::ir::IRValue,
+        right: &crate::ir::IRValue,
+    ) -> Result<Box<dyn Any>> {
+        let left_value = self.evaluate_value(left)?;
+        let short_circuit = match op {
+            crate::ir::IRBinaryOperator::And => Some(false),
+            crate::ir::IRBinaryOperator::Or => Some(true),
+            _ => None,
+        };
+        if let (Some(determined), Some(l)) = (short_circuit, left_value.downcast_ref::<bool>()) {
+            if *l == determined {
+                return Ok(Box::new(determined));
+            }
         }
+        let right_value = self.evaluate_value(right)?;
+        self.execute_binary_op(op, left_value, right_value)
     }
 
     fn execute_binary_op(
@@ -154,115 +561,147 @@ impl Runtime {
         left: Box<dyn Any>,
         right: Box<dyn Any>,
     ) -> Result<Box<dyn Any>> {
+        if let Some(result) = linear_algebra_op(op, left.as_ref(), right.as_ref()) {
+            return result;
+        }
+        if let Some(result) = complex_op(op, left.as_ref(), right.as_ref()) {
+            return result;
+        }
+        if let Some(result) = quaternion_op(op, left.as_ref(), right.as_ref()) {
+            return result;
+        }
+        if let Some(result) = tensor_op(op, left.as_ref(), right.as_ref()) {
+            return result;
+        }
+        let (left, right) = promote_mixed_numbers(left, right);
         match op {
             crate::ir::IRBinaryOperator::Add => {
                 if let (Some(l), Some(r)) = (
                     left.downcast_ref::<i64>(),
                     right.downcast_ref::<i64>(),
                 ) {
-                    Ok(Box::new(l + r))
+                    Ok(Box::new(self.overflow_mode.select(
+                        "addition",
+                        l.checked_add(*r),
+                        l.wrapping_add(*r),
+                        l.saturating_add(*r),
+                    )?))
                 } else if let (Some(l), Some(r)) = (
                     left.downcast_ref::<f64>(),
                     right.downcast_ref::<f64>(),
                 ) {
                     Ok(Box::new(l + r))
+                } else if let (Some(l), Some(r)) = (
+                    left.downcast_ref::<String>(),
+                    right.downcast_ref::<String>(),
+                ) {
+                    Ok(Box::new(format!("{}{}", l, r)))
                 } else {
-                    Err(SlangError::Runtime("Invalid operands for addition".to_string()))
+                    Err(SlangError::runtime(RuntimeErrorKind::TypeMismatch, "Invalid operands for addition"))
                 }
             }
-            crate::ir::IRBinaryOperator::Subtract => {
+            crate::ir::IRBinaryOperator::Sub | crate::ir::IRBinaryOperator::Subtract => {
                 if let (Some(l), Some(r)) = (
                     left.downcast_ref::<i64>(),
                     right.downcast_ref::<i64>(),
                 ) {
-                    Ok(Box::new(l - r))
+                    Ok(Box::new(self.overflow_mode.select(
+                        "subtraction",
+                        l.checked_sub(*r),
+                        l.wrapping_sub(*r),
+                        l.saturating_sub(*r),
+                    )?))
                 } else if let (Some(l), Some(r)) = (
                     left.downcast_ref::<f64>(),
                     right.downcast_ref::<f64>(),
                 ) {
                     Ok(Box::new(l - r))
                 } else {
-                    Err(SlangError::Runtime("Invalid operands for subtraction".to_string()))
+                    Err(SlangError::runtime(RuntimeErrorKind::TypeMismatch, "Invalid operands for subtraction"))
                 }
             }
-            crate::ir::IRBinaryOperator::Multiply => {
+            crate::ir::IRBinaryOperator::Mul | crate::ir::IRBinaryOperator::Multiply => {
                 if let (Some(l), Some(r)) = (
                     left.downcast_ref::<i64>(),
                     right.downcast_ref::<i64>(),
                 ) {
-                    Ok(Box::new(l * r))
+                    Ok(Box::new(self.overflow_mode.select(
+                        "multiplication",
+                        l.checked_mul(*r),
+                        l.wrapping_mul(*r),
+                        l.saturating_mul(*r),
+                    )?))
                 } else if let (Some(l), Some(r)) = (
                     left.downcast_ref::<f64>(),
                     right.downcast_ref::<f64>(),
                 ) {
                     Ok(Box::new(l * r))
                 } else {
-                    Err(SlangError::Runtime("Invalid operands for multiplication".to_string()))
+                    Err(SlangError::runtime(RuntimeErrorKind::TypeMismatch, "Invalid operands for multiplication"))
                 }
             }
-            crate::ir::IRBinaryOperator::Divide => {
+            crate::ir::IRBinaryOperator::Div | crate::ir::IRBinaryOperator::Divide => {
                 if let (Some(l), Some(r)) = (
                     left.downcast_ref::<i64>(),
                     right.downcast_ref::<i64>(),
                 ) {
                     if *r == 0 {
-                        Err(SlangError::Runtime("Division by zero".to_string()))
+                        Err(SlangError::runtime(RuntimeErrorKind::DivByZero, "Division by zero"))
                     } else {
-                        Ok(Box::new(l / r))
+                        // 溢れるのは `i64::MIN / -1` だけ
+                        Ok(Box::new(self.overflow_mode.select(
+                            "division",
+                            l.checked_div(*r),
+                            l.wrapping_div(*r),
+                            l.saturating_div(*r),
+                        )?))
                     }
                 } else if let (Some(l), Some(r)) = (
                     left.downcast_ref::<f64>(),
                     right.downcast_ref::<f64>(),
                 ) {
                     if *r == 0.0 {
-                        Err(SlangError::Runtime("Division by zero".to_string()))
+                        Err(SlangError::runtime(RuntimeErrorKind::DivByZero, "Division by zero"))
                     } else {
                         Ok(Box::new(l / r))
                     }
                 } else {
-                    Err(SlangError::Runtime("Invalid operands for division".to_string()))
+                    Err(SlangError::runtime(RuntimeErrorKind::TypeMismatch, "Invalid operands for division"))
                 }
             }
-            crate::ir::IRBinaryOperator::Modulo => {
+            crate::ir::IRBinaryOperator::Mod | crate::ir::IRBinaryOperator::Modulo => {
                 if let (Some(l), Some(r)) = (
                     left.downcast_ref::<i64>(),
                     right.downcast_ref::<i64>(),
                 ) {
                     if *r == 0 {
-                        Err(SlangError::Runtime("Modulo by zero".to_string()))
+                        Err(SlangError::runtime(RuntimeErrorKind::DivByZero, "Modulo by zero"))
                     } else {
-                        Ok(Box::new(l % r))
+                        // `i64::MIN % -1` の数学的な値は 0 なので、飽和させる場合も 0
+                        Ok(Box::new(self.overflow_mode.select(
+                            "modulo",
+                            l.checked_rem(*r),
+                            l.wrapping_rem(*r),
+                            l.wrapping_rem(*r),
+                        )?))
                     }
-                } else {
-                    Err(SlangError::Runtime("Invalid operands for modulo".to_string()))
-                }
-            }
-            crate::ir::IRBinaryOperator::Equals => {
-                if let (Some(l), Some(r)) = (
-                    left.downcast_ref::<i64>(),
-                    right.downcast_ref::<i64>(),
-                ) {
-                    Ok(Box::new(l == r))
                 } else if let (Some(l), Some(r)) = (
                     left.downcast_ref::<f64>(),
                     right.downcast_ref::<f64>(),
                 ) {
-                    Ok(Box::new(l == r))
-                } else if let (Some(l), Some(r)) = (
-                    left.downcast_ref::<String>(),
-                    right.downcast_ref::<String>(),
-                ) {
-                    Ok(Box::new(l == r))
-                } else if let (Some(l), Some(r)) = (
-                    left.downcast_ref::<bool>(),
-                    right.downcast_ref::<bool>(),
-                ) {
-                    Ok(Box::new(l == r))
+                    if *r == 0.0 {
+                        Err(SlangError::runtime(RuntimeErrorKind::DivByZero, "Modulo by zero"))
+                    } else {
+                        Ok(Box::new(l % r))
+                    }
                 } else {
-                    Err(SlangError::Runtime("Invalid operands for equality".to_string()))
+                    Err(SlangError::runtime(RuntimeErrorKind::TypeMismatch, "Invalid operands for modulo"))
                 }
             }
-            crate::ir::IRBinaryOperator::NotEquals => {
+            crate::ir::IRBinaryOperator::Eq | crate::ir::IRBinaryOperator::Equals => {
+                Ok(Box::new(scalar_equal(left.as_ref(), right.as_ref())?))
+            }
+            crate::ir::IRBinaryOperator::Neq | crate::ir::IRBinaryOperator::NotEquals => {
                 if let (Some(l), Some(r)) = (
                     left.downcast_ref::<i64>(),
                     right.downcast_ref::<i64>(),
@@ -283,11 +722,18 @@ impl Runtime {
                     right.downcast_ref::<bool>(),
                 ) {
                     Ok(Box::new(l != r))
+                } else if let (Some(l), Some(r)) = (
+                    left.downcast_ref::<char>(),
+                    right.downcast_ref::<char>(),
+                ) {
+                    Ok(Box::new(l != r))
+                } else if left.is::<()>() || right.is::<()>() {
+                    Ok(Box::new(!(left.is::<()>() && right.is::<()>())))
                 } else {
-                    Err(SlangError::Runtime("Invalid operands for inequality".to_string()))
+                    Err(SlangError::runtime(RuntimeErrorKind::TypeMismatch, "Invalid operands for inequality"))
                 }
             }
-            crate::ir::IRBinaryOperator::LessThan => {
+            crate::ir::IRBinaryOperator::Lt | crate::ir::IRBinaryOperator::LessThan => {
                 if let (Some(l), Some(r)) = (
                     left.downcast_ref::<i64>(),
                     right.downcast_ref::<i64>(),
@@ -298,11 +744,16 @@ impl Runtime {
                     right.downcast_ref::<f64>(),
                 ) {
                     Ok(Box::new(l < r))
+                } else if let (Some(l), Some(r)) = (
+                    left.downcast_ref::<String>(),
+                    right.downcast_ref::<String>(),
+                ) {
+                    Ok(Box::new(l < r))
                 } else {
-                    Err(SlangError::Runtime("Invalid operands for less than".to_string()))
+                    Err(SlangError::runtime(RuntimeErrorKind::TypeMismatch, "Invalid operands for less than"))
                 }
             }
-            crate::ir::IRBinaryOperator::GreaterThan => {
+            crate::ir::IRBinaryOperator::Gt | crate::ir::IRBinaryOperator::GreaterThan => {
                 if let (Some(l), Some(r)) = (
                     left.downcast_ref::<i64>(),
                     right.downcast_ref::<i64>(),
@@ -313,11 +764,16 @@ impl Runtime {
                     right.downcast_ref::<f64>(),
                 ) {
                     Ok(Box::new(l > r))
+                } else if let (Some(l), Some(r)) = (
+                    left.downcast_ref::<String>(),
+                    right.downcast_ref::<String>(),
+                ) {
+                    Ok(Box::new(l > r))
                 } else {
-                    Err(SlangError::Runtime("Invalid operands for greater than".to_string()))
+                    Err(SlangError::runtime(RuntimeErrorKind::TypeMismatch, "Invalid operands for greater than"))
                 }
             }
-            crate::ir::IRBinaryOperator::LessThanEquals => {
+            crate::ir::IRBinaryOperator::Lte | crate::ir::IRBinaryOperator::LessThanEquals => {
                 if let (Some(l), Some(r)) = (
                     left.downcast_ref::<i64>(),
                     right.downcast_ref::<i64>(),
@@ -328,11 +784,16 @@ impl Runtime {
                     right.downcast_ref::<f64>(),
                 ) {
                     Ok(Box::new(l <= r))
+                } else if let (Some(l), Some(r)) = (
+                    left.downcast_ref::<String>(),
+                    right.downcast_ref::<String>(),
+                ) {
+                    Ok(Box::new(l <= r))
                 } else {
-                    Err(SlangError::Runtime("Invalid operands for less than or equal".to_string()))
+                    Err(SlangError::runtime(RuntimeErrorKind::TypeMismatch, "Invalid operands for less than or equal"))
                 }
             }
-            crate::ir::IRBinaryOperator::GreaterThanEquals => {
+            crate::ir::IRBinaryOperator::Gte | crate::ir::IRBinaryOperator::GreaterThanEquals => {
                 if let (Some(l), Some(r)) = (
                     left.downcast_ref::<i64>(),
                     right.downcast_ref::<i64>(),
@@ -343,164 +804,1052 @@ impl Runtime {
                     right.downcast_ref::<f64>(),
                 ) {
                     Ok(Box::new(l >= r))
+                } else if let (Some(l), Some(r)) = (
+                    left.downcast_ref::<String>(),
+                    right.downcast_ref::<String>(),
+                ) {
+                    Ok(Box::new(l >= r))
+                } else {
+                    Err(SlangError::runtime(RuntimeErrorKind::TypeMismatch, "Invalid operands for greater than or equal"))
+                }
+            }
+            crate::ir::IRBinaryOperator::And | crate::ir::IRBinaryOperator::Or => {
+                if let (Some(l), Some(r)) = (
+                    left.downcast_ref::<bool>(),
+                    right.downcast_ref::<bool>(),
+                ) {
+                    let result = match op {
+                        crate::ir::IRBinaryOperator::And => *l && *r,
+                        _ => *l || *r,
+                    };
+                    Ok(Box::new(result))
                 } else {
-                    Err(SlangError::Runtime("Invalid operands for greater than or equal".to_string()))
+                    Err(SlangError::runtime(RuntimeErrorKind::TypeMismatch, "Invalid operands for logical operator"))
                 }
             }
-            _ => todo!(),
         }
     }
 
+    // 演算子ごとに受け付ける値の型を列挙する。それ以外 (char の符号反転など) は型の不一致としてエラーにする
     fn execute_unary_op(
         &mut self,
         op: &crate::ir::IRUnaryOperator,
         expr: Box<dyn Any>,
     ) -> Result<Box<dyn Any>> {
         match op {
-            crate::ir::IRUnaryOperator::Negate => {
+            crate::ir::IRUnaryOperator::Neg | crate::ir::IRUnaryOperator::Negate => {
                 if let Some(i) = expr.downcast_ref::<i64>() {
-                    Ok(Box::new(-i))
+                    Ok(Box::new(self.overflow_mode.select(
+                        "negation",
+                        i.checked_neg(),
+                        i.wrapping_neg(),
+                        i.saturating_neg(),
+                    )?))
                 } else if let Some(f) = expr.downcast_ref::<f64>() {
                     Ok(Box::new(-f))
+                } else if let Some(c) = expr.downcast_ref::<ComplexValue>() {
+                    Ok(Box::new(ComplexValue { re: -c.re, im: -c.im }))
                 } else {
-                    Err(SlangError::Runtime("Invalid operand for negation".to_string()))
+                    Err(SlangError::runtime(RuntimeErrorKind::TypeMismatch, format!(
+                        "Invalid operand for negation: {}",
+                        runtime_type_name(expr.as_ref())
+                    )))
                 }
             }
             crate::ir::IRUnaryOperator::Not => {
                 if let Some(b) = expr.downcast_ref::<bool>() {
                     Ok(Box::new(!b))
                 } else {
-                    Err(SlangError::Runtime("Invalid operand for logical not".to_string()))
+                    Err(SlangError::runtime(RuntimeErrorKind::TypeMismatch, format!(
+                        "Invalid operand for logical not: {}",
+                        runtime_type_name(expr.as_ref())
+                    )))
                 }
             }
-            _ => todo!(),
         }
     }
 
-    fn execute_function(&mut self, function: &str, arguments: &[Box<dyn Any>]) -> Result<Box<dyn Any>> {
-        if let Some(func) = self.standard_library.get_function(function) {
-            if func.parameters.len() != arguments.len() {
-                return Err(SlangError::Runtime(format!(
-                    "Function {} expects {} arguments, got {}",
-                    function, func.parameters.len(), arguments.len()
-                )));
-            }
-
-            // Create a new scope for the function call
-            let mut scope = HashMap::new();
-            for (param, arg) in func.parameters.iter().zip(arguments.iter()) {
-                scope.insert(param.name.clone(), arg.clone());
-            }
-
-            // Execute the function body
-            let result = self.execute_block(&func.body, &mut scope)?;
-            Ok(result)
-        } else {
-            Err(SlangError::Runtime(format!("Function not found: {}", function)))
+    // 名前はローカル変数のクロージャ、ユーザー定義関数、組み込み関数の順に探す
+    fn execute_function_call(&mut self, function: &str, arguments: Vec<Box<dyn Any>>) -> Result<Box<dyn Any>> {
+        let closure = self.memory_manager.get_value(function)
+            .and_then(|value| value.downcast_ref::<ClosureValue>())
+            .cloned();
+        if let Some(closure) = closure {
+            return self.call_closure(&closure, arguments);
+        }
+        if let Some(callee) = self.functions.get(function).cloned() {
+            return self.call_user_function(&callee, HashMap::new(), arguments);
+        }
+        if self.standard_library.is_in_place(function) {
+            let mut arguments = arguments.into_iter();
+            let mut target = arguments.next()
+                .ok_or_else(|| SlangError::runtime(RuntimeErrorKind::ArgumentCount, format!("{}() requires an argument", function)))?;
+            let rest: Vec<_> = arguments.collect();
+            return self.standard_library.call_in_place(function, target.as_mut(), &rest);
         }
+        let func = self.standard_library.get_function(function)
+            .ok_or_else(|| SlangError::runtime(RuntimeErrorKind::UndefinedFunction, format!("Function not found: {}", function)))?;
+        func(&arguments)
     }
-}
 
-struct MemoryManager {
-    heap: HashMap<String, Box<dyn Any>>,
-    stack: Vec<Box<dyn Any>>,
-}
+    fn call_closure(&mut self, closure: &ClosureValue, arguments: Vec<Box<dyn Any>>) -> Result<Box<dyn Any>> {
+        let callee = self.functions.get(&closure.function).cloned()
+            .ok_or_else(|| SlangError::runtime(RuntimeErrorKind::UndefinedFunction, format!("Function not found: {}", closure.function)))?;
+        let captured = closure.captured.iter()
+            .map(|(name, value)| Ok((name.clone(), clone_value(value.as_ref())?)))
+            .collect::<Result<HashMap<_, _>>>()?;
+        self.call_user_function(&callee, captured, arguments)
+    }
 
-impl MemoryManager {
-    fn new() -> Self {
-        Self {
-            heap: HashMap::new(),
-            stack: Vec::new(),
+    // `captured` (クロージャが捕まえた値) と引数を束縛した新しいフレームで実行する。名前が重なれば引数が優先される
+    fn call_user_function(
+        &mut self,
+        function: &crate::ir::IRFunction,
+        mut scope: HashMap<String, Box<dyn Any>>,
+        arguments: Vec<Box<dyn Any>>,
+    ) -> Result<Box<dyn Any>> {
+        if function.parameters.len() != arguments.len() {
+            return Err(SlangError::runtime(RuntimeErrorKind::ArgumentCount, format!(
+                "Function {} expects {} arguments, got {}",
+                function.name, function.parameters.len(), arguments.len()
+            )));
         }
+        scope.extend(function.parameters.iter()
+            .map(|param| param.name.clone())
+            .zip(arguments));
+        let result = self.execute_in_frame(function, scope)?;
+        Ok(result.unwrap_or_else(|| Box::new(())))
     }
 
-    fn allocate(&mut self, size: usize, _priority: i32) -> usize {
-        let address = self.stack.len();
-        self.stack.push(Box::new(vec![0u8; size]));
-        address
+    // 新しいフレームで関数を実行する。失敗や中断で抜けた場合も含め、終わったらフレームを
+    // 優先度の低い変数から解放し、その順序を observer に知らせる
+    fn execute_in_frame(
+        &mut self,
+        function: &crate::ir::IRFunction,
+        scope: HashMap<String, Box<dyn Any>>,
+    ) -> Result<Option<Box<dyn Any>>> {
+        self.memory_manager.push_frame(scope);
+        self.priority_ownership_manager.push_frame();
+        let result = self.execute_function(function);
+        self.priority_ownership_manager.pop_frame();
+        let released = self.memory_manager.pop_frame();
+        if let Some(observer) = self.observer.clone() {
+            observer.on_frame_released(&function.name, &released);
+        }
+        result
     }
+}
 
-    fn allocate_value(&mut self, name: String, value: Box<dyn Any>) {
-        self.heap.insert(name, value);
+impl Default for Runtime {
+    fn default() -> Self {
+        Self::new()
     }
+}
 
-    fn get_value(&self, name: &str) -> Option<&Box<dyn Any>> {
-        self.heap.get(name)
-    }
+enum ControlFlow {
+    Next,
+    Jump(String),
+    Return(Option<Box<dyn Any>>),
+}
 
-    fn deallocate(&mut self, address: usize) {
-        if address < self.stack.len() {
-            self.stack[address] = Box::new(vec![0u8; 0]);
-        }
+// int と float の演算では int を float にしてから計算する (型検査でも結果は float)。それ以外はそのまま
+fn promote_mixed_numbers(left: Box<dyn Any>, right: Box<dyn Any>) -> (Box<dyn Any>, Box<dyn Any>) {
+    match (left.downcast_ref::<i64>(), right.downcast_ref::<i64>()) {
+        (Some(l), None) if right.is::<f64>() => (Box::new(*l as f64), right),
+        (None, Some(r)) if left.is::<f64>() => (left, Box::new(*r as f64)),
+        _ => (left, right),
     }
 }
 
-struct PriorityOwnershipManager {
-    priorities: HashMap<String, Vec<i32>>,
+// 数値・文字列・真偽値・文字の `==`。両辺が同じ型でなければエラー
+fn scalar_equal(left: &dyn Any, right: &dyn Any) -> Result<bool> {
+    if let (Some(l), Some(r)) = (left.downcast_ref::<i64>(), right.downcast_ref::<i64>()) {
+        Ok(l == r)
+    } else if let (Some(l), Some(r)) = (left.downcast_ref::<f64>(), right.downcast_ref::<f64>()) {
+        Ok(l == r)
+    } else if let (Some(l), Some(r)) = (left.downcast_ref::<String>(), right.downcast_ref::<String>()) {
+        Ok(l == r)
+    } else if let (Some(l), Some(r)) = (left.downcast_ref::<bool>(), right.downcast_ref::<bool>()) {
+        Ok(l == r)
+    } else if let (Some(l), Some(r)) = (left.downcast_ref::<char>(), right.downcast_ref::<char>()) {
+        Ok(l == r)
+    } else if left.is::<()>() || right.is::<()>() {
+        // `null` は `null` とだけ等しい
+        Ok(left.is::<()>() && right.is::<()>())
+    } else {
+        Err(SlangError::runtime(RuntimeErrorKind::TypeMismatch, "Invalid operands for equality"))
+    }
 }
 
-impl PriorityOwnershipManager {
-    fn new() -> Self {
-        Self {
-            priorities: HashMap::new(),
-        }
+// `execute_binary_op` の `==` と同じ順で、複素数などの値の比較を先に試す (`assert_eq` が使う)
+fn values_equal(left: &dyn Any, right: &dyn Any) -> Result<bool> {
+    let op = crate::ir::IRBinaryOperator::Equals;
+    let result = linear_algebra_op(&op, left, right)
+        .or_else(|| complex_op(&op, left, right))
+        .or_else(|| quaternion_op(&op, left, right))
+        .or_else(|| tensor_op(&op, left, right));
+    match result {
+        Some(result) => result?.downcast_ref::<bool>().copied().ok_or_else(|| {
+            SlangError::runtime(RuntimeErrorKind::TypeMismatch, "Invalid operands for equality")
+        }),
+        None => scalar_equal(left, right),
     }
+}
 
-    fn set_priority(&mut self, name: String, priority: i32) {
-        self.priorities.entry(name).or_insert_with(Vec::new).push(priority);
-    }
+// エラーメッセージやデバッグ表示に値を示す。`Value` にできない値は型名で示す
+fn describe_value(value: &dyn Any) -> String {
+    Value::from_any(value).map_or_else(|_| runtime_type_name(value).to_string(), |value| value.to_string())
+}
 
-    fn get_priority(&self, name: &str) -> Option<&Vec<i32>> {
-        self.priorities.get(name)
+// `Box<dyn Any>` は Clone できないため、ランタイムが扱う値の型ごとに複製する
+fn clone_value(value: &dyn Any) -> Result<Box<dyn Any>> {
+    if let Some(i) = value.downcast_ref::<i64>() {
+        Ok(Box::new(*i))
+    } else if let Some(f) = value.downcast_ref::<f64>() {
+        Ok(Box::new(*f))
+    } else if let Some(b) = value.downcast_ref::<bool>() {
+        Ok(Box::new(*b))
+    } else if let Some(c) = value.downcast_ref::<char>() {
+        Ok(Box::new(*c))
+    } else if let Some(s) = value.downcast_ref::<String>() {
+        Ok(Box::new(s.clone()))
+    } else if value.downcast_ref::<()>().is_some() {
+        Ok(Box::new(()))
+    } else if let Some(complex) = value.downcast_ref::<ComplexValue>() {
+        Ok(Box::new(*complex))
+    } else if let Some(quaternion) = value.downcast_ref::<QuaternionValue>() {
+        Ok(Box::new(*quaternion))
+    } else if let Some(vector) = value.downcast_ref::<VectorValue>() {
+        Ok(Box::new(vector.clone()))
+    } else if let Some(matrix) = value.downcast_ref::<MatrixValue>() {
+        Ok(Box::new(matrix.clone()))
+    } else if let Some(tensor) = value.downcast_ref::<TensorValue>() {
+        Ok(Box::new(tensor.clone()))
+    } else if let Some(closure) = value.downcast_ref::<ClosureValue>() {
+        Ok(Box::new(closure.clone()))
+    } else if let Some(elements) = value.downcast_ref::<Vec<Box<dyn Any>>>() {
+        let elements = elements.iter()
+            .map(|element| clone_value(element.as_ref()))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Box::new(elements))
+    } else {
+        Err(SlangError::runtime(RuntimeErrorKind::TypeMismatch, "Cannot copy value of unknown type"))
     }
+}
 
-    fn transfer_ownership(&mut self, from: &str, to: &str) -> Result<()> {
-        if let Some(priorities) = self.priorities.get(from) {
-            if let Some(last_priority) = priorities.last() {
-                self.set_priority(to.to_string(), *last_priority);
-                Ok(())
-            } else {
-                Err(SlangError::Runtime("No priority found for source".to_string()))
-            }
-        } else {
-            Err(SlangError::Runtime("Source not found".to_string()))
+// 変換の規則は型検査・最適化と同じ `TypeCast::cast_literal` に従う。配列は要素ごとに変換する
+fn cast_value(value: &dyn Any, target: &crate::type_system::Type) -> Result<Box<dyn Any>> {
+    use crate::ast::Literal;
+    use crate::type_system::Type;
+    match (target, value.downcast_ref::<Vec<Box<dyn Any>>>(), value.downcast_ref::<VectorValue>()) {
+        (Type::Array(element_type) | Type::FixedArray(element_type, _), Some(elements), _) => {
+            let elements = elements.iter()
+                .map(|element| cast_value(element.as_ref(), element_type))
+                .collect::<Result<Vec<_>>>()?;
+            return Ok(Box::new(elements));
         }
+        // ベクトルの要素は常に Float で持っているので、長さが合えばそのまま
+        (Type::Vector(size, _), _, Some(vector)) if vector.elements.len() == *size => {
+            return Ok(Box::new(vector.clone()));
+        }
+        _ => {}
+    }
+    let literal = if let Some(i) = value.downcast_ref::<i64>() {
+        Literal::Int(*i)
+    } else if let Some(f) = value.downcast_ref::<f64>() {
+        Literal::Float(*f)
+    } else if let Some(b) = value.downcast_ref::<bool>() {
+        Literal::Bool(*b)
+    } else if let Some(c) = value.downcast_ref::<char>() {
+        Literal::Char(*c)
+    } else if let Some(s) = value.downcast_ref::<String>() {
+        Literal::String(s.clone())
+    } else {
+        return Err(SlangError::runtime(RuntimeErrorKind::InvalidCast, format!(
+            "Cannot cast {} to {}",
+            runtime_type_name(value),
+            target
+        )));
+    };
+    let invalid = |message: String| SlangError::runtime(RuntimeErrorKind::InvalidCast, message);
+    match crate::type_system::TypeCast::new().cast_literal(&literal, target) {
+        Ok(Literal::Int(i)) => Ok(Box::new(i)),
+        Ok(Literal::Float(f)) => Ok(Box::new(f)),
+        Ok(Literal::Bool(b)) => Ok(Box::new(b)),
+        Ok(Literal::Char(c)) => Ok(Box::new(c)),
+        Ok(Literal::String(s)) => Ok(Box::new(s)),
+        Ok(other) => Err(invalid(format!("Cannot cast {} to {}", other, target))),
+        Err(SlangError::Type(message)) => Err(invalid(format!("{}: {}", message, literal))),
+        Err(error) => Err(error),
     }
 }
 
-struct StandardLibrary {
-    functions: HashMap<String, Box<dyn Fn(&[Box<dyn Any>]) -> Result<Box<dyn Any>>>>,
+fn expect_arity(name: &str, args: &[Box<dyn Any>], count: usize) -> Result<()> {
+    if args.len() == count {
+        Ok(())
+    } else {
+        Err(SlangError::runtime(RuntimeErrorKind::ArgumentCount, format!("{}() takes {} arguments, got {}", name, count, args.len())))
+    }
 }
 
-impl StandardLibrary {
-    fn new() -> Self {
-        let mut functions = HashMap::new();
-        functions.insert(
-            "print".to_string(),
-            Box::new(|args: &[Box<dyn Any>]| {
-                for arg in args {
-                    println!("{:?}", arg);
-                }
-                Ok(Box::new(()))
-            }) as Box<dyn Fn(&[Box<dyn Any>]) -> Result<Box<dyn Any>>>,
-        );
-        Self { functions }
-    }
+fn string_arg<'a>(name: &str, args: &'a [Box<dyn Any>], index: usize) -> Result<&'a String> {
+    args[index].downcast_ref::<String>().ok_or_else(|| SlangError::runtime(RuntimeErrorKind::TypeMismatch, format!(
+        "{}() expects a String as argument {}, got {}",
+        name, index + 1, runtime_type_name(args[index].as_ref())
+    )))
+}
 
-    fn get_function(&self, name: &str) -> Option<&dyn Fn(&[Box<dyn Any>]) -> Result<Box<dyn Any>>> {
-        self.functions.get(name).map(|f| f.as_ref())
-    }
+fn int_arg(name: &str, args: &[Box<dyn Any>], index: usize) -> Result<i64> {
+    args[index].downcast_ref::<i64>().copied().ok_or_else(|| SlangError::runtime(RuntimeErrorKind::TypeMismatch, format!(
+        "{}() expects an Int as argument {}, got {}",
+        name, index + 1, runtime_type_name(args[index].as_ref())
+    )))
 }
 
-impl fmt::Debug for Runtime {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Runtime")
+// Int は Float に変換して受け取る
+fn float_arg(name: &str, args: &[Box<dyn Any>], index: usize) -> Result<f64> {
+    if let Some(i) = args[index].downcast_ref::<i64>() {
+        Ok(*i as f64)
+    } else if let Some(f) = args[index].downcast_ref::<f64>() {
+        Ok(*f)
+    } else {
+        Err(SlangError::runtime(RuntimeErrorKind::TypeMismatch, format!(
+            "{}() expects a number as argument {}, got {}",
+            name, index + 1, runtime_type_name(args[index].as_ref())
+        )))
     }
 }
 
-impl fmt::Debug for MemoryManager {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "MemoryManager")
+fn runtime_type_name(value: &dyn Any) -> &'static str {
+    if value.is::<i64>() {
+        "Int"
+    } else if value.is::<f64>() {
+        "Float"
+    } else if value.is::<bool>() {
+        "Bool"
+    } else if value.is::<char>() {
+        "Char"
+    } else if value.is::<String>() {
+        "String"
+    } else if value.is::<Vec<Box<dyn Any>>>() {
+        "Array"
+    } else if value.is::<ComplexValue>() {
+        "Complex"
+    } else if value.is::<QuaternionValue>() {
+        "Quaternion"
+    } else if value.is::<VectorValue>() {
+        "Vector"
+    } else if value.is::<MatrixValue>() {
+        "Matrix"
+    } else if value.is::<TensorValue>() {
+        "Tensor"
+    } else if value.is::<ClosureValue>() {
+        "Function"
+    } else if value.is::<()>() {
+        "Unit"
+    } else {
+        "unknown"
+    }
+}
+
+// 複素数と数値の演算。Int と Float は虚部 0 の複素数として扱う。
+// どちらのオペランドも複素数でなければ `None`
+fn complex_op(op: &crate::ir::IRBinaryOperator, left: &dyn Any, right: &dyn Any) -> Option<Result<Box<dyn Any>>> {
+    use crate::ir::IRBinaryOperator::*;
+    if !left.is::<ComplexValue>() && !right.is::<ComplexValue>() {
+        return None;
+    }
+    let as_complex = |value: &dyn Any| {
+        if let Some(c) = value.downcast_ref::<ComplexValue>() {
+            Some(*c)
+        } else if let Some(f) = value.downcast_ref::<f64>() {
+            Some(ComplexValue { re: *f, im: 0.0 })
+        } else {
+            value.downcast_ref::<i64>().map(|i| ComplexValue { re: *i as f64, im: 0.0 })
+        }
+    };
+    let (Some(l), Some(r)) = (as_complex(left), as_complex(right)) else {
+        return Some(Err(SlangError::runtime(RuntimeErrorKind::TypeMismatch, format!(
+            "Invalid operands for complex arithmetic: {} and {}",
+            runtime_type_name(left),
+            runtime_type_name(right)
+        ))));
+    };
+    let result: Result<Box<dyn Any>> = match op {
+        Add => Ok(Box::new(ComplexValue { re: l.re + r.re, im: l.im + r.im })),
+        Sub | Subtract => Ok(Box::new(ComplexValue { re: l.re - r.re, im: l.im - r.im })),
+        Mul | Multiply => Ok(Box::new(ComplexValue {
+            re: l.re * r.re - l.im * r.im,
+            im: l.re * r.im + l.im * r.re,
+        })),
+        Div | Divide => {
+            let denominator = r.re * r.re + r.im * r.im;
+            if denominator == 0.0 {
+                Err(SlangError::runtime(RuntimeErrorKind::DivByZero, "Division by zero"))
+            } else {
+                Ok(Box::new(ComplexValue {
+                    re: (l.re * r.re + l.im * r.im) / denominator,
+                    im: (l.im * r.re - l.re * r.im) / denominator,
+                }))
+            }
+        }
+        Eq | Equals => Ok(Box::new(l == r)),
+        Neq | NotEquals => Ok(Box::new(l != r)),
+        _ => Err(SlangError::runtime(RuntimeErrorKind::TypeMismatch, format!("Operator {:?} is not defined for complex numbers", op))),
+    };
+    Some(result)
+}
+
+// 四元数どうしの `+`/`-`/`*` (ハミルトン積) と等値比較。スカラーとの演算はない。
+// どちらのオペランドも四元数でなければ `None`
+fn quaternion_op(op: &crate::ir::IRBinaryOperator, left: &dyn Any, right: &dyn Any) -> Option<Result<Box<dyn Any>>> {
+    use crate::ir::IRBinaryOperator::*;
+    let (l, r) = (left.downcast_ref::<QuaternionValue>(), right.downcast_ref::<QuaternionValue>());
+    if l.is_none() && r.is_none() {
+        return None;
+    }
+    let (Some(l), Some(r)) = (l, r) else {
+        return Some(Err(SlangError::runtime(RuntimeErrorKind::TypeMismatch, format!(
+            "Invalid operands for quaternion arithmetic: {} and {}",
+            runtime_type_name(left),
+            runtime_type_name(right)
+        ))));
+    };
+    let result: Result<Box<dyn Any>> = match op {
+        Add => Ok(Box::new(QuaternionValue { w: l.w + r.w, x: l.x + r.x, y: l.y + r.y, z: l.z + r.z })),
+        Sub | Subtract => Ok(Box::new(QuaternionValue { w: l.w - r.w, x: l.x - r.x, y: l.y - r.y, z: l.z - r.z })),
+        Mul | Multiply => Ok(Box::new(l.hamilton_product(r))),
+        Eq | Equals => Ok(Box::new(l == r)),
+        Neq | NotEquals => Ok(Box::new(l != r)),
+        _ => Err(SlangError::runtime(RuntimeErrorKind::TypeMismatch, format!("Operator {:?} is not defined for quaternions", op))),
+    };
+    Some(result)
+}
+
+// テンソルどうしの要素ごとの `+`/`*`。どちらのオペランドもテンソルでなければ `None`
+fn tensor_op(op: &crate::ir::IRBinaryOperator, left: &dyn Any, right: &dyn Any) -> Option<Result<Box<dyn Any>>> {
+    use crate::ir::IRBinaryOperator::*;
+    let (l, r) = (left.downcast_ref::<TensorValue>(), right.downcast_ref::<TensorValue>());
+    if l.is_none() && r.is_none() {
+        return None;
+    }
+    let result: Result<Box<dyn Any>> = match (op, l, r) {
+        (Add | Mul | Multiply, Some(l), Some(r)) => {
+            if l.shape == r.shape {
+                let elements = l.elements.iter().zip(&r.elements).map(|(x, y)| match op {
+                    Add => x + y,
+                    _ => x * y,
+                });
+                Ok(Box::new(TensorValue { shape: l.shape.clone(), elements: elements.collect() }))
+            } else {
+                Err(SlangError::runtime(RuntimeErrorKind::TypeMismatch, format!(
+                    "Shape mismatch: tensors of shape {:?} and {:?}",
+                    l.shape, r.shape
+                )))
+            }
+        }
+        _ => Err(SlangError::runtime(RuntimeErrorKind::TypeMismatch, format!(
+            "Unsupported operation {:?} between {} and {}",
+            op,
+            runtime_type_name(left),
+            runtime_type_name(right)
+        ))),
+    };
+    Some(result)
+}
+
+// `tensor()`/`reshape()` に渡す形。正の整数の配列でなければならない
+fn shape_arg(name: &str, args: &[Box<dyn Any>], index: usize) -> Result<Vec<usize>> {
+    let invalid = || SlangError::runtime(RuntimeErrorKind::TypeMismatch, format!(
+        "{}() expects an Array of positive Ints as argument {}",
+        name, index + 1
+    ));
+    let dimensions = args[index].downcast_ref::<Vec<Box<dyn Any>>>().ok_or_else(invalid)?;
+    if dimensions.is_empty() {
+        return Err(invalid());
+    }
+    dimensions
+        .iter()
+        .map(|dimension| {
+            dimension.downcast_ref::<i64>().and_then(|n| usize::try_from(*n).ok()).filter(|n| *n > 0).ok_or_else(invalid)
+        })
+        .collect()
+}
+
+// ベクトル・行列どうしの `+`/`-` と行列の積。どちらのオペランドもベクトル・行列でなければ `None`
+fn linear_algebra_op(op: &crate::ir::IRBinaryOperator, left: &dyn Any, right: &dyn Any) -> Option<Result<Box<dyn Any>>> {
+    use crate::ir::IRBinaryOperator::*;
+    let elementwise = |a: &[f64], b: &[f64]| -> Vec<f64> {
+        match op {
+            Add => a.iter().zip(b).map(|(x, y)| x + y).collect(),
+            _ => a.iter().zip(b).map(|(x, y)| x - y).collect(),
+        }
+    };
+    let result: Result<Box<dyn Any>> = match (op, left.downcast_ref::<VectorValue>(), right.downcast_ref::<VectorValue>()) {
+        (Add | Sub | Subtract, Some(l), Some(r)) => {
+            if l.elements.len() == r.elements.len() {
+                Ok(Box::new(VectorValue { elements: elementwise(&l.elements, &r.elements) }))
+            } else {
+                Err(SlangError::runtime(RuntimeErrorKind::TypeMismatch, format!(
+                    "Dimension mismatch: vectors of length {} and {}",
+                    l.elements.len(),
+                    r.elements.len()
+                )))
+            }
+        }
+        _ => match (op, left.downcast_ref::<MatrixValue>(), right.downcast_ref::<MatrixValue>()) {
+            (Add | Sub | Subtract, Some(l), Some(r)) => {
+                if (l.rows, l.cols) == (r.rows, r.cols) {
+                    Ok(Box::new(MatrixValue { rows: l.rows, cols: l.cols, elements: elementwise(&l.elements, &r.elements) }))
+                } else {
+                    Err(SlangError::runtime(RuntimeErrorKind::TypeMismatch, format!(
+                        "Dimension mismatch: {}x{} and {}x{} matrices",
+                        l.rows, l.cols, r.rows, r.cols
+                    )))
+                }
+            }
+            (Mul | Multiply, Some(l), Some(r)) => {
+                if l.cols == r.rows {
+                    let mut elements = Vec::with_capacity(l.rows * r.cols);
+                    for row in 0..l.rows {
+                        for col in 0..r.cols {
+                            elements.push((0..l.cols).map(|k| l.get(row, k) * r.get(k, col)).sum());
+                        }
+                    }
+                    Ok(Box::new(MatrixValue { rows: l.rows, cols: r.cols, elements }))
+                } else {
+                    Err(SlangError::runtime(RuntimeErrorKind::TypeMismatch, format!(
+                        "Dimension mismatch: cannot multiply {}x{} and {}x{} matrices",
+                        l.rows, l.cols, r.rows, r.cols
+                    )))
+                }
+            }
+            _ => {
+                let is_linear = |value: &dyn Any| value.is::<VectorValue>() || value.is::<MatrixValue>();
+                if !is_linear(left) && !is_linear(right) {
+                    return None;
+                }
+                Err(SlangError::runtime(RuntimeErrorKind::TypeMismatch, format!(
+                    "Unsupported operation {:?} between {} and {}",
+                    op,
+                    runtime_type_name(left),
+                    runtime_type_name(right)
+                )))
+            }
+        },
+    };
+    Some(result)
+}
+
+// 変数は呼び出しごとのフレームに置く。先頭のフレームはトップレベル用で、
+// 名前は現在のフレームの中だけで解決する。呼び出し元のローカル変数は見えない
+struct MemoryManager {
+    frames: Vec<HashMap<String, Box<dyn Any>>>,
+    // フレームごとの優先所有格。`frames` と同じ深さで積む
+    priorities: Vec<HashMap<String, i32>>,
+}
+
+impl MemoryManager {
+    fn new() -> Self {
+        Self {
+            frames: vec![HashMap::new()],
+            priorities: vec![HashMap::new()],
+        }
+    }
+
+    fn push_frame(&mut self, frame: HashMap<String, Box<dyn Any>>) {
+        self.frames.push(frame);
+        self.priorities.push(HashMap::new());
+    }
+
+    // フレームの変数を優先度の低い順に解放し、解放した順の名前を返す。
+    // 優先度の高い変数ほど長く生き残る。優先度のない変数は 0 として扱う
+    fn pop_frame(&mut self) -> Vec<String> {
+        if self.frames.len() <= 1 {
+            return Vec::new();
+        }
+        let frame = self.frames.pop().expect("checked above");
+        let priorities = self.priorities.pop().unwrap_or_default();
+        let mut values: Vec<(String, Box<dyn Any>)> = frame.into_iter().collect();
+        values.sort_by(|(a, _), (b, _)| {
+            let priority = |name: &str| priorities.get(name).copied().unwrap_or(0);
+            priority(a).cmp(&priority(b)).then_with(|| a.cmp(b))
+        });
+        values.into_iter().map(|(name, value)| {
+            drop(value);
+            name
+        }).collect()
+    }
+
+    // 現在のフレームの変数に優先度を記録する
+    fn set_priority(&mut self, name: &str, priority: i32) {
+        if let Some(priorities) = self.priorities.last_mut() {
+            priorities.insert(name.to_string(), priority);
+        }
+    }
+
+    fn current_frame(&mut self) -> &mut HashMap<String, Box<dyn Any>> {
+        self.frames.last_mut().expect("the top-level frame is never popped")
+    }
+
+    // 現在のフレームに変数を作る。同名の外側の変数は隠れる
+    fn define(&mut self, name: String, value: Box<dyn Any>) {
+        self.current_frame().insert(name, value);
+    }
+
+    // 既存の変数を書き換える。見つからなければ現在のフレームに作る
+    fn assign(&mut self, name: String, value: Box<dyn Any>) {
+        match self.get_value_mut(&name) {
+            Some(slot) => *slot = value,
+            None => self.define(name, value),
+        }
+    }
+
+    fn get_value(&self, name: &str) -> Option<&Box<dyn Any>> {
+        self.frames.last()?.get(name)
+    }
+
+    fn get_value_mut(&mut self, name: &str) -> Option<&mut Box<dyn Any>> {
+        self.frames.last_mut()?.get_mut(name)
+    }
+}
+
+// 変数ごとの優先度の履歴。`MemoryManager` と同じくフレームごとに持ち、名前は現在のフレームの中だけで解決する
+struct PriorityOwnershipManager {
+    frames: Vec<HashMap<String, Vec<i32>>>,
+}
+
+impl PriorityOwnershipManager {
+    fn new() -> Self {
+        Self {
+            frames: vec![HashMap::new()],
+        }
+    }
+
+    fn push_frame(&mut self) {
+        self.frames.push(HashMap::new());
+    }
+
+    // 関数を抜けたらその関数で宣言した優先度を捨てる。トップレベルのフレームは残す
+    fn pop_frame(&mut self) {
+        if self.frames.len() > 1 {
+            self.frames.pop();
+        }
+    }
+
+    fn set_priority(&mut self, name: String, priority: i32) {
+        if let Some(priorities) = self.frames.last_mut() {
+            priorities.entry(name).or_default().push(priority);
+        }
+    }
+
+    fn get_priority(&self, name: &str) -> Option<&Vec<i32>> {
+        self.frames.last()?.get(name)
+    }
+
+    fn transfer_ownership(&mut self, from: &str, to: &str) -> Result<()> {
+        if let Some(priorities) = self.get_priority(from) {
+            if let Some(last_priority) = priorities.last() {
+                self.set_priority(to.to_string(), *last_priority);
+                Ok(())
+            } else {
+                Err(SlangError::runtime(RuntimeErrorKind::Other, format!("No priority found for source: {}", from)))
+            }
+        } else {
+            Err(SlangError::runtime(RuntimeErrorKind::UndefinedVariable, format!("Source not found: {}", from)))
+        }
+    }
+}
+
+type NativeFunction = Box<dyn Fn(&[Box<dyn Any>]) -> Result<Box<dyn Any>>>;
+type NativeMethod = Box<dyn Fn(&mut dyn Any, &[Box<dyn Any>]) -> Result<Box<dyn Any>>>;
+
+struct StandardLibrary {
+    functions: HashMap<String, NativeFunction>,
+    // (レシーバの型名, メソッド名) をキーにする。型検査側は `Type::get_method_signature`
+    methods: HashMap<(&'static str, String), NativeMethod>,
+    // 第1引数をその場で書き換える関数 (`push`, `pop`)。第1引数がレシーバとして渡される
+    in_place_functions: HashMap<String, NativeMethod>,
+}
+
+impl StandardLibrary {
+    fn new() -> Self {
+        let mut functions = HashMap::new();
+        functions.insert(
+            "print".to_string(),
+            Box::new(|args: &[Box<dyn Any>]| {
+                for arg in args {
+                    println!("{:?}", arg);
+                }
+                Ok(Box::new(()) as Box<dyn Any>)
+            }) as NativeFunction,
+        );
+        functions.insert(
+            "len".to_string(),
+            Box::new(|args: &[Box<dyn Any>]| {
+                let [value] = args else {
+                    return Err(SlangError::runtime(RuntimeErrorKind::ArgumentCount, format!("len() takes exactly one argument, got {}", args.len())));
+                };
+                if let Some(elements) = value.downcast_ref::<Vec<Box<dyn Any>>>() {
+                    Ok(Box::new(elements.len() as i64) as Box<dyn Any>)
+                } else if let Some(s) = value.downcast_ref::<String>() {
+                    Ok(Box::new(s.chars().count() as i64) as Box<dyn Any>)
+                } else {
+                    Err(SlangError::runtime(RuntimeErrorKind::TypeMismatch, format!("len() expects an array, got {}", runtime_type_name(value.as_ref()))))
+                }
+            }) as NativeFunction,
+        );
+        functions.insert(
+            "assert".to_string(),
+            Box::new(|args: &[Box<dyn Any>]| {
+                expect_arity("assert", args, 1)?;
+                match args[0].downcast_ref::<bool>() {
+                    Some(true) => Ok(Box::new(()) as Box<dyn Any>),
+                    Some(false) => Err(SlangError::runtime(RuntimeErrorKind::AssertionFailed, "Assertion failed")),
+                    None => Err(SlangError::runtime(RuntimeErrorKind::TypeMismatch, format!(
+                        "assert() expects a Bool, got {}",
+                        runtime_type_name(args[0].as_ref())
+                    ))),
+                }
+            }) as NativeFunction,
+        );
+        // 比べ方は `==` と同じ。比べられない組み合わせは失敗ではなく型の不一致
+        functions.insert(
+            "assert_eq".to_string(),
+            Box::new(|args: &[Box<dyn Any>]| {
+                expect_arity("assert_eq", args, 2)?;
+                let (left, right) = (args[0].as_ref(), args[1].as_ref());
+                if values_equal(left, right)? {
+                    Ok(Box::new(()) as Box<dyn Any>)
+                } else {
+                    Err(SlangError::runtime(RuntimeErrorKind::AssertionFailed, format!(
+                        "Assertion failed: {} != {}",
+                        describe_value(left),
+                        describe_value(right)
+                    )))
+                }
+            }) as NativeFunction,
+        );
+        functions.insert(
+            "panic".to_string(),
+            Box::new(|args: &[Box<dyn Any>]| {
+                expect_arity("panic", args, 1)?;
+                Err(SlangError::runtime(RuntimeErrorKind::Panic, format!("panic: {}", string_arg("panic", args, 0)?)))
+            }) as NativeFunction,
+        );
+        functions.insert(
+            "concat".to_string(),
+            Box::new(|args: &[Box<dyn Any>]| {
+                expect_arity("concat", args, 2)?;
+                let a = string_arg("concat", args, 0)?;
+                let b = string_arg("concat", args, 1)?;
+                Ok(Box::new(format!("{}{}", a, b)) as Box<dyn Any>)
+            }) as NativeFunction,
+        );
+        // 位置は文字単位で、`end` は含まない
+        functions.insert(
+            "substring".to_string(),
+            Box::new(|args: &[Box<dyn Any>]| {
+                expect_arity("substring", args, 3)?;
+                let s = string_arg("substring", args, 0)?;
+                let start = int_arg("substring", args, 1)?;
+                let end = int_arg("substring", args, 2)?;
+                let length = s.chars().count();
+                let out_of_bounds = || SlangError::runtime(RuntimeErrorKind::IndexOutOfBounds, format!(
+                    "substring() range {}..{} out of bounds for string of length {}",
+                    start, end, length
+                ));
+                let (Ok(start), Ok(end)) = (usize::try_from(start), usize::try_from(end)) else {
+                    return Err(out_of_bounds());
+                };
+                if start > end || end > length {
+                    return Err(out_of_bounds());
+                }
+                let result: String = s.chars().skip(start).take(end - start).collect();
+                Ok(Box::new(result) as Box<dyn Any>)
+            }) as NativeFunction,
+        );
+        // 文字列の埋め込み (`"{x}"`) はこの関数の呼び出しに脱糖される
+        functions.insert(
+            "to_string".to_string(),
+            Box::new(|args: &[Box<dyn Any>]| {
+                expect_arity("to_string", args, 1)?;
+                let value = Value::from_any(args[0].as_ref()).map_err(|_| SlangError::runtime(RuntimeErrorKind::TypeMismatch, format!(
+                    "to_string() cannot format a value of type {}",
+                    runtime_type_name(args[0].as_ref())
+                )))?;
+                Ok(Box::new(value.to_string()) as Box<dyn Any>)
+            }) as NativeFunction,
+        );
+        functions.insert(
+            "to_upper".to_string(),
+            Box::new(|args: &[Box<dyn Any>]| {
+                expect_arity("to_upper", args, 1)?;
+                Ok(Box::new(string_arg("to_upper", args, 0)?.to_uppercase()) as Box<dyn Any>)
+            }) as NativeFunction,
+        );
+        functions.insert(
+            "to_lower".to_string(),
+            Box::new(|args: &[Box<dyn Any>]| {
+                expect_arity("to_lower", args, 1)?;
+                Ok(Box::new(string_arg("to_lower", args, 0)?.to_lowercase()) as Box<dyn Any>)
+            }) as NativeFunction,
+        );
+        // 数値関数: 引数がすべて Int なら Int を返し、Float が混ざれば Float に揃える
+        functions.insert(
+            "abs".to_string(),
+            Box::new(|args: &[Box<dyn Any>]| {
+                expect_arity("abs", args, 1)?;
+                if let Some(i) = args[0].downcast_ref::<i64>() {
+                    let result = i.checked_abs()
+                        .ok_or_else(|| SlangError::runtime(RuntimeErrorKind::Overflow, "abs() overflowed"))?;
+                    Ok(Box::new(result) as Box<dyn Any>)
+                } else {
+                    Ok(Box::new(float_arg("abs", args, 0)?.abs()) as Box<dyn Any>)
+                }
+            }) as NativeFunction,
+        );
+        functions.insert(
+            "sqrt".to_string(),
+            Box::new(|args: &[Box<dyn Any>]| {
+                expect_arity("sqrt", args, 1)?;
+                Ok(Box::new(float_arg("sqrt", args, 0)?.sqrt()) as Box<dyn Any>)
+            }) as NativeFunction,
+        );
+        functions.insert(
+            "pow".to_string(),
+            Box::new(|args: &[Box<dyn Any>]| {
+                expect_arity("pow", args, 2)?;
+                if let (Some(base), Some(exponent)) = (args[0].downcast_ref::<i64>(), args[1].downcast_ref::<i64>()) {
+                    // 整数どうしの結果は整数 (型検査と同じ)。負の指数は整数にならないので float で渡してもらう
+                    let exponent = u32::try_from(*exponent).map_err(|_| SlangError::runtime(
+                        RuntimeErrorKind::Other,
+                        "pow() with int arguments needs a non-negative exponent; pass floats instead",
+                    ))?;
+                    let result = base.checked_pow(exponent)
+                        .ok_or_else(|| SlangError::runtime(RuntimeErrorKind::Overflow, "pow() overflowed"))?;
+                    return Ok(Box::new(result) as Box<dyn Any>);
+                }
+                let base = float_arg("pow", args, 0)?;
+                let exponent = float_arg("pow", args, 1)?;
+                Ok(Box::new(base.powf(exponent)) as Box<dyn Any>)
+            }) as NativeFunction,
+        );
+        functions.insert(
+            "min".to_string(),
+            Box::new(|args: &[Box<dyn Any>]| {
+                expect_arity("min", args, 2)?;
+                if let (Some(a), Some(b)) = (args[0].downcast_ref::<i64>(), args[1].downcast_ref::<i64>()) {
+                    return Ok(Box::new(*a.min(b)) as Box<dyn Any>);
+                }
+                Ok(Box::new(float_arg("min", args, 0)?.min(float_arg("min", args, 1)?)) as Box<dyn Any>)
+            }) as NativeFunction,
+        );
+        functions.insert(
+            "max".to_string(),
+            Box::new(|args: &[Box<dyn Any>]| {
+                expect_arity("max", args, 2)?;
+                if let (Some(a), Some(b)) = (args[0].downcast_ref::<i64>(), args[1].downcast_ref::<i64>()) {
+                    return Ok(Box::new(*a.max(b)) as Box<dyn Any>);
+                }
+                Ok(Box::new(float_arg("max", args, 0)?.max(float_arg("max", args, 1)?)) as Box<dyn Any>)
+            }) as NativeFunction,
+        );
+        functions.insert(
+            "vec".to_string(),
+            Box::new(|args: &[Box<dyn Any>]| {
+                if args.is_empty() {
+                    return Err(SlangError::runtime(RuntimeErrorKind::ArgumentCount, "vec() takes at least one argument"));
+                }
+                let elements = (0..args.len()).map(|i| float_arg("vec", args, i)).collect::<Result<Vec<_>>>()?;
+                Ok(Box::new(VectorValue { elements }) as Box<dyn Any>)
+            }) as NativeFunction,
+        );
+        functions.insert(
+            "quat".to_string(),
+            Box::new(|args: &[Box<dyn Any>]| {
+                expect_arity("quat", args, 4)?;
+                Ok(Box::new(QuaternionValue {
+                    w: float_arg("quat", args, 0)?,
+                    x: float_arg("quat", args, 1)?,
+                    y: float_arg("quat", args, 2)?,
+                    z: float_arg("quat", args, 3)?,
+                }) as Box<dyn Any>)
+            }) as NativeFunction,
+        );
+        // `tensor(形, 要素)`。要素は行優先で並べた数値の配列
+        functions.insert(
+            "tensor".to_string(),
+            Box::new(|args: &[Box<dyn Any>]| {
+                expect_arity("tensor", args, 2)?;
+                let shape = shape_arg("tensor", args, 0)?;
+                let data = args[1].downcast_ref::<Vec<Box<dyn Any>>>().ok_or_else(|| SlangError::runtime(RuntimeErrorKind::TypeMismatch, format!(
+                    "tensor() expects an Array as argument 2, got {}",
+                    runtime_type_name(args[1].as_ref())
+                )))?;
+                let size: usize = shape.iter().product();
+                if data.len() != size {
+                    return Err(SlangError::runtime(RuntimeErrorKind::Other, format!(
+                        "tensor() of shape {:?} needs {} elements, got {}",
+                        shape, size, data.len()
+                    )));
+                }
+                let elements = (0..data.len()).map(|i| float_arg("tensor", data, i)).collect::<Result<Vec<_>>>()?;
+                Ok(Box::new(TensorValue { shape, elements }) as Box<dyn Any>)
+            }) as NativeFunction,
+        );
+        // 要素の並びはそのままで形だけを変える
+        functions.insert(
+            "reshape".to_string(),
+            Box::new(|args: &[Box<dyn Any>]| {
+                expect_arity("reshape", args, 2)?;
+                let tensor = args[0].downcast_ref::<TensorValue>().ok_or_else(|| SlangError::runtime(RuntimeErrorKind::TypeMismatch, format!(
+                    "reshape() expects a Tensor as argument 1, got {}",
+                    runtime_type_name(args[0].as_ref())
+                )))?;
+                let shape = shape_arg("reshape", args, 1)?;
+                if shape.iter().product::<usize>() != tensor.elements.len() {
+                    return Err(SlangError::runtime(RuntimeErrorKind::Other, format!(
+                        "Cannot reshape tensor of shape {:?} to {:?}",
+                        tensor.shape, shape
+                    )));
+                }
+                Ok(Box::new(TensorValue { shape, elements: tensor.elements.clone() }) as Box<dyn Any>)
+            }) as NativeFunction,
+        );
+        // 引数はそれぞれ 1 行分の配列。すべて同じ長さでなければならない
+        functions.insert(
+            "mat".to_string(),
+            Box::new(|args: &[Box<dyn Any>]| {
+                let mut elements = Vec::new();
+                let mut cols = None;
+                for (index, row) in args.iter().enumerate() {
+                    let row = row.downcast_ref::<Vec<Box<dyn Any>>>().ok_or_else(|| SlangError::runtime(RuntimeErrorKind::TypeMismatch, format!(
+                        "mat() expects an Array as argument {}, got {}",
+                        index + 1, runtime_type_name(row.as_ref())
+                    )))?;
+                    if *cols.get_or_insert(row.len()) != row.len() || row.is_empty() {
+                        return Err(SlangError::runtime(RuntimeErrorKind::Other, "mat() rows must be non-empty and of equal length"));
+                    }
+                    for i in 0..row.len() {
+                        elements.push(float_arg("mat", row, i)?);
+                    }
+                }
+                let Some(cols) = cols else {
+                    return Err(SlangError::runtime(RuntimeErrorKind::ArgumentCount, "mat() takes at least one row"));
+                };
+                Ok(Box::new(MatrixValue { rows: args.len(), cols, elements }) as Box<dyn Any>)
+            }) as NativeFunction,
+        );
+        let mut in_place_functions: HashMap<String, NativeMethod> = HashMap::new();
+        in_place_functions.insert(
+            "push".to_string(),
+            Box::new(|target: &mut dyn Any, args: &[Box<dyn Any>]| {
+                let [value] = args else {
+                    return Err(SlangError::runtime(RuntimeErrorKind::ArgumentCount, format!("push() takes exactly two arguments, got {}", args.len() + 1)));
+                };
+                let type_name = runtime_type_name(target);
+                let elements = target.downcast_mut::<Vec<Box<dyn Any>>>()
+                    .ok_or_else(|| SlangError::runtime(RuntimeErrorKind::TypeMismatch, format!("push() expects an array, got {}", type_name)))?;
+                elements.push(clone_value(value.as_ref())?);
+                Ok(Box::new(()) as Box<dyn Any>)
+            }),
+        );
+        in_place_functions.insert(
+            "pop".to_string(),
+            Box::new(|target: &mut dyn Any, args: &[Box<dyn Any>]| {
+                if !args.is_empty() {
+                    return Err(SlangError::runtime(RuntimeErrorKind::ArgumentCount, format!("pop() takes exactly one argument, got {}", args.len() + 1)));
+                }
+                let type_name = runtime_type_name(target);
+                target.downcast_mut::<Vec<Box<dyn Any>>>()
+                    .ok_or_else(|| SlangError::runtime(RuntimeErrorKind::TypeMismatch, format!("pop() expects an array, got {}", type_name)))?
+                    .pop()
+                    .ok_or_else(|| SlangError::runtime(RuntimeErrorKind::IndexOutOfBounds, "pop() on an empty array"))
+            }),
+        );
+        let mut methods: HashMap<(&'static str, String), NativeMethod> = HashMap::new();
+        methods.insert(
+            ("Array", "len".to_string()),
+            Box::new(|receiver: &mut dyn Any, _args: &[Box<dyn Any>]| {
+                let elements = receiver.downcast_ref::<Vec<Box<dyn Any>>>()
+                    .ok_or_else(|| SlangError::runtime(RuntimeErrorKind::TypeMismatch, "len() receiver is not an array"))?;
+                Ok(Box::new(elements.len() as i64) as Box<dyn Any>)
+            }),
+        );
+        methods.insert(
+            ("Array", "push".to_string()),
+            Box::new(|receiver: &mut dyn Any, args: &[Box<dyn Any>]| {
+                let [value] = args else {
+                    return Err(SlangError::runtime(RuntimeErrorKind::ArgumentCount, "push() takes exactly one argument"));
+                };
+                let value = clone_value(value.as_ref())?;
+                receiver.downcast_mut::<Vec<Box<dyn Any>>>()
+                    .ok_or_else(|| SlangError::runtime(RuntimeErrorKind::TypeMismatch, "push() receiver is not an array"))?
+                    .push(value);
+                Ok(Box::new(()) as Box<dyn Any>)
+            }),
+        );
+        methods.insert(
+            ("String", "len".to_string()),
+            Box::new(|receiver: &mut dyn Any, _args: &[Box<dyn Any>]| {
+                let s = receiver.downcast_ref::<String>()
+                    .ok_or_else(|| SlangError::runtime(RuntimeErrorKind::TypeMismatch, "len() receiver is not a string"))?;
+                Ok(Box::new(s.chars().count() as i64) as Box<dyn Any>)
+            }),
+        );
+        methods.insert(
+            ("Quaternion", "conjugate".to_string()),
+            Box::new(|receiver: &mut dyn Any, _args: &[Box<dyn Any>]| {
+                let q = receiver.downcast_ref::<QuaternionValue>()
+                    .ok_or_else(|| SlangError::runtime(RuntimeErrorKind::TypeMismatch, "conjugate() receiver is not a quaternion"))?;
+                Ok(Box::new(q.conjugate()) as Box<dyn Any>)
+            }),
+        );
+        methods.insert(
+            ("Quaternion", "normalize".to_string()),
+            Box::new(|receiver: &mut dyn Any, _args: &[Box<dyn Any>]| {
+                let q = receiver.downcast_ref::<QuaternionValue>()
+                    .ok_or_else(|| SlangError::runtime(RuntimeErrorKind::TypeMismatch, "normalize() receiver is not a quaternion"))?;
+                let normalized = q.normalize()
+                    .ok_or_else(|| SlangError::runtime(RuntimeErrorKind::DivByZero, "Cannot normalize a zero quaternion"))?;
+                Ok(Box::new(normalized) as Box<dyn Any>)
+            }),
+        );
+        Self { functions, methods, in_place_functions }
+    }
+
+    fn is_in_place(&self, name: &str) -> bool {
+        self.in_place_functions.contains_key(name)
+    }
+
+    fn call_in_place(&self, name: &str, target: &mut dyn Any, args: &[Box<dyn Any>]) -> Result<Box<dyn Any>> {
+        let f = self.in_place_functions.get(name)
+            .ok_or_else(|| SlangError::runtime(RuntimeErrorKind::UndefinedFunction, format!("Function not found: {}", name)))?;
+        f(target, args)
+    }
+
+    fn call_method(&self, receiver: &mut dyn Any, method: &str, args: &[Box<dyn Any>]) -> Result<Box<dyn Any>> {
+        let type_name = runtime_type_name(receiver);
+        let f = self.methods.get(&(type_name, method.to_string()))
+            .ok_or_else(|| SlangError::runtime(RuntimeErrorKind::UndefinedFunction, format!("No method '{}' on {}", method, type_name)))?;
+        f(receiver, args)
+    }
+
+    fn get_function(&self, name: &str) -> Option<&NativeFunction> {
+        self.functions.get(name)
+    }
+}
+
+impl fmt::Debug for Runtime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Runtime")
+    }
+}
+
+impl fmt::Debug for MemoryManager {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "MemoryManager")
     }
 }
 
@@ -514,4 +1863,1173 @@ impl fmt::Debug for StandardLibrary {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "StandardLibrary")
     }
-} 
\ No newline at end of file
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::*;
+    use crate::type_system::Type;
+
+    fn block(label: &str, instructions: Vec<IRInstruction>) -> IRBlock {
+        IRBlock {
+            label: label.to_string(),
+            instructions,
+        }
+    }
+
+    fn variable(name: &str) -> IRValue {
+        IRValue::Variable(name.to_string())
+    }
+
+    fn binary(left: IRValue, op: IRBinaryOperator, right: IRValue) -> IRValue {
+        IRValue::BinaryOp {
+            left: Box::new(left),
+            op,
+            right: Box::new(right),
+        }
+    }
+
+    // while n > 1 { result = result * n; n = n - 1; } return result;
+    fn factorial(n: i64) -> IRFunction {
+        IRFunction {
+            name: "factorial".to_string(),
+            parameters: vec![],
+            return_type: Type::Int,
+            priorities: vec![],
+            blocks: vec![
+                block("entry", vec![
+                    IRInstruction::Let { name: "n".to_string(), value: IRValue::Int(n) },
+                    IRInstruction::Let { name: "result".to_string(), value: IRValue::Int(1) },
+                    IRInstruction::Branch { label: "while_header".to_string() },
+                ]),
+                block("while_header", vec![IRInstruction::ConditionalBranch {
+                    condition: binary(variable("n"), IRBinaryOperator::GreaterThan, IRValue::Int(1)),
+                    then_label: "while_body".to_string(),
+                    else_label: "while_exit".to_string(),
+                }]),
+                block("while_body", vec![
+                    IRInstruction::Assignment {
+                        target: "result".to_string(),
+                        value: binary(variable("result"), IRBinaryOperator::Mul, variable("n")),
+                    },
+                    IRInstruction::Assignment {
+                        target: "n".to_string(),
+                        value: binary(variable("n"), IRBinaryOperator::Sub, IRValue::Int(1)),
+                    },
+                    IRInstruction::Branch { label: "while_header".to_string() },
+                ]),
+                block("while_exit", vec![
+                    IRInstruction::Return(Some(variable("result"))),
+                    // Return の後は実行されない
+                    IRInstruction::Assignment { target: "result".to_string(), value: IRValue::Int(0) },
+                ]),
+            ],
+        }
+    }
+
+    // `execute` は `main` のフレームを抜けるときに解放するので、変数を調べられるようにトップレベルで実行する
+    fn run(function: IRFunction) -> Runtime {
+        let mut runtime = Runtime::new();
+        runtime.execute_function(&function).unwrap();
+        runtime
+    }
+
+    fn int_variable(runtime: &Runtime, name: &str) -> i64 {
+        *runtime.memory_manager.get_value(name).unwrap().downcast_ref::<i64>().unwrap()
+    }
+
+    // 構文解析・型検査・IR への変換を通して、ループと再帰の両方で階乗を求める
+    #[test]
+    fn test_factorial_runs_end_to_end() {
+        let factorials = "
+            fn loop_factorial(n: int) -> int {
+                let mut k = n;
+                let mut result = 1;
+                while k > 1 { result = result * k; k = k - 1; }
+                return result;
+            }
+            fn factorial(n: int) -> int {
+                if n <= 1 { return 1; }
+                return n * factorial(n - 1);
+            }";
+        let ir = crate::compile(&format!("{} fn main() -> int {{ return 0; }}", factorials)).unwrap();
+        let mut runtime = Runtime::new();
+        runtime.execute(&ir).unwrap();
+        for (n, expected) in [(0i64, 1i64), (1, 1), (5, 120), (10, 3628800)] {
+            for function in ["factorial", "loop_factorial"] {
+                let result = runtime.execute_function_call(function, vec![Box::new(n)]).unwrap();
+                assert_eq!(result.downcast_ref::<i64>(), Some(&expected), "{}({})", function, n);
+            }
+        }
+
+        crate::run(&format!("{} fn main() -> void {{ assert_eq(factorial(6), loop_factorial(6)); }}", factorials)).unwrap();
+        assert!(crate::run(&format!("{} fn main() -> void {{ assert_eq(factorial(5), 100); }}", factorials)).is_err());
+    }
+
+    #[test]
+    fn test_conditional_branch_skips_untaken_block() {
+        let runtime = run(IRFunction {
+            name: "main".to_string(),
+            parameters: vec![],
+            return_type: Type::Unit,
+            priorities: vec![],
+            blocks: vec![
+                block("entry", vec![IRInstruction::ConditionalBranch {
+                    condition: IRValue::Bool(false),
+                    then_label: "then".to_string(),
+                    else_label: "else".to_string(),
+                }]),
+                block("then", vec![IRInstruction::Let { name: "x".to_string(), value: IRValue::Int(1) }]),
+                block("else", vec![IRInstruction::Let { name: "x".to_string(), value: IRValue::Int(2) }]),
+            ],
+        });
+        assert_eq!(int_variable(&runtime, "x"), 2);
+    }
+
+    fn compile(source: &str) -> IRFunction {
+        crate::compiler::Compiler::new().compile(source).unwrap()
+    }
+
+    #[test]
+    fn test_mutually_recursive_user_functions() {
+        let mut ir = IR::new();
+        ir.add_function(compile("fn is_even(n: int) -> int { if n == 0 { return 1; } return is_odd(n - 1); }"));
+        ir.add_function(compile("fn is_odd(n: int) -> int { if n == 0 { return 0; } return is_even(n - 1); }"));
+        ir.add_function(compile("fn main() -> int { return is_even(10); }"));
+        let mut runtime = Runtime::new();
+        runtime.execute(&ir).unwrap();
+
+        let is_even = |runtime: &mut Runtime, n: i64| {
+            let result = runtime.execute_function_call("is_even", vec![Box::new(n)]).unwrap();
+            *result.downcast_ref::<i64>().unwrap() == 1
+        };
+        assert!(is_even(&mut runtime, 10));
+        assert!(!is_even(&mut runtime, 7));
+    }
+
+    #[test]
+    fn test_user_function_scope_does_not_leak_into_caller() {
+        let mut ir = IR::new();
+        ir.add_function(compile("fn double(n: int) -> int { return n * 2; }"));
+        ir.add_function(compile("fn main() -> int { return double(21); }"));
+        let mut runtime = Runtime::new();
+        runtime.execute(&ir).unwrap();
+
+        let result = runtime.execute_function_call("double", vec![Box::new(21i64)]).unwrap();
+        assert_eq!(result.downcast_ref::<i64>(), Some(&42));
+        assert!(runtime.memory_manager.get_value("n").is_none());
+        assert_eq!(runtime.memory_manager.frames.len(), 1);
+        assert!(runtime.execute_function_call("double", vec![]).is_err());
+    }
+
+    #[test]
+    fn test_recursive_calls_keep_their_own_locals() {
+        let mut ir = IR::new();
+        ir.add_function(compile("fn fib(n: int) -> int { if n < 2 { return n; } return fib(n - 1) + fib(n - 2); }"));
+        ir.add_function(compile("fn main() -> int { return 0; }"));
+        let mut runtime = Runtime::new();
+        runtime.execute(&ir).unwrap();
+
+        let result = runtime.execute_function_call("fib", vec![Box::new(10i64)]).unwrap();
+        assert_eq!(result.downcast_ref::<i64>(), Some(&55));
+    }
+
+    #[test]
+    fn test_frames_only_see_their_own_variables() {
+        let mut memory = MemoryManager::new();
+        memory.define("x".to_string(), Box::new(1i64));
+        memory.push_frame(HashMap::new());
+        // トップレベルの変数も見えず、代入は現在のフレームに変数を作る
+        assert!(memory.get_value("x").is_none());
+        memory.assign("x".to_string(), Box::new(10i64));
+        assert_eq!(memory.get_value("x").unwrap().downcast_ref::<i64>(), Some(&10));
+
+        memory.push_frame(HashMap::new());
+        assert!(memory.get_value("x").is_none());
+        assert!(memory.get_value_mut("x").is_none());
+        memory.pop_frame();
+        memory.pop_frame();
+
+        assert_eq!(memory.get_value("x").unwrap().downcast_ref::<i64>(), Some(&1));
+        memory.pop_frame();
+        assert_eq!(memory.frames.len(), 1);
+    }
+
+    #[test]
+    fn test_frame_is_released_in_priority_order() {
+        let mut memory = MemoryManager::new();
+        memory.push_frame(HashMap::new());
+        for name in ["high", "low", "plain", "top"] {
+            memory.define(name.to_string(), Box::new(0i64));
+        }
+        memory.set_priority("high", 5);
+        memory.set_priority("low", -1);
+        memory.set_priority("top", i32::MAX);
+
+        // 優先度の低い変数から解放され、高い変数ほど後まで残る
+        assert_eq!(memory.pop_frame(), vec!["low", "plain", "high", "top"]);
+        assert!(memory.pop_frame().is_empty());
+    }
+
+    #[test]
+    fn test_return_leaves_function_from_inside_loop() {
+        let function = factorial(4);
+        let mut runtime = Runtime::new();
+        let result = runtime.execute_function(&function).unwrap().unwrap();
+        assert_eq!(result.downcast_ref::<i64>(), Some(&24));
+
+        let mut ir = IR::new();
+        ir.add_function(compile("fn clamp(n: int) -> int { while n > 0 { if n < 3 { return n; } return n - 10; } return 0; }"));
+        ir.add_function(compile("fn main() -> int { return 0; }"));
+        runtime.execute(&ir).unwrap();
+        for (n, expected) in [(2, 2), (15, 5), (0, 0)] {
+            let result = runtime.execute_function_call("clamp", vec![Box::new(n as i64)]).unwrap();
+            assert_eq!(result.downcast_ref::<i64>(), Some(&expected));
+        }
+    }
+
+    #[test]
+    fn test_function_without_return_value_yields_unit() {
+        let mut ir = IR::new();
+        ir.add_function(compile("fn noop() -> int { return; }"));
+        ir.add_function(compile("fn main() -> int { return 0; }"));
+        let mut runtime = Runtime::new();
+        runtime.execute(&ir).unwrap();
+        assert!(runtime.execute_function(&ir.functions[0]).unwrap().is_none());
+        let result = runtime.execute_function_call("noop", vec![]).unwrap();
+        assert!(result.is::<()>());
+    }
+
+    fn evaluate(value: IRValue) -> Result<Box<dyn Any>> {
+        Runtime::new().evaluate_value(&value)
+    }
+
+    fn evaluate_bool(value: IRValue) -> bool {
+        *evaluate(value).unwrap().downcast_ref::<bool>().unwrap()
+    }
+
+    #[test]
+    fn test_logical_operators_short_circuit() {
+        // 右辺は未定義の変数なので、評価されればエラーになる
+        let undefined = || variable("undefined");
+        assert!(!evaluate_bool(binary(IRValue::Bool(false), IRBinaryOperator::And, undefined())));
+        assert!(evaluate_bool(binary(IRValue::Bool(true), IRBinaryOperator::Or, undefined())));
+        assert!(evaluate(binary(IRValue::Bool(true), IRBinaryOperator::And, undefined())).is_err());
+        assert!(evaluate(binary(IRValue::Bool(false), IRBinaryOperator::Or, undefined())).is_err());
+
+        assert!(evaluate_bool(binary(IRValue::Bool(true), IRBinaryOperator::And, IRValue::Bool(true))));
+        assert!(!evaluate_bool(binary(IRValue::Bool(false), IRBinaryOperator::Or, IRValue::Bool(false))));
+        assert!(evaluate(binary(IRValue::Int(1), IRBinaryOperator::And, IRValue::Bool(true))).is_err());
+    }
+
+    // `set_debug(true)` の戻り値の表示は `Any { .. }` ではなく値そのものを示す
+    #[test]
+    fn test_describe_value_shows_the_value() {
+        assert_eq!(describe_value(&42i64), "42");
+        assert_eq!(describe_value(&"hi".to_string()), "hi");
+        let array: Vec<Box<dyn Any>> = vec![Box::new(1.5f64), Box::new(2.0f64)];
+        assert_eq!(describe_value(&array), "[1.5, 2]");
+        assert_eq!(describe_value(&ComplexValue { re: 1.0, im: 2.0 }), runtime_type_name(&ComplexValue { re: 1.0, im: 2.0 }));
+
+        let mut runtime = Runtime::new();
+        runtime.set_debug(true);
+        let result = runtime.execute_function(&compile("fn main() -> int { return 7; }")).unwrap().unwrap();
+        assert_eq!(result.downcast_ref::<i64>(), Some(&7));
+    }
+
+    #[test]
+    fn test_string_comparison_is_lexicographic() {
+        let string = |s: &str| IRValue::String(s.to_string());
+        assert!(evaluate_bool(binary(string("apple"), IRBinaryOperator::LessThan, string("banana"))));
+        assert!(evaluate_bool(binary(string("b"), IRBinaryOperator::Gt, string("abc"))));
+        assert!(evaluate_bool(binary(string("same"), IRBinaryOperator::Lte, string("same"))));
+        assert!(!evaluate_bool(binary(string("Z"), IRBinaryOperator::GreaterThanEquals, string("a"))));
+        assert!(evaluate(binary(string("1"), IRBinaryOperator::LessThan, IRValue::Int(2))).is_err());
+
+        // 型検査を通したプログラムからも比べられる
+        crate::run(
+            r#"fn main() -> void {
+                assert("abc" < "abd");
+                assert("b" >= "abc");
+                let words = ["pear", "apple"];
+                assert(!(words[0] <= words[1]));
+            }"#,
+        )
+        .unwrap();
+        assert!(crate::run(r#"fn main() -> void { assert("b" < "a"); }"#).is_err());
+        assert!(crate::compile(r#"fn main() -> bool { return "1" < 2; }"#).is_err());
+    }
+
+    #[test]
+    fn test_compiled_logical_operators_skip_right_operand() {
+        // `missing` は定義されていないので、評価されれば実行時エラーになる
+        let mut runtime = Runtime::new();
+        for (source, expected) in [
+            ("fn main() -> int { if false && missing() { return 1; } return 0; }", 0),
+            ("fn main() -> int { if true || missing() { return 1; } return 0; }", 1),
+            ("fn main() -> int { if true && 1 < 2 { return 1; } return 0; }", 1),
+        ] {
+            let result = runtime.execute_function(&compile(source)).unwrap().unwrap();
+            assert_eq!(result.downcast_ref::<i64>(), Some(&expected), "{}", source);
+        }
+        let evaluated = compile("fn main() -> int { if true && missing() { return 1; } return 0; }");
+        assert!(runtime.execute_function(&evaluated).is_err());
+    }
+
+    #[test]
+    fn test_for_loop_visits_elements_in_order() {
+        let mut runtime = Runtime::new();
+        for (source, expected) in [
+            ("fn main() -> int { for x in [1, 5, 7] { if x > 4 { return x; } } return 0; }", 5),
+            ("fn main() -> int { for x in [1, 2, 3] { if x < 3 { continue; } return x; } return 0; }", 3),
+            ("fn main() -> int { for x in [1, 2, 3] { break; } return 0; }", 0),
+            ("fn main() -> int { for x in [] { return 1; } return 0; }", 0),
+        ] {
+            let result = runtime.execute_function(&compile(source)).unwrap().unwrap();
+            assert_eq!(result.downcast_ref::<i64>(), Some(&expected), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_for_loop_over_ranges() {
+        let mut runtime = Runtime::new();
+        for (source, expected) in [
+            ("fn main() -> int { let xs = [0]; for (i in 0..5) { push(xs, pop(xs) + i); } return pop(xs); }", 10),
+            ("fn main() -> int { let xs = [0]; for i in 1..=4 { push(xs, pop(xs) + i); } return pop(xs); }", 10),
+            ("fn main() -> int { for i in 3..3 { return 1; } return 0; }", 0),
+            ("fn main() -> int { let n = 2; for i in n * 2..10 { return i; } return 0; }", 4),
+            ("fn main() -> int { for (i in [7, 8]) { return i; } return 0; }", 7),
+            ("fn main() -> int { let total = 0; for i in 0..5 { total = total + i; } return total; }", 10),
+            // `..=` の終わりが `i64::MAX` でもインデックスを進めて溢れることはない
+            ("fn main() -> int { let n = 0; for i in 9223372036854775806..=9223372036854775807 { n = n + 1; } return n; }", 2),
+            ("fn main() -> int { let n = 0; for i in 9223372036854775807..=9223372036854775807 { n = n + 1; continue; } return n; }", 1),
+            ("fn main() -> int { let n = 0; for i in 5..=3 { n = n + 1; } return n; }", 0),
+        ] {
+            let result = runtime.execute_function(&compile(source)).unwrap().unwrap();
+            assert_eq!(result.downcast_ref::<i64>(), Some(&expected), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_bool_and_char_literals_in_conditions() {
+        let mut runtime = Runtime::new();
+        for (source, expected) in [
+            ("fn main() -> int { if true { return 1; } return 2; }", 1),
+            ("fn main() -> int { if false { return 1; } return 2; }", 2),
+            ("fn main() -> int { if 'a' == 'a' { return 1; } return 2; }", 1),
+            ("fn main() -> int { if 'a' != 'b' { return 1; } return 2; }", 1),
+        ] {
+            let result = runtime.execute_function(&compile(source)).unwrap().unwrap();
+            assert_eq!(result.downcast_ref::<i64>(), Some(&expected), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_array_functions() {
+        let mut runtime = Runtime::new();
+        let source = "fn main() -> int { let xs = [1, 2]; push(xs, 3); push(xs, 4); let last = pop(xs); return last * 10 + len(xs); }";
+        let result = runtime.execute_function(&compile(source)).unwrap().unwrap();
+        assert_eq!(result.downcast_ref::<i64>(), Some(&43));
+
+        let call = |runtime: &mut Runtime, name: &str, arguments: Vec<IRValue>| {
+            runtime.evaluate_value(&IRValue::Call { function: name.to_string(), arguments })
+        };
+        let array = || IRValue::Array(vec![IRValue::Int(1), IRValue::Int(2)]);
+        let result = call(&mut runtime, "len", vec![array()]).unwrap();
+        assert_eq!(result.downcast_ref::<i64>(), Some(&2));
+        let result = call(&mut runtime, "pop", vec![array()]).unwrap();
+        assert_eq!(result.downcast_ref::<i64>(), Some(&2));
+
+        assert!(call(&mut runtime, "len", vec![]).is_err());
+        assert!(call(&mut runtime, "len", vec![IRValue::Int(1)]).is_err());
+        assert!(call(&mut runtime, "push", vec![IRValue::Int(1), IRValue::Int(2)]).is_err());
+        assert!(call(&mut runtime, "push", vec![array()]).is_err());
+        assert!(call(&mut runtime, "pop", vec![]).is_err());
+        assert!(call(&mut runtime, "pop", vec![IRValue::Array(vec![])]).is_err());
+    }
+
+    #[test]
+    fn test_string_functions() {
+        let call = |name: &str, arguments: Vec<IRValue>| {
+            evaluate(IRValue::Call { function: name.to_string(), arguments })
+        };
+        let string = |s: &str| IRValue::String(s.to_string());
+        let string_result = |name: &str, arguments: Vec<IRValue>| {
+            call(name, arguments).unwrap().downcast_ref::<String>().unwrap().clone()
+        };
+
+        assert_eq!(string_result("concat", vec![string("foo"), string("bar")]), "foobar");
+        assert_eq!(string_result("substring", vec![string("héllo"), IRValue::Int(1), IRValue::Int(4)]), "éll");
+        assert_eq!(string_result("substring", vec![string("abc"), IRValue::Int(3), IRValue::Int(3)]), "");
+        assert_eq!(string_result("to_upper", vec![string("Slang")]), "SLANG");
+        assert_eq!(string_result("to_lower", vec![string("Slang")]), "slang");
+
+        assert!(call("substring", vec![string("abc"), IRValue::Int(1), IRValue::Int(4)]).is_err());
+        assert!(call("substring", vec![string("abc"), IRValue::Int(2), IRValue::Int(1)]).is_err());
+        assert!(call("substring", vec![string("abc"), IRValue::Int(-1), IRValue::Int(1)]).is_err());
+        assert!(call("concat", vec![string("a")]).is_err());
+        assert!(call("concat", vec![string("a"), IRValue::Int(1)]).is_err());
+        assert!(call("to_upper", vec![IRValue::Int(1)]).is_err());
+    }
+
+    #[test]
+    fn test_numeric_functions() {
+        let call = |name: &str, arguments: Vec<IRValue>| {
+            evaluate(IRValue::Call { function: name.to_string(), arguments })
+        };
+        let int = |name: &str, arguments: Vec<IRValue>| *call(name, arguments).unwrap().downcast_ref::<i64>().unwrap();
+        let float = |name: &str, arguments: Vec<IRValue>| *call(name, arguments).unwrap().downcast_ref::<f64>().unwrap();
+
+        assert_eq!(int("abs", vec![IRValue::Int(-3)]), 3);
+        assert_eq!(float("abs", vec![IRValue::Float(-2.5)]), 2.5);
+        assert_eq!(float("sqrt", vec![IRValue::Float(2.0)]), 2.0_f64.sqrt());
+        assert_eq!(float("sqrt", vec![IRValue::Int(9)]), 3.0);
+        assert_eq!(int("pow", vec![IRValue::Int(2), IRValue::Int(10)]), 1024);
+        assert_eq!(float("pow", vec![IRValue::Int(2), IRValue::Float(-1.0)]), 0.5);
+        assert_eq!(float("pow", vec![IRValue::Float(4.0), IRValue::Float(0.5)]), 2.0);
+        assert_eq!(int("min", vec![IRValue::Int(3), IRValue::Int(-1)]), -1);
+        assert_eq!(float("min", vec![IRValue::Int(3), IRValue::Float(1.5)]), 1.5);
+        assert_eq!(int("max", vec![IRValue::Int(3), IRValue::Int(-1)]), 3);
+        assert_eq!(float("max", vec![IRValue::Float(0.5), IRValue::Int(2)]), 2.0);
+
+        assert!(call("abs", vec![IRValue::Int(i64::MIN)]).is_err());
+        assert!(call("pow", vec![IRValue::Int(10), IRValue::Int(100)]).is_err());
+        assert!(call("pow", vec![IRValue::Int(2), IRValue::Int(-1)]).is_err());
+        assert!(call("sqrt", vec![]).is_err());
+        assert!(call("max", vec![IRValue::Int(1)]).is_err());
+        assert!(call("min", vec![IRValue::Int(1), IRValue::String("2".to_string())]).is_err());
+        assert!(call("abs", vec![IRValue::Bool(true)]).is_err());
+    }
+
+    #[test]
+    fn test_registered_host_function_is_callable() {
+        let mut runtime = Runtime::new();
+        runtime.register_function("double", vec![Type::Int], Type::Int, |args: &[Value]| match args {
+            [x] => Ok(Value::from(i64::try_from(x.clone())? * 2)),
+            _ => Err(SlangError::runtime(RuntimeErrorKind::ArgumentCount, "double() takes one argument")),
+        });
+        let result = runtime
+            .execute_function(&compile("fn main() -> int { return double(21) + 1; }"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(result.downcast_ref::<i64>(), Some(&43));
+        assert!(runtime.execute_function(&compile("fn main() -> int { return double(1.5); }")).is_err());
+    }
+
+    #[test]
+    fn test_registered_host_function_from_checked_source() {
+        let calls = Rc::new(std::cell::RefCell::new(Vec::new()));
+        let mut runtime = Runtime::new();
+        let record = calls.clone();
+        runtime.register_function("record", vec![Type::String, Type::Array(Box::new(Type::Int))], Type::Int, move |args: &[Value]| {
+            record.borrow_mut().push(args.to_vec());
+            Ok(Value::from(args.len() as i64))
+        });
+        runtime.run("fn main() { let n = record(\"xs\", [1, 2]); assert_eq(n, 2); }").unwrap();
+        assert_eq!(*calls.borrow(), vec![vec![Value::from("xs"), Value::from(vec![Value::from(1), Value::from(2)])]]);
+
+        let ir = runtime.compile("fn main() -> int { return record(\"a\", [3]) * 10; }").unwrap();
+        runtime.execute(&ir).unwrap();
+        assert_eq!(calls.borrow().len(), 2);
+        // 登録した型に合わない呼び出しは型検査で弾く。登録していない実行環境では未定義
+        let error = runtime.compile("fn main() -> int { return record(1, [2]); }").unwrap_err();
+        assert!(error.to_string().contains("Argument type mismatch"), "{}", error);
+        assert!(runtime.compile("fn main() -> string { return record(\"a\", [1]); }").is_err());
+        assert!(crate::compile("fn main() -> int { return record(\"a\", [1]); }").is_err());
+    }
+
+    #[test]
+    fn test_priority_round_trip() {
+        let mut runtime = Runtime::new();
+        assert_eq!(runtime.get_priority("x"), None);
+        runtime.set_priority("x", 3);
+        runtime.set_priority("x", 1);
+        assert_eq!(runtime.get_priority("x"), Some(1));
+    }
+
+    #[test]
+    fn test_branch_to_unknown_label_is_an_error() {
+        let mut ir = IR::new();
+        ir.add_function(IRFunction {
+            name: "main".to_string(),
+            parameters: vec![],
+            return_type: Type::Unit,
+            priorities: vec![],
+            blocks: vec![block("entry", vec![IRInstruction::Branch { label: "missing".to_string() }])],
+        });
+        assert!(Runtime::new().execute(&ir).is_err());
+    }
+
+    fn call(function: &str, arguments: Vec<IRValue>) -> IRValue {
+        IRValue::Call { function: function.to_string(), arguments }
+    }
+
+    fn row(elements: &[i64]) -> IRValue {
+        IRValue::Array(elements.iter().map(|e| IRValue::Int(*e)).collect())
+    }
+
+    #[test]
+    fn test_vector_and_matrix_arithmetic() {
+        let v = |elements: &[f64]| call("vec", elements.iter().map(|e| IRValue::Float(*e)).collect());
+        let sum = evaluate(binary(v(&[1.0, 2.0, 3.0]), IRBinaryOperator::Add, v(&[0.5, 0.5, 0.5]))).unwrap();
+        assert_eq!(sum.downcast_ref::<VectorValue>().unwrap().elements, vec![1.5, 2.5, 3.5]);
+        assert!(evaluate(binary(v(&[1.0, 2.0]), IRBinaryOperator::Sub, v(&[1.0]))).is_err());
+
+        // [[1, 2, 3], [4, 5, 6]] * [[1, 0], [0, 1], [1, 1]]
+        let a = call("mat", vec![row(&[1, 2, 3]), row(&[4, 5, 6])]);
+        let b = call("mat", vec![row(&[1, 0]), row(&[0, 1]), row(&[1, 1])]);
+        let product = evaluate(binary(a.clone(), IRBinaryOperator::Mul, b)).unwrap();
+        assert_eq!(
+            product.downcast_ref::<MatrixValue>().unwrap(),
+            &MatrixValue { rows: 2, cols: 2, elements: vec![4.0, 5.0, 10.0, 11.0] }
+        );
+        assert!(evaluate(binary(a.clone(), IRBinaryOperator::Mul, a.clone())).is_err());
+        assert!(evaluate(binary(a, IRBinaryOperator::Add, v(&[1.0]))).is_err());
+        assert!(evaluate(call("mat", vec![row(&[1, 2]), row(&[3])])).is_err());
+    }
+
+    #[test]
+    fn test_quaternion_arithmetic() {
+        let q = |w: i64, x: i64, y: i64, z: i64| call("quat", [w, x, y, z].into_iter().map(IRValue::Int).collect());
+        let quaternion = |value: IRValue| *evaluate(value).unwrap().downcast_ref::<QuaternionValue>().unwrap();
+        let expected = |w, x, y, z| QuaternionValue { w, x, y, z };
+
+        // (1 + 2i + 3j + 4k)(5 + 6i + 7j + 8k) = -60 + 12i + 30j + 24k
+        assert_eq!(quaternion(binary(q(1, 2, 3, 4), IRBinaryOperator::Mul, q(5, 6, 7, 8))), expected(-60.0, 12.0, 30.0, 24.0));
+        // 可換ではない: ij = k, ji = -k
+        assert_eq!(quaternion(binary(q(0, 1, 0, 0), IRBinaryOperator::Mul, q(0, 0, 1, 0))), expected(0.0, 0.0, 0.0, 1.0));
+        assert_eq!(quaternion(binary(q(0, 0, 1, 0), IRBinaryOperator::Mul, q(0, 1, 0, 0))), expected(0.0, 0.0, 0.0, -1.0));
+        assert_eq!(quaternion(binary(q(1, 2, 3, 4), IRBinaryOperator::Sub, q(1, 1, 1, 1))), expected(0.0, 1.0, 2.0, 3.0));
+
+        let method = |receiver: IRValue, method: &str| IRValue::MethodCall {
+            receiver: Box::new(receiver),
+            method: method.to_string(),
+            arguments: Vec::new(),
+        };
+        assert_eq!(quaternion(method(q(1, 2, 3, 4), "conjugate")), expected(1.0, -2.0, -3.0, -4.0));
+        assert_eq!(quaternion(method(q(0, 3, 0, 4), "normalize")), expected(0.0, 0.6, 0.0, 0.8));
+        let error = evaluate(method(q(0, 0, 0, 0), "normalize")).unwrap_err();
+        assert_eq!(error.runtime_kind(), Some(RuntimeErrorKind::DivByZero));
+
+        let error = evaluate(binary(q(1, 2, 3, 4), IRBinaryOperator::Add, IRValue::Float(1.0))).unwrap_err();
+        assert_eq!(error.runtime_kind(), Some(RuntimeErrorKind::TypeMismatch));
+        assert!(evaluate(binary(q(1, 2, 3, 4), IRBinaryOperator::Div, q(1, 2, 3, 4))).is_err());
+        assert!(evaluate(call("quat", vec![IRValue::Int(1)])).is_err());
+    }
+
+    #[test]
+    fn test_tensor_operations() {
+        let t = || call("tensor", vec![row(&[2, 3]), row(&[1, 2, 3, 4, 5, 6])]);
+        let tensor = |value: IRValue| evaluate(value).unwrap().downcast_ref::<TensorValue>().unwrap().clone();
+        let reshape = |value: IRValue, shape: &[i64]| call("reshape", vec![value, row(shape)]);
+
+        let reshaped = tensor(reshape(t(), &[6]));
+        assert_eq!(reshaped, TensorValue { shape: vec![6], elements: vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0] });
+        assert_eq!(tensor(binary(t(), IRBinaryOperator::Add, t())).elements, vec![2.0, 4.0, 6.0, 8.0, 10.0, 12.0]);
+        assert_eq!(tensor(binary(t(), IRBinaryOperator::Mul, t())).elements, vec![1.0, 4.0, 9.0, 16.0, 25.0, 36.0]);
+
+        assert!(evaluate(reshape(t(), &[4])).is_err());
+        assert!(evaluate(binary(t(), IRBinaryOperator::Add, reshape(t(), &[3, 2]))).is_err());
+        assert!(evaluate(binary(t(), IRBinaryOperator::Sub, t())).is_err());
+        assert!(evaluate(binary(t(), IRBinaryOperator::Add, IRValue::Int(1))).is_err());
+        assert!(evaluate(call("tensor", vec![row(&[2, 2]), row(&[1, 2, 3])])).is_err());
+        assert!(evaluate(call("tensor", vec![row(&[0]), row(&[])])).is_err());
+    }
+
+    #[test]
+    fn test_cast() {
+        assert_eq!(*evaluate_source("5 as float").unwrap().downcast_ref::<f64>().unwrap(), 5.0);
+        assert_eq!(*evaluate_source("-2.7 as int").unwrap().downcast_ref::<i64>().unwrap(), -2);
+        assert_eq!(evaluate_source("1.5 as string").unwrap().downcast_ref::<String>().unwrap(), "1.5");
+        assert_eq!(*evaluate_source("\"42\" as int + 1").unwrap().downcast_ref::<i64>().unwrap(), 43);
+        assert_eq!(*evaluate_source("\"0.25\" as float").unwrap().downcast_ref::<f64>().unwrap(), 0.25);
+
+        let error = evaluate_source("\"abc\" as int").unwrap_err();
+        assert_eq!(error.runtime_kind(), Some(RuntimeErrorKind::InvalidCast));
+        assert_eq!(error.to_string(), "Runtime error: Cannot convert string to integer: \"abc\"");
+        assert!(evaluate_source("[1] as int").is_err());
+        let error = evaluate_source("1e300 as int").unwrap_err();
+        assert_eq!(error.runtime_kind(), Some(RuntimeErrorKind::InvalidCast));
+        assert!(error.to_string().contains("Float is out of range for int"), "{}", error);
+        // 定数畳み込みできない値も実行時に確かめる
+        for source in ["fn main() -> int { let f = 1e300; return f as int; }", "fn main() -> int { return sqrt(-1.0) as int; }"] {
+            assert_eq!(run_main(source).unwrap_err().runtime_kind(), Some(RuntimeErrorKind::InvalidCast), "{}", source);
+        }
+
+        let floats = evaluate_source("[1, 2] as [float]").unwrap();
+        let floats: Vec<f64> = floats.downcast_ref::<Vec<Box<dyn Any>>>().unwrap()
+            .iter()
+            .map(|element| *element.downcast_ref::<f64>().unwrap())
+            .collect();
+        assert_eq!(floats, vec![1.0, 2.0]);
+        let error = evaluate_source("[\"1\", \"x\"] as [int]").unwrap_err();
+        assert_eq!(error.runtime_kind(), Some(RuntimeErrorKind::InvalidCast));
+    }
+
+    fn evaluate_source(source: &str) -> Result<Box<dyn Any>> {
+        let expression = crate::parser::Parser::new(crate::lexer::Lexer::new(source)).parse_standalone_expression()?;
+        evaluate(crate::compiler::Compiler::new().compile_value(&expression)?)
+    }
+
+    #[test]
+    fn test_complex_arithmetic() {
+        let complex = |source: &str| *evaluate_source(source).unwrap().downcast_ref::<ComplexValue>().unwrap();
+        assert_eq!(complex("(1 + 2i) * (3 + 4i)"), ComplexValue { re: -5.0, im: 10.0 });
+        assert_eq!(complex("(3 + 4i) - 1.5"), ComplexValue { re: 1.5, im: 4.0 });
+        assert_eq!(complex("(-5 + 10i) / (3 + 4i)"), ComplexValue { re: 1.0, im: 2.0 });
+        assert_eq!(complex("-(2i)"), ComplexValue { re: -0.0, im: -2.0 });
+        assert!(*evaluate_source("1 + 2i == 1 + 2i").unwrap().downcast_ref::<bool>().unwrap());
+
+        // 大きさ 0 の複素数では割れない
+        let error = evaluate_source("(1 + 2i) / (0 + 0i)").unwrap_err();
+        assert!(error.to_string().contains("Division by zero"), "{}", error);
+        assert!(evaluate_source("1i < 2i").is_err());
+    }
+
+    #[test]
+    fn test_integer_overflow_modes() {
+        let max_plus_one = binary(IRValue::Int(i64::MAX), IRBinaryOperator::Add, IRValue::Int(1));
+        let run = |mode: OverflowMode, value: &IRValue| Runtime::with_overflow_mode(mode).evaluate(value);
+
+        assert_eq!(run(OverflowMode::Wrapping, &max_plus_one).unwrap(), Value::Int(i64::MIN));
+        assert_eq!(run(OverflowMode::Saturating, &max_plus_one).unwrap(), Value::Int(i64::MAX));
+        let error = run(OverflowMode::Checked, &max_plus_one).unwrap_err();
+        assert_eq!(error.runtime_kind(), Some(RuntimeErrorKind::Overflow));
+        assert_eq!(error.to_string(), "Runtime error: Integer overflow in addition");
+        // 既定は Checked
+        assert!(Runtime::new().evaluate(&max_plus_one).is_err());
+
+        let min = || IRValue::Int(i64::MIN);
+        let negate_min = IRValue::UnaryOp { op: IRUnaryOperator::Negate, expr: Box::new(min()) };
+        let min_div = binary(min(), IRBinaryOperator::Div, IRValue::Int(-1));
+        let min_mod = binary(min(), IRBinaryOperator::Mod, IRValue::Int(-1));
+        let min_mul = binary(min(), IRBinaryOperator::Mul, IRValue::Int(2));
+        assert_eq!(run(OverflowMode::Saturating, &negate_min).unwrap(), Value::Int(i64::MAX));
+        assert_eq!(run(OverflowMode::Wrapping, &min_div).unwrap(), Value::Int(i64::MIN));
+        assert_eq!(run(OverflowMode::Saturating, &min_mod).unwrap(), Value::Int(0));
+        assert_eq!(run(OverflowMode::Saturating, &min_mul).unwrap(), Value::Int(i64::MIN));
+        for value in [&negate_min, &min_div, &min_mod, &min_mul] {
+            assert!(run(OverflowMode::Checked, value).is_err(), "{}", value);
+        }
+        assert_eq!(run(OverflowMode::Checked, &binary(IRValue::Int(2), IRBinaryOperator::Sub, IRValue::Int(3))).unwrap(), Value::Int(-1));
+    }
+
+    #[test]
+    fn test_profiler_counts_loop_body_and_calls() {
+        let mut compiler = crate::compiler::Compiler::new();
+        compiler.parse(
+            "fn step(i: int) -> int { return i + 1; }
+             fn main() -> int { let mut i = 0; while i < 5 { i = step(i); } return i; }",
+        ).unwrap();
+        let ir = compiler.compile_program().unwrap();
+
+        let mut runtime = Runtime::new();
+        runtime.execute(&ir).unwrap();
+        assert!(runtime.take_profile().is_empty());
+
+        runtime.enable_profiling();
+        runtime.execute(&ir).unwrap();
+        let profile = runtime.take_profile();
+        assert_eq!(profile.get("main"), Some(&1));
+        assert_eq!(profile.get("step"), Some(&5));
+        assert_eq!(profile.get("step:entry:0"), Some(&5));
+        let count_in = |prefix: &str| {
+            profile.iter().find(|(key, _)| key.starts_with(prefix) && key.ends_with(":0")).map(|(_, count)| *count)
+        };
+        assert_eq!(count_in("main:while_body"), Some(5));
+        // 条件は抜けるときの 1 回を含めて評価される
+        assert_eq!(count_in("main:while_header"), Some(6));
+
+        // 取り出すと 0 から数え直す
+        assert!(runtime.take_profile().is_empty());
+    }
+
+    #[test]
+    fn test_assert_and_panic() {
+        assert!(evaluate_source("assert(1 == 1)").is_ok());
+        let error = evaluate_source("assert(false)").unwrap_err();
+        assert_eq!(error.runtime_kind(), Some(RuntimeErrorKind::AssertionFailed));
+        assert_eq!(error.to_string(), "Runtime error: Assertion failed");
+        assert_eq!(evaluate_source("assert(1)").unwrap_err().runtime_kind(), Some(RuntimeErrorKind::TypeMismatch));
+
+        assert!(evaluate_source("assert_eq(\"ab\", \"ab\")").is_ok());
+        assert!(evaluate_source("assert_eq(1 + 2i, 1 + 2i)").is_ok());
+        let error = evaluate_source("assert_eq([1, 2][0], 2)").unwrap_err();
+        assert_eq!(error.runtime_kind(), Some(RuntimeErrorKind::AssertionFailed));
+        assert!(error.to_string().contains("Assertion failed: 1 != 2"), "{}", error);
+        // `==` で比べられない組み合わせは失敗ではなく型の不一致
+        assert_eq!(evaluate_source("assert_eq(1, 1.0)").unwrap_err().runtime_kind(), Some(RuntimeErrorKind::TypeMismatch));
+
+        let mut runtime = Runtime::new();
+        let error = runtime
+            .execute_function(&compile("fn main() -> int { panic(\"unreachable state\"); return 1; }"))
+            .unwrap_err();
+        assert_eq!(error.runtime_kind(), Some(RuntimeErrorKind::Panic));
+        assert!(error.to_string().contains("panic: unreachable state"), "{}", error);
+    }
+
+    #[test]
+    fn test_optional_values_compare_with_null() {
+        let mut runtime = Runtime::new();
+        let source = "fn main() -> int { let x: int? = null; let y: int? = 4; \
+                      if x == null { if y != null { return y + 1; } } return 0; }";
+        let result = runtime.execute_function(&compile(source)).unwrap().unwrap();
+        assert_eq!(result.downcast_ref::<i64>(), Some(&5));
+        assert_eq!(evaluate(binary(IRValue::Null, IRBinaryOperator::Eq, IRValue::Null)).unwrap().downcast_ref::<bool>(), Some(&true));
+        assert_eq!(evaluate(binary(IRValue::Int(1), IRBinaryOperator::Neq, IRValue::Null)).unwrap().downcast_ref::<bool>(), Some(&true));
+    }
+
+    #[test]
+    fn test_unary_operator_on_wrong_type_is_an_error() {
+        // 変数に入った bool の符号反転も、パニックせずに型の不一致になる
+        let mut runtime = Runtime::new();
+        let error = runtime
+            .execute_function(&compile("fn main() -> void { let flag = true; let x = -flag; }"))
+            .unwrap_err();
+        assert_eq!(error.runtime_kind(), Some(RuntimeErrorKind::TypeMismatch));
+        assert!(error.to_string().contains("Invalid operand for negation: Bool"), "{}", error);
+
+        let error = evaluate_source("-'a'").unwrap_err();
+        assert_eq!(error.runtime_kind(), Some(RuntimeErrorKind::TypeMismatch));
+        assert!(error.to_string().contains("Char"), "{}", error);
+        let error = evaluate_source("!1").unwrap_err();
+        assert!(error.to_string().contains("Invalid operand for logical not: Int"), "{}", error);
+
+        assert_eq!(evaluate_source("-2.5").unwrap().downcast_ref::<f64>(), Some(&-2.5));
+        assert_eq!(evaluate_source("!false").unwrap().downcast_ref::<bool>(), Some(&true));
+    }
+
+    #[test]
+    fn test_runtime_errors_carry_their_kind() {
+        let mut runtime = Runtime::new();
+        let error = runtime
+            .execute_function(&compile("fn main() -> int { let zero = 0; return 1 / zero; }"))
+            .unwrap_err();
+        assert_eq!(error.runtime_kind(), Some(RuntimeErrorKind::DivByZero));
+        // 表示は種類を含まない
+        assert_eq!(error.to_string(), "Runtime error: Division by zero");
+
+        assert_eq!(evaluate_source("7 % 0").unwrap_err().runtime_kind(), Some(RuntimeErrorKind::DivByZero));
+        assert_eq!(evaluate_source("[1, 2][2]").unwrap_err().runtime_kind(), Some(RuntimeErrorKind::IndexOutOfBounds));
+        assert_eq!(evaluate_source("1 + true").unwrap_err().runtime_kind(), Some(RuntimeErrorKind::TypeMismatch));
+        assert_eq!(SlangError::Type("x".to_string()).runtime_kind(), None);
+    }
+
+    #[test]
+    fn test_lambda_captures_enclosing_scope() {
+        let mut ir = crate::compile(
+            "fn apply(f: fn(int) -> int, x: int) -> int { return f(x); }
+             fn main() -> int {
+                 let mut k = 10;
+                 let add_k = fn(x: int) -> int { return x + k; };
+                 k = 100;
+                 return apply(add_k, 1) + apply(fn(x: int) { return x * k; }, 2);
+             }",
+        ).unwrap();
+        assert!(ir.functions.iter().any(|function| function.name == "main$lambda0" && function.is_lambda()));
+        crate::optimizer::Optimizer::new().optimize(&mut ir);
+
+        let mut runtime = Runtime::new();
+        runtime.execute(&ir).unwrap();
+        // `add_k` は作った時点の `k` (10) を捕まえている
+        let result = runtime.execute_function_call("main", Vec::new()).unwrap();
+        assert_eq!(result.downcast_ref::<i64>(), Some(&211));
+
+        let error = runtime.execute_function_call("apply", vec![Box::new(1i64), Box::new(1i64)]).unwrap_err();
+        assert_eq!(error.runtime_kind(), Some(RuntimeErrorKind::UndefinedFunction));
+    }
+
+    // 命令を実行する直前の、`main` の中での `x` と `y` の優先度と、`x` の優先度の履歴の長さを記録する
+    #[derive(Clone)]
+    struct PrioritySample {
+        function: String,
+        x: Option<i32>,
+        y: Option<i32>,
+        history: usize,
+    }
+
+    #[derive(Default)]
+    struct PriorityTrace(std::cell::RefCell<Vec<PrioritySample>>);
+
+    impl ExecutionObserver for PriorityTrace {
+        fn on_instruction(&self, loc: Location, runtime: &mut Runtime) -> StepAction {
+            let history = runtime.priority_ownership_manager.get_priority("x").map_or(0, Vec::len);
+            self.0.borrow_mut().push(PrioritySample {
+                function: loc.function,
+                x: runtime.get_priority("x"),
+                y: runtime.get_priority("y"),
+                history,
+            });
+            StepAction::Continue
+        }
+    }
+
+    fn trace_priorities(source: &str) -> (Result<()>, Vec<PrioritySample>) {
+        let mut compiler = crate::compiler::Compiler::new();
+        compiler.parse(source).unwrap();
+        let ir = compiler.compile_program().unwrap();
+        let trace = Rc::new(PriorityTrace::default());
+        let mut runtime = Runtime::new();
+        runtime.set_observer(trace.clone());
+        let result = runtime.execute(&ir);
+        // 関数を抜けると宣言した優先度は残らない
+        assert_eq!(runtime.get_priority("x"), None);
+        let trace = trace.0.borrow().clone();
+        (result, trace)
+    }
+
+    #[test]
+    fn test_transfer_priority() {
+        let (result, trace) = trace_priorities(
+            "fn main() -> void { Var:type:priority: 3 let x = 1; let y = 2; transfer x -> y; return; }",
+        );
+        result.unwrap();
+        let last = trace.last().unwrap();
+        assert_eq!((last.function.as_str(), last.x, last.y), ("main", Some(3), Some(3)));
+
+        // 移動元に優先度がなければエラー
+        let (result, _) = trace_priorities("fn main() -> void { let x = 1; let y = 2; transfer x -> y; }");
+        let error = result.unwrap_err();
+        assert!(error.to_string().contains("Source not found: x"), "{}", error);
+    }
+
+    #[test]
+    fn test_declared_priority_is_recorded() {
+        let (result, trace) = trace_priorities(
+            "fn main() -> void { Var:type:priority: 3, 7 let x = 1; let y = 2; transfer x -> y; return; }",
+        );
+        result.unwrap();
+        let last = trace.last().unwrap();
+        assert_eq!((last.x, last.y), (Some(7), Some(7)));
+    }
+
+    #[test]
+    fn test_priorities_are_scoped_to_the_declaring_frame() {
+        // 呼び出した関数の `x` の優先度は、呼び出し元の同名の `x` には残らない
+        let (result, trace) = trace_priorities(
+            "fn callee() -> int { Var:type:priority: 3 let x = 1; return x; } \
+             fn main() -> void { let x = callee(); let y = 2; transfer x -> y; }",
+        );
+        let error = result.unwrap_err();
+        assert!(error.to_string().contains("Source not found: x"), "{}", error);
+        assert!(trace.iter().any(|sample| sample.function == "callee" && sample.x == Some(3)));
+        assert!(trace.iter().filter(|sample| sample.function == "main").all(|sample| sample.x.is_none()));
+
+        // 再帰しても優先度の履歴はフレームごとで、積み上がらない
+        let (result, trace) = trace_priorities(
+            "fn down(n: int) -> int { Var:type:priority: 2 let x = n; if n == 0 { return 0; } return down(n - 1); } \
+             fn main() -> void { let r = down(5); }",
+        );
+        result.unwrap();
+        assert_eq!(trace.iter().map(|sample| sample.history).max(), Some(1));
+    }
+
+    // `crate::compile` (型検査を含む) を通したプログラムの `main` を実行して戻り値を返す
+    fn run_main(source: &str) -> Result<Box<dyn Any>> {
+        let ir = crate::compile(source)?;
+        let mut runtime = Runtime::new();
+        for function in &ir.functions {
+            runtime.functions.insert(function.name.clone(), Rc::new(function.clone()));
+        }
+        let main = runtime.functions.get("main").cloned().expect("no main function");
+        Ok(runtime.execute_function(&main)?.unwrap_or_else(|| Box::new(())))
+    }
+
+    #[test]
+    fn test_print_from_checked_source() {
+        crate::run("fn main() -> void { print(1, \"two\", [3.0]); print(); }").unwrap();
+        assert!(run_main("fn main() -> void { print(true); }").unwrap().is::<()>());
+        // 利用者定義の関数は組み込み関数より優先される
+        let value = run_main("fn print(x: int) -> int { return x + 1; } fn main() -> int { return print(1); }").unwrap();
+        assert_eq!(value.downcast_ref::<i64>(), Some(&2));
+        let error = crate::compile("fn main() -> void { prnt(1); }").unwrap_err();
+        assert!(error.to_string().contains("Undefined function: prnt"), "{}", error);
+    }
+
+    #[test]
+    fn test_array_builtins_from_checked_source() {
+        let source = "fn main() -> int { let mut xs = [1, 2]; push(xs, 3); let last = pop(xs); \
+                      return last * 100 + len(xs) * 10 + len(\"a\"); }";
+        assert_eq!(run_main(source).unwrap().downcast_ref::<i64>(), Some(&321));
+        crate::run(source).unwrap();
+        let error = |source: &str| crate::compile(source).unwrap_err().to_string();
+        assert!(error("fn main() -> void { let xs = [1]; push(xs, 1.5); }").contains("push() expects (array, element), got ([int], float)"));
+        assert!(error("fn main() -> int { return len(1); }").contains("len() expects"));
+        assert!(error("fn main() -> float { let xs = [1]; return pop(xs); }").contains("Return type mismatch"));
+
+        // 空の配列は注釈や引数の型から要素の型が決まる
+        let source = "fn total(xs: [int]) -> int { let mut sum = 0; for x in xs { sum = sum + x; } return sum; } \
+                      fn main() -> int { let mut xs: [int] = []; push(xs, 4); push(xs, 5); return total(xs) + total([]); }";
+        assert_eq!(run_main(source).unwrap().downcast_ref::<i64>(), Some(&9));
+        assert!(error("fn main() -> void { let xs = []; }").contains("annotate it like `let xs: [int] = [];`"));
+        assert!(error("fn main() -> void { let xs: int = []; }").contains("Type mismatch in let statement"));
+    }
+
+    #[test]
+    fn test_string_builtins_from_checked_source() {
+        let source = "fn main() -> string { let s = concat(\"Hello, \", \"World\"); return to_lower(substring(to_upper(s), 7, 12)); }";
+        assert_eq!(run_main(source).unwrap().downcast_ref::<String>().map(String::as_str), Some("world"));
+        crate::run(source).unwrap();
+        let error = |source: &str| crate::compile(source).unwrap_err().to_string();
+        assert!(error("fn main() -> string { return concat(\"a\", 1); }").contains("concat() expects (string, string), got (string, int)"));
+        assert!(error("fn main() -> string { return substring(\"abc\", 0); }").contains("substring() expects"));
+        assert!(error("fn main() -> int { return to_upper(\"a\"); }").contains("Return type mismatch"));
+    }
+
+    #[test]
+    fn test_math_builtins_from_checked_source() {
+        let int = |source: &str| *run_main(source).unwrap().downcast_ref::<i64>().unwrap();
+        let float = |source: &str| *run_main(source).unwrap().downcast_ref::<f64>().unwrap();
+        assert_eq!(int("fn main() -> int { return abs(-3) + pow(2, 10) + min(3, -1) + max(3, -1); }"), 1029);
+        assert_eq!(float("fn main() -> float { return abs(-2.5) + sqrt(9) + pow(2, -1.0) + min(3, 1.5) + max(0.5, 2); }"), 9.5);
+        crate::run("fn main() -> float { return sqrt(2.0); }").unwrap();
+        let error = |source: &str| crate::compile(source).unwrap_err().to_string();
+        assert!(error("fn main() -> int { return sqrt(4); }").contains("Return type mismatch"));
+        assert!(error("fn main() -> int { return min(1, 2.0); }").contains("Return type mismatch"));
+        assert!(error("fn main() -> float { return abs(\"1\"); }").contains("abs() expects (int) or (float), got (string)"));
+        assert!(error("fn main() -> float { return max(1.0); }").contains("max() expects"));
+    }
+
+    #[test]
+    fn test_vector_and_matrix_constructors_from_checked_source() {
+        let vector = run_main("fn main() -> vec3<float> { let a = vec(1, 2, 3); let b: vec3<float> = vec(0.5, 0.5, 0.5); return a + b - b + b; }")
+            .unwrap();
+        assert_eq!(vector.downcast_ref::<VectorValue>().unwrap().elements, vec![1.5, 2.5, 3.5]);
+        let matrix = run_main("fn main() -> mat2x1<float> { let m = mat([1, 2], [3, 4]); return m * mat([1.0], [10.0]) + mat([0], [1]); }")
+            .unwrap();
+        let matrix = matrix.downcast_ref::<MatrixValue>().unwrap();
+        assert_eq!((matrix.rows, matrix.cols, matrix.elements.clone()), (2, 1, vec![21.0, 44.0]));
+        crate::run("fn main() -> void { let v = vec(1, 2) + vec(3, 4); }").unwrap();
+
+        let error = |source: &str| crate::compile(source).unwrap_err().to_string();
+        assert!(error("fn main() -> void { let v = vec(1, 2) + vec(1, 2, 3); }").contains("Dimension mismatch"));
+        assert!(error("fn main() -> void { let m = mat([1, 2], [3, 4]) * mat([1, 2], [3, 4], [5, 6]); }").contains("Dimension mismatch"));
+        assert!(error("fn main() -> void { let m = mat([1, 2], [3]); }").contains("mat() expects"));
+        assert!(error("fn main() -> void { let v = vec(\"x\"); }").contains("vec() expects (number, ...), got (string)"));
+    }
+
+    #[test]
+    fn test_quaternion_constructor_from_checked_source() {
+        let source = "fn main() -> quat<float> { let i = quat(0, 1, 0, 0); let j: quat<float> = quat(0.0, 0.0, 1.0, 0.0); \
+                      return (i * j + quat(1, 0, 0, 0)).conjugate(); }";
+        let value = run_main(source).unwrap();
+        assert_eq!(value.downcast_ref::<QuaternionValue>(), Some(&QuaternionValue { w: 1.0, x: 0.0, y: 0.0, z: -1.0 }));
+        crate::run(source).unwrap();
+        let error = |source: &str| crate::compile(source).unwrap_err().to_string();
+        assert!(error("fn main() -> void { let q = quat(1, 2, 3); }").contains("quat() expects (w, x, y, z) with numbers, got (int, int, int)"));
+        assert!(error("fn main() -> void { let q = quat(1, 0, 0, 0) + 1.0; }").contains("Cannot apply"));
+    }
+
+    #[test]
+    fn test_tensor_constructor_from_checked_source() {
+        let source = "fn main() -> tensor<3x2, float> { let t = tensor([2, 3], [1, 2, 3, 4, 5, 6]); \
+                      let u: tensor<2x3, float> = t * t; return reshape(u + t, [3, 2]); }";
+        let value = run_main(source).unwrap();
+        let tensor = value.downcast_ref::<TensorValue>().unwrap();
+        assert_eq!(tensor.shape, vec![3, 2]);
+        assert_eq!(tensor.elements, vec![2.0, 6.0, 12.0, 20.0, 30.0, 42.0]);
+        crate::run(source).unwrap();
+
+        let error = |source: &str| crate::compile(source).unwrap_err().to_string();
+        assert!(error("fn main() -> void { let t = tensor([2, 2], [1, 2, 3]); }").contains("tensor<2x2, float> needs 4 elements, got 3"));
+        assert!(error("fn main() -> void { let t = tensor([2, 0], [1]); }").contains("positive integer literals"));
+        assert!(error("fn main() -> void { let t = tensor([2], [\"a\", \"b\"]); }").contains("tensor() expects (shape, array of numbers)"));
+        assert!(error("fn main() -> void { let t = reshape(tensor([4], [1, 2, 3, 4]), [3]); }").contains("Cannot reshape"));
+    }
+
+    #[test]
+    fn test_assert_and_panic_from_checked_source() {
+        // ガイドの例をそのまま動かす。最後の `panic` で止まる
+        for (guide, message) in [(include_str!("../../doc/guide.md"), "ここには来ない"), (include_str!("../../doc/guide_en.md"), "never reached")] {
+            let start = guide.find("```slang\nfn main() {\n    assert(").expect("assert example in the guide") + "```slang\n".len();
+            let snippet = &guide[start..start + guide[start..].find("```").unwrap()];
+            let error = crate::run(snippet).unwrap_err();
+            assert_eq!(error.runtime_kind(), Some(RuntimeErrorKind::Panic), "{}", error);
+            assert!(error.to_string().contains(message), "{}", error);
+        }
+        assert!(run_main("fn main() -> int { assert(true); assert_eq(\"a\", \"a\"); return 1; }").is_ok());
+        let error = run_main("fn main() { assert_eq(1 + 1, 3); }").unwrap_err();
+        assert_eq!(error.runtime_kind(), Some(RuntimeErrorKind::AssertionFailed));
+        // `panic` で終わる経路には `return` がなくてよい
+        let error = run_main("fn main() -> int { if false { return 1; } panic(\"no value\"); }").unwrap_err();
+        assert_eq!(error.runtime_kind(), Some(RuntimeErrorKind::Panic));
+
+        let error = |source: &str| crate::compile(source).unwrap_err().to_string();
+        assert!(error("fn main() { assert(1); }").contains("assert() expects (bool), got (int)"));
+        assert!(error("fn main() { assert_eq(1, 1.0); }").contains("assert_eq() expects"));
+        assert!(error("fn main() { panic(1); }").contains("panic() expects (string)"));
+    }
+
+    #[test]
+    fn test_interpolated_strings_run() {
+        let source = "fn main() -> string { let name = \"slang\"; let n = 2; let xs = [1.5, 2.0]; let c = 'c'; \
+                      return \"{name} v{n + 1}: {xs} {n > 1} {c}\"; }";
+        assert_eq!(run_main(source).unwrap().downcast_ref::<String>().map(String::as_str), Some("slang v3: [1.5, 2] true c"));
+        crate::run(source).unwrap();
+        let error = crate::compile("fn main() -> string { let v = vec(1, 2); return \"{v}\"; }").unwrap_err();
+        assert!(error.to_string().contains("to_string() expects"), "{}", error);
+        assert_eq!(evaluate_source("to_string(vec(1, 2))").unwrap_err().runtime_kind(), Some(RuntimeErrorKind::TypeMismatch));
+    }
+
+    #[test]
+    fn test_every_standard_library_function_has_a_signature() {
+        let library = StandardLibrary::new();
+        for name in library.functions.keys().chain(library.in_place_functions.keys()) {
+            assert!(crate::type_system::check_builtin_call(name, &[], &[]).is_some(), "{} has no signature", name);
+        }
+    }
+
+    #[test]
+    fn test_if_let_and_while_let_run() {
+        let int = |source: &str| *run_main(source).unwrap().downcast_ref::<i64>().unwrap();
+        assert_eq!(int("fn main() -> int { if let x = 41 { return x + 1; } return 0; }"), 42);
+        let source = "fn pick(first: int, second: int?) -> int { let t = (first, second); \
+                      if let (a, null) = t { return a; } else if let (0, b) = t { if b != null { return b; } } return -1; } \
+                      fn main() -> int { return pick(7, null) * 100 + pick(0, 5) * 10 + pick(1, 2); }";
+        assert_eq!(int(source), 7 * 100 + 5 * 10 - 1);
+        assert_eq!(int("fn main() -> int { let person = (\"John\", 30); if let (_, 30) = person { return 1; } else { return 2; } }"), 1);
+
+        // `while let` は周回ごとに式を評価し直し、合わなくなったら抜ける
+        let source = "fn main() -> int { let mut xs = [3, 2, 1, 0]; let mut total = 0; \
+                      while let (n, true) = (pop(xs), len(xs) > 0) { total = total * 10 + n; } return total; }";
+        assert_eq!(int(source), 12);
+        crate::run(source).unwrap();
+
+        let error = crate::compile("struct P { x: int } fn main(p: P) -> int { if let P { x } = p { return x; } return 0; }").unwrap_err();
+        assert!(error.to_string().contains("Struct patterns cannot be lowered"), "{}", error);
+    }
+
+    #[test]
+    fn test_match_runs_first_matching_arm() {
+        let int = |source: &str| *run_main(source).unwrap().downcast_ref::<i64>().unwrap();
+        let classify = "fn classify(n: int) -> int { match n { 0 => { return 10; } 1 => { return 20; } other => { return other * 2; } } } ";
+        assert_eq!(int(&format!("{}fn main() -> int {{ return classify(0) + classify(1) + classify(7); }}", classify)), 44);
+        let source = "fn main() -> int { let mut total = 0; for n in [1, 2, 3] { \
+                      match (n, n > 1) { (1, _) => { total = total + 100; } (x, true) => { let n = x * 10; total = total + n; } (_, false) => { total = 0; } } } \
+                      return total; }";
+        assert_eq!(int(source), 150);
+        let source = r#"fn main() -> int { let mut code = 0; match "b" { "a" => { code = 1; } "b" => { code = 2; } _ => { code = 3; } } return code; }"#;
+        assert_eq!(int(source), 2);
+        crate::run(&format!("{}fn main() -> void {{ assert_eq(classify(5), 10); }}", classify)).unwrap();
+
+        // 構造体の値はまだ IR にできないので、構造体パターンの `match` も変換できない
+        let error = crate::compile("struct P { x: int } fn main(p: P) -> int { match p { P { x } => { return x; } } }").unwrap_err();
+        assert!(error.to_string().contains("Struct patterns cannot be lowered"), "{}", error);
+    }
+
+    #[test]
+    fn test_blocks_scope_their_variables() {
+        let int = |source: &str| *run_main(source).unwrap().downcast_ref::<i64>().unwrap();
+        assert_eq!(int("fn main() -> int { let x = 1; if true { let x = 2; } return x; }"), 1);
+        assert_eq!(int("fn main() -> int { let x = 1; if true { let x = \"s\"; assert_eq(x, \"s\"); } return x + 1; }"), 2);
+        assert_eq!(int("fn main() -> int { let x = 1; while let y = x { let x = y + 10; assert_eq(x, 11); break; } return x; }"), 1);
+        // ループ変数は外側の同名の変数を書き換えない
+        assert_eq!(int("fn main() -> int { let i = 100; let mut sum = 0; for i in 0..4 { sum = sum + i; } return i + sum; }"), 106);
+        assert_eq!(int("fn main() -> int { let x = [5]; for x in x { assert_eq(x, 5); } return len(x); }"), 1);
+        // 捕まえるのはラムダを書いた位置で見えている変数
+        let source = "fn main() -> int { let x = 1; let mut r = 0; if true { let x = 2; let f = fn() -> int { return x; }; r = f(); } \
+                      return r * 10 + x; }";
+        assert_eq!(int(source), 21);
+        crate::run(source).unwrap();
+
+        let error = crate::compile("fn main() -> int { if true { let y = 1; } return y; }").unwrap_err();
+        assert!(error.to_string().contains("Undefined variable: y"), "{}", error);
+        assert!(crate::compile("fn main() -> int { for i in 0..3 { } return i; }").is_err());
+    }
+
+    #[test]
+    fn test_callee_cannot_see_caller_variables() {
+        // 型検査を通さずに、呼び出し先から `main` の変数を参照する
+        let mut compiler = crate::Compiler::new();
+        compiler.parse("fn peek() -> int { return secret; } fn main() -> int { let secret = 1; return peek(); }").unwrap();
+        let error = Runtime::new().execute(&compiler.compile_program().unwrap()).unwrap_err();
+        assert_eq!(error.runtime_kind(), Some(RuntimeErrorKind::UndefinedVariable), "{}", error);
+
+        compiler.parse("fn poke() -> void { secret = 2; } fn main() -> int { let secret = 1; poke(); assert_eq(secret, 1); return secret; }").unwrap();
+        Runtime::new().execute(&compiler.compile_program().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_local_closures_shadow_functions() {
+        let source = "fn twice(x: int) -> int { return x * 100; } \
+                      fn main() -> int { let twice = fn(x: int) -> int { return x * 2; }; return twice(1); }";
+        assert_eq!(run_main(source).unwrap().downcast_ref::<i64>(), Some(&2));
+        crate::run(source).unwrap();
+        let source = "fn main() -> int { let len = fn(s: string) -> int { return 7; }; return len(\"ab\"); }";
+        assert_eq!(run_main(source).unwrap().downcast_ref::<i64>(), Some(&7));
+        // ラムダの外では関数が見える
+        let source = "fn twice(x: int) -> int { return x * 100; } \
+                      fn main() -> int { if true { let twice = fn(x: int) -> int { return x * 2; }; } return twice(1); }";
+        assert_eq!(run_main(source).unwrap().downcast_ref::<i64>(), Some(&100));
+        let source = "fn main() -> int { if true { let len = fn(s: string) -> int { return 7; }; } return len(\"ab\"); }";
+        assert_eq!(run_main(source).unwrap().downcast_ref::<i64>(), Some(&2));
+    }
+
+    #[test]
+    fn test_captured_variables_cannot_be_assigned() {
+        let error = |source: &str| crate::compile(source).unwrap_err().to_string();
+        let message = "Cannot assign to `n` inside a lambda: closures capture variables by value";
+        assert!(error("fn main() -> int { let mut n = 0; let inc = fn() { n = n + 1; }; inc(); return n; }").contains(message));
+        assert!(error("fn main(n: int) -> void { let f = fn() { if true { n = 1; } }; }").contains(message));
+        assert!(error("fn main() -> void { let mut xs = [1]; let f = fn() { push(xs, 2); }; }").contains("Cannot assign to `xs`"));
+        // ラムダの中で束縛し直した変数や、ラムダの引数には代入できる
+        let source = "fn main() -> int { let n = 1; let f = fn(m: int) -> int { let mut n = m; n = n + 1; return n; }; return f(n) + n; }";
+        assert_eq!(run_main(source).unwrap().downcast_ref::<i64>(), Some(&3));
+    }
+
+    #[test]
+    fn test_mixed_int_and_float_operations() {
+        let float = |source: &str| *run_main(source).unwrap().downcast_ref::<f64>().unwrap();
+        let boolean = |source: &str| *run_main(source).unwrap().downcast_ref::<bool>().unwrap();
+        assert_eq!(float("fn main() -> float { return 1 + 2.0; }"), 3.0);
+        assert_eq!(float("fn main() -> float { let n = 3; return 0.5 * n - 1; }"), 0.5);
+        assert_eq!(float("fn main() -> float { return 7 / 2.0; }"), 3.5);
+        assert_eq!(float("fn main() -> float { return 7.5 % 2; }"), 1.5);
+        assert!(boolean("fn main() -> bool { return 1 == 1.0; }"));
+        assert!(boolean("fn main() -> bool { return 1 != 1.5; }"));
+        assert!(boolean("fn main() -> bool { return 2.5 > 2; }"));
+        // int どうしは int のまま
+        assert_eq!(run_main("fn main() -> int { return 7 / 2; }").unwrap().downcast_ref::<i64>(), Some(&3));
+        assert!(crate::compile("fn main() -> int { return 1 + 2.0; }").is_err());
+    }
+
+    #[test]
+    fn test_fixed_arrays_run() {
+        let source = "fn total(xs: [int]) -> int { let mut sum = 0; for x in xs { sum = sum + x; } return sum; } \
+                      fn main() -> int { let xs: [int; 3] = [1, 2, 3]; return total(xs) * 10 + xs[2] + len(xs); }";
+        assert_eq!(run_main(source).unwrap().downcast_ref::<i64>(), Some(&66));
+    }
+
+    #[test]
+    fn test_frames_are_released_in_priority_order_when_functions_return() {
+        #[derive(Default)]
+        struct Releases(std::cell::RefCell<Vec<(String, Vec<String>)>>);
+        impl ExecutionObserver for Releases {
+            fn on_instruction(&self, _loc: Location, _runtime: &mut Runtime) -> StepAction {
+                StepAction::Continue
+            }
+
+            fn on_frame_released(&self, function: &str, released: &[String]) {
+                self.0.borrow_mut().push((function.to_string(), released.to_vec()));
+            }
+        }
+
+        let source = "fn helper(n: int) -> int { Var:type:priority: 3 let high = n; Var:type:priority: 1 let low = n; \
+                                                let plain = n; return high + low + plain; } \
+                      fn main() -> void { Var:type:priority: 9 let keep = 1; let total = helper(keep); }";
+        let ir = crate::compile(source).unwrap();
+        let releases = Rc::new(Releases::default());
+        let mut runtime = Runtime::new();
+        runtime.set_observer(releases.clone());
+        runtime.execute(&ir).unwrap();
+
+        let names = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+        assert_eq!(*releases.0.borrow(), vec![
+            ("helper".to_string(), names(&["n", "plain", "low", "high"])),
+            ("main".to_string(), names(&["total", "keep"])),
+        ]);
+        // `main` の変数も残らない
+        assert!(runtime.memory_manager.get_value("keep").is_none());
+        assert_eq!(runtime.memory_manager.frames.len(), 1);
+    }
+}
@@ -0,0 +1,31 @@
+use std::collections::HashMap;
+
+// 実行回数を数える。命令は `Location` と同じ `関数名:ブロック名:番号`、関数の呼び出しは関数名をキーにする。
+// どちらの名前も `:` を含まないので衝突しない
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+    counts: HashMap<String, u64>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_instruction(&mut self, function: &str, block: &str, index: usize) {
+        *self.counts.entry(format!("{}:{}:{}", function, block, index)).or_default() += 1;
+    }
+
+    pub fn record_call(&mut self, function: &str) {
+        *self.counts.entry(function.to_string()).or_default() += 1;
+    }
+
+    pub fn counts(&self) -> &HashMap<String, u64> {
+        &self.counts
+    }
+
+    // 数えた回数を取り出して 0 から数え直す
+    pub fn take(&mut self) -> HashMap<String, u64> {
+        std::mem::take(&mut self.counts)
+    }
+}
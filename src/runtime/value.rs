@@ -0,0 +1,266 @@
+use super::runtime_type_name;
+use crate::error::{Result, RuntimeErrorKind, SlangError};
+use std::any::Any;
+use std::fmt;
+
+// ホストとの間で受け渡す値 (`Runtime::register_function` の引数と戻り値、`Runtime::evaluate` の結果)。
+// 実行時の内部では `Box<dyn Any>` のまま持ち、境界でこの型に変換する。
+// `char` は 1 文字の `Str` として渡す。複素数・ベクトル・行列はまだ渡せない
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+    Array(Vec<Value>),
+    // 値のない結果 (`()`) と `null`
+    Null,
+}
+
+impl Value {
+    // エラーメッセージ用の型名。実行時の型名 (`runtime_type_name`) と揃える
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "Int",
+            Value::Float(_) => "Float",
+            Value::Bool(_) => "Bool",
+            Value::Str(_) => "String",
+            Value::Array(_) => "Array",
+            Value::Null => "Unit",
+        }
+    }
+
+    pub(super) fn from_any(value: &dyn Any) -> Result<Value> {
+        if let Some(i) = value.downcast_ref::<i64>() {
+            Ok(Value::Int(*i))
+        } else if let Some(f) = value.downcast_ref::<f64>() {
+            Ok(Value::Float(*f))
+        } else if let Some(b) = value.downcast_ref::<bool>() {
+            Ok(Value::Bool(*b))
+        } else if let Some(c) = value.downcast_ref::<char>() {
+            Ok(Value::Str(c.to_string()))
+        } else if let Some(s) = value.downcast_ref::<String>() {
+            Ok(Value::Str(s.clone()))
+        } else if let Some(elements) = value.downcast_ref::<Vec<Box<dyn Any>>>() {
+            let elements = elements.iter()
+                .map(|element| Value::from_any(element.as_ref()))
+                .collect::<Result<Vec<_>>>()?;
+            Ok(Value::Array(elements))
+        } else if value.is::<()>() {
+            Ok(Value::Null)
+        } else {
+            Err(SlangError::runtime(RuntimeErrorKind::TypeMismatch, format!(
+                "Cannot pass a value of type {} to the host",
+                runtime_type_name(value)
+            )))
+        }
+    }
+
+    pub(super) fn into_any(self) -> Box<dyn Any> {
+        match self {
+            Value::Int(i) => Box::new(i),
+            Value::Float(f) => Box::new(f),
+            Value::Bool(b) => Box::new(b),
+            Value::Str(s) => Box::new(s),
+            Value::Array(elements) => {
+                Box::new(elements.into_iter().map(Value::into_any).collect::<Vec<_>>())
+            }
+            Value::Null => Box::new(()),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Str(s) => write!(f, "{}", s),
+            Value::Array(elements) => {
+                write!(f, "[")?;
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", element)?;
+                }
+                write!(f, "]")
+            }
+            Value::Null => write!(f, "null"),
+        }
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Int(value)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Float(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::Str(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::Str(value.to_string())
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(value: Vec<Value>) -> Self {
+        Value::Array(value)
+    }
+}
+
+impl From<()> for Value {
+    fn from(_: ()) -> Self {
+        Value::Null
+    }
+}
+
+fn mismatch(expected: &str, value: &Value) -> SlangError {
+    SlangError::runtime(RuntimeErrorKind::TypeMismatch, format!("Expected {}, got {}", expected, value.type_name()))
+}
+
+impl TryFrom<Value> for i64 {
+    type Error = SlangError;
+
+    fn try_from(value: Value) -> Result<Self> {
+        match value {
+            Value::Int(i) => Ok(i),
+            value => Err(mismatch("Int", &value)),
+        }
+    }
+}
+
+impl TryFrom<Value> for f64 {
+    type Error = SlangError;
+
+    fn try_from(value: Value) -> Result<Self> {
+        match value {
+            Value::Float(f) => Ok(f),
+            value => Err(mismatch("Float", &value)),
+        }
+    }
+}
+
+impl TryFrom<Value> for bool {
+    type Error = SlangError;
+
+    fn try_from(value: Value) -> Result<Self> {
+        match value {
+            Value::Bool(b) => Ok(b),
+            value => Err(mismatch("Bool", &value)),
+        }
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = SlangError;
+
+    fn try_from(value: Value) -> Result<Self> {
+        match value {
+            Value::Str(s) => Ok(s),
+            value => Err(mismatch("String", &value)),
+        }
+    }
+}
+
+impl TryFrom<Value> for Vec<Value> {
+    type Error = SlangError;
+
+    fn try_from(value: Value) -> Result<Self> {
+        match value {
+            Value::Array(elements) => Ok(elements),
+            value => Err(mismatch("Array", &value)),
+        }
+    }
+}
+
+impl TryFrom<Value> for () {
+    type Error = SlangError;
+
+    fn try_from(value: Value) -> Result<Self> {
+        match value {
+            Value::Null => Ok(()),
+            value => Err(mismatch("Unit", &value)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::IRValue;
+    use crate::runtime::Runtime;
+    use crate::type_system::Type;
+
+    #[test]
+    fn test_values_round_trip_through_native_function() {
+        let mut runtime = Runtime::new();
+        // IR を直接評価するので、登録した型は検査に使われない
+        runtime.register_function("identity", vec![Type::Int], Type::Int, |args: &[Value]| Ok(args[0].clone()));
+        let mut identity = |argument: IRValue| {
+            runtime.evaluate(&IRValue::Call { function: "identity".to_string(), arguments: vec![argument] }).unwrap()
+        };
+
+        assert_eq!(identity(IRValue::Int(42)), Value::Int(42));
+        assert_eq!(identity(IRValue::Float(1.5)), Value::Float(1.5));
+        assert_eq!(identity(IRValue::Bool(true)), Value::Bool(true));
+        assert_eq!(identity(IRValue::String("hi".to_string())), Value::from("hi"));
+        assert_eq!(identity(IRValue::Char('c')), Value::from("c"));
+        assert_eq!(identity(IRValue::Null), Value::Null);
+        assert_eq!(
+            identity(IRValue::Array(vec![IRValue::Int(1), IRValue::Array(vec![IRValue::Bool(false)])])),
+            Value::Array(vec![Value::Int(1), Value::Array(vec![Value::Bool(false)])])
+        );
+    }
+
+    #[test]
+    fn test_host_values_flow_back_into_programs() {
+        let mut runtime = Runtime::new();
+        runtime.register_function("numbers", vec![], Type::Array(Box::new(Type::Int)), |_: &[Value]| Ok(Value::from(vec![Value::from(2), Value::from(3)])));
+        let sum = IRValue::BinaryOp {
+            left: Box::new(IRValue::Index {
+                array: Box::new(IRValue::Call { function: "numbers".to_string(), arguments: vec![] }),
+                index: Box::new(IRValue::Int(1)),
+            }),
+            op: crate::ir::IRBinaryOperator::Add,
+            right: Box::new(IRValue::Int(1)),
+        };
+        assert_eq!(runtime.evaluate(&sum).unwrap(), Value::Int(4));
+
+        // 表せない値はエラーにする
+        assert!(runtime.evaluate(&IRValue::Complex(1.0, 2.0)).is_err());
+    }
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(i64::try_from(Value::from(3)).unwrap(), 3);
+        assert_eq!(f64::try_from(Value::from(0.5)).unwrap(), 0.5);
+        assert!(bool::try_from(Value::from(true)).unwrap());
+        assert_eq!(String::try_from(Value::from("a")).unwrap(), "a");
+        assert_eq!(Vec::<Value>::try_from(Value::from(vec![Value::Null])).unwrap(), vec![Value::Null]);
+        assert_eq!(Value::from(()), Value::Null);
+
+        let error = i64::try_from(Value::from(1.5)).unwrap_err();
+        assert_eq!(error.to_string(), "Runtime error: Expected Int, got Float");
+        assert_eq!(Value::from(vec![Value::from(1), Value::from("a")]).to_string(), "[1, a]");
+    }
+}
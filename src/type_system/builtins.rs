@@ -0,0 +1,154 @@
+use crate::ast::{Expression, Literal};
+use crate::error::{Result, SlangError};
+use crate::type_system::Type;
+
+// 標準ライブラリ (`runtime::StandardLibrary::new`) の関数の呼び出しの型。引数の型 (一部は引数の式そのもの)
+// から戻り値の型を決める。`name` が組み込み関数でなければ `None`。実行時の表と同じ名前をすべて扱うこと
+pub(crate) fn check_builtin_call(name: &str, arguments: &[Expression], arg_types: &[Type]) -> Option<Result<Type>> {
+    let result = match (name, arg_types) {
+        // 引数の数も型も問わない
+        ("print", _) => Ok(Type::Unit),
+        ("len", [Type::Array(_) | Type::FixedArray(..) | Type::String]) => Ok(Type::Int),
+        // `push` と `pop` は第1引数の配列をその場で書き換える。長さの決まった `[T; N]` には使えない
+        ("push", [Type::Array(element), value]) if value.is_assignable_to(element) => Ok(Type::Unit),
+        ("pop", [Type::Array(element)]) => Ok((**element).clone()),
+        ("concat", [Type::String, Type::String]) => Ok(Type::String),
+        ("substring", [Type::String, Type::Int, Type::Int]) => Ok(Type::String),
+        ("to_upper" | "to_lower", [Type::String]) => Ok(Type::String),
+        ("abs", [Type::Int]) => Ok(Type::Int),
+        ("abs", [Type::Float]) => Ok(Type::Float),
+        ("sqrt", [argument]) if argument.is_numeric() => Ok(Type::Float),
+        // 両方が int なら int、どちらかが float なら float
+        ("pow" | "min" | "max", [Type::Int, Type::Int]) => Ok(Type::Int),
+        ("pow" | "min" | "max", [a, b]) if a.is_numeric() && b.is_numeric() => Ok(Type::Float),
+        // 要素は常に float で持つ
+        ("vec", [_, ..]) if arg_types.iter().all(Type::is_numeric) => Ok(Type::Vector(arg_types.len(), Box::new(Type::Float))),
+        // 行の長さは型に入るので、各行は配列リテラルで書く
+        ("mat", [_, ..]) => match matrix_columns(arguments, arg_types) {
+            Some(cols) => Ok(Type::Matrix(arg_types.len(), cols, Box::new(Type::Float))),
+            None => Err(mismatch(name, "(row, ...) with numeric array literals of equal length", arg_types)),
+        },
+        ("quat", [w, x, y, z]) if [w, x, y, z].iter().all(|t| t.is_numeric()) => Ok(Type::Quaternion(Box::new(Type::Float))),
+        // `tensor([2, 3], data)`。形は型に入るので正の整数リテラルの配列で書く
+        ("tensor", [_, Type::Array(element)]) if element.is_numeric() => check_tensor(arguments),
+        ("reshape", [_, _]) => check_reshape(arguments, arg_types),
+        ("assert", [Type::Bool]) => Ok(Type::Unit),
+        // `==` と同じく暗黙の変換をせずに比べる
+        ("assert_eq", [a, b]) if a.unifies_with(b) => Ok(Type::Unit),
+        // 戻らない呼び出しとして扱う (`checker::statement_diverges`)
+        ("panic", [Type::String]) => Ok(Type::Unit),
+        ("to_string", [value]) if is_printable(value) => Ok(Type::String),
+        _ => Err(mismatch(name, expected_arguments(name)?, arg_types)),
+    };
+    Some(result)
+}
+
+// 標準ライブラリの関数の名前か
+pub(crate) fn is_builtin(name: &str) -> bool {
+    name == "print" || expected_arguments(name).is_some()
+}
+
+// 引数が合わなかったときのエラーで示す、受け付ける引数の説明
+fn expected_arguments(name: &str) -> Option<&'static str> {
+    let expected = match name {
+        "len" => "(array) or (string)",
+        "push" => "(array, element)",
+        "pop" => "(array)",
+        "concat" => "(string, string)",
+        "substring" => "(string, int, int)",
+        "to_upper" | "to_lower" => "(string)",
+        "abs" => "(int) or (float)",
+        "sqrt" => "(number)",
+        "pow" | "min" | "max" => "(number, number)",
+        "vec" => "(number, ...)",
+        "mat" => "(row, ...)",
+        "quat" => "(w, x, y, z) with numbers",
+        "tensor" => "(shape, array of numbers)",
+        "reshape" => "(tensor, shape)",
+        "assert" => "(bool)",
+        "assert_eq" => "(value, value) of the same type",
+        "panic" => "(string)",
+        "to_string" => "(a number, bool, char, string, array or optional of these)",
+        _ => return None,
+    };
+    Some(expected)
+}
+
+// `mat([1, 2], [3, 4])` の列数。どの行も同じ長さの数値の配列リテラルでなければ `None`
+fn matrix_columns(rows: &[Expression], row_types: &[Type]) -> Option<usize> {
+    let mut cols = None;
+    for (row, row_type) in rows.iter().zip(row_types) {
+        let (Expression::Array(elements), Type::Array(element)) = (row, row_type) else {
+            return None;
+        };
+        if !element.is_numeric() || *cols.get_or_insert(elements.len()) != elements.len() {
+            return None;
+        }
+    }
+    cols
+}
+
+fn check_tensor(arguments: &[Expression]) -> Result<Type> {
+    let shape = shape_literal(&arguments[0])
+        .ok_or_else(|| SlangError::Type("tensor() expects the shape as an array of positive integer literals".to_string()))?;
+    let size = tensor_size(&shape);
+    let tensor = Type::Tensor(shape, Box::new(Type::Float));
+    // 要素の数は配列リテラルで渡されたときだけ分かる。それ以外は実行時に確かめる
+    if let Expression::Array(data) = &arguments[1] {
+        if data.len() != size {
+            return Err(SlangError::Type(format!("{} needs {} elements, got {}", tensor, size, data.len())));
+        }
+    }
+    Ok(tensor)
+}
+
+// `reshape(t, [2, 3])`。新しい形は正の整数リテラルの配列で書き、要素数が変わってはならない
+fn check_reshape(arguments: &[Expression], arg_types: &[Type]) -> Result<Type> {
+    let Type::Tensor(dimensions, element) = &arg_types[0] else {
+        return Err(SlangError::Type(format!("reshape() expects a tensor, got {}", arg_types[0])));
+    };
+    let shape = shape_literal(&arguments[1]).ok_or_else(|| {
+        SlangError::Type("reshape() expects the new shape as an array of positive integer literals".to_string())
+    })?;
+    let (from, to) = (tensor_size(dimensions), tensor_size(&shape));
+    let reshaped = Type::Tensor(shape, element.clone());
+    if from != to {
+        return Err(SlangError::Type(format!(
+            "Cannot reshape {} to {}: {} elements vs {}",
+            arg_types[0], reshaped, from, to
+        )));
+    }
+    Ok(reshaped)
+}
+
+// `[2, 3]` のような正の整数リテラルの配列
+fn shape_literal(shape: &Expression) -> Option<Vec<usize>> {
+    match shape {
+        Expression::Array(elements) if !elements.is_empty() => elements
+            .iter()
+            .map(|element| match element {
+                Expression::Literal(Literal::Int(n)) => usize::try_from(*n).ok().filter(|n| *n > 0),
+                _ => None,
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
+fn tensor_size(dimensions: &[usize]) -> usize {
+    dimensions.iter().product()
+}
+
+// `to_string` で文字列にできる型 (実行時の `Value` に変換できる値)
+fn is_printable(type_: &Type) -> bool {
+    match type_ {
+        Type::Int | Type::Float | Type::Bool | Type::Char | Type::String => true,
+        Type::Array(element) | Type::FixedArray(element, _) | Type::Optional(element) => matches!(**element, Type::Var(_)) || is_printable(element),
+        _ => false,
+    }
+}
+
+fn mismatch(name: &str, expected: &str, arg_types: &[Type]) -> SlangError {
+    let got = arg_types.iter().map(|t| t.to_string()).collect::<Vec<_>>().join(", ");
+    SlangError::Type(format!("{}() expects {}, got ({})", name, expected, got))
+}
@@ -13,10 +13,18 @@ impl TypeCast {
     pub fn cast_literal(&self, literal: &Literal, target_type: &Type) -> Result<Literal> {
         match (literal, target_type) {
             (Literal::Int(i), Type::Float) => Ok(Literal::Float(*i as f64)),
-            (Literal::Float(f), Type::Int) => Ok(Literal::Int(*f as i64)),
+            // 小数部は切り捨てる。int に収まらない値と NaN は飽和させずにエラーにする
+            (Literal::Float(f), Type::Int) => {
+                if (i64::MIN as f64..-(i64::MIN as f64)).contains(f) {
+                    Ok(Literal::Int(*f as i64))
+                } else {
+                    Err(SlangError::Type("Float is out of range for int".to_string()))
+                }
+            }
             (Literal::Int(i), Type::String) => Ok(Literal::String(i.to_string())),
             (Literal::Float(f), Type::String) => Ok(Literal::String(f.to_string())),
             (Literal::Bool(b), Type::String) => Ok(Literal::String(b.to_string())),
+            (Literal::Char(c), Type::String) => Ok(Literal::String(c.to_string())),
             (Literal::String(s), Type::Int) => {
                 s.parse::<i64>()
                     .map(Literal::Int)
@@ -42,6 +50,24 @@ impl TypeCast {
         }
     }
 
+    // 配列リテラル (要素はすべてリテラル) を `[T]` / `[T; N]` / `vecN<T>` に変換する。要素ごとに `cast_literal` を使う
+    pub fn cast_literals(&self, elements: &[Literal], target_type: &Type) -> Result<Vec<Literal>> {
+        let element_type = match target_type {
+            Type::Array(element_type) => element_type,
+            Type::FixedArray(element_type, size) | Type::Vector(size, element_type) if *size == elements.len() => {
+                element_type
+            }
+            _ => {
+                return Err(SlangError::Type(format!(
+                    "Cannot cast an array of {} elements to {:?}",
+                    elements.len(),
+                    target_type
+                )))
+            }
+        };
+        elements.iter().map(|element| self.cast_literal(element, element_type)).collect()
+    }
+
     pub fn is_cast_allowed(&self, from_type: &Type, to_type: &Type) -> bool {
         match (from_type, to_type) {
             (Type::Int, Type::Float) => true,
@@ -49,10 +75,16 @@ impl TypeCast {
             (Type::Int, Type::String) => true,
             (Type::Float, Type::String) => true,
             (Type::Bool, Type::String) => true,
+            (Type::Char, Type::String) => true,
             (Type::String, Type::Int) => true,
             (Type::String, Type::Float) => true,
             (Type::String, Type::Bool) => true,
             (a, b) if a == b => true,
+            // 配列・同じ長さの配列やベクトルは要素を変換できれば変換できる
+            (Type::Array(a), Type::Array(b)) => self.is_cast_allowed(a, b),
+            (Type::FixedArray(a, n), Type::FixedArray(b, m)) | (Type::Vector(n, a), Type::Vector(m, b)) => {
+                n == m && self.is_cast_allowed(a, b)
+            }
             _ => false,
         }
     }
@@ -64,23 +96,90 @@ impl TypeCast {
             (Type::Int, Type::String) => Some(2),
             (Type::Float, Type::String) => Some(2),
             (Type::Bool, Type::String) => Some(2),
+            (Type::Char, Type::String) => Some(2),
             (Type::String, Type::Int) => Some(3),
             (Type::String, Type::Float) => Some(3),
             (Type::String, Type::Bool) => Some(3),
             (a, b) if a == b => Some(0),
+            // 作り直す 1 と要素の変換のコストの和
+            (Type::Array(a), Type::Array(b)) => Some(1 + self.get_cast_cost(a, b)?),
+            (Type::FixedArray(a, n), Type::FixedArray(b, m)) | (Type::Vector(n, a), Type::Vector(m, b)) if n == m => {
+                Some(1 + self.get_cast_cost(a, b)?)
+            }
             _ => None,
         }
     }
 }
 
+impl Default for TypeCast {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Literal {
     fn get_type(&self) -> Type {
         match self {
             Literal::Int(_) => Type::Int,
             Literal::Float(_) => Type::Float,
+            Literal::Complex(_, _) => Type::Complex(Box::new(Type::Float)),
             Literal::Bool(_) => Type::Bool,
+            Literal::Char(_) => Type::Char,
             Literal::String(_) => Type::String,
-            Literal::Null => Type::Unit,
+            Literal::Null => Type::null(),
         }
     }
-} 
\ No newline at end of file
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn array(element: Type) -> Type {
+        Type::Array(Box::new(element))
+    }
+
+    #[test]
+    fn test_array_casts() {
+        let type_cast = TypeCast::new();
+        assert!(type_cast.is_cast_allowed(&array(Type::Int), &array(Type::Float)));
+        assert!(type_cast.is_cast_allowed(&array(array(Type::Int)), &array(array(Type::String))));
+        assert!(type_cast.is_cast_allowed(&Type::Vector(3, Box::new(Type::Int)), &Type::Vector(3, Box::new(Type::Float))));
+        assert!(!type_cast.is_cast_allowed(&Type::Vector(3, Box::new(Type::Int)), &Type::Vector(2, Box::new(Type::Float))));
+        assert!(!type_cast.is_cast_allowed(&array(Type::Bool), &array(Type::Int)));
+        assert!(!type_cast.is_cast_allowed(&array(Type::Int), &Type::Int));
+
+        assert_eq!(type_cast.get_cast_cost(&array(Type::Int), &array(Type::Int)), Some(0));
+        assert_eq!(type_cast.get_cast_cost(&array(Type::Int), &array(Type::Float)), Some(2));
+        assert_eq!(type_cast.get_cast_cost(&array(Type::String), &array(Type::Int)), Some(4));
+        assert_eq!(type_cast.get_cast_cost(&array(array(Type::String)), &array(array(Type::Int))), Some(5));
+        assert_eq!(type_cast.get_cast_cost(&array(Type::Bool), &array(Type::Int)), None);
+    }
+
+    #[test]
+    fn test_cast_literals() {
+        let type_cast = TypeCast::new();
+        assert_eq!(
+            type_cast.cast_literals(&[Literal::Int(1), Literal::Int(2)], &array(Type::Float)).unwrap(),
+            vec![Literal::Float(1.0), Literal::Float(2.0)]
+        );
+        assert_eq!(
+            type_cast.cast_literals(&[Literal::String("7".to_string())], &Type::Vector(1, Box::new(Type::Int))).unwrap(),
+            vec![Literal::Int(7)]
+        );
+        assert!(type_cast.cast_literals(&[Literal::String("x".to_string())], &array(Type::Int)).is_err());
+        assert!(type_cast.cast_literals(&[Literal::Int(1)], &Type::Vector(2, Box::new(Type::Float))).is_err());
+        assert!(type_cast.cast_literals(&[Literal::Int(1)], &Type::Float).is_err());
+    }
+
+    #[test]
+    fn test_float_to_int_range() {
+        let type_cast = TypeCast::new();
+        let to_int = |f: f64| type_cast.cast_literal(&Literal::Float(f), &Type::Int);
+        assert_eq!(to_int(-2.7).unwrap(), Literal::Int(-2));
+        assert_eq!(to_int(-9223372036854775808.0).unwrap(), Literal::Int(i64::MIN));
+        // `i64::MAX as f64` は 2^63 に丸められるので収まらない
+        for f in [i64::MAX as f64, 1e300, -1e300, f64::INFINITY, f64::NEG_INFINITY, f64::NAN] {
+            assert!(to_int(f).is_err(), "{}", f);
+        }
+    }
+}
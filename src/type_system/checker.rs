@@ -1,64 +1,291 @@
 use crate::ast::*;
 use crate::error::{Result, SlangError};
-use crate::type_system::Type;
-use std::collections::HashMap;
+use crate::type_system::exhaustiveness::check_match;
+use crate::type_system::{check_builtin_call, Type, TypeCast};
+use std::collections::{HashMap, HashSet};
+
+// 戻り値の型を省略したラムダで、まだ `return` を見ていないことを表す。検査器は他に型変数を使わない
+const UNINFERRED_RETURN: Type = Type::Var(0);
 
 #[derive(Debug, Clone)]
 pub struct TypeChecker {
     type_vars: HashMap<String, Type>,
+    // `mut` なしの `let` で束縛した変数。代入できない
+    immutable_vars: HashSet<String>,
+    // 検査中のラムダが外側から捕まえている変数。クロージャは値を複製して捕まえるので代入できない
+    captured_vars: HashSet<String>,
     current_function: Option<Type>,
+    // 検査中の関数の型パラメータ。同じ名前の構造体より優先される
+    type_params: Vec<String>,
     type_definitions: HashMap<String, TypeDefinition>,
+    // `break`/`continue` が書ける位置かどうかの判定に使う
+    loop_depth: usize,
+    // 関数名ごとの (型パラメータ, 関数の型)
+    function_signatures: HashMap<String, (Vec<String>, Type)>,
+    // トレイト名ごとの、メソッド名とその関数の型
+    trait_signatures: HashMap<String, HashMap<String, Type>>,
+    // エラーにはしない指摘 (到達しない `match` の腕など)
+    warnings: Vec<String>,
 }
 
 impl TypeChecker {
     pub fn new() -> Self {
         Self {
             type_vars: HashMap::new(),
+            immutable_vars: HashSet::new(),
+            captured_vars: HashSet::new(),
             current_function: None,
+            type_params: Vec::new(),
             type_definitions: HashMap::new(),
+            loop_depth: 0,
+            function_signatures: HashMap::new(),
+            trait_signatures: HashMap::new(),
+            warnings: Vec::new(),
         }
     }
 
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    // `check_program` が記録したトレイトのメソッドの型。`Self` は `Type::Named("Self")` のまま残る
+    pub fn trait_method(&self, trait_name: &str, method: &str) -> Option<&Type> {
+        self.trait_signatures.get(trait_name)?.get(method)
+    }
+
+    // ソースの外で定義された関数 (ホストの関数など) を、呼び出せる関数として登録する。
+    // `function_type` は `Type::Function`。同名の関数をソースで定義した場合はそちらが優先される
+    pub fn declare_function(&mut self, name: &str, function_type: Type) {
+        self.function_signatures.insert(name.to_string(), (Vec::new(), function_type));
+    }
+
     pub fn check_ast(&mut self, ast: &AST) -> Result<()> {
-        // 型定義を収集
-        for type_def in &ast.type_definitions {
-            self.type_definitions.insert(type_def.name.clone(), type_def.clone());
+        self.check_program(&Program::from(ast.clone()))
+    }
+
+    // モジュールは名前空間を分けない。中の項目も外側と同じ表に登録し、同じ名前の型は一つだけ定義できる。
+    // マクロは検査の前に展開されている前提で無視する
+    pub fn check_program(&mut self, program: &Program) -> Result<()> {
+        let mut functions = Vec::new();
+        let mut structs = Vec::new();
+        let mut traits = Vec::new();
+        collect_items(&program.items, &mut functions, &mut structs, &mut traits);
+
+        for struct_ in &structs {
+            if self.type_definitions.insert(struct_.name.clone(), TypeDefinition::from(*struct_)).is_some() {
+                return Err(SlangError::Type(format!("Type `{}` is defined more than once", struct_.name)));
+            }
+        }
+        for struct_ in &structs {
+            self.check_struct(struct_)?;
+        }
+        for struct_ in &structs {
+            self.check_inline_recursion(&mut vec![struct_.name.clone()])?;
+        }
+        for trait_ in &traits {
+            self.check_trait(trait_)?;
+            let methods = trait_
+                .methods
+                .iter()
+                .map(|method| (method.name.clone(), self.resolve_type(&trait_method_type(method), &[])))
+                .collect();
+            if self.trait_signatures.insert(trait_.name.clone(), methods).is_some() {
+                return Err(SlangError::Type(format!("Trait `{}` is defined more than once", trait_.name)));
+            }
         }
 
-        // 関数をチェック
-        for function in &ast.functions {
+        // `declare_function` で先に宣言された関数の型にも、このプログラムの構造体を解決する
+        let declared = std::mem::take(&mut self.function_signatures);
+        self.function_signatures = declared
+            .into_iter()
+            .map(|(name, (type_params, function_type))| {
+                let function_type = self.resolve_type(&function_type, &type_params);
+                (name, (type_params, function_type))
+            })
+            .collect();
+        for function in &functions {
+            let signature = self.resolve_type(&function_type(function), &function.type_params);
+            self.function_signatures.insert(function.name.clone(), (function.type_params.clone(), signature));
+        }
+        for function in functions {
             self.check_function(function)?;
         }
+        Ok(())
+    }
 
+    // フィールドの型は定義済みの型か、構造体の型パラメータでなければならない
+    fn check_struct(&self, struct_: &Struct) -> Result<()> {
+        let mut seen = HashSet::new();
+        for field in &struct_.fields {
+            if !seen.insert(&field.name) {
+                return Err(SlangError::Type(format!(
+                    "Field `{}` is defined more than once in `{}`",
+                    field.name, struct_.name
+                )));
+            }
+            if let Some(name) = self.unknown_type(&field.type_, &struct_.type_params) {
+                return Err(SlangError::Type(format!(
+                    "Unknown type `{}` in field `{}` of `{}`",
+                    name, field.name, struct_.name
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    // メソッドのシグネチャでは、定義済みの型に加えて実装する型を表す `Self` が使える
+    fn check_trait(&self, trait_: &Trait) -> Result<()> {
+        let allowed = ["Self".to_string()];
+        let mut seen = HashSet::new();
+        for method in &trait_.methods {
+            if !seen.insert(&method.name) {
+                return Err(SlangError::Type(format!(
+                    "Method `{}` is declared more than once in trait `{}`",
+                    method.name, trait_.name
+                )));
+            }
+            let mut params = HashSet::new();
+            for param in &method.params {
+                if !params.insert(&param.name) {
+                    return Err(SlangError::Type(format!(
+                        "Duplicate parameter `{}` in method `{}` of trait `{}`",
+                        param.name, method.name, trait_.name
+                    )));
+                }
+            }
+            let types = method.params.iter().map(|param| &param.type_annotation).chain([&method.return_type]);
+            for type_ in types {
+                if let Some(name) = self.unknown_type(type_, &allowed) {
+                    return Err(SlangError::Type(format!(
+                        "Unknown type `{}` in method `{}` of trait `{}`",
+                        name, method.name, trait_.name
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // 型に現れる名前のうち、定義されておらず `type_params` にも含まれない最初のもの
+    fn unknown_type(&self, type_: &Type, type_params: &[String]) -> Option<String> {
+        let mut names = Vec::new();
+        collect_named_types(type_, false, &mut names);
+        names
+            .into_iter()
+            .find(|name| !self.type_definitions.contains_key(name) && !type_params.contains(name))
+    }
+
+    // 注釈に書かれた型の中の構造体の名前を `Type::Struct` にする。`type_params` の名前は型パラメータのまま
+    fn resolve_type(&self, type_: &Type, type_params: &[String]) -> Type {
+        type_.resolve_structs(&|name| {
+            self.type_definitions.contains_key(name) && !type_params.iter().any(|param| param == name)
+        })
+    }
+
+    // 検査中の関数の中に書かれた注釈の型
+    fn resolve(&self, type_: &Type) -> Type {
+        self.resolve_type(type_, &self.type_params)
+    }
+
+    // 構造体のフィールドの型
+    fn field_type(&self, type_name: &str, field: &str) -> Option<Type> {
+        let definition = self.type_definitions.get(type_name)?;
+        let field = definition.fields.iter().find(|f| f.name == field)?;
+        Some(self.resolve_type(&field.type_annotation, &[]))
+    }
+
+    // `path` の先頭の型が、間接参照なしに `path` の末尾の型を経由して自分自身に戻るかを調べる
+    fn check_inline_recursion(&self, path: &mut Vec<String>) -> Result<()> {
+        let Some(definition) = path.last().and_then(|name| self.type_definitions.get(name)) else {
+            return Ok(());
+        };
+        for field in &definition.fields {
+            let mut names = Vec::new();
+            collect_named_types(&field.type_annotation, true, &mut names);
+            for name in names {
+                if name == path[0] {
+                    return Err(SlangError::Type(format!(
+                        "Recursive type `{}` has infinite size ({} -> {}); store it behind a pointer such as `*{}`",
+                        name,
+                        path.join(" -> "),
+                        name,
+                        name
+                    )));
+                }
+                // 先頭を含まない循環は、その循環に含まれる型から調べたときに報告する
+                if !path.contains(&name) {
+                    path.push(name);
+                    self.check_inline_recursion(path)?;
+                    path.pop();
+                }
+            }
+        }
         Ok(())
     }
 
     fn check_function(&mut self, function: &Function) -> Result<()> {
         // 関数の型を設定
-        let function_type = Type::Function {
-            params: function.parameters.iter().map(|p| p.type_annotation.clone()).collect(),
-            return_type: Box::new(function.return_type.clone()),
-            priority: Some(function.priority as u32),
-        };
-        self.current_function = Some(function_type);
+        self.type_params = function.type_params.clone();
+        self.current_function = Some(self.resolve(&function_type(function)));
 
         // パラメータの型を登録
         for param in &function.parameters {
-            self.type_vars.insert(param.name.clone(), param.type_annotation.clone());
+            let param_type = self.resolve(&param.type_annotation);
+            self.declare_var(&param.name, param_type, param.mutable);
         }
 
         // 関数本体をチェック
         self.check_block(&function.body)?;
 
+        // 値を返す関数は、どの経路でも末尾に達する前に `return` しなければならない
+        if !matches!(function.return_type, Type::Unit | Type::Void) && !block_diverges(&function.body) {
+            return Err(SlangError::Type(format!(
+                "Function `{}` must return {:?} on every path",
+                function.name, function.return_type
+            )));
+        }
+
         // 関数の型をクリア
         self.current_function = None;
+        self.type_params.clear();
 
         Ok(())
     }
 
+    // ブロックの中で束縛した変数はブロックを抜けると見えなくなり、隠していた外側の変数が戻る
     fn check_block(&mut self, block: &Block) -> Result<()> {
-        for statement in &block.statements {
-            self.check_statement(statement)?;
+        self.in_scope(|checker| block.statements.iter().try_for_each(|statement| checker.check_statement(statement)))
+    }
+
+    fn in_scope(&mut self, check: impl FnOnce(&mut Self) -> Result<()>) -> Result<()> {
+        let enclosing_vars = self.type_vars.clone();
+        let enclosing_immutable = self.immutable_vars.clone();
+        let enclosing_captured = self.captured_vars.clone();
+        let result = check(self);
+        self.type_vars = enclosing_vars;
+        self.immutable_vars = enclosing_immutable;
+        self.captured_vars = enclosing_captured;
+        result
+    }
+
+    // 現在のスコープに変数を束縛する。同名の外側の変数 (ラムダが捕まえた変数も) は隠れる
+    fn declare_var(&mut self, name: &str, type_: Type, mutable: bool) {
+        self.type_vars.insert(name.to_string(), type_);
+        self.captured_vars.remove(name);
+        if mutable {
+            self.immutable_vars.remove(name);
+        } else {
+            self.immutable_vars.insert(name.to_string());
+        }
+    }
+
+    // ラムダの中から外側の変数を書き換えようとしていないか。書き換えても外側には反映されない
+    fn check_not_captured(&self, name: &str) -> Result<()> {
+        if self.captured_vars.contains(name) {
+            return Err(SlangError::Type(format!(
+                "Cannot assign to `{}` inside a lambda: closures capture variables by value",
+                name
+            )));
         }
         Ok(())
     }
@@ -66,54 +293,106 @@ impl TypeChecker {
     fn check_statement(&mut self, statement: &Statement) -> Result<()> {
         match statement {
             Statement::Let(stmt) => {
-                let value_type = self.check_expression(&stmt.value)?;
-                if let Some(annotated_type) = &stmt.type_annotation {
-                    if !value_type.is_compatible_with(annotated_type) {
+                let mut value_type = self.check_expression(&stmt.value)?;
+                let annotated_type = stmt.type_annotation.as_ref().map(|annotation| self.resolve(annotation));
+                if let Some(annotated_type) = &annotated_type {
+                    value_type = fit_array_literal(&stmt.value, value_type, annotated_type);
+                    if !value_type.is_assignable_to(annotated_type) {
                         return Err(SlangError::Type(format!(
                             "Type mismatch in let statement: expected {:?}, got {:?}",
                             annotated_type, value_type
                         )));
                     }
                 }
-                self.type_vars.insert(stmt.name.clone(), value_type);
+                // `let x: int? = 1;` の `x` は注釈どおり `int?` として扱う
+                let variable_type = annotated_type.unwrap_or(value_type);
+                if variable_type == Type::empty_array() {
+                    return Err(SlangError::Type(format!(
+                        "Cannot determine the element type of an empty array literal; annotate it like `let {}: [int] = [];`",
+                        stmt.name
+                    )));
+                }
+                self.declare_var(&stmt.name, variable_type, stmt.mutable);
+            }
+            // 戻り値の型を省略したラムダでは、最初の `return` の値が戻り値の型になる
+            Statement::Return(stmt) if self.return_type_is_uninferred() => {
+                let value_type = match &stmt.value {
+                    Some(value) => self.check_expression(value)?,
+                    None => Type::Void,
+                };
+                if let Some(Type::Function { return_type, .. }) = &mut self.current_function {
+                    **return_type = value_type;
+                }
             }
             Statement::Return(stmt) => {
+                if let (None, Some(Type::Function { return_type, .. })) = (&stmt.value, &self.current_function) {
+                    if !matches!(return_type.as_ref(), Type::Unit | Type::Void) {
+                        return Err(SlangError::Type(format!("Missing return value: expected {:?}", return_type)));
+                    }
+                }
                 if let Some(value) = &stmt.value {
                     let value_type = self.check_expression(value)?;
-                    if let Some(function_type) = &self.current_function {
-                        if let Type::Function { return_type, .. } = function_type {
-                            if !value_type.is_compatible_with(return_type) {
-                                return Err(SlangError::Type(format!(
-                                    "Return type mismatch: expected {:?}, got {:?}",
-                                    return_type, value_type
-                                )));
-                            }
+                    if let Some(Type::Function { return_type, .. }) = &self.current_function {
+                        let value_type = fit_array_literal(value, value_type, return_type);
+                        if !value_type.is_assignable_to(return_type) {
+                            return Err(SlangError::Type(format!(
+                                "Return type mismatch: expected {:?}, got {:?}",
+                                return_type, value_type
+                            )));
                         }
                     }
                 }
             }
             Statement::If(stmt) => {
                 let condition_type = self.check_expression(&stmt.condition)?;
-                if !condition_type.is_compatible_with(&Type::Bool) {
+                if !condition_type.unifies_with(&Type::Bool) {
                     return Err(SlangError::Type("If condition must be boolean".to_string()));
                 }
-                self.check_block(&stmt.then_block)?;
+                // `x != null` なら then 側、`x == null` なら else 側で `x` を中身の型として扱う
+                let narrowed = null_check(&stmt.condition).and_then(|(name, non_null_in_then)| {
+                    match self.type_vars.get(name) {
+                        Some(Type::Optional(inner)) if !matches!(**inner, Type::Var(_)) => {
+                            Some((name, (**inner).clone(), non_null_in_then))
+                        }
+                        _ => None,
+                    }
+                });
+                let narrow_then = narrowed.as_ref().filter(|(_, _, in_then)| *in_then);
+                self.check_narrowed_block(&stmt.then_block, narrow_then)?;
+                if let Some(else_block) = &stmt.else_block {
+                    let narrow_else = narrowed.as_ref().filter(|(_, _, in_then)| !*in_then);
+                    self.check_narrowed_block(else_block, narrow_else)?;
+                }
+            }
+            Statement::IfLet(stmt) => {
+                let value_type = self.check_expression(&stmt.expression)?;
+                self.check_pattern_scope(&stmt.pattern, &value_type, |checker| checker.check_block(&stmt.then_block))?;
                 if let Some(else_block) = &stmt.else_block {
                     self.check_block(else_block)?;
                 }
             }
             Statement::While(stmt) => {
                 let condition_type = self.check_expression(&stmt.condition)?;
-                if !condition_type.is_compatible_with(&Type::Bool) {
+                if !condition_type.unifies_with(&Type::Bool) {
                     return Err(SlangError::Type("While condition must be boolean".to_string()));
                 }
-                self.check_block(&stmt.body)?;
+                self.check_loop_body(&stmt.body)?;
+            }
+            Statement::WhileLet(stmt) => {
+                let value_type = self.check_expression(&stmt.expression)?;
+                self.check_pattern_scope(&stmt.pattern, &value_type, |checker| checker.check_loop_body(&stmt.body))?;
             }
             Statement::For(stmt) => {
-                let iterator_type = self.check_expression(&stmt.iterator)?;
-                if let Type::Array(element_type) = iterator_type {
-                    self.type_vars.insert(stmt.variable.clone(), *element_type);
-                    self.check_block(&stmt.body)?;
+                let iterator_type = match stmt.iterator.as_ref() {
+                    Expression::Range { start, end, .. } => self.check_range(start, end)?,
+                    iterator => self.check_expression(iterator)?,
+                };
+                // ループ変数は本体の中だけで見え、代入できない
+                if let Type::Array(element_type) | Type::FixedArray(element_type, _) = iterator_type {
+                    self.in_scope(|checker| {
+                        checker.declare_var(&stmt.variable, *element_type, false);
+                        checker.check_loop_body(&stmt.body)
+                    })?;
                 } else {
                     return Err(SlangError::Type("For iterator must be an array".to_string()));
                 }
@@ -124,6 +403,27 @@ impl TypeChecker {
                     self.check_pattern(&arm.pattern, &value_type)?;
                     self.check_block(&arm.body)?;
                 }
+                let coverage = check_match(&self.type_definitions, &value_type, &stmt.arms);
+                for i in coverage.unreachable {
+                    self.warnings.push(format!("Unreachable match arm `{}`", stmt.arms[i].pattern));
+                }
+                if let Some(missing) = coverage.missing {
+                    return Err(SlangError::Type(format!("Non-exhaustive match: `{}` is not covered", missing)));
+                }
+            }
+            Statement::Break if self.loop_depth == 0 => {
+                return Err(SlangError::Type("`break` outside of a loop".to_string()));
+            }
+            Statement::Continue if self.loop_depth == 0 => {
+                return Err(SlangError::Type("`continue` outside of a loop".to_string()));
+            }
+            Statement::Break | Statement::Continue => {}
+            Statement::Transfer(stmt) => {
+                for name in [&stmt.from, &stmt.to] {
+                    if !self.type_vars.contains_key(name) {
+                        return Err(SlangError::Type(format!("Undefined variable: {}", name)));
+                    }
+                }
             }
             Statement::Expression(expr) => {
                 self.check_expression(expr)?;
@@ -132,6 +432,23 @@ impl TypeChecker {
         Ok(())
     }
 
+    // `if let` / `while let` のパターンで束縛した変数は `check` の間だけ見える
+    fn check_pattern_scope(
+        &mut self,
+        pattern: &Pattern,
+        value_type: &Type,
+        check: impl FnOnce(&mut Self) -> Result<()>,
+    ) -> Result<()> {
+        self.in_scope(|checker| checker.check_pattern(pattern, value_type).and_then(|()| check(checker)))
+    }
+
+    fn check_loop_body(&mut self, body: &Block) -> Result<()> {
+        self.loop_depth += 1;
+        let result = self.check_block(body);
+        self.loop_depth -= 1;
+        result
+    }
+
     fn check_expression(&mut self, expression: &Expression) -> Result<Type> {
         match expression {
             Expression::Literal(lit) => Ok(self.get_literal_type(lit)),
@@ -146,15 +463,47 @@ impl TypeChecker {
                 self.check_binary_operation(&op.op, left_type, right_type)
             }
             Expression::UnaryOp(op) => {
-                let expr_type = self.check_expression(&op.right)?;
+                let expr_type = self.check_expression(&op.expr)?;
                 self.check_unary_operation(&op.op, expr_type)
             }
             Expression::Call(call) => {
-                let function_type = self.check_expression(&Expression::Identifier(call.function.clone()))?;
                 let arg_types: Vec<Type> = call.arguments
                     .iter()
                     .map(|arg| self.check_expression(arg))
                     .collect::<Result<Vec<_>>>()?;
+                // ローカル変数 (クロージャ) は同名の関数や組み込み関数より優先される
+                let function_type = match self.function_signatures.get(&call.function) {
+                    _ if self.type_vars.contains_key(&call.function) => {
+                        self.check_expression(&Expression::Identifier(call.function.clone()))?
+                    }
+                    Some((type_params, function_type)) => instantiate(type_params, function_type, &arg_types),
+                    None => {
+                        // `push`/`pop` は第1引数の変数を書き換える
+                        if let ("push" | "pop", Some(Expression::Identifier(target))) =
+                            (call.function.as_str(), call.arguments.first())
+                        {
+                            self.check_not_captured(target)?;
+                        }
+                        if let Some(result) = check_builtin_call(&call.function, &call.arguments, &arg_types) {
+                            return result;
+                        }
+                        return Err(SlangError::Type(format!("Undefined function: {}", call.function)));
+                    }
+                };
+                self.check_ownership(&call.function, &function_type)?;
+                // 仮引数より多い引数もそのまま残し、`check_function_call` で個数を検査する
+                let arg_types = match &function_type {
+                    Type::Function { params, .. } => call.arguments
+                        .iter()
+                        .zip(arg_types)
+                        .enumerate()
+                        .map(|(i, (argument, arg_type))| match params.get(i) {
+                            Some(param) => fit_array_literal(argument, arg_type, param),
+                            None => arg_type,
+                        })
+                        .collect(),
+                    _ => arg_types,
+                };
                 self.check_function_call(function_type, arg_types)
             }
             Expression::Assignment(assign) => {
@@ -162,7 +511,15 @@ impl TypeChecker {
                 let target_type = self.type_vars.get(&assign.target)
                     .cloned()
                     .ok_or_else(|| SlangError::Type(format!("Undefined variable: {}", assign.target)))?;
-                if !value_type.is_compatible_with(&target_type) {
+                self.check_not_captured(&assign.target)?;
+                if self.immutable_vars.contains(&assign.target) {
+                    return Err(SlangError::Type(format!(
+                        "Cannot assign to immutable variable `{0}`; declare it with `let mut {0}`",
+                        assign.target
+                    )));
+                }
+                let value_type = fit_array_literal(&assign.value, value_type, &target_type);
+                if !value_type.is_assignable_to(&target_type) {
                     return Err(SlangError::Type(format!(
                         "Assignment type mismatch: expected {:?}, got {:?}",
                         target_type, value_type
@@ -170,6 +527,133 @@ impl TypeChecker {
                 }
                 Ok(Type::Unit)
             }
+            Expression::Array(elements) => {
+                let mut element_types = elements.iter();
+                let mut element_type = match element_types.next() {
+                    Some(first) => self.check_expression(first)?,
+                    // 要素の型は `fit_array_literal` で注釈や引数の型から決める
+                    None => return Ok(Type::empty_array()),
+                };
+                for element in element_types {
+                    let other = self.check_expression(element)?;
+                    element_type = join_element_types(&element_type, &other).ok_or_else(|| {
+                        SlangError::Type(format!(
+                            "Array element type mismatch: expected {:?}, got {:?}",
+                            element_type, other
+                        ))
+                    })?;
+                }
+                Ok(Type::Array(Box::new(element_type)))
+            }
+            Expression::Tuple(elements) => {
+                let element_types = elements
+                    .iter()
+                    .map(|element| self.check_expression(element))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(Type::Tuple(element_types))
+            }
+            Expression::Range { .. } => Err(SlangError::Type(
+                "Ranges can only be used as the iterator of a `for` loop".to_string(),
+            )),
+            Expression::Lambda { params, return_type, body } => self.check_lambda(params, return_type.as_ref(), body),
+            Expression::Cast { expr, target } => {
+                let source_type = self.check_expression(expr)?;
+                if TypeCast::new().is_cast_allowed(&source_type, target) {
+                    Ok(target.clone())
+                } else {
+                    Err(SlangError::Type(format!("Cannot cast {} to {}", source_type, target)))
+                }
+            }
+            Expression::Index { array, index } => {
+                let array_type = self.check_expression(array)?;
+                let index_type = self.check_expression(index)?;
+                if index_type != Type::Int {
+                    return Err(SlangError::Type(format!(
+                        "Array index must be int, got {:?}",
+                        index_type
+                    )));
+                }
+                match array_type {
+                    Type::Array(element_type) | Type::FixedArray(element_type, _) | Type::Vector(_, element_type) => {
+                        Ok(*element_type)
+                    }
+                    other => Err(SlangError::Type(format!("Cannot index into {:?}", other))),
+                }
+            }
+            Expression::FieldAccess { base, field } => {
+                let base_type = self.check_expression(base)?;
+                let type_name = match &base_type {
+                    Type::Struct(name) => name,
+                    Type::Optional(_) => {
+                        return Err(SlangError::Type(format!(
+                            "Cannot access field '{}' on possibly-null value of type {}; check it against null first",
+                            field, base_type
+                        )))
+                    }
+                    other => {
+                        return Err(SlangError::Type(format!(
+                            "Cannot access field '{}' on non-struct type {:?}",
+                            field, other
+                        )))
+                    }
+                };
+                if !self.type_definitions.contains_key(type_name) {
+                    return Err(SlangError::Type(format!("Type '{}' not found", type_name)));
+                }
+                self.field_type(type_name, field)
+                    .ok_or_else(|| SlangError::Type(format!(
+                        "Field '{}' not found in struct '{}'",
+                        field, type_name
+                    )))
+            }
+            Expression::MethodCall { receiver, method, arguments } => {
+                let receiver_type = self.check_expression(receiver)?;
+                let method_type = receiver_type.get_method_signature(method)
+                    .ok_or_else(|| SlangError::Type(format!(
+                        "No method '{}' on type {:?}",
+                        method, receiver_type
+                    )))?;
+                let arg_types = arguments
+                    .iter()
+                    .map(|arg| self.check_expression(arg))
+                    .collect::<Result<Vec<_>>>()?;
+                self.check_function_call(method_type, arg_types)
+            }
+            Expression::StructLiteral { name, fields } => {
+                let type_def = self.type_definitions.get(name)
+                    .cloned()
+                    .ok_or_else(|| SlangError::Type(format!("Type '{}' not found", name)))?;
+                for (i, (field, value)) in fields.iter().enumerate() {
+                    if fields[..i].iter().any(|(other, _)| other == field) {
+                        return Err(SlangError::Type(format!(
+                            "Field '{}' specified more than once in '{}' literal",
+                            field, name
+                        )));
+                    }
+                    let expected = self.field_type(name, field)
+                        .ok_or_else(|| SlangError::Type(format!(
+                            "Field '{}' not found in struct '{}'",
+                            field, name
+                        )))?;
+                    let value_type = self.check_expression(value)?;
+                    let value_type = fit_array_literal(value, value_type, &expected);
+                    if !value_type.is_assignable_to(&expected) {
+                        return Err(SlangError::Type(format!(
+                            "Field '{}' type mismatch: expected {:?}, got {:?}",
+                            field, expected, value_type
+                        )));
+                    }
+                }
+                if let Some(missing) = type_def.fields.iter()
+                    .find(|f| !fields.iter().any(|(field, _)| field == &f.name))
+                {
+                    return Err(SlangError::Type(format!(
+                        "Missing field '{}' in '{}' literal",
+                        missing.name, name
+                    )));
+                }
+                Ok(Type::Struct(name.clone()))
+            }
         }
     }
 
@@ -177,34 +661,48 @@ impl TypeChecker {
         match literal {
             Literal::Int(_) => Type::Int,
             Literal::Float(_) => Type::Float,
+            Literal::Complex(_, _) => Type::Complex(Box::new(Type::Float)),
             Literal::Bool(_) => Type::Bool,
+            Literal::Char(_) => Type::Char,
             Literal::String(_) => Type::String,
-            Literal::Null => Type::Unit,
+            Literal::Null => Type::null(),
         }
     }
 
     fn check_binary_operation(&mut self, op: &BinaryOperator, left: Type, right: Type) -> Result<Type> {
+        check_non_null_operands(op, &left, &right)?;
+        if let Some(result) = check_linear_algebra(op, &left, &right) {
+            return result;
+        }
+        if left.is_complex() || right.is_complex() {
+            return check_complex_operation(op, &left, &right);
+        }
+        if let Some(result) = check_quaternion_operation(op, &left, &right) {
+            return result;
+        }
+        if let Some(result) = check_tensor_operation(op, &left, &right) {
+            return result;
+        }
         match op {
+            // 文字列の埋め込みは `+` による連結に脱糖される
+            BinaryOperator::Add if left == Type::String && right == Type::String => Ok(Type::String),
+            // int と float を混ぜると int を float にして計算する (`runtime::promote_mixed_numbers`)
             BinaryOperator::Add | BinaryOperator::Sub | BinaryOperator::Mul | BinaryOperator::Div => {
                 if left.is_numeric() && right.is_numeric() {
-                    if left == Type::Float || right == Type::Float {
-                        Ok(Type::Float)
-                    } else {
-                        Ok(Type::Int)
-                    }
+                    Ok(numeric_result(&left, &right))
                 } else {
                     Err(SlangError::Type("Numeric operation requires numeric operands".to_string()))
                 }
             }
             BinaryOperator::Mod => {
                 if left.is_numeric() && right.is_numeric() {
-                    Ok(Type::Int)
+                    Ok(numeric_result(&left, &right))
                 } else {
                     Err(SlangError::Type("Modulo operation requires numeric operands".to_string()))
                 }
             }
             BinaryOperator::Eq | BinaryOperator::Neq | BinaryOperator::Equals | BinaryOperator::NotEquals => {
-                if left.is_compatible_with(&right) {
+                if left.unifies_with(&right) || (left.is_numeric() && right.is_numeric()) {
                     Ok(Type::Bool)
                 } else {
                     Err(SlangError::Type("Cannot compare incompatible types".to_string()))
@@ -212,10 +710,11 @@ impl TypeChecker {
             }
             BinaryOperator::Lt | BinaryOperator::Lte | BinaryOperator::Gt | BinaryOperator::Gte |
             BinaryOperator::LessThan | BinaryOperator::LessThanEquals | BinaryOperator::GreaterThan | BinaryOperator::GreaterThanEquals => {
-                if left.is_numeric() && right.is_numeric() {
+                // 文字列どうしは辞書順で比べる
+                if (left.is_numeric() && right.is_numeric()) || (left == Type::String && right == Type::String) {
                     Ok(Type::Bool)
                 } else {
-                    Err(SlangError::Type("Comparison requires numeric operands".to_string()))
+                    Err(SlangError::Type("Comparison requires numeric or string operands".to_string()))
                 }
             }
             BinaryOperator::And | BinaryOperator::Or => {
@@ -242,7 +741,7 @@ impl TypeChecker {
     fn check_unary_operation(&mut self, op: &UnaryOperator, expr: Type) -> Result<Type> {
         match op {
             UnaryOperator::Neg | UnaryOperator::Negate => {
-                if expr.is_numeric() {
+                if expr.is_numeric() || expr.is_complex() {
                     Ok(expr)
                 } else {
                     Err(SlangError::Type("Negation requires numeric operand".to_string()))
@@ -258,13 +757,96 @@ impl TypeChecker {
         }
     }
 
+    // `narrowed` の変数をブロックの中だけ `null` でない型にする
+    fn check_narrowed_block(&mut self, block: &Block, narrowed: Option<&(&str, Type, bool)>) -> Result<()> {
+        let Some((name, inner, _)) = narrowed else {
+            return self.check_block(block);
+        };
+        let optional = self.type_vars.insert(name.to_string(), inner.clone());
+        let result = self.check_block(block);
+        if let Some(optional) = optional {
+            self.type_vars.insert(name.to_string(), optional);
+        }
+        result
+    }
+
+    // 本体は外側の変数を参照できるが、本体で束縛した変数や `break` できるループは外に漏らさない
+    fn check_lambda(&mut self, params: &[Parameter], return_type: Option<&Type>, body: &Block) -> Result<Type> {
+        let enclosing_vars = self.type_vars.clone();
+        let enclosing_immutable = self.immutable_vars.clone();
+        let enclosing_loop_depth = std::mem::replace(&mut self.loop_depth, 0);
+        let param_types: Vec<Type> = params.iter().map(|param| self.resolve(&param.type_annotation)).collect();
+        let enclosing_function = self.current_function.replace(Type::Function {
+            params: param_types.clone(),
+            return_type: Box::new(return_type.map_or(UNINFERRED_RETURN, |t| self.resolve(t))),
+            priority: None,
+        });
+        let enclosing_captured = self.captured_vars.clone();
+        self.captured_vars.extend(self.type_vars.keys().cloned());
+        for (param, param_type) in params.iter().zip(param_types) {
+            self.declare_var(&param.name, param_type, param.mutable);
+        }
+
+        let result = self.check_block(body);
+        let lambda_type = match self.current_function.take() {
+            Some(Type::Function { params, return_type, priority }) if *return_type == UNINFERRED_RETURN => {
+                Type::Function { params, return_type: Box::new(Type::Void), priority }
+            }
+            lambda_type => lambda_type.expect("current_function is set while checking a lambda body"),
+        };
+
+        self.type_vars = enclosing_vars;
+        self.immutable_vars = enclosing_immutable;
+        self.captured_vars = enclosing_captured;
+        self.loop_depth = enclosing_loop_depth;
+        self.current_function = enclosing_function;
+        result?;
+
+        if let Type::Function { return_type, .. } = &lambda_type {
+            if !matches!(return_type.as_ref(), Type::Unit | Type::Void) && !block_diverges(body) {
+                return Err(SlangError::Type(format!("Lambda must return {:?} on every path", return_type)));
+            }
+        }
+        Ok(lambda_type)
+    }
+
+    fn return_type_is_uninferred(&self) -> bool {
+        matches!(&self.current_function, Some(Type::Function { return_type, .. }) if **return_type == UNINFERRED_RETURN)
+    }
+
+    // 優先度の低い関数から、それを所有できる優先度の高い関数は呼び出せない (`Type::can_own`)
+    fn check_ownership(&self, name: &str, callee: &Type) -> Result<()> {
+        if let Some(caller) = &self.current_function {
+            if callee.can_own(caller) {
+                return Err(SlangError::Type(format!(
+                    "Priority violation: `{}` has priority {} and cannot be called from a function with priority {}",
+                    name,
+                    callee.get_priority().unwrap_or_default(),
+                    caller.get_priority().unwrap_or_default(),
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    // `for` で回す範囲は整数の列として扱う
+    fn check_range(&mut self, start: &Expression, end: &Expression) -> Result<Type> {
+        for bound in [start, end] {
+            let bound_type = self.check_expression(bound)?;
+            if !bound_type.unifies_with(&Type::Int) {
+                return Err(SlangError::Type(format!("Range bounds must be int, got {}", bound_type)));
+            }
+        }
+        Ok(Type::Array(Box::new(Type::Int)))
+    }
+
     fn check_function_call(&mut self, function_type: Type, arg_types: Vec<Type>) -> Result<Type> {
         if let Type::Function { params, return_type, .. } = function_type {
             if params.len() != arg_types.len() {
                 return Err(SlangError::Type("Wrong number of arguments".to_string()));
             }
             for (param_type, arg_type) in params.iter().zip(arg_types.iter()) {
-                if !arg_type.is_compatible_with(param_type) {
+                if !arg_type.is_assignable_to(param_type) {
                     return Err(SlangError::Type(format!(
                         "Argument type mismatch: expected {:?}, got {:?}",
                         param_type, arg_type
@@ -280,12 +862,12 @@ impl TypeChecker {
     fn check_pattern(&mut self, pattern: &Pattern, value_type: &Type) -> Result<()> {
         match pattern {
             Pattern::Identifier(name) => {
-                self.type_vars.insert(name.clone(), value_type.clone());
+                self.declare_var(name, value_type.clone(), true);
                 Ok(())
             }
             Pattern::Literal(lit) => {
                 let lit_type = self.get_literal_type(lit);
-                if !lit_type.is_compatible_with(value_type) {
+                if !lit_type.unifies_with(value_type) {
                     return Err(SlangError::Type(format!(
                         "Pattern type mismatch: expected {:?}, got {:?}",
                         value_type, lit_type
@@ -311,38 +893,23 @@ impl TypeChecker {
                 }
             }
             Pattern::Struct { name, fields } => {
-                if let Some(type_def) = self.type_definitions.get(name) {
-                    let mut field_types = type_def.fields.clone();
-                    for field in fields {
-                        if let Some(field_type) = field_types.iter()
-                            .find(|f| f.name == field.name)
-                            .map(|f| &f.type_annotation)
-                        {
-                            self.check_pattern(&field.pattern, field_type)?;
-                        } else {
-                            return Err(SlangError::Type(format!(
-                                "Unknown field: {} in type {}",
-                                field.name, name
-                            )));
-                        }
-                    }
-                    Ok(())
-                } else {
-                    Err(SlangError::Type(format!("Unknown type: {}", name)))
+                self.check_struct_pattern(name, fields)?;
+                match value_type {
+                    Type::Struct(value_name) | Type::Named(value_name) if value_name == name => Ok(()),
+                    _ => Err(SlangError::Type(format!(
+                        "Pattern type mismatch: expected {:?}, got struct pattern '{}'",
+                        value_type, name
+                    ))),
                 }
             }
         }
     }
 
     fn check_struct_pattern(&mut self, name: &str, fields: &[FieldPattern]) -> Result<()> {
-        if let Some(type_def) = self.type_definitions.get(name) {
-            let field_types: HashMap<_, _> = type_def.fields.iter()
-                .map(|field| (field.name.clone(), field.type_annotation.clone()))
-                .collect();
-
+        if self.type_definitions.contains_key(name) {
             for field in fields {
-                if let Some(field_type) = field_types.get(&field.name) {
-                    self.check_pattern(&field.pattern, field_type.clone())?;
+                if let Some(field_type) = self.field_type(name, &field.name) {
+                    self.check_pattern(&field.pattern, &field_type)?;
                 } else {
                     return Err(SlangError::Type(format!("Field '{}' not found in struct '{}'", field.name, name)));
                 }
@@ -352,4 +919,1059 @@ impl TypeChecker {
             Err(SlangError::Type(format!("Type '{}' not found", name)))
         }
     }
-} 
\ No newline at end of file
+}
+
+// 引数の型から型パラメータを決めて関数の型に代入する。束縛は呼び出しごとに作り直す
+fn instantiate(type_params: &[String], function_type: &Type, arg_types: &[Type]) -> Type {
+    if type_params.is_empty() {
+        return function_type.clone();
+    }
+    let mut bindings = HashMap::new();
+    if let Type::Function { params, .. } = function_type {
+        for (param, arg) in params.iter().zip(arg_types) {
+            bind_type_params(type_params, param, arg, &mut bindings);
+        }
+    }
+    function_type.substitute(&bindings)
+}
+
+// 最初に現れた位置の型で束縛する。食い違いは代入後の引数チェックで検出される
+fn bind_type_params(type_params: &[String], param: &Type, arg: &Type, bindings: &mut HashMap<String, Type>) {
+    match (param, arg) {
+        (Type::Named(name), _) if type_params.contains(name) => {
+            bindings.entry(name.clone()).or_insert_with(|| arg.clone());
+        }
+        (Type::Array(p), Type::Array(a) | Type::FixedArray(a, _))
+        | (Type::FixedArray(p, _), Type::FixedArray(a, _))
+        | (Type::Pointer(p), Type::Pointer(a)) => {
+            bind_type_params(type_params, p, a, bindings);
+        }
+        (Type::Tuple(ps), Type::Tuple(args)) => {
+            for (p, a) in ps.iter().zip(args) {
+                bind_type_params(type_params, p, a, bindings);
+            }
+        }
+        _ => {}
+    }
+}
+
+impl Default for TypeChecker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// 複素数は四則演算と等値比較だけができる。大小比較はない
+pub(super) fn check_complex_operation(op: &BinaryOperator, left: &Type, right: &Type) -> Result<Type> {
+    let result = left.complex_arithmetic_result(right).ok_or_else(|| {
+        SlangError::Type(format!("Cannot apply {} to {} and {}", op, left, right))
+    })?;
+    match op {
+        BinaryOperator::Add | BinaryOperator::Sub | BinaryOperator::Mul | BinaryOperator::Div |
+        BinaryOperator::Divide => Ok(result),
+        BinaryOperator::Eq | BinaryOperator::Neq | BinaryOperator::Equals | BinaryOperator::NotEquals => Ok(Type::Bool),
+        _ => Err(SlangError::Type(format!("Operator {} is not defined for complex numbers", op))),
+    }
+}
+
+// 数値どうしの演算の結果の型。どちらかが float なら float
+fn numeric_result(left: &Type, right: &Type) -> Type {
+    if *left == Type::Float || *right == Type::Float {
+        Type::Float
+    } else {
+        Type::Int
+    }
+}
+
+// 四元数どうしの `+`/`-`/`*` (ハミルトン積) と等値比較。スカラーとの演算は認めない。
+// どちらのオペランドも四元数でなければ `None`
+pub(super) fn check_quaternion_operation(op: &BinaryOperator, left: &Type, right: &Type) -> Option<Result<Type>> {
+    if !left.is_quaternion() && !right.is_quaternion() {
+        return None;
+    }
+    let result = match (left, right) {
+        (Type::Quaternion(a), Type::Quaternion(b)) if a.is_numeric() && b.is_numeric() => {
+            Type::Quaternion(Box::new(Type::Float))
+        }
+        _ => return Some(Err(SlangError::Type(format!("Cannot apply {} to {} and {}", op, left, right)))),
+    };
+    Some(match op {
+        BinaryOperator::Add | BinaryOperator::Sub | BinaryOperator::Mul => Ok(result),
+        BinaryOperator::Eq | BinaryOperator::Neq | BinaryOperator::Equals | BinaryOperator::NotEquals => Ok(Type::Bool),
+        _ => Err(SlangError::Type(format!("Operator {} is not defined for quaternions", op))),
+    })
+}
+
+// ベクトルと行列の演算。どちらのオペランドもベクトル・行列でなければ `None`
+// - `+`/`-`: 同じ次元・同じ要素型どうし。結果も同じ型
+// - `*`: `matAxB<T> * matBxC<T>` が `matAxC<T>`
+pub(super) fn check_linear_algebra(op: &BinaryOperator, left: &Type, right: &Type) -> Option<Result<Type>> {
+    let is_linear = |t: &Type| matches!(t, Type::Vector(..) | Type::Matrix(..));
+    if !is_linear(left) && !is_linear(right) {
+        return None;
+    }
+    let verb = match op {
+        BinaryOperator::Add => "add",
+        BinaryOperator::Sub => "subtract",
+        _ => "multiply",
+    };
+    let mismatch = || Err(SlangError::Type(format!("Dimension mismatch: cannot {} {} and {}", verb, left, right)));
+    let result = match (op, left, right) {
+        (BinaryOperator::Add | BinaryOperator::Sub, Type::Vector(n, a), Type::Vector(m, b)) if a == b => {
+            if n == m { Ok(left.clone()) } else { mismatch() }
+        }
+        (BinaryOperator::Add | BinaryOperator::Sub, Type::Matrix(r1, c1, a), Type::Matrix(r2, c2, b)) if a == b => {
+            if (r1, c1) == (r2, c2) { Ok(left.clone()) } else { mismatch() }
+        }
+        (BinaryOperator::Mul, Type::Matrix(rows, inner, a), Type::Matrix(inner2, cols, b)) if a == b => {
+            if inner == inner2 { Ok(Type::Matrix(*rows, *cols, a.clone())) } else { mismatch() }
+        }
+        _ => Err(SlangError::Type(format!("Unsupported operation {} between {} and {}", op, left, right))),
+    };
+    Some(result)
+}
+
+// テンソルの要素ごとの `+`/`*`。同じ形・同じ要素型どうしで、結果も同じ型。
+// どちらのオペランドもテンソルでなければ `None`
+pub(super) fn check_tensor_operation(op: &BinaryOperator, left: &Type, right: &Type) -> Option<Result<Type>> {
+    if !left.is_tensor() && !right.is_tensor() {
+        return None;
+    }
+    let result = match (op, left, right) {
+        (BinaryOperator::Add | BinaryOperator::Mul, Type::Tensor(d1, a), Type::Tensor(d2, b)) if a == b => {
+            if d1 == d2 {
+                Ok(left.clone())
+            } else {
+                Err(SlangError::Type(format!("Shape mismatch: cannot apply {} to {} and {}", op, left, right)))
+            }
+        }
+        _ => Err(SlangError::Type(format!("Unsupported operation {} between {} and {}", op, left, right))),
+    };
+    Some(result)
+}
+
+// モジュールの中も含めて、関数・構造体・トレイトを出現順に集める
+fn collect_items<'a>(
+    items: &'a [Item],
+    functions: &mut Vec<&'a Function>,
+    structs: &mut Vec<&'a Struct>,
+    traits: &mut Vec<&'a Trait>,
+) {
+    for item in items {
+        match item {
+            Item::Function(function) => functions.push(function),
+            Item::Struct(struct_) => structs.push(struct_),
+            Item::Trait(trait_) => traits.push(trait_),
+            Item::Module(module) => collect_items(&module.items, functions, structs, traits),
+            Item::Macro(_) => {}
+        }
+    }
+}
+
+// 型に現れる利用者定義の型の名前を集める。`inline_only` の場合は値の中に直接置かれるものだけ
+// (ポインタ・配列・関数の先にあるものは除く)
+fn collect_named_types(type_: &Type, inline_only: bool, names: &mut Vec<String>) {
+    match type_ {
+        Type::Named(name) => names.push(name.clone()),
+        Type::Tuple(types) => {
+            for type_ in types {
+                collect_named_types(type_, inline_only, names);
+            }
+        }
+        Type::FixedArray(element, _)
+        | Type::Vector(_, element)
+        | Type::Matrix(_, _, element)
+        | Type::Tensor(_, element)
+        | Type::Quaternion(element)
+        | Type::Complex(element) => collect_named_types(element, inline_only, names),
+        Type::Array(element) | Type::Pointer(element) if !inline_only => {
+            collect_named_types(element, inline_only, names);
+        }
+        Type::Function { params, return_type, .. } if !inline_only => {
+            for param in params {
+                collect_named_types(param, inline_only, names);
+            }
+            collect_named_types(return_type, inline_only, names);
+        }
+        _ => {}
+    }
+}
+
+// 優先度を指定していない関数は `priority: None` になり、優先所有格の検査を受けない
+// ブロックの末尾まで実行が進まないか。どの経路でも `return` するか、抜け出せない無限ループに入る場合
+// `x != null` / `x == null` (左右は逆でもよい) なら変数名と、then 側で `null` でないかどうか
+fn null_check(condition: &Expression) -> Option<(&str, bool)> {
+    let Expression::BinaryOp(binary) = condition else {
+        return None;
+    };
+    let non_null_in_then = match binary.op {
+        BinaryOperator::Neq | BinaryOperator::NotEquals => true,
+        BinaryOperator::Eq | BinaryOperator::Equals => false,
+        _ => return None,
+    };
+    match (binary.left.as_ref(), binary.right.as_ref()) {
+        (Expression::Identifier(name), Expression::Literal(Literal::Null))
+        | (Expression::Literal(Literal::Null), Expression::Identifier(name)) => Some((name, non_null_in_then)),
+        _ => None,
+    }
+}
+
+// `null` かもしれない値は比較以外の演算に使えない。先に `null` と比べて絞り込む
+pub(super) fn check_non_null_operands(op: &BinaryOperator, left: &Type, right: &Type) -> Result<()> {
+    if matches!(op, BinaryOperator::Eq | BinaryOperator::Neq | BinaryOperator::Equals | BinaryOperator::NotEquals) {
+        return Ok(());
+    }
+    match [left, right].into_iter().find(|t| t.is_optional()) {
+        // `null` リテラルの型には推論用の型変数が入っているので、型名を出さない
+        Some(Type::Optional(inner)) if matches!(**inner, Type::Var(_)) => Err(SlangError::Type(
+            "Operand is null; null can only be compared with == or !=".to_string(),
+        )),
+        Some(optional) => Err(SlangError::Type(format!(
+            "Operand of type {} may be null; check it against null first",
+            optional
+        ))),
+        None => Ok(()),
+    }
+}
+
+fn block_diverges(block: &Block) -> bool {
+    block.statements.iter().any(statement_diverges)
+}
+
+fn statement_diverges(statement: &Statement) -> bool {
+    match statement {
+        Statement::Return(_) => true,
+        // 組み込みの `panic` はそこで実行を止める
+        Statement::Expression(expression) => {
+            matches!(expression.as_ref(), Expression::Call(call) if call.function == "panic")
+        }
+        Statement::If(stmt) => stmt
+            .else_block
+            .as_ref()
+            .is_some_and(|else_block| block_diverges(&stmt.then_block) && block_diverges(else_block)),
+        Statement::IfLet(stmt) => stmt
+            .else_block
+            .as_ref()
+            .is_some_and(|else_block| block_diverges(&stmt.then_block) && block_diverges(else_block)),
+        // `match` は網羅性を検査済みなので、すべての腕が抜けなければよい
+        Statement::Match(stmt) => !stmt.arms.is_empty() && stmt.arms.iter().all(|arm| block_diverges(&arm.body)),
+        // `while true` は `break` しない限り抜けない。`for` は 0 回で終わることがある
+        Statement::While(stmt) => {
+            matches!(stmt.condition.as_ref(), Expression::Literal(Literal::Bool(true))) && !block_breaks(&stmt.body)
+        }
+        _ => false,
+    }
+}
+
+// ブロックの中にこのループを抜ける `break` があるか。内側のループの `break` は数えない
+fn block_breaks(block: &Block) -> bool {
+    block.statements.iter().any(|statement| match statement {
+        Statement::Break => true,
+        Statement::If(stmt) => block_breaks(&stmt.then_block) || stmt.else_block.as_ref().is_some_and(block_breaks),
+        Statement::IfLet(stmt) => block_breaks(&stmt.then_block) || stmt.else_block.as_ref().is_some_and(block_breaks),
+        Statement::Match(stmt) => stmt.arms.iter().any(|arm| block_breaks(&arm.body)),
+        _ => false,
+    })
+}
+
+// 配列リテラルの要素の型をそろえる。`null` や `T?` が混ざっていれば要素の型は `T?` になる
+fn join_element_types(current: &Type, other: &Type) -> Option<Type> {
+    match (current, other) {
+        (Type::Optional(inner), t) | (t, Type::Optional(inner)) if matches!(**inner, Type::Var(_)) && !t.is_optional() => {
+            Some(Type::Optional(Box::new(t.clone())))
+        }
+        _ if other.is_assignable_to(current) => Some(current.clone()),
+        _ if current.is_assignable_to(other) => Some(other.clone()),
+        _ => None,
+    }
+}
+
+// `[T; N]` が期待される位置に書いた配列リテラルは、要素が N 個なら `[T; N]` の値になる
+fn fit_array_literal(value: &Expression, value_type: Type, expected: &Type) -> Type {
+    match (value, &value_type, expected) {
+        (Expression::Array(elements), _, Type::Array(_) | Type::FixedArray(_, 0)) if elements.is_empty() => expected.clone(),
+        (Expression::Array(elements), Type::Array(element), Type::FixedArray(expected_element, len))
+            if elements.len() == *len && element.unifies_with(expected_element) =>
+        {
+            expected.clone()
+        }
+        _ => value_type,
+    }
+}
+
+fn function_type(function: &Function) -> Type {
+    Type::Function {
+        params: function.parameters.iter().map(|p| p.type_annotation.clone()).collect(),
+        return_type: Box::new(function.return_type.clone()),
+        priority: if function.priorities.is_empty() { None } else { Some(function.primary_priority() as u32) },
+    }
+}
+
+fn trait_method_type(method: &TraitMethod) -> Type {
+    Type::Function {
+        params: method.params.iter().map(|p| p.type_annotation.clone()).collect(),
+        return_type: Box::new(method.return_type.clone()),
+        priority: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn int(value: i64) -> Box<Expression> {
+        Box::new(Expression::Literal(Literal::Int(value)))
+    }
+
+    fn string(value: &str) -> Box<Expression> {
+        Box::new(Expression::Literal(Literal::String(value.to_string())))
+    }
+
+    #[test]
+    fn test_no_implicit_string_number_coercion() {
+        let mut checker = TypeChecker::new();
+        let let_statement = |type_annotation: Type, value: Box<Expression>| Statement::Let(LetStatement {
+            name: "x".to_string(),
+            mutable: true,
+            type_annotation: Some(type_annotation),
+            value,
+            priority: None,
+        });
+        assert!(checker.check_statement(&let_statement(Type::Int, string("hi"))).is_err());
+        assert!(checker.check_statement(&let_statement(Type::String, int(1))).is_err());
+        assert!(checker.check_statement(&let_statement(Type::Int, int(1))).is_ok());
+
+        let assignment = Expression::Assignment(Box::new(AssignmentExpression {
+            target: "x".to_string(),
+            value: string("hi"),
+        }));
+        assert!(checker.check_expression(&assignment).is_err());
+
+        checker.current_function = Some(Type::Function {
+            params: vec![],
+            return_type: Box::new(Type::Int),
+            priority: None,
+        });
+        let return_statement = |value: Box<Expression>| Statement::Return(ReturnStatement { value: Some(value) });
+        assert!(checker.check_statement(&return_statement(string("1"))).is_err());
+        assert!(checker.check_statement(&return_statement(Box::new(Expression::Literal(Literal::Float(1.0))))).is_err());
+        assert!(checker.check_statement(&return_statement(int(1))).is_ok());
+    }
+
+    #[test]
+    fn test_break_and_continue_only_inside_loops() {
+        let mut checker = TypeChecker::new();
+        let loop_body = Block {
+            statements: vec![Statement::Break, Statement::Continue],
+        };
+        let while_loop = Statement::While(WhileStatement {
+            condition: Box::new(Expression::Literal(Literal::Bool(true))),
+            body: loop_body.clone(),
+        });
+        assert!(checker.check_statement(&while_loop).is_ok());
+
+        let for_loop = Statement::For(ForStatement {
+            variable: "x".to_string(),
+            iterator: Box::new(Expression::Array(vec![*int(1)])),
+            body: loop_body,
+        });
+        assert!(checker.check_statement(&for_loop).is_ok());
+
+        assert!(checker.check_statement(&Statement::Break).is_err());
+        assert!(checker.check_statement(&Statement::Continue).is_err());
+
+        // ループ内の `if` からは抜けられるが、ループを抜けた後は再びエラー
+        let nested = Statement::While(WhileStatement {
+            condition: Box::new(Expression::Literal(Literal::Bool(true))),
+            body: Block {
+                statements: vec![Statement::If(IfStatement {
+                    condition: Box::new(Expression::Literal(Literal::Bool(true))),
+                    then_block: Block {
+                        statements: vec![Statement::Break],
+                    },
+                    else_block: None,
+                })],
+            },
+        });
+        assert!(checker.check_statement(&nested).is_ok());
+        assert!(checker.check_statement(&Statement::Break).is_err());
+    }
+
+    #[test]
+    fn test_check_index_expression() {
+        let mut checker = TypeChecker::new();
+        checker.type_vars.insert("v".to_string(), Type::Vector(3, Box::new(Type::Float)));
+        let index = Expression::Index {
+            array: Box::new(Expression::Identifier("v".to_string())),
+            index: int(1),
+        };
+        assert_eq!(checker.check_expression(&index).unwrap(), Type::Float);
+
+        let index = Expression::Index {
+            array: Box::new(Expression::Array(vec![*int(1)])),
+            index: Box::new(Expression::Literal(Literal::Float(0.0))),
+        };
+        assert!(checker.check_expression(&index).is_err());
+
+        let index = Expression::Index {
+            array: int(1),
+            index: int(0),
+        };
+        assert!(checker.check_expression(&index).is_err());
+    }
+
+    #[test]
+    fn test_check_field_access() {
+        let mut checker = TypeChecker::new();
+        checker.type_definitions.insert("Point".to_string(), TypeDefinition {
+            name: "Point".to_string(),
+            fields: vec![Field {
+                name: "x".to_string(),
+                type_annotation: Type::Float,
+            }],
+        });
+        checker.type_vars.insert("p".to_string(), Type::Struct("Point".to_string()));
+
+        let access = |field: &str| Expression::FieldAccess {
+            base: Box::new(Expression::Identifier("p".to_string())),
+            field: field.to_string(),
+        };
+        assert_eq!(checker.check_expression(&access("x")).unwrap(), Type::Float);
+        assert!(checker.check_expression(&access("z")).is_err());
+
+        let on_int = Expression::FieldAccess {
+            base: int(1),
+            field: "x".to_string(),
+        };
+        assert!(checker.check_expression(&on_int).is_err());
+    }
+
+    #[test]
+    fn test_check_method_call() {
+        let mut checker = TypeChecker::new();
+        checker.type_vars.insert("xs".to_string(), Type::Array(Box::new(Type::Int)));
+        let method_call = |method: &str, arguments: Vec<Expression>| Expression::MethodCall {
+            receiver: Box::new(Expression::Identifier("xs".to_string())),
+            method: method.to_string(),
+            arguments,
+        };
+
+        assert_eq!(checker.check_expression(&method_call("len", vec![])).unwrap(), Type::Int);
+        assert_eq!(checker.check_expression(&method_call("push", vec![*int(1)])).unwrap(), Type::Unit);
+        assert!(checker.check_expression(&method_call("push", vec![])).is_err());
+        assert!(checker.check_expression(&method_call("pop", vec![])).is_err());
+    }
+
+    #[test]
+    fn test_check_struct_literal() {
+        let mut checker = TypeChecker::new();
+        checker.type_definitions.insert("Point".to_string(), TypeDefinition {
+            name: "Point".to_string(),
+            fields: vec![
+                Field {
+                    name: "x".to_string(),
+                    type_annotation: Type::Int,
+                },
+                Field {
+                    name: "y".to_string(),
+                    type_annotation: Type::Int,
+                },
+            ],
+        });
+        let literal = |fields: Vec<(&str, Expression)>| Expression::StructLiteral {
+            name: "Point".to_string(),
+            fields: fields.into_iter().map(|(name, value)| (name.to_string(), value)).collect(),
+        };
+
+        let point = literal(vec![("x", *int(1)), ("y", *int(2))]);
+        assert_eq!(checker.check_expression(&point).unwrap(), Type::Struct("Point".to_string()));
+
+        let missing = literal(vec![("x", *int(1))]);
+        assert!(checker.check_expression(&missing).is_err());
+
+        let unknown = literal(vec![("x", *int(1)), ("y", *int(2)), ("z", *int(3))]);
+        assert!(checker.check_expression(&unknown).is_err());
+
+        let duplicate = literal(vec![("x", *int(1)), ("x", *int(2)), ("y", *int(3))]);
+        assert!(checker.check_expression(&duplicate).is_err());
+
+        let mismatched = literal(vec![("x", *int(1)), ("y", Expression::Literal(Literal::Bool(true)))]);
+        assert!(checker.check_expression(&mismatched).is_err());
+    }
+
+    #[test]
+    fn test_let_annotation_is_checked_against_value() {
+        let parse = |source: &str| Parser::new(Lexer::new(source)).parse().unwrap();
+        let mut checker = TypeChecker::new();
+        assert!(checker.check_ast(&parse("fn f() -> int { let x: float = 1; return 0; }")).is_err());
+        assert!(checker.check_ast(&parse("fn f() -> int { let x: float = 1.0; return 0; }")).is_ok());
+    }
+
+    #[test]
+    fn test_char_literal_type() {
+        let mut checker = TypeChecker::new();
+        let literal = Expression::Literal(Literal::Char('a'));
+        assert_eq!(checker.check_expression(&literal).unwrap(), Type::Char);
+    }
+
+    #[test]
+    fn test_generic_call_binds_type_params_per_call() {
+        let mut checker = TypeChecker::new();
+        let named = |name: &str| Type::Named(name.to_string());
+        checker.function_signatures.insert("same".to_string(), (
+            vec!["T".to_string()],
+            Type::Function {
+                params: vec![named("T"), named("T")],
+                return_type: Box::new(named("T")),
+                priority: None,
+            },
+        ));
+        let call = |arguments: Vec<Expression>| Expression::Call(Box::new(CallExpression {
+            function: "same".to_string(),
+            arguments,
+        }));
+
+        assert_eq!(checker.check_expression(&call(vec![*int(1), *int(2)])).unwrap(), Type::Int);
+        // 前の呼び出しの `T = int` を引きずらない
+        assert_eq!(checker.check_expression(&call(vec![*string("a"), *string("b")])).unwrap(), Type::String);
+        assert!(checker.check_expression(&call(vec![*int(1), *string("b")])).is_err());
+    }
+
+    #[test]
+    fn test_vector_and_matrix_operations() {
+        let mut checker = TypeChecker::new();
+        let vec = |n| Type::Vector(n, Box::new(Type::Float));
+        let mat = |r, c| Type::Matrix(r, c, Box::new(Type::Float));
+
+        assert_eq!(checker.check_binary_operation(&BinaryOperator::Add, vec(3), vec(3)).unwrap(), vec(3));
+        assert_eq!(checker.check_binary_operation(&BinaryOperator::Sub, mat(2, 3), mat(2, 3)).unwrap(), mat(2, 3));
+        assert_eq!(checker.check_binary_operation(&BinaryOperator::Mul, mat(2, 3), mat(3, 4)).unwrap(), mat(2, 4));
+
+        let error = checker.check_binary_operation(&BinaryOperator::Add, vec(3), vec(2)).unwrap_err();
+        assert!(error.to_string().contains("Dimension mismatch: cannot add vec3<float> and vec2<float>"), "{}", error);
+        let error = checker.check_binary_operation(&BinaryOperator::Mul, mat(2, 3), mat(2, 3)).unwrap_err();
+        assert!(error.to_string().contains("cannot multiply mat2x3<float> and mat2x3<float>"), "{}", error);
+        assert!(checker.check_binary_operation(&BinaryOperator::Sub, mat(2, 2), mat(3, 2)).is_err());
+        assert!(checker.check_binary_operation(&BinaryOperator::Add, vec(3), Type::Float).is_err());
+        assert!(checker
+            .check_binary_operation(&BinaryOperator::Add, vec(3), Type::Vector(3, Box::new(Type::Int)))
+            .is_err());
+    }
+
+    #[test]
+    fn test_tensor_reshape_and_elementwise_operations() {
+        let mut checker = TypeChecker::new();
+        let tensor = |dimensions: &[usize]| Type::Tensor(dimensions.to_vec(), Box::new(Type::Float));
+        checker.type_vars.insert("t".to_string(), tensor(&[2, 3]));
+        let mut check = |source: &str| {
+            let expression = Parser::new(Lexer::new(source)).parse_standalone_expression().unwrap();
+            checker.check_expression(&expression)
+        };
+
+        assert_eq!(check("reshape(t, [6])").unwrap(), tensor(&[6]));
+        assert_eq!(check("reshape(t, [3, 1, 2])").unwrap(), tensor(&[3, 1, 2]));
+        let error = check("reshape(t, [4])").unwrap_err();
+        assert!(error.to_string().contains("Cannot reshape tensor<2x3, float> to tensor<4, float>: 6 elements vs 4"), "{}", error);
+        assert!(check("reshape(t, [0, 6])").is_err());
+        assert!(check("reshape(t)").is_err());
+        assert!(check("reshape(1, [1])").is_err());
+
+        assert_eq!(check("t + t").unwrap(), tensor(&[2, 3]));
+        assert_eq!(check("t * t").unwrap(), tensor(&[2, 3]));
+        assert_eq!(check("reshape(t, [6]) * reshape(t, [6])").unwrap(), tensor(&[6]));
+        let error = check("t + reshape(t, [3, 2])").unwrap_err();
+        assert!(error.to_string().contains("Shape mismatch: cannot apply + to tensor<2x3, float> and tensor<3x2, float>"), "{}", error);
+        assert!(check("t - t").is_err());
+        assert!(check("t + 1.0").is_err());
+    }
+
+    #[test]
+    fn test_cast_expression() {
+        let mut checker = TypeChecker::new();
+        let mut check = |source: &str| {
+            let expression = Parser::new(Lexer::new(source)).parse_standalone_expression().unwrap();
+            checker.check_expression(&expression)
+        };
+        assert_eq!(check("5 as float").unwrap(), Type::Float);
+        assert_eq!(check("2.5 as int + 1").unwrap(), Type::Int);
+        assert_eq!(check("true as string").unwrap(), Type::String);
+        // 変換できるかどうかは型だけで決まる。文字列の中身は実行時に調べる
+        assert_eq!(check("\"abc\" as int").unwrap(), Type::Int);
+
+        let error = check("true as int").unwrap_err();
+        assert!(error.to_string().contains("Cannot cast bool to int"), "{}", error);
+        assert_eq!(check("[1, 2] as [float]").unwrap(), Type::Array(Box::new(Type::Float)));
+        assert!(check("[true] as [int]").is_err());
+        assert!(check("[1] as string").is_err());
+        assert!(check("'a' as int").is_err());
+    }
+
+    #[test]
+    fn test_lambda_expression() {
+        let mut checker = TypeChecker::new();
+        checker.type_vars.insert("k".to_string(), Type::Int);
+        let mut check = |source: &str| {
+            let expression = Parser::new(Lexer::new(source)).parse_standalone_expression().unwrap();
+            checker.check_expression(&expression)
+        };
+        let function = |params: Vec<Type>, return_type: Type| Type::Function { params, return_type: Box::new(return_type), priority: None };
+
+        // 外側の変数を参照でき、省略した戻り値の型は `return` から決まる
+        assert_eq!(check("fn(x: int) -> int { return x + k; }").unwrap(), function(vec![Type::Int], Type::Int));
+        assert_eq!(check("fn(x: float) { return x * 2.0; }").unwrap(), function(vec![Type::Float], Type::Float));
+        assert_eq!(check("fn() { let y = k; }").unwrap(), function(vec![], Type::Void));
+
+        let error = check("fn(x: int) { if x > 0 { return 1; } return 1.5; }").unwrap_err();
+        assert!(error.to_string().contains("Return type mismatch"), "{}", error);
+        let error = check("fn(x: int) -> int { if x > 0 { return 1; } }").unwrap_err();
+        assert!(error.to_string().contains("Lambda must return"), "{}", error);
+        // 本体のループの外では `break` できず、仮引数は外に漏れない
+        assert!(check("fn() { break; }").is_err());
+        assert!(check("fn(y: int) { return; }").is_ok());
+        assert!(check("y").is_err());
+    }
+
+    #[test]
+    fn test_quaternion_operations() {
+        let mut checker = TypeChecker::new();
+        let quat = |element: Type| Type::Quaternion(Box::new(element));
+        for op in [BinaryOperator::Mul, BinaryOperator::Add, BinaryOperator::Sub] {
+            assert_eq!(checker.check_binary_operation(&op, quat(Type::Float), quat(Type::Float)).unwrap(), quat(Type::Float));
+        }
+        assert_eq!(checker.check_binary_operation(&BinaryOperator::Mul, quat(Type::Int), quat(Type::Float)).unwrap(), quat(Type::Float));
+        assert_eq!(checker.check_binary_operation(&BinaryOperator::Eq, quat(Type::Float), quat(Type::Float)).unwrap(), Type::Bool);
+
+        let error = checker.check_binary_operation(&BinaryOperator::Add, quat(Type::Float), Type::Float).unwrap_err();
+        assert!(error.to_string().contains("Cannot apply + to quat<float> and float"), "{}", error);
+        assert!(checker.check_binary_operation(&BinaryOperator::Mul, Type::Int, quat(Type::Float)).is_err());
+        assert!(checker.check_binary_operation(&BinaryOperator::Div, quat(Type::Float), quat(Type::Float)).is_err());
+        assert!(checker.check_binary_operation(&BinaryOperator::Add, quat(Type::Float), Type::Complex(Box::new(Type::Float))).is_err());
+
+        checker.type_vars.insert("q".to_string(), quat(Type::Float));
+        for method in ["conjugate", "normalize"] {
+            let call = Expression::MethodCall {
+                receiver: Box::new(Expression::Identifier("q".to_string())),
+                method: method.to_string(),
+                arguments: vec![],
+            };
+            assert_eq!(checker.check_expression(&call).unwrap(), quat(Type::Float));
+        }
+    }
+
+    #[test]
+    fn test_complex_arithmetic() {
+        let mut checker = TypeChecker::new();
+        let mut check = |source: &str| {
+            let expression = Parser::new(Lexer::new(source)).parse_standalone_expression().unwrap();
+            checker.check_expression(&expression)
+        };
+        let complex = Type::Complex(Box::new(Type::Float));
+        assert_eq!(check("(1 + 2i) * (3 + 4i)").unwrap(), complex);
+        assert_eq!(check("2.5 - 1i / 2").unwrap(), complex);
+        assert_eq!(check("-3i").unwrap(), complex);
+        assert_eq!(check("1i == 1i").unwrap(), Type::Bool);
+        assert!(check("1i < 2i").is_err());
+        assert!(check("1i % 2").is_err());
+        assert!(check("1i + true").is_err());
+    }
+
+    fn check_source(source: &str) -> Result<()> {
+        TypeChecker::new().check_ast(&Parser::new(Lexer::new(source)).parse()?)
+    }
+
+    #[test]
+    fn test_priority_ownership() {
+        // 優先度の高い関数は低い関数を呼び出せる
+        check_source(
+            "fn high(x: int) -> int priority 2 { return low(x); } \
+             fn low(x: int) -> int priority 0 { return x; }",
+        )
+        .unwrap();
+        // 同じ優先度どうし (再帰を含む) や、優先度を指定していない関数との呼び出しは制約を受けない
+        check_source(
+            "fn a(x: int) -> int priority 1 { return b(x); } \
+             fn b(x: int) -> int priority 1 { return b(x); } \
+             fn main() -> int { return a(1); }",
+        )
+        .unwrap();
+
+        // 優先度の低い関数から、それを所有できる高い関数は呼び出せない
+        let error = check_source(
+            "fn high(x: int) -> int priority 2 { return x; } \
+             fn low(x: int) -> int priority 0 { return high(x); }",
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("Priority violation: `high` has priority 2"), "{}", error);
+    }
+
+    #[test]
+    fn test_reassignment_needs_mut() {
+        let error = check_source("fn main() -> int { let x = 1; x = 2; return x; }").unwrap_err();
+        assert!(error.to_string().contains("Cannot assign to immutable variable `x`"), "{}", error);
+        check_source("fn main() -> int { let mut x = 1; x = 2; return x; }").unwrap();
+
+        // `let` で束縛し直すと可変性も変わる
+        assert!(check_source("fn main() -> int { let mut x = 1; let x = 2; x = 3; return x; }").is_err());
+
+        // 引数は `mut` を付けたときだけ、ループ変数はいつも代入できない
+        let error = check_source("fn main(n: int) -> int { n = 1; return n; }").unwrap_err();
+        assert!(error.to_string().contains("Cannot assign to immutable variable `n`"), "{}", error);
+        let error = check_source("fn main(n: int) -> int { for i in 0..n { i = 0; } return n; }").unwrap_err();
+        assert!(error.to_string().contains("Cannot assign to immutable variable `i`"), "{}", error);
+        assert!(check_source("fn main(xs: [int]) -> int { for x in xs { x = 0; } return 0; }").is_err());
+        let error = check_source("fn main() -> int { let f = fn(n: int) -> int { n = 1; return n; }; return f(0); }").unwrap_err();
+        assert!(error.to_string().contains("Cannot assign to immutable variable `n`"), "{}", error);
+        check_source("fn main(mut n: int) -> int { n = n + 1; return n; }").unwrap();
+        check_source("fn main() -> int { let f = fn(mut n: int) -> int { n = 1; return n; }; return f(0); }").unwrap();
+    }
+
+    #[test]
+    fn test_every_path_must_return() {
+        let error = check_source("fn f(x: int) -> int { if x > 0 { return 1; } }").unwrap_err();
+        assert!(error.to_string().contains("Function `f` must return Int on every path"), "{}", error);
+        assert!(check_source("fn f(xs: [int]) -> int { for x in xs { return x; } }").is_err());
+        assert!(check_source("fn f() -> int { while true { break; } }").is_err());
+        assert!(check_source("fn f() -> int { return; }").is_err());
+
+        check_source("fn f(x: int) -> int { if x > 0 { return 1; } else { return 2; } }").unwrap();
+        check_source("fn f(x: int) -> int { if x > 0 { return 1; } else if x < 0 { return -1; } else { return 0; } }")
+            .unwrap();
+        check_source("fn f() -> int { while true { if false { return 1; } } }").unwrap();
+        check_source("fn f() -> void { let x = 1; }").unwrap();
+    }
+
+    #[test]
+    fn test_match_exhaustiveness() {
+        let mut checker = TypeChecker::new();
+        checker.type_definitions.insert(
+            "Point".to_string(),
+            struct_definition("Point", vec![("x", Type::Int), ("y", Type::Bool)]),
+        );
+        checker.type_vars.insert("b".to_string(), Type::Bool);
+        checker.type_vars.insert("t".to_string(), Type::Tuple(vec![Type::Bool, Type::Int]));
+        checker.type_vars.insert("n".to_string(), Type::Int);
+        checker.type_vars.insert("p".to_string(), Type::Struct("Point".to_string()));
+        let bool_ = |value: bool| Pattern::Literal(Literal::Bool(value));
+        let check = |checker: &mut TypeChecker, value: &str, patterns: Vec<Pattern>| {
+            checker.check_statement(&Statement::Match(MatchStatement {
+                expression: Box::new(Expression::Identifier(value.to_string())),
+                arms: patterns.into_iter().map(|pattern| MatchArm { pattern, body: Block { statements: vec![] } }).collect(),
+            }))
+        };
+        let missing = |result: Result<()>| result.unwrap_err().to_string();
+
+        check(&mut checker, "b", vec![bool_(true), bool_(false)]).unwrap();
+        check(&mut checker, "b", vec![bool_(false), Pattern::Identifier("other".to_string())]).unwrap();
+        assert_eq!(missing(check(&mut checker, "b", vec![bool_(true)])), "Type error: Non-exhaustive match: `false` is not covered");
+        assert!(missing(check(&mut checker, "n", vec![Pattern::Literal(Literal::Int(1))])).contains("`_` is not covered"));
+
+        // タプルと構造体は中身の組み合わせまで見る
+        let first = |value: bool| Pattern::Tuple(vec![bool_(value), Pattern::Wildcard]);
+        check(&mut checker, "t", vec![first(true), first(false)]).unwrap();
+        let error = missing(check(&mut checker, "t", vec![
+            first(true),
+            Pattern::Tuple(vec![bool_(false), Pattern::Literal(Literal::Int(0))]),
+        ]));
+        assert!(error.contains("`(false, _)` is not covered"), "{}", error);
+        let y = |value: bool| Pattern::Struct {
+            name: "Point".to_string(),
+            fields: vec![FieldPattern { name: "y".to_string(), pattern: Box::new(bool_(value)) }],
+        };
+        check(&mut checker, "p", vec![y(true), y(false)]).unwrap();
+        let error = missing(check(&mut checker, "p", vec![y(true)]));
+        assert!(error.contains("`Point { x: _, y: false }` is not covered"), "{}", error);
+        assert!(checker.warnings().is_empty());
+
+        // ワイルドカードの後の腕には到達しない
+        check(&mut checker, "b", vec![Pattern::Wildcard, bool_(true)]).unwrap();
+        check(&mut checker, "b", vec![bool_(true), bool_(false), bool_(true)]).unwrap();
+        check_source("fn f(b: bool) -> int { match b { true => { return 1; } false => { return 0; } } }").unwrap();
+        assert!(check_source("fn f(n: int) -> int { match n { 0 => { return 1; } } }").is_err());
+        assert_eq!(
+            checker.warnings(),
+            &["Unreachable match arm `true`".to_string(), "Unreachable match arm `true`".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_struct_patterns_are_checked_against_the_value() {
+        let mut checker = TypeChecker::new();
+        checker.type_definitions.insert(
+            "Point".to_string(),
+            struct_definition("Point", vec![("x", Type::Int), ("y", Type::Bool)]),
+        );
+        let point = Type::Struct("Point".to_string());
+        let pattern = |name: &str, field: &str, pattern: Pattern| Pattern::Struct {
+            name: name.to_string(),
+            fields: vec![FieldPattern { name: field.to_string(), pattern: Box::new(pattern) }],
+        };
+
+        checker.check_pattern(&pattern("Point", "x", Pattern::Identifier("px".to_string())), &point).unwrap();
+        assert_eq!(checker.type_vars["px"], Type::Int);
+        let error = |checker: &mut TypeChecker, pattern: Pattern, value_type: &Type| {
+            checker.check_pattern(&pattern, value_type).unwrap_err().to_string()
+        };
+        assert_eq!(
+            error(&mut checker, pattern("Point", "z", Pattern::Wildcard), &point),
+            "Type error: Field 'z' not found in struct 'Point'"
+        );
+        assert_eq!(
+            error(&mut checker, pattern("Line", "x", Pattern::Wildcard), &point),
+            "Type error: Type 'Line' not found"
+        );
+        assert!(error(&mut checker, pattern("Point", "y", Pattern::Literal(Literal::Int(1))), &point)
+            .contains("Pattern type mismatch"));
+        assert!(error(&mut checker, pattern("Point", "x", Pattern::Wildcard), &Type::Bool)
+            .contains("got struct pattern 'Point'"));
+    }
+
+    fn struct_definition(name: &str, fields: Vec<(&str, Type)>) -> TypeDefinition {
+        TypeDefinition {
+            name: name.to_string(),
+            fields: fields
+                .into_iter()
+                .map(|(name, type_annotation)| Field { name: name.to_string(), type_annotation })
+                .collect(),
+        }
+    }
+
+    fn check_definitions(type_definitions: Vec<TypeDefinition>) -> Result<()> {
+        TypeChecker::new().check_ast(&AST { functions: vec![], type_definitions })
+    }
+
+    #[test]
+    fn test_recursive_type_definitions() {
+        let named = |name: &str| Type::Named(name.to_string());
+        let pointer = |name: &str| Type::Pointer(Box::new(named(name)));
+
+        // ポインタや配列を経由した自己参照は大きさが決まる
+        check_definitions(vec![struct_definition("Node", vec![("value", Type::Int), ("next", pointer("Node"))])]).unwrap();
+        check_definitions(vec![struct_definition("Tree", vec![("children", Type::Array(Box::new(named("Tree"))))])])
+            .unwrap();
+
+        let error = check_definitions(vec![struct_definition("T", vec![("inner", named("T"))])]).unwrap_err();
+        assert!(error.to_string().contains("Recursive type `T` has infinite size (T -> T)"), "{}", error);
+
+        // タプルの中や、別の型を経由した循環も無限の大きさになる
+        let error = check_definitions(vec![
+            struct_definition("A", vec![("b", Type::Tuple(vec![Type::Int, named("B")]))]),
+            struct_definition("B", vec![("a", named("A")), ("next", pointer("B"))]),
+        ])
+        .unwrap_err();
+        assert!(error.to_string().contains("(A -> B -> A)"), "{}", error);
+
+        let error = check_definitions(vec![struct_definition("Node", vec![("next", pointer("Missing"))])]).unwrap_err();
+        assert!(error.to_string().contains("Unknown type `Missing` in field `next` of `Node`"), "{}", error);
+
+        // `[T; N]` は要素を直に持つ
+        let fixed = Type::FixedArray(Box::new(named("Grid")), 2);
+        let error = check_definitions(vec![struct_definition("Grid", vec![("cells", fixed)])]).unwrap_err();
+        assert!(error.to_string().contains("(Grid -> Grid)"), "{}", error);
+    }
+
+    #[test]
+    fn test_struct_and_fixed_array_annotations() {
+        let check = |source: &str| TypeChecker::new().check_program(&Parser::new(Lexer::new(source)).parse_program().unwrap());
+        let point = "struct Point { x: int, y: int } ";
+        check(&format!("{}fn f(p: Point) -> Point {{ let q: Point = Point {{ x: p.y, y: p.x }}; return q; }}", point)).unwrap();
+        check(&format!("{}fn f() -> int {{ let ps: [Point; 1] = [Point {{ x: 1, y: 2 }}]; return ps[0].x; }}", point)).unwrap();
+        check(&format!("{}fn f() -> int {{ let g = fn(p: Point) -> int {{ return p.x; }}; return g(Point {{ x: 1, y: 2 }}); }}", point))
+            .unwrap();
+
+        check("fn sum(xs: [int]) -> int { return len(xs); } fn f() -> int { let xs: [int; 3] = [1, 2, 3]; return sum(xs) + xs[2]; }")
+            .unwrap();
+        check("fn first(xs: [int; 2]) -> int { return xs[0]; } fn f() -> int { return first([1, 2]); }").unwrap();
+        check("fn f() -> int { let mut xs: [int; 2] = [1, 2]; xs = [3, 4]; for x in xs { } return len(xs); }").unwrap();
+        check("fn f() -> [float] { let xs: [int; 0] = []; let mut ys: [float] = []; ys = []; return []; }").unwrap();
+
+        let error = |source: &str| check(source).unwrap_err().to_string();
+        assert!(error("fn f() -> void { let xs: [int; 3] = [1, 2]; }").contains("expected FixedArray"));
+        assert!(error("fn f(ys: [int]) -> void { let xs: [int; 2] = ys; }").contains("Type mismatch in let statement"));
+        assert!(error("fn first(xs: [int; 2]) -> int { return xs[0]; } fn f() -> int { return first([1]); }")
+            .contains("Argument type mismatch"));
+        assert!(error("fn f() -> void { let mut xs: [int; 2] = [1, 2]; push(xs, 3); }").contains("push() expects"));
+    }
+
+    #[test]
+    fn test_ranges_only_iterate_ints() {
+        check_source("fn f(n: int) -> int { for i in 0..n { return i; } return 0; }").unwrap();
+        let error = check_source("fn f() -> int { for i in 0..1.5 { return i; } return 0; }").unwrap_err();
+        assert!(error.to_string().contains("Range bounds must be int, got float"), "{}", error);
+        assert!(check_source("fn f() -> int { for i in 0..=3 { return i + 1.0; } return 0; }").is_err());
+        assert!(check_source("fn f() -> int { let r = 0..3; return 0; }").is_err());
+    }
+
+    #[test]
+    fn test_optional_values_must_be_checked_against_null() {
+        check_source("fn f() -> void { let x: int? = null; let y: int? = 1; let z = x == y; }").unwrap();
+        check_source("fn f(x: int?) -> int { if x != null { return x + 1; } return 0; }").unwrap();
+        check_source("fn f(x: int?) -> int { if null == x { return 0; } else { return x; } }").unwrap();
+
+        let error = check_source("fn f() -> int { return null + 1; }").unwrap_err();
+        assert_eq!(error.to_string(), "Type error: Operand is null; null can only be compared with == or !=");
+        let error = check_source("fn f(x: int?) -> int { return x + 1; }").unwrap_err();
+        assert_eq!(error.to_string(), "Type error: Operand of type int? may be null; check it against null first");
+        // 絞り込みは then 側のブロックの中だけ
+        assert!(check_source("fn f(x: int?) -> int { if x != null { let y = x; } return x; }").is_err());
+        assert!(check_source("fn f() -> void { let x: int = null; }").is_err());
+        assert!(check_source("fn f() -> void { let x: string? = 1; }").is_err());
+
+        let mut checker = TypeChecker::new();
+        checker.type_vars.insert("p".to_string(), Type::Optional(Box::new(Type::Named("P".to_string()))));
+        let field = Parser::new(Lexer::new("p.x")).parse_standalone_expression().unwrap();
+        let error = checker.check_expression(&field).unwrap_err();
+        assert!(error.to_string().contains("possibly-null value of type P?"), "{}", error);
+    }
+
+    #[test]
+    fn test_calls_with_too_many_arguments_are_rejected() {
+        let error = check_source("fn f(a: int) -> int { return a; } fn g() -> int { return f(1, \"junk\", 3.0); }")
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Type error: Wrong number of arguments");
+        assert!(check_source("fn g() -> int { let f = fn(a: int) -> int { return a; }; return f(1, 2); }").is_err());
+        check_source("fn g() -> int { let f = fn(a: int) -> int { return a; }; return f(1); }").unwrap();
+    }
+
+    #[test]
+    fn test_null_inside_tuples_and_arrays() {
+        check_source("fn g() -> (int, int?) { return (1, null); }").unwrap();
+        check_source("fn f() -> void { let t: (int, int?) = (1, null); let u: (int, int?) = (1, 2); }").unwrap();
+        check_source("fn g() -> [int?] { return [null]; } fn h() -> [int?] { return [1, null]; }").unwrap();
+        check_source("fn f() -> void { let a: [int?] = [null, 1]; let b: [int?] = [1, 2]; }").unwrap();
+        check_source("fn take(t: (int, int?)) -> int { return 0; } fn f() -> int { return take((1, null)); }").unwrap();
+
+        assert!(check_source("fn g() -> (int, int) { return (1, null); }").is_err());
+        assert!(check_source("fn g() -> [int] { return [1, null]; }").is_err());
+        assert!(check_source("fn f() -> void { let a = [1, \"a\"]; }").is_err());
+    }
+
+    #[test]
+    fn test_if_let_binds_in_then_block() {
+        check_source(
+            "fn f(t: (int, string)) -> int { if let (a, b) = t { let s: string = b; return a; } else { return 0; } }",
+        )
+        .unwrap();
+        check_source("fn f(x: int?) -> int { if let null = x { return 0; } while let (n, _) = (1, 2) { return n; } return 1; }")
+            .unwrap();
+
+        // 束縛はブロックの外には見えない
+        let error = check_source("fn f(t: (int, int)) -> int { if let (a, b) = t { } return a; }").unwrap_err();
+        assert!(error.to_string().contains("Undefined variable: a"), "{}", error);
+        let error = check_source("fn f(t: (int, int)) -> int { if let (a, b, c) = t { } return 0; }").unwrap_err();
+        assert!(error.to_string().contains("Tuple pattern length mismatch"), "{}", error);
+        assert!(check_source("fn f(t: (int, int)) -> int { if let (a, b) = t { return b + \"s\"; } return 0; }").is_err());
+        // 両方の腕が戻れば、その後ろに `return` はいらない
+        check_source("fn f(t: (int, int)) -> int { if let (a, _) = t { return a; } else { return 0; } }").unwrap();
+    }
+
+    fn struct_item(name: &str, type_params: &[&str], fields: &[(&str, Type)]) -> Item {
+        Item::Struct(Struct {
+            name: name.to_string(),
+            type_params: type_params.iter().map(|param| param.to_string()).collect(),
+            fields: fields
+                .iter()
+                .map(|(name, type_)| StructField { name: name.to_string(), type_: type_.clone(), priority: None })
+                .collect(),
+        })
+    }
+
+    fn function_items(source: &str) -> Vec<Item> {
+        let ast = Parser::new(Lexer::new(source)).parse().unwrap();
+        ast.functions.into_iter().map(Item::Function).collect()
+    }
+
+    #[test]
+    fn test_check_program_structs() {
+        let named = |name: &str| Type::Named(name.to_string());
+        let point = struct_item("Point", &[], &[("x", Type::Int), ("y", Type::Int)]);
+        let mut items = vec![
+            struct_item("Line", &[], &[("from", named("Point")), ("to", named("Point"))]),
+            point.clone(),
+            struct_item("Boxed", &["T"], &[("value", named("T")), ("next", Type::Pointer(Box::new(named("Boxed"))))]),
+        ];
+        items.extend(function_items("fn length(l: Line) -> int { return l.to.x - l.from.x; }"));
+        TypeChecker::new().check_program(&Program { items }).unwrap();
+
+        let check = |items: Vec<Item>| TypeChecker::new().check_program(&Program { items }).unwrap_err().to_string();
+        let error = check(vec![struct_item("Line", &[], &[("from", named("Point"))])]);
+        assert!(error.contains("Unknown type `Point` in field `from` of `Line`"), "{}", error);
+        let error = check(vec![struct_item("Node", &[], &[("next", named("Node"))])]);
+        assert!(error.contains("Recursive type `Node`"), "{}", error);
+        let error = check(vec![point.clone(), point.clone()]);
+        assert!(error.contains("Type `Point` is defined more than once"), "{}", error);
+        let error = check(vec![struct_item("P", &[], &[("x", Type::Int), ("x", Type::Float)])]);
+        assert!(error.contains("Field `x` is defined more than once in `P`"), "{}", error);
+
+        // 関数の本体も検査される
+        let mut items = vec![point];
+        items.extend(function_items("fn f(p: Point) -> int { return p.z; }"));
+        assert!(TypeChecker::new().check_program(&Program { items }).is_err());
+
+        // ソースの `struct` も同じように検査される
+        let check_source = |source: &str| TypeChecker::new().check_program(&Parser::new(Lexer::new(source)).parse_program()?);
+        check_source("struct P { x: int @2 } fn f(p: P) -> int { return p.x; }").unwrap();
+        assert!(check_source("struct P { x: Q @2 }").is_err());
+    }
+
+    #[test]
+    fn test_check_program_modules_and_traits() {
+        let shape = |return_type: Type| {
+            Item::Trait(Trait {
+                name: "Shape".to_string(),
+                methods: vec![TraitMethod {
+                    name: "area".to_string(),
+                    params: vec![Parameter { name: "self".to_string(), type_annotation: Type::Named("Self".to_string()), mutable: false }],
+                    return_type,
+                }],
+            })
+        };
+        // モジュールの中の構造体は外側の関数からも使える
+        let geometry = Item::Module(Module {
+            name: "geometry".to_string(),
+            items: vec![
+                struct_item("Point", &[], &[("x", Type::Int)]),
+                Item::Module(Module { name: "inner".to_string(), items: function_items("fn origin() -> int { return 0; }") }),
+                shape(Type::Named("Point".to_string())),
+            ],
+        });
+        let mut items = vec![geometry];
+        items.extend(function_items("fn get(p: Point) -> int { return p.x + origin(); }"));
+        TypeChecker::new().check_program(&Program { items }).unwrap();
+
+        let error = TypeChecker::new()
+            .check_program(&Program { items: vec![shape(Type::Named("Area".to_string()))] })
+            .unwrap_err();
+        assert!(error.to_string().contains("Unknown type `Area` in method `area` of trait `Shape`"), "{}", error);
+
+        // `AST` から変換したものも同じように検査できる
+        let ast = Parser::new(Lexer::new("fn f() -> int { return 1; }")).parse().unwrap();
+        TypeChecker::new().check_program(&Program::from(ast)).unwrap();
+    }
+
+    #[test]
+    fn test_trait_method_signatures_are_recorded() {
+        let parse = |source: &str| Parser::new(Lexer::new(source)).parse_program().unwrap();
+        let mut checker = TypeChecker::new();
+        checker
+            .check_program(&parse("struct Point { x: int } trait Measure { fn distance(self: Self, to: Point) -> float; }"))
+            .unwrap();
+        assert_eq!(
+            checker.trait_method("Measure", "distance"),
+            Some(&Type::Function {
+                params: vec![Type::Named("Self".to_string()), Type::Struct("Point".to_string())],
+                return_type: Box::new(Type::Float),
+                priority: None,
+            })
+        );
+        assert_eq!(checker.trait_method("Measure", "length"), None);
+        assert_eq!(checker.trait_method("Shape", "distance"), None);
+
+        let error = |source: &str| TypeChecker::new().check_program(&parse(source)).unwrap_err().to_string();
+        assert!(error("trait T { fn f(p: Point) -> int; }").contains("Unknown type `Point` in method `f` of trait `T`"));
+        assert!(error("trait T { fn f() -> int; fn f() -> float; }").contains("Method `f` is declared more than once"));
+        assert!(error("trait T { fn f(a: int, a: int) -> int; }").contains("Duplicate parameter `a`"));
+        assert!(error("trait T { } module m { trait T { } }").contains("Trait `T` is defined more than once"));
+    }
+}
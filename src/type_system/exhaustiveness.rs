@@ -0,0 +1,232 @@
+use crate::ast::*;
+use crate::type_system::Type;
+use std::collections::HashMap;
+
+// `match` の腕の網羅性と到達可能性を調べる。パターンを構築子とワイルドカードの木に直し、
+// 「前の腕がどれも受け付けない値を、このパターンが受け付けるか」(有用性) で判定する
+
+#[derive(Debug, Clone, PartialEq)]
+enum Constructor {
+    Bool(bool),
+    Tuple,
+    Struct(String),
+    // 整数・文字列などのリテラル。取り得る値を列挙できないので、網羅するにはワイルドカードが要る
+    Literal(String),
+}
+
+#[derive(Debug, Clone)]
+enum Pat {
+    Wild,
+    Constructor(Constructor, Vec<Pat>),
+}
+
+#[derive(Debug, Default)]
+pub(super) struct MatchCoverage {
+    // 前の腕だけですべて受け付けられてしまう腕の番号
+    pub unreachable: Vec<usize>,
+    // どの腕にも当てはまらない値の例
+    pub missing: Option<String>,
+}
+
+pub(super) fn check_match(
+    type_definitions: &HashMap<String, TypeDefinition>,
+    value_type: &Type,
+    arms: &[MatchArm],
+) -> MatchCoverage {
+    let space = PatternSpace { type_definitions };
+    let mut rows: Vec<Vec<Pat>> = Vec::new();
+    let mut coverage = MatchCoverage::default();
+    for (i, arm) in arms.iter().enumerate() {
+        let row = vec![space.lower(&arm.pattern, value_type)];
+        if !space.is_useful(&rows, &row, std::slice::from_ref(value_type)) {
+            coverage.unreachable.push(i);
+        }
+        rows.push(row);
+    }
+    coverage.missing = space
+        .missing(&rows, std::slice::from_ref(value_type))
+        .map(|mut witness| witness.remove(0));
+    coverage
+}
+
+struct PatternSpace<'a> {
+    type_definitions: &'a HashMap<String, TypeDefinition>,
+}
+
+impl PatternSpace<'_> {
+    fn lower(&self, pattern: &Pattern, type_: &Type) -> Pat {
+        match pattern {
+            Pattern::Wildcard | Pattern::Identifier(_) => Pat::Wild,
+            Pattern::Literal(Literal::Bool(b)) => Pat::Constructor(Constructor::Bool(*b), Vec::new()),
+            Pattern::Literal(lit) => Pat::Constructor(Constructor::Literal(lit.to_string()), Vec::new()),
+            Pattern::Tuple(patterns) => {
+                let types = self.field_types(&Constructor::Tuple, type_);
+                let fields = patterns
+                    .iter()
+                    .enumerate()
+                    .map(|(i, pattern)| self.lower(pattern, types.get(i).unwrap_or(&Type::Unit)))
+                    .collect();
+                Pat::Constructor(Constructor::Tuple, fields)
+            }
+            // 書かれていないフィールドは何でもよい
+            Pattern::Struct { name, fields } => {
+                let definition = self.type_definitions.get(name);
+                let fields = definition
+                    .map(|definition| definition.fields.as_slice())
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|field| {
+                        fields
+                            .iter()
+                            .find(|pattern| pattern.name == field.name)
+                            .map_or(Pat::Wild, |pattern| self.lower(&pattern.pattern, &field.type_annotation))
+                    })
+                    .collect();
+                Pat::Constructor(Constructor::Struct(name.clone()), fields)
+            }
+        }
+    }
+
+    // 型の値をすべて表す構築子の一覧。列挙できない型は `None`
+    fn constructors(&self, type_: &Type) -> Option<Vec<Constructor>> {
+        match type_ {
+            Type::Bool => Some(vec![Constructor::Bool(true), Constructor::Bool(false)]),
+            Type::Tuple(_) => Some(vec![Constructor::Tuple]),
+            Type::Struct(name) if self.type_definitions.contains_key(name) => {
+                Some(vec![Constructor::Struct(name.clone())])
+            }
+            _ => None,
+        }
+    }
+
+    fn field_types(&self, constructor: &Constructor, type_: &Type) -> Vec<Type> {
+        match (constructor, type_) {
+            (Constructor::Tuple, Type::Tuple(types)) => types.clone(),
+            (Constructor::Struct(name), _) => self
+                .type_definitions
+                .get(name)
+                .map(|definition| {
+                    definition
+                        .fields
+                        .iter()
+                        .map(|field| field.type_annotation.resolve_structs(&|name| self.type_definitions.contains_key(name)))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            _ => Vec::new(),
+        }
+    }
+
+    // 先頭の列が `constructor` に当てはまる行だけを残し、その中身を列に展開する
+    fn specialize(&self, rows: &[Vec<Pat>], constructor: &Constructor, arity: usize) -> Vec<Vec<Pat>> {
+        rows.iter()
+            .filter_map(|row| {
+                let mut fields = match &row[0] {
+                    Pat::Wild => vec![Pat::Wild; arity],
+                    Pat::Constructor(head, fields) if head == constructor => fields.clone(),
+                    Pat::Constructor(..) => return None,
+                };
+                fields.extend_from_slice(&row[1..]);
+                Some(fields)
+            })
+            .collect()
+    }
+
+    // 先頭の列がワイルドカードの行だけを残す
+    fn default_rows(&self, rows: &[Vec<Pat>]) -> Vec<Vec<Pat>> {
+        rows.iter()
+            .filter(|row| matches!(row[0], Pat::Wild))
+            .map(|row| row[1..].to_vec())
+            .collect()
+    }
+
+    // 先頭の列に現れる構築子で、型の構築子がすべて揃っているならその一覧を返す
+    fn complete_signature(&self, rows: &[Vec<Pat>], type_: &Type) -> Option<Vec<Constructor>> {
+        let constructors = self.constructors(type_)?;
+        let all_used = constructors.iter().all(|constructor| {
+            rows.iter().any(|row| matches!(&row[0], Pat::Constructor(head, _) if head == constructor))
+        });
+        all_used.then_some(constructors)
+    }
+
+    fn is_useful(&self, rows: &[Vec<Pat>], row: &[Pat], types: &[Type]) -> bool {
+        let Some((head, rest)) = row.split_first() else {
+            return rows.is_empty();
+        };
+        match head {
+            Pat::Constructor(constructor, fields) => {
+                let field_types = self.field_types(constructor, &types[0]);
+                let mut row = fields.clone();
+                row.extend_from_slice(rest);
+                self.is_useful(
+                    &self.specialize(rows, constructor, fields.len()),
+                    &row,
+                    &[field_types.as_slice(), &types[1..]].concat(),
+                )
+            }
+            Pat::Wild => match self.complete_signature(rows, &types[0]) {
+                Some(constructors) => constructors.iter().any(|constructor| {
+                    let field_types = self.field_types(constructor, &types[0]);
+                    let mut row = vec![Pat::Wild; field_types.len()];
+                    row.extend_from_slice(rest);
+                    self.is_useful(
+                        &self.specialize(rows, constructor, field_types.len()),
+                        &row,
+                        &[field_types.as_slice(), &types[1..]].concat(),
+                    )
+                }),
+                None => self.is_useful(&self.default_rows(rows), rest, &types[1..]),
+            },
+        }
+    }
+
+    // どの行にも当てはまらない値があれば、列ごとにその例を書いたものを返す
+    fn missing(&self, rows: &[Vec<Pat>], types: &[Type]) -> Option<Vec<String>> {
+        let Some((type_, rest)) = types.split_first() else {
+            return rows.is_empty().then(Vec::new);
+        };
+        if let Some(constructors) = self.complete_signature(rows, type_) {
+            return constructors.iter().find_map(|constructor| {
+                let field_types = self.field_types(constructor, type_);
+                let arity = field_types.len();
+                let mut witness = self.missing(
+                    &self.specialize(rows, constructor, arity),
+                    &[field_types.as_slice(), rest].concat(),
+                )?;
+                let fields: Vec<String> = witness.drain(..arity).collect();
+                witness.insert(0, self.render(constructor, fields));
+                Some(witness)
+            });
+        }
+        let mut witness = self.missing(&self.default_rows(rows), rest)?;
+        // 先頭の列に現れない構築子があればそれを例にする
+        let unused = self.constructors(type_).and_then(|constructors| {
+            constructors.into_iter().find(|constructor| {
+                !rows.iter().any(|row| matches!(&row[0], Pat::Constructor(head, _) if head == constructor))
+            })
+        });
+        let head = match unused {
+            Some(constructor) => {
+                let fields = vec!["_".to_string(); self.field_types(&constructor, type_).len()];
+                self.render(&constructor, fields)
+            }
+            None => "_".to_string(),
+        };
+        witness.insert(0, head);
+        Some(witness)
+    }
+
+    fn render(&self, constructor: &Constructor, fields: Vec<String>) -> String {
+        match constructor {
+            Constructor::Bool(b) => b.to_string(),
+            Constructor::Literal(lit) => lit.clone(),
+            Constructor::Tuple => format!("({})", fields.join(", ")),
+            Constructor::Struct(name) => {
+                let names = self.type_definitions[name].fields.iter().map(|field| field.name.as_str());
+                let fields: Vec<String> =
+                    names.zip(fields).map(|(name, pattern)| format!("{}: {}", name, pattern)).collect();
+                format!("{} {{ {} }}", name, fields.join(", "))
+            }
+        }
+    }
+}
@@ -1,68 +1,63 @@
 use crate::ast::*;
 use crate::error::{Result, SlangError};
+use crate::type_system::checker::{check_complex_operation, check_linear_algebra, check_non_null_operands};
 use crate::type_system::Type;
 use std::collections::HashMap;
 
 #[derive(Debug, Clone)]
 pub struct TypeInference {
     type_vars: HashMap<String, Type>,
-    constraints: Vec<TypeConstraint>,
     type_definitions: HashMap<String, TypeDefinition>,
-}
-
-#[derive(Debug, Clone)]
-pub struct TypeConstraint {
-    pub left: Type,
-    pub right: Type,
+    // 型変数 `Type::Var(n)` に束縛された型
+    substitution: HashMap<u32, Type>,
+    next_type_var: u32,
+    // 推論中の関数の戻り値の型
+    current_return_type: Option<Type>,
+    // 関数名ごとの (型パラメータ, 関数の型)
+    function_signatures: HashMap<String, (Vec<String>, Type)>,
 }
 
 impl TypeInference {
     pub fn new() -> Self {
         Self {
             type_vars: HashMap::new(),
-            constraints: Vec::new(),
             type_definitions: HashMap::new(),
+            substitution: HashMap::new(),
+            next_type_var: 0,
+            current_return_type: None,
+            function_signatures: HashMap::new(),
         }
     }
 
     pub fn infer_types(&mut self, ast: &AST) -> Result<()> {
-        for function in &ast.functions {
-            self.infer_function(function)?;
-        }
         for type_def in &ast.type_definitions {
             self.infer_type_definition(type_def)?;
         }
+        for function in &ast.functions {
+            self.function_signatures.insert(function.name.clone(), (function.type_params.clone(), function_type(function)));
+        }
+        for function in &ast.functions {
+            self.infer_function(function)?;
+        }
         self.solve_constraints()
     }
 
     fn infer_function(&mut self, function: &Function) -> Result<()> {
-        // 関数の型を推論
-        let param_types: Vec<Type> = function.parameters
-            .iter()
-            .map(|p| p.type_annotation.clone())
-            .collect();
-
-        let return_type = function.return_type.clone();
-        let function_type = Type::Function {
-            params: param_types,
-            return_type: Box::new(return_type),
-            priority: Some(function.priority as u32),
-        };
+        // パラメータの型を登録
+        for param in &function.parameters {
+            self.type_vars.insert(param.name.clone(), param.type_annotation.clone());
+        }
 
         // 関数本体の型を推論
-        self.infer_block(&function.body)?;
-
-        Ok(())
+        let enclosing = self.current_return_type.replace(function.return_type.clone());
+        let result = self.infer_block(&function.body);
+        self.current_return_type = enclosing;
+        result
     }
 
     fn infer_type_definition(&mut self, type_def: &TypeDefinition) -> Result<()> {
         // 型定義の型を推論
         let type_name = type_def.name.clone();
-        let type_fields: Vec<Type> = type_def.fields
-            .iter()
-            .map(|f| f.type_annotation.clone())
-            .collect();
-
         let struct_type = Type::Named(type_name);
         self.type_vars.insert(type_def.name.clone(), struct_type);
         self.type_definitions.insert(type_def.name.clone(), type_def.clone());
@@ -80,17 +75,23 @@ impl TypeInference {
     fn infer_statement(&mut self, statement: &Statement) -> Result<()> {
         match statement {
             Statement::Let(stmt) => {
-                let value_type = self.infer_expression(&stmt.value)?;
+                let mut value_type = self.infer_expression(&stmt.value)?;
                 if let Some(annotated_type) = &stmt.type_annotation {
-                    self.add_constraint(value_type.clone(), annotated_type.clone())?;
+                    value_type = self.widen_to_optional(value_type, annotated_type);
                 }
-                self.type_vars.insert(stmt.name.clone(), value_type);
+                let variable_type = self.fresh_type_var();
+                self.add_constraint(variable_type.clone(), value_type)?;
+                if let Some(annotated_type) = &stmt.type_annotation {
+                    self.add_constraint(variable_type.clone(), annotated_type.clone())?;
+                }
+                self.type_vars.insert(stmt.name.clone(), variable_type);
             }
             Statement::Return(stmt) => {
                 if let Some(value) = &stmt.value {
                     let value_type = self.infer_expression(value)?;
                     // 戻り値の型を関数の戻り値の型と一致させる
                     if let Some(return_type) = self.get_current_return_type() {
+                        let value_type = self.widen_to_optional(value_type, &return_type);
                         self.add_constraint(value_type, return_type)?;
                     }
                 }
@@ -103,19 +104,38 @@ impl TypeInference {
                     self.infer_block(else_block)?;
                 }
             }
+            Statement::IfLet(stmt) => {
+                let value_type = self.infer_expression(&stmt.expression)?;
+                self.infer_pattern_scope(&stmt.pattern, &value_type, &stmt.then_block)?;
+                if let Some(else_block) = &stmt.else_block {
+                    self.infer_block(else_block)?;
+                }
+            }
             Statement::While(stmt) => {
                 let condition_type = self.infer_expression(&stmt.condition)?;
                 self.add_constraint(condition_type, Type::Bool)?;
                 self.infer_block(&stmt.body)?;
             }
+            Statement::WhileLet(stmt) => {
+                let value_type = self.infer_expression(&stmt.expression)?;
+                self.infer_pattern_scope(&stmt.pattern, &value_type, &stmt.body)?;
+            }
             Statement::For(stmt) => {
-                let iterator_type = self.infer_expression(&stmt.iterator)?;
+                let iterator_type = match stmt.iterator.as_ref() {
+                    Expression::Range { start, end, .. } => {
+                        for bound in [start, end] {
+                            let bound_type = self.infer_expression(bound)?;
+                            self.add_constraint(bound_type, Type::Int)?;
+                        }
+                        Type::Array(Box::new(Type::Int))
+                    }
+                    iterator => self.infer_expression(iterator)?,
+                };
                 // イテレータの型は配列またはイテレータ型である必要がある
-                if let Type::Array(element_type) = iterator_type {
-                    self.type_vars.insert(stmt.variable.clone(), *element_type);
-                } else {
-                    return Err(SlangError::Type("Iterator must be an array".to_string()));
-                }
+                let element_type = self.fresh_type_var();
+                self.add_constraint(iterator_type, Type::Array(Box::new(element_type.clone())))
+                    .map_err(|_| SlangError::Type("Iterator must be an array".to_string()))?;
+                self.type_vars.insert(stmt.variable.clone(), element_type);
                 self.infer_block(&stmt.body)?;
             }
             Statement::Match(stmt) => {
@@ -125,6 +145,14 @@ impl TypeInference {
                     self.infer_block(&arm.body)?;
                 }
             }
+            Statement::Break | Statement::Continue => {}
+            Statement::Transfer(stmt) => {
+                for name in [&stmt.from, &stmt.to] {
+                    if !self.type_vars.contains_key(name) {
+                        return Err(SlangError::Type(format!("Undefined variable: {}", name)));
+                    }
+                }
+            }
             Statement::Expression(expr) => {
                 self.infer_expression(expr)?;
             }
@@ -146,11 +174,14 @@ impl TypeInference {
                 self.infer_binary_operation(&op.op, left_type, right_type)
             }
             Expression::UnaryOp(op) => {
-                let expr_type = self.infer_expression(&op.right)?;
+                let expr_type = self.infer_expression(&op.expr)?;
                 self.infer_unary_operation(&op.op, expr_type)
             }
             Expression::Call(call) => {
-                let function_type = self.infer_expression(&Expression::Identifier(call.function.clone()))?;
+                let function_type = match self.function_signatures.get(&call.function).cloned() {
+                    Some((type_params, function_type)) => self.instantiate(&type_params, &function_type),
+                    None => self.infer_expression(&Expression::Identifier(call.function.clone()))?,
+                };
                 let arg_types: Vec<Type> = call.arguments
                     .iter()
                     .map(|arg| self.infer_expression(arg))
@@ -165,56 +196,204 @@ impl TypeInference {
                 self.add_constraint(value_type, target_type)?;
                 Ok(Type::Unit)
             }
+            Expression::Array(elements) => {
+                // 空の `[]` の要素は新しい型変数にし、使われ方から決める
+                let mut element_types = elements.iter();
+                let mut element_type = match element_types.next() {
+                    Some(first) => self.infer_expression(first)?,
+                    None => self.fresh_type_var(),
+                };
+                for element in element_types {
+                    let other = self.infer_expression(element)?;
+                    // `[1, null]` のように `null` が混ざっていれば要素の型は `int?` になる
+                    let other = self.widen_to_optional(other, &self.resolve(&element_type));
+                    element_type = self.widen_to_optional(element_type, &self.resolve(&other));
+                    self.add_constraint(other, element_type.clone())?;
+                }
+                Ok(Type::Array(Box::new(element_type)))
+            }
+            Expression::Tuple(elements) => {
+                let element_types = elements
+                    .iter()
+                    .map(|element| self.infer_expression(element))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(Type::Tuple(element_types))
+            }
+            Expression::Range { .. } => Err(SlangError::Type(
+                "Ranges can only be used as the iterator of a `for` loop".to_string(),
+            )),
+            // 変換できるかどうかは `TypeChecker` が調べる
+            Expression::Cast { expr, target } => {
+                self.infer_expression(expr)?;
+                Ok(target.clone())
+            }
+            // 戻り値の型を省略したラムダは型変数を戻り値の型にして本体の `return` から決める
+            Expression::Lambda { params, return_type, body } => {
+                let enclosing_vars = self.type_vars.clone();
+                for param in params {
+                    self.type_vars.insert(param.name.clone(), param.type_annotation.clone());
+                }
+                let return_type = match return_type {
+                    Some(return_type) => return_type.clone(),
+                    None => self.fresh_type_var(),
+                };
+                let enclosing = self.current_return_type.replace(return_type.clone());
+                let result = self.infer_block(body);
+                self.current_return_type = enclosing;
+                self.type_vars = enclosing_vars;
+                result?;
+                Ok(Type::Function {
+                    params: params.iter().map(|param| param.type_annotation.clone()).collect(),
+                    return_type: Box::new(return_type),
+                    priority: None,
+                })
+            }
+            Expression::Index { array, index } => {
+                let array_type = self.infer_expression(array)?;
+                let index_type = self.infer_expression(index)?;
+                self.add_constraint(index_type, Type::Int)?;
+                match self.resolve(&array_type) {
+                    Type::Array(element_type) | Type::FixedArray(element_type, _) | Type::Vector(_, element_type) => {
+                        Ok(*element_type)
+                    }
+                    Type::Var(_) => {
+                        let element_type = self.fresh_type_var();
+                        self.add_constraint(array_type, Type::Array(Box::new(element_type.clone())))?;
+                        Ok(element_type)
+                    }
+                    other => Err(SlangError::Type(format!("Cannot index into {}", other))),
+                }
+            }
+            Expression::FieldAccess { base, field } => {
+                let base_type = self.infer_expression(base)?;
+                match self.resolve(&base_type) {
+                    Type::Named(type_name) => self.get_field_types(&type_name)?
+                        .remove(field)
+                        .ok_or_else(|| SlangError::Type(format!(
+                            "Field '{}' not found in struct '{}'",
+                            field, type_name
+                        ))),
+                    other => Err(SlangError::Type(format!(
+                        "Cannot access field '{}' on non-struct type {}",
+                        field, other
+                    ))),
+                }
+            }
+            Expression::MethodCall { receiver, method, arguments } => {
+                let receiver_type = self.infer_expression(receiver)?;
+                let receiver_type = self.resolve(&receiver_type);
+                let method_type = receiver_type.get_method_signature(method)
+                    .ok_or_else(|| SlangError::Type(format!(
+                        "No method '{}' on type {:?}",
+                        method, receiver_type
+                    )))?;
+                let arg_types = arguments
+                    .iter()
+                    .map(|arg| self.infer_expression(arg))
+                    .collect::<Result<Vec<_>>>()?;
+                self.infer_function_call(method_type, arg_types)
+            }
+            Expression::StructLiteral { name, fields } => {
+                let field_types = self.get_field_types(name)?;
+                for (field, value) in fields {
+                    let field_type = field_types.get(field)
+                        .cloned()
+                        .ok_or_else(|| SlangError::Type(format!(
+                            "Field '{}' not found in struct '{}'",
+                            field, name
+                        )))?;
+                    let value_type = self.infer_expression(value)?;
+                    self.add_constraint(value_type, field_type)?;
+                }
+                Ok(Type::Named(name.clone()))
+            }
         }
     }
 
-    fn infer_literal(&self, literal: &Literal) -> Type {
+    fn infer_literal(&mut self, literal: &Literal) -> Type {
         match literal {
             Literal::Int(_) => Type::Int,
             Literal::Float(_) => Type::Float,
+            Literal::Complex(_, _) => Type::Complex(Box::new(Type::Float)),
             Literal::Bool(_) => Type::Bool,
+            Literal::Char(_) => Type::Char,
             Literal::String(_) => Type::String,
-            Literal::Null => Type::Unit,
+            Literal::Null => Type::Optional(Box::new(self.fresh_type_var())),
+        }
+    }
+
+    // `T?` が期待される所に `null` でない `T` の値を渡すときは `T?` として扱う
+    fn widen_to_optional(&self, value_type: Type, expected: &Type) -> Type {
+        match self.resolve(&value_type) {
+            Type::Optional(_) | Type::Var(_) => value_type,
+            resolved if expected.is_optional() => Type::Optional(Box::new(resolved)),
+            _ => value_type,
         }
     }
 
+    // ベクトル・行列・複素数の演算は型検査と同じ規則で決める
     fn infer_binary_operation(&mut self, op: &BinaryOperator, left: Type, right: Type) -> Result<Type> {
+        let left = self.resolve(&left);
+        let right = self.resolve(&right);
+        check_non_null_operands(op, &left, &right)?;
+        if let Some(result) = check_linear_algebra(op, &left, &right) {
+            return result;
+        }
+        if left.is_complex() || right.is_complex() {
+            return check_complex_operation(op, &left, &right);
+        }
         match op {
-            BinaryOperator::Add | BinaryOperator::Sub | BinaryOperator::Mul | BinaryOperator::Div => {
-                if left.is_numeric() && right.is_numeric() {
-                    if left == Type::Float || right == Type::Float {
-                        Ok(Type::Float)
-                    } else {
-                        Ok(Type::Int)
-                    }
-                } else {
-                    Err(SlangError::Type("Numeric operation requires numeric operands".to_string()))
-                }
+            BinaryOperator::Add | BinaryOperator::Sub | BinaryOperator::Mul | BinaryOperator::Div |
+            BinaryOperator::Mod | BinaryOperator::Divide | BinaryOperator::Modulo => {
+                self.infer_numeric_operands(left, right)
             }
-            BinaryOperator::Eq | BinaryOperator::Neq => {
-                self.add_constraint(left.clone(), right.clone())?;
+            BinaryOperator::Eq | BinaryOperator::Neq | BinaryOperator::Equals | BinaryOperator::NotEquals => {
+                self.add_constraint(left, right)?;
                 Ok(Type::Bool)
             }
-            BinaryOperator::Lt | BinaryOperator::Lte | BinaryOperator::Gt | BinaryOperator::Gte => {
-                if left.is_numeric() && right.is_numeric() {
-                    Ok(Type::Bool)
+            BinaryOperator::Lt | BinaryOperator::Lte | BinaryOperator::Gt | BinaryOperator::Gte |
+            BinaryOperator::LessThan | BinaryOperator::LessThanEquals | BinaryOperator::GreaterThan | BinaryOperator::GreaterThanEquals => {
+                // 文字列どうしは辞書順で比べる
+                if left == Type::String || right == Type::String {
+                    self.add_constraint(left, Type::String)?;
+                    self.add_constraint(right, Type::String)?;
                 } else {
-                    Err(SlangError::Type("Comparison requires numeric operands".to_string()))
+                    self.infer_numeric_operands(left, right)?;
                 }
+                Ok(Type::Bool)
             }
             BinaryOperator::And | BinaryOperator::Or => {
-                self.add_constraint(left.clone(), Type::Bool)?;
-                self.add_constraint(right.clone(), Type::Bool)?;
+                self.add_constraint(left, Type::Bool)?;
+                self.add_constraint(right, Type::Bool)?;
                 Ok(Type::Bool)
             }
-            _ => todo!(),
+        }
+    }
+
+    // 数値演算の結果の型。片方が未解決の型変数ならもう片方の型に揃える
+    fn infer_numeric_operands(&mut self, left: Type, right: Type) -> Result<Type> {
+        if left.is_numeric() && right.is_numeric() {
+            if left == Type::Float || right == Type::Float {
+                Ok(Type::Float)
+            } else {
+                Ok(Type::Int)
+            }
+        } else if matches!(left, Type::Var(_)) || matches!(right, Type::Var(_)) {
+            self.add_constraint(left.clone(), right)?;
+            match self.resolve(&left) {
+                result @ (Type::Int | Type::Float | Type::Var(_)) => Ok(result),
+                _ => Err(SlangError::Type("Numeric operation requires numeric operands".to_string())),
+            }
+        } else {
+            Err(SlangError::Type("Numeric operation requires numeric operands".to_string()))
         }
     }
 
     fn infer_unary_operation(&mut self, op: &UnaryOperator, expr: Type) -> Result<Type> {
         match op {
-            UnaryOperator::Neg => {
-                if expr.is_numeric() {
+            UnaryOperator::Neg | UnaryOperator::Negate => {
+                let expr = self.resolve(&expr);
+                if expr.is_numeric() || expr.is_complex() || matches!(expr, Type::Var(_)) {
                     Ok(expr)
                 } else {
                     Err(SlangError::Type("Negation requires numeric operand".to_string()))
@@ -224,7 +403,6 @@ impl TypeInference {
                 self.add_constraint(expr, Type::Bool)?;
                 Ok(Type::Bool)
             }
-            _ => todo!(),
         }
     }
 
@@ -242,6 +420,14 @@ impl TypeInference {
         }
     }
 
+    // パターンで束縛した変数は `block` の中だけで見える
+    fn infer_pattern_scope(&mut self, pattern: &Pattern, value_type: &Type, block: &Block) -> Result<()> {
+        let enclosing_vars = self.type_vars.clone();
+        let result = self.infer_pattern(pattern, value_type).and_then(|()| self.infer_block(block));
+        self.type_vars = enclosing_vars;
+        result
+    }
+
     fn infer_pattern(&mut self, pattern: &Pattern, value_type: &Type) -> Result<()> {
         match pattern {
             Pattern::Identifier(name) => {
@@ -297,80 +483,405 @@ impl TypeInference {
         }
     }
 
+    // 呼び出しごとに型パラメータを新しい型変数に置き換える
+    fn instantiate(&mut self, type_params: &[String], function_type: &Type) -> Type {
+        let bindings: HashMap<String, Type> = type_params
+            .iter()
+            .map(|name| (name.clone(), self.fresh_type_var()))
+            .collect();
+        function_type.substitute(&bindings)
+    }
+
+    fn fresh_type_var(&mut self) -> Type {
+        let id = self.next_type_var;
+        self.next_type_var += 1;
+        Type::Var(id)
+    }
+
     fn add_constraint(&mut self, left: Type, right: Type) -> Result<()> {
-        self.constraints.push(TypeConstraint { left, right });
-        Ok(())
+        self.unify(&left, &right)
     }
 
+    // 推論が終わった変数の型から型変数を取り除く
     fn solve_constraints(&mut self) -> Result<()> {
-        let mut constraints = std::mem::take(&mut self.constraints);
-        while !constraints.is_empty() {
-            let mut new_constraints = Vec::new();
-            for constraint in constraints {
-                if let Some(new_constraint) = self.unify(&constraint.left, &constraint.right)? {
-                    new_constraints.push(new_constraint);
-                }
+        let resolved: HashMap<String, Type> = self.type_vars
+            .iter()
+            .map(|(name, type_)| (name.clone(), self.resolve(type_)))
+            .collect();
+        self.type_vars = resolved;
+        Ok(())
+    }
+
+    // 代入を最後まで適用した型を返す
+    fn resolve(&self, type_: &Type) -> Type {
+        match type_ {
+            Type::Var(id) => match self.substitution.get(id) {
+                Some(bound) => self.resolve(bound),
+                None => type_.clone(),
+            },
+            Type::Array(t) => Type::Array(Box::new(self.resolve(t))),
+            Type::FixedArray(t, len) => Type::FixedArray(Box::new(self.resolve(t)), *len),
+            Type::Tuple(types) => Type::Tuple(types.iter().map(|t| self.resolve(t)).collect()),
+            Type::Vector(dim, t) => Type::Vector(*dim, Box::new(self.resolve(t))),
+            Type::Matrix(rows, cols, t) => Type::Matrix(*rows, *cols, Box::new(self.resolve(t))),
+            Type::Tensor(dims, t) => Type::Tensor(dims.clone(), Box::new(self.resolve(t))),
+            Type::Quaternion(t) => Type::Quaternion(Box::new(self.resolve(t))),
+            Type::Complex(t) => Type::Complex(Box::new(self.resolve(t))),
+            Type::Pointer(t) => Type::Pointer(Box::new(self.resolve(t))),
+            Type::Optional(t) => Type::Optional(Box::new(self.resolve(t))),
+            Type::Function { params, return_type, priority } => Type::Function {
+                params: params.iter().map(|t| self.resolve(t)).collect(),
+                return_type: Box::new(self.resolve(return_type)),
+                priority: *priority,
+            },
+            _ => type_.clone(),
+        }
+    }
+
+    fn occurs(id: u32, type_: &Type) -> bool {
+        match type_ {
+            Type::Var(other) => *other == id,
+            Type::Array(t) | Type::FixedArray(t, _) | Type::Vector(_, t) | Type::Matrix(_, _, t) | Type::Tensor(_, t) |
+            Type::Quaternion(t) | Type::Complex(t) | Type::Pointer(t) | Type::Optional(t) => Self::occurs(id, t),
+            Type::Tuple(types) => types.iter().any(|t| Self::occurs(id, t)),
+            Type::Function { params, return_type, .. } => {
+                params.iter().any(|t| Self::occurs(id, t)) || Self::occurs(id, return_type)
             }
-            constraints = new_constraints;
+            _ => false,
         }
-        Ok(())
     }
 
-    fn unify(&mut self, left: &Type, right: &Type) -> Result<Option<TypeConstraint>> {
-        match (left, right) {
-            (Type::Named(name), other) | (other, Type::Named(name)) => {
-                if let Some(defined_type) = self.type_vars.get(name) {
-                    Ok(Some(TypeConstraint {
-                        left: defined_type.clone(),
-                        right: other.clone(),
-                    }))
-                } else {
-                    Ok(None)
+    fn unify(&mut self, left: &Type, right: &Type) -> Result<()> {
+        let left = self.resolve(left);
+        let right = self.resolve(right);
+        match (&left, &right) {
+            (Type::Var(a), Type::Var(b)) if a == b => Ok(()),
+            (Type::Var(id), other) | (other, Type::Var(id)) => {
+                // `?0 = [?0]` のような無限の型を作らない
+                if Self::occurs(*id, other) {
+                    return Err(SlangError::Type(format!(
+                        "Cannot construct infinite type: ?{} = {}",
+                        id, other
+                    )));
                 }
+                self.substitution.insert(*id, other.clone());
+                Ok(())
             }
-            (Type::Array(t1), Type::Array(t2)) => {
-                Ok(Some(TypeConstraint {
-                    left: *t1.clone(),
-                    right: *t2.clone(),
-                }))
+            (Type::Array(t1), Type::Array(t2)) |
+            (Type::Quaternion(t1), Type::Quaternion(t2)) |
+            (Type::Complex(t1), Type::Complex(t2)) |
+            (Type::Pointer(t1), Type::Pointer(t2)) |
+            (Type::Optional(t1), Type::Optional(t2)) => self.unify(t1, t2),
+            (Type::FixedArray(t1, n1), Type::FixedArray(t2, n2)) if n1 == n2 => self.unify(t1, t2),
+            (Type::Vector(d1, t1), Type::Vector(d2, t2)) if d1 == d2 => self.unify(t1, t2),
+            // 構造体は名前で一致を判定する。型検査前の注釈では同じ構造体が `Named` のこともある
+            (Type::Struct(a), Type::Struct(b) | Type::Named(b)) | (Type::Named(b), Type::Struct(a)) if a == b => Ok(()),
+            (Type::Matrix(r1, c1, t1), Type::Matrix(r2, c2, t2)) if r1 == r2 && c1 == c2 => {
+                self.unify(t1, t2)
             }
+            (Type::Tensor(d1, t1), Type::Tensor(d2, t2)) if d1 == d2 => self.unify(t1, t2),
             (Type::Tuple(t1), Type::Tuple(t2)) => {
                 if t1.len() != t2.len() {
                     return Err(SlangError::Type("Tuple length mismatch".to_string()));
                 }
                 for (a, b) in t1.iter().zip(t2.iter()) {
-                    if let Some(constraint) = self.unify(a, b)? {
-                        return Ok(Some(constraint));
-                    }
+                    self.unify(a, b)?;
                 }
-                Ok(None)
+                Ok(())
             }
             (Type::Function { params: p1, return_type: r1, priority: pr1 },
              Type::Function { params: p2, return_type: r2, priority: pr2 }) => {
                 if p1.len() != p2.len() {
                     return Err(SlangError::Type("Function parameter count mismatch".to_string()));
                 }
-                for (a, b) in p1.iter().zip(p2.iter()) {
-                    if let Some(constraint) = self.unify(a, b)? {
-                        return Ok(Some(constraint));
-                    }
-                }
-                if let Some(constraint) = self.unify(r1, r2)? {
-                    return Ok(Some(constraint));
-                }
                 if pr1 != pr2 {
                     return Err(SlangError::Type("Function priority mismatch".to_string()));
                 }
-                Ok(None)
+                for (a, b) in p1.iter().zip(p2.iter()) {
+                    self.unify(a, b)?;
+                }
+                self.unify(r1, r2)
             }
-            (a, b) if a == b => Ok(None),
-            _ => Err(SlangError::Type("Type mismatch".to_string())),
+            (a, b) if a == b => Ok(()),
+            _ => Err(SlangError::Type(format!("Type mismatch: {} and {}", left, right))),
         }
     }
 
     fn get_current_return_type(&self) -> Option<Type> {
-        // 現在の関数の戻り値の型を取得
-        // 実装は関数のコンテキスト管理に依存
-        None
+        self.current_return_type.clone()
     }
-} 
\ No newline at end of file
+}
+
+fn function_type(function: &Function) -> Type {
+    Type::Function {
+        params: function.parameters.iter().map(|p| p.type_annotation.clone()).collect(),
+        return_type: Box::new(function.return_type.clone()),
+        priority: Some(function.primary_priority() as u32),
+    }
+}
+
+impl Default for TypeInference {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(value: i64) -> Expression {
+        Expression::Literal(Literal::Int(value))
+    }
+
+    #[test]
+    fn test_infer_array_literal() {
+        let mut inference = TypeInference::new();
+        let array = Expression::Array(vec![int(1), int(2)]);
+        assert_eq!(inference.infer_expression(&array).unwrap(), Type::Array(Box::new(Type::Int)));
+
+        let Type::Array(element) = inference.infer_expression(&Expression::Array(vec![])).unwrap() else {
+            panic!("expected an array type");
+        };
+        inference.add_constraint(Type::Int, (*element).clone()).unwrap();
+        assert_eq!(inference.resolve(&element), Type::Int);
+    }
+
+    #[test]
+    fn test_infer_index_expression() {
+        let mut inference = TypeInference::new();
+        let index = Expression::Index {
+            array: Box::new(Expression::Array(vec![int(1), int(2)])),
+            index: Box::new(int(0)),
+        };
+        assert_eq!(inference.infer_expression(&index).unwrap(), Type::Int);
+
+        let index = Expression::Index {
+            array: Box::new(int(1)),
+            index: Box::new(int(0)),
+        };
+        assert!(inference.infer_expression(&index).is_err());
+    }
+
+    fn binary(left: Expression, op: BinaryOperator, right: Expression) -> Expression {
+        Expression::BinaryOp(Box::new(BinaryOpExpression {
+            left: Box::new(left),
+            op,
+            right: Box::new(right),
+        }))
+    }
+
+    #[test]
+    fn test_infer_comparisons() {
+        let mut inference = TypeInference::new();
+        for op in [
+            BinaryOperator::Equals,
+            BinaryOperator::NotEquals,
+            BinaryOperator::LessThan,
+            BinaryOperator::LessThanEquals,
+            BinaryOperator::GreaterThan,
+            BinaryOperator::GreaterThanEquals,
+        ] {
+            assert_eq!(inference.infer_expression(&binary(int(1), op, int(2))).unwrap(), Type::Bool);
+        }
+        let string = || Expression::Literal(Literal::String("a".to_string()));
+        assert!(inference.infer_expression(&binary(int(1), BinaryOperator::LessThan, string())).is_err());
+        assert_eq!(inference.infer_expression(&binary(string(), BinaryOperator::LessThan, string())).unwrap(), Type::Bool);
+
+        let negated = Expression::UnaryOp(Box::new(UnaryOpExpression { op: UnaryOperator::Negate, expr: Box::new(int(1)) }));
+        assert_eq!(inference.infer_expression(&negated).unwrap(), Type::Int);
+    }
+
+    #[test]
+    fn test_infer_linear_algebra_like_checker() {
+        let mut inference = TypeInference::new();
+        let vec = |n| Type::Vector(n, Box::new(Type::Float));
+        assert_eq!(inference.infer_binary_operation(&BinaryOperator::Add, vec(3), vec(3)).unwrap(), vec(3));
+        assert!(inference.infer_binary_operation(&BinaryOperator::Add, vec(3), vec(2)).is_err());
+        let complex = Type::Complex(Box::new(Type::Float));
+        assert_eq!(inference.infer_binary_operation(&BinaryOperator::Equals, complex, Type::Int).unwrap(), Type::Bool);
+    }
+
+    #[test]
+    fn test_infer_tuple_literal() {
+        let mut inference = TypeInference::new();
+        let tuple = Expression::Tuple(vec![int(1), Expression::Literal(Literal::String("a".to_string()))]);
+        assert_eq!(
+            inference.infer_expression(&tuple).unwrap(),
+            Type::Tuple(vec![Type::Int, Type::String])
+        );
+    }
+    fn let_statement(name: &str, value: Expression) -> Statement {
+        Statement::Let(LetStatement {
+            name: name.to_string(),
+            mutable: false,
+            type_annotation: None,
+            value: Box::new(value),
+            priority: None,
+        })
+    }
+
+    #[test]
+    fn test_let_without_annotation_is_solved() {
+        let mut inference = TypeInference::new();
+        let block = Block {
+            statements: vec![
+                let_statement("x", int(5)),
+                let_statement("y", Expression::BinaryOp(Box::new(BinaryOpExpression {
+                    left: Box::new(Expression::Identifier("x".to_string())),
+                    op: BinaryOperator::Add,
+                    right: Box::new(Expression::Literal(Literal::Float(1.5))),
+                }))),
+            ],
+        };
+        inference.infer_block(&block).unwrap();
+        inference.solve_constraints().unwrap();
+        assert_eq!(inference.type_vars["x"], Type::Int);
+        assert_eq!(inference.type_vars["y"], Type::Float);
+    }
+
+    #[test]
+    fn test_unify_occurs_check() {
+        let mut inference = TypeInference::new();
+        let var = inference.fresh_type_var();
+        assert!(inference.unify(&var, &Type::Array(Box::new(var.clone()))).is_err());
+
+        let other = inference.fresh_type_var();
+        inference.unify(&Type::Array(Box::new(var.clone())), &Type::Array(Box::new(other.clone()))).unwrap();
+        inference.unify(&other, &Type::Bool).unwrap();
+        assert_eq!(inference.resolve(&var), Type::Bool);
+    }
+
+    #[test]
+    fn test_unify_fixed_arrays_and_structs() {
+        let fixed = |element: Type, len| Type::FixedArray(Box::new(element), len);
+        let point = || Type::Struct("Point".to_string());
+        let mut inference = TypeInference::new();
+        let var = inference.fresh_type_var();
+        inference.unify(&fixed(Type::Int, 3), &fixed(var.clone(), 3)).unwrap();
+        assert_eq!(inference.resolve(&fixed(var.clone(), 3)), fixed(Type::Int, 3));
+        assert!(inference.unify(&fixed(Type::Int, 3), &fixed(Type::Int, 2)).is_err());
+        assert!(inference.unify(&fixed(Type::Int, 3), &Type::Array(Box::new(Type::Int))).is_err());
+
+        let var = inference.fresh_type_var();
+        inference.unify(&fixed(var.clone(), 2), &fixed(point(), 2)).unwrap();
+        assert_eq!(inference.resolve(&var), point());
+        inference.unify(&point(), &Type::Named("Point".to_string())).unwrap();
+        assert!(inference.unify(&point(), &Type::Struct("Line".to_string())).is_err());
+        let var = inference.fresh_type_var();
+        assert!(inference.unify(&var, &fixed(var.clone(), 1)).is_err());
+
+        // `[T; 3]` を受け取る総称関数に `[int; 3]` を渡すと `T` が int になる
+        let source = "fn first<T>(xs: [T; 3]) -> T { return xs[0]; } fn f(ys: [int; 3]) -> void { let a = first(ys); }";
+        let ast = crate::parser::Parser::new(crate::lexer::Lexer::new(source)).parse().unwrap();
+        let mut inference = TypeInference::new();
+        inference.infer_types(&ast).unwrap();
+        assert_eq!(inference.type_vars["a"], Type::Int);
+    }
+
+    #[test]
+    fn test_return_uses_enclosing_function_type() {
+        let function = |value: Expression| Function {
+            name: "f".to_string(),
+            type_params: vec![],
+            parameters: vec![Parameter { name: "n".to_string(), type_annotation: Type::Int, mutable: false }],
+            return_type: Type::Int,
+            priorities: vec![],
+            body: Block {
+                statements: vec![Statement::Return(ReturnStatement { value: Some(Box::new(value)) })],
+            },
+        };
+
+        let mut inference = TypeInference::new();
+        assert!(inference.infer_function(&function(Expression::Identifier("n".to_string()))).is_ok());
+        assert!(inference.get_current_return_type().is_none());
+
+        let mut inference = TypeInference::new();
+        let string = Expression::Literal(Literal::String("a".to_string()));
+        assert!(inference.infer_function(&function(string)).is_err());
+    }
+
+    #[test]
+    fn test_float_returned_from_int_function_is_rejected() {
+        let function = Function {
+            name: "f".to_string(),
+            type_params: vec![],
+            parameters: vec![],
+            return_type: Type::Int,
+            priorities: vec![],
+            body: Block {
+                statements: vec![Statement::Return(ReturnStatement {
+                    value: Some(Box::new(Expression::Literal(Literal::Float(1.5)))),
+                })],
+            },
+        };
+        let mut inference = TypeInference::new();
+        assert!(matches!(inference.infer_function(&function), Err(SlangError::Type(_))));
+    }
+
+    #[test]
+    fn test_generic_function_is_instantiated_per_call() {
+        let named = |name: &str| Type::Named(name.to_string());
+        let identity = Function {
+            name: "identity".to_string(),
+            type_params: vec!["T".to_string()],
+            parameters: vec![Parameter { name: "x".to_string(), type_annotation: named("T"), mutable: false }],
+            return_type: named("T"),
+            priorities: vec![],
+            body: Block {
+                statements: vec![Statement::Return(ReturnStatement {
+                    value: Some(Box::new(Expression::Identifier("x".to_string()))),
+                })],
+            },
+        };
+        let call = |argument: Expression| Expression::Call(Box::new(CallExpression {
+            function: "identity".to_string(),
+            arguments: vec![argument],
+        }));
+        let main = Function {
+            name: "main".to_string(),
+            type_params: vec![],
+            parameters: vec![],
+            return_type: Type::Unit,
+            priorities: vec![],
+            body: Block {
+                statements: vec![
+                    let_statement("a", call(int(1))),
+                    let_statement("b", call(Expression::Literal(Literal::String("s".to_string())))),
+                ],
+            },
+        };
+        let mut ast = AST::new();
+        ast.add_function(identity);
+        ast.add_function(main);
+
+        let mut inference = TypeInference::new();
+        inference.infer_types(&ast).unwrap();
+        assert_eq!(inference.type_vars["a"], Type::Int);
+        assert_eq!(inference.type_vars["b"], Type::String);
+    }
+
+    #[test]
+    fn test_null_unifies_with_any_optional() {
+        let infer = |source: &str| {
+            let ast = crate::parser::Parser::new(crate::lexer::Lexer::new(source)).parse().unwrap();
+            let mut inference = TypeInference::new();
+            inference.infer_types(&ast).map(|_| inference.type_vars)
+        };
+        let optional = |inner: Type| Type::Optional(Box::new(inner));
+
+        let type_vars = infer("fn f() -> void { let x: int? = null; let y: int? = 1; let n = null; let b = n == y; }").unwrap();
+        assert_eq!(type_vars["x"], optional(Type::Int));
+        assert_eq!(type_vars["y"], optional(Type::Int));
+        assert_eq!(type_vars["n"], optional(Type::Int));
+
+        let error = infer("fn f() -> void { let x = null + 1; }").unwrap_err();
+        assert_eq!(error.to_string(), "Type error: Operand is null; null can only be compared with == or !=");
+        assert!(infer("fn f() -> void { let x: int = null; }").is_err());
+
+        let type_vars = infer("fn f() -> void { let a = [1, null]; let b = [null, 2]; let t: (int, int?) = (1, null); }").unwrap();
+        assert_eq!(type_vars["a"], Type::Array(Box::new(optional(Type::Int))));
+        assert_eq!(type_vars["b"], Type::Array(Box::new(optional(Type::Int))));
+        assert_eq!(type_vars["t"], Type::Tuple(vec![Type::Int, optional(Type::Int)]));
+    }
+}
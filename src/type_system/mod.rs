@@ -1,14 +1,16 @@
 use std::collections::HashMap;
 use std::fmt;
 
-use crate::ast::*;
 use crate::error::Result;
 use crate::error::SlangError;
 
+mod builtins;
 mod inference;
 mod cast;
 mod checker;
+mod exhaustiveness;
 
+pub(crate) use builtins::{check_builtin_call, is_builtin};
 pub use inference::TypeInference;
 pub use cast::TypeCast;
 pub use checker::TypeChecker;
@@ -23,6 +25,8 @@ pub enum Type {
     Char,
     Void,
     Array(Box<Type>),
+    // `[T; N]`。長さが型に入る配列。実行時の値は `[T]` と同じ
+    FixedArray(Box<Type>, usize),
     Tuple(Vec<Type>),
     Vector(usize, Box<Type>),
     Matrix(usize, usize, Box<Type>),
@@ -35,7 +39,14 @@ pub enum Type {
         priority: Option<u32>,
     },
     Pointer(Box<Type>),
+    // `T?`。`null` の型は中身が決まっていない `Optional` で、どの `T?` とも一致する
+    Optional(Box<Type>),
+    // 型検査で解決される前の型の名前。型パラメータはこのまま残る
     Named(String),
+    // `type` で定義した構造体。型検査が `Named` から解決する
+    Struct(String),
+    // 推論中の未解決の型
+    Var(u32),
 }
 
 impl Type {
@@ -67,10 +78,35 @@ impl Type {
         matches!(self, Type::Pointer(_))
     }
 
+    pub fn is_optional(&self) -> bool {
+        matches!(self, Type::Optional(_))
+    }
+
+    // 型検査での `null` の型。推論では中身を新しい型変数にする
+    pub fn null() -> Type {
+        Type::Optional(Box::new(Type::Var(0)))
+    }
+
+    // 型検査での空の配列リテラル `[]` の型。要素の型は注釈や引数の型から決める
+    pub fn empty_array() -> Type {
+        Type::Array(Box::new(Type::Var(0)))
+    }
+
     pub fn is_numeric(&self) -> bool {
         matches!(self, Type::Int | Type::Float)
     }
 
+    // 複素数と数値 (または複素数) の四則演算の結果の型。複素数が関わらなければ `None`。
+    // 実部・虚部は常に float として扱う
+    pub fn complex_arithmetic_result(&self, other: &Type) -> Option<Type> {
+        let operand = |t: &Type| t.is_numeric() || t.is_complex();
+        if (self.is_complex() || other.is_complex()) && operand(self) && operand(other) {
+            Some(Type::Complex(Box::new(Type::Float)))
+        } else {
+            None
+        }
+    }
+
     pub fn get_vector_dimension(&self) -> Option<usize> {
         match self {
             Type::Vector(dim, _) => Some(*dim),
@@ -103,6 +139,21 @@ impl Type {
         }
     }
 
+    // 組み込みメソッドのシグネチャ。ランタイムの標準ライブラリと対応させておくこと
+    pub fn get_method_signature(&self, method: &str) -> Option<Type> {
+        let (params, return_type) = match (self, method) {
+            (Type::Array(_) | Type::FixedArray(_, _) | Type::Vector(_, _) | Type::String, "len") => (vec![], Type::Int),
+            (Type::Array(element), "push") => (vec![(**element).clone()], Type::Unit),
+            (Type::Quaternion(_), "conjugate" | "normalize") => (vec![], Type::Quaternion(Box::new(Type::Float))),
+            _ => return None,
+        };
+        Some(Type::Function {
+            params,
+            return_type: Box::new(return_type),
+            priority: None,
+        })
+    }
+
     pub fn get_pointer_type(&self) -> Option<&Type> {
         match self {
             Type::Pointer(inner) => Some(inner),
@@ -127,24 +178,101 @@ impl Type {
         }
     }
 
-    pub fn is_compatible_with(&self, other: &Type) -> bool {
+    // 暗黙の変換を認めない一致判定。代入・戻り値・引数などの検査に使う
+    // `null` の型 (`Optional(Var)`) は、タプルや配列の中にあってもどの `T?` とも一致する
+    pub fn unifies_with(&self, other: &Type) -> bool {
+        match (self, other) {
+            (Type::Optional(a), Type::Optional(b)) if matches!(**a, Type::Var(_)) || matches!(**b, Type::Var(_)) => true,
+            (Type::Optional(a), Type::Optional(b)) | (Type::Array(a), Type::Array(b)) => a.unifies_with(b),
+            (Type::FixedArray(a, n1), Type::FixedArray(b, n2)) => n1 == n2 && a.unifies_with(b),
+            (Type::Tuple(t1), Type::Tuple(t2)) => {
+                t1.len() == t2.len() && t1.iter().zip(t2).all(|(a, b)| a.unifies_with(b))
+            }
+            (
+                Type::Function { params: p1, return_type: r1, priority: pr1 },
+                Type::Function { params: p2, return_type: r2, priority: pr2 },
+            ) => {
+                p1.len() == p2.len()
+                    && p1.iter().zip(p2).all(|(a, b)| a.unifies_with(b))
+                    && r1.unifies_with(r2)
+                    && pr1 == pr2
+            }
+            _ => self == other,
+        }
+    }
+
+    // `self` の値を `target` の変数・引数・戻り値に渡せるか。`T` の値はそのまま `T?` に渡せる
+    // `[T; N]` の値は長さを忘れて `[T]` として渡せる。タプルと配列は要素ごとに同じ規則で判定する
+    pub fn is_assignable_to(&self, target: &Type) -> bool {
+        match (self, target) {
+            (_, Type::Optional(inner)) if !self.is_optional() => self.is_assignable_to(inner),
+            (Type::Array(element) | Type::FixedArray(element, _), Type::Array(target_element)) => {
+                element.is_assignable_to(target_element)
+            }
+            (Type::FixedArray(element, n1), Type::FixedArray(target_element, n2)) => {
+                n1 == n2 && element.is_assignable_to(target_element)
+            }
+            (Type::Tuple(t1), Type::Tuple(t2)) => {
+                t1.len() == t2.len() && t1.iter().zip(t2).all(|(a, b)| a.is_assignable_to(b))
+            }
+            _ => self.unifies_with(target),
+        }
+    }
+
+    // 型パラメータ (`Type::Named`) を束縛された型に置き換える
+    pub fn substitute(&self, bindings: &HashMap<String, Type>) -> Type {
+        self.map_named(&|name| bindings.get(name).cloned())
+    }
+
+    // 構造体の名前の `Named` を `Struct` にする。`is_struct` が偽の名前 (型パラメータなど) はそのまま
+    pub fn resolve_structs(&self, is_struct: &dyn Fn(&str) -> bool) -> Type {
+        self.map_named(&|name| is_struct(name).then(|| Type::Struct(name.to_string())))
+    }
+
+    // 型の中の `Named` を `replace` が返す型に置き換える。`None` ならそのまま
+    fn map_named(&self, replace: &dyn Fn(&str) -> Option<Type>) -> Type {
+        let map = |t: &Type| Box::new(t.map_named(replace));
+        match self {
+            Type::Named(name) => replace(name).unwrap_or_else(|| self.clone()),
+            Type::Array(t) => Type::Array(map(t)),
+            Type::FixedArray(t, len) => Type::FixedArray(map(t), *len),
+            Type::Tuple(types) => Type::Tuple(types.iter().map(|t| t.map_named(replace)).collect()),
+            Type::Vector(dim, t) => Type::Vector(*dim, map(t)),
+            Type::Matrix(rows, cols, t) => Type::Matrix(*rows, *cols, map(t)),
+            Type::Tensor(dims, t) => Type::Tensor(dims.clone(), map(t)),
+            Type::Quaternion(t) => Type::Quaternion(map(t)),
+            Type::Complex(t) => Type::Complex(map(t)),
+            Type::Pointer(t) => Type::Pointer(map(t)),
+            Type::Optional(t) => Type::Optional(map(t)),
+            Type::Function { params, return_type, priority } => Type::Function {
+                params: params.iter().map(|t| t.map_named(replace)).collect(),
+                return_type: map(return_type),
+                priority: *priority,
+            },
+            _ => self.clone(),
+        }
+    }
+
+    // 明示的なキャストで変換できるかどうか。数値と文字列の相互変換も含む
+    pub fn is_coercible_to(&self, other: &Type) -> bool {
         match (self, other) {
             (Type::Int, Type::Float) | (Type::Float, Type::Int) => true,
             (Type::Int, Type::String) | (Type::Float, Type::String) | (Type::Bool, Type::String) => true,
             (Type::String, Type::Int) | (Type::String, Type::Float) | (Type::String, Type::Bool) => true,
-            (Type::Array(t1), Type::Array(t2)) => t1.is_compatible_with(t2),
+            (Type::Array(t1), Type::Array(t2)) => t1.is_coercible_to(t2),
+            (Type::FixedArray(t1, n1), Type::FixedArray(t2, n2)) => n1 == n2 && t1.is_coercible_to(t2),
             (Type::Tuple(t1), Type::Tuple(t2)) => {
-                t1.len() == t2.len() && t1.iter().zip(t2.iter()).all(|(a, b)| a.is_compatible_with(b))
+                t1.len() == t2.len() && t1.iter().zip(t2.iter()).all(|(a, b)| a.is_coercible_to(b))
             }
-            (Type::Vector(d1, t1), Type::Vector(d2, t2)) => d1 == d2 && t1.is_compatible_with(t2),
+            (Type::Vector(d1, t1), Type::Vector(d2, t2)) => d1 == d2 && t1.is_coercible_to(t2),
             (Type::Matrix(r1, c1, t1), Type::Matrix(r2, c2, t2)) => {
-                r1 == r2 && c1 == c2 && t1.is_compatible_with(t2)
+                r1 == r2 && c1 == c2 && t1.is_coercible_to(t2)
             }
             (Type::Tensor(d1, t1), Type::Tensor(d2, t2)) => {
-                d1 == d2 && t1.is_compatible_with(t2)
+                d1 == d2 && t1.is_coercible_to(t2)
             }
-            (Type::Quaternion(t1), Type::Quaternion(t2)) => t1.is_compatible_with(t2),
-            (Type::Complex(t1), Type::Complex(t2)) => t1.is_compatible_with(t2),
+            (Type::Quaternion(t1), Type::Quaternion(t2)) => t1.is_coercible_to(t2),
+            (Type::Complex(t1), Type::Complex(t2)) => t1.is_coercible_to(t2),
             (
                 Type::Function {
                     params: p1,
@@ -158,21 +286,26 @@ impl Type {
                 },
             ) => {
                 p1.len() == p2.len()
-                    && p1.iter().zip(p2.iter()).all(|(a, b)| a.is_compatible_with(b))
-                    && r1.is_compatible_with(r2)
+                    && p1.iter().zip(p2.iter()).all(|(a, b)| a.is_coercible_to(b))
+                    && r1.is_coercible_to(r2)
                     && pr1 == pr2
             }
-            (Type::Pointer(t1), Type::Pointer(t2)) => t1.is_compatible_with(t2),
-            (Type::Named(n1), Type::Named(n2)) => n1 == n2,
+            (Type::Pointer(t1), Type::Pointer(t2)) => t1.is_coercible_to(t2),
+            (Type::Optional(t1), Type::Optional(t2)) => t1.is_coercible_to(t2),
+            (Type::Named(n1), Type::Named(n2)) | (Type::Struct(n1), Type::Struct(n2)) => n1 == n2,
             (t1, t2) => t1 == t2,
         }
     }
 
+    // 優先所有格の規則: 優先度が真に高い関数だけが低い関数を所有できる。
+    // 型検査では、呼び出される関数が呼び出し元を所有できる (呼び出し元より優先度が高い) 呼び出しを拒否する。
+    // 同じ優先度どうし (再帰を含む) は互いに呼び出せる。優先度を指定していない関数はどちら側でも制約を受けない
     pub fn can_own(&self, other: &Type) -> bool {
         match (self, other) {
-            (Type::Function { priority: p1, .. }, Type::Function { priority: p2, .. }) => {
-                p1 > p2
-            }
+            (
+                Type::Function { priority: Some(p1), .. },
+                Type::Function { priority: Some(p2), .. },
+            ) => p1 > p2,
             _ => false,
         }
     }
@@ -189,6 +322,7 @@ impl fmt::Display for Type {
             Type::Char => write!(f, "char"),
             Type::Void => write!(f, "void"),
             Type::Array(t) => write!(f, "[{}]", t),
+            Type::FixedArray(t, len) => write!(f, "[{}; {}]", t, len),
             Type::Tuple(types) => {
                 write!(f, "(")?;
                 for (i, t) in types.iter().enumerate() {
@@ -218,6 +352,10 @@ impl fmt::Display for Type {
                 return_type,
                 priority,
             } => {
+                // 関数定義と同じく、優先度は前に置く
+                if let Some(p) = priority {
+                    write!(f, "Function:type:priority: {} ", p)?;
+                }
                 write!(f, "fn(")?;
                 for (i, param) in params.iter().enumerate() {
                     if i > 0 {
@@ -225,14 +363,12 @@ impl fmt::Display for Type {
                     }
                     write!(f, "{}", param)?;
                 }
-                write!(f, ") -> {}", return_type)?;
-                if let Some(p) = priority {
-                    write!(f, " @{}", p)?;
-                }
-                Ok(())
+                write!(f, ") -> {}", return_type)
             }
             Type::Pointer(t) => write!(f, "*{}", t),
-            Type::Named(name) => write!(f, "{}", name),
+            Type::Optional(t) => write!(f, "{}?", t),
+            Type::Named(name) | Type::Struct(name) => write!(f, "{}", name),
+            Type::Var(id) => write!(f, "?{}", id),
         }
     }
 }
@@ -242,39 +378,39 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_type_compatibility() {
-        assert!(Type::Int.is_compatible_with(&Type::Float));
-        assert!(Type::Float.is_compatible_with(&Type::Int));
-        assert!(Type::Int.is_compatible_with(&Type::String));
-        assert!(Type::String.is_compatible_with(&Type::Int));
+    fn test_type_coercion() {
+        assert!(Type::Int.is_coercible_to(&Type::Float));
+        assert!(Type::Float.is_coercible_to(&Type::Int));
+        assert!(Type::Int.is_coercible_to(&Type::String));
+        assert!(Type::String.is_coercible_to(&Type::Int));
 
         let array1 = Type::Array(Box::new(Type::Int));
         let array2 = Type::Array(Box::new(Type::Float));
-        assert!(array1.is_compatible_with(&array2));
+        assert!(array1.is_coercible_to(&array2));
 
         let tuple1 = Type::Tuple(vec![Type::Int, Type::String]);
         let tuple2 = Type::Tuple(vec![Type::Float, Type::String]);
-        assert!(tuple1.is_compatible_with(&tuple2));
+        assert!(tuple1.is_coercible_to(&tuple2));
 
         let vec1 = Type::Vector(3, Box::new(Type::Int));
         let vec2 = Type::Vector(3, Box::new(Type::Float));
-        assert!(vec1.is_compatible_with(&vec2));
+        assert!(vec1.is_coercible_to(&vec2));
 
         let mat1 = Type::Matrix(2, 2, Box::new(Type::Int));
         let mat2 = Type::Matrix(2, 2, Box::new(Type::Float));
-        assert!(mat1.is_compatible_with(&mat2));
+        assert!(mat1.is_coercible_to(&mat2));
 
         let tensor1 = Type::Tensor(vec![2, 3, 4], Box::new(Type::Int));
         let tensor2 = Type::Tensor(vec![2, 3, 4], Box::new(Type::Float));
-        assert!(tensor1.is_compatible_with(&tensor2));
+        assert!(tensor1.is_coercible_to(&tensor2));
 
         let quat1 = Type::Quaternion(Box::new(Type::Int));
         let quat2 = Type::Quaternion(Box::new(Type::Float));
-        assert!(quat1.is_compatible_with(&quat2));
+        assert!(quat1.is_coercible_to(&quat2));
 
         let complex1 = Type::Complex(Box::new(Type::Int));
         let complex2 = Type::Complex(Box::new(Type::Float));
-        assert!(complex1.is_compatible_with(&complex2));
+        assert!(complex1.is_coercible_to(&complex2));
 
         let func1 = Type::Function {
             params: vec![Type::Int],
@@ -286,11 +422,42 @@ mod tests {
             return_type: Box::new(Type::Float),
             priority: Some(1),
         };
-        assert!(func1.is_compatible_with(&func2));
+        assert!(func1.is_coercible_to(&func2));
 
         let ptr1 = Type::Pointer(Box::new(Type::Int));
         let ptr2 = Type::Pointer(Box::new(Type::Float));
-        assert!(ptr1.is_compatible_with(&ptr2));
+        assert!(ptr1.is_coercible_to(&ptr2));
+    }
+
+    #[test]
+    fn test_unification_is_strict() {
+        assert!(Type::Int.unifies_with(&Type::Int));
+        assert!(!Type::Int.unifies_with(&Type::Float));
+        assert!(!Type::Int.unifies_with(&Type::String));
+        assert!(!Type::String.unifies_with(&Type::Bool));
+        assert!(!Type::Array(Box::new(Type::Int)).unifies_with(&Type::Array(Box::new(Type::String))));
+        assert!(Type::Named("Point".to_string()).unifies_with(&Type::Named("Point".to_string())));
+    }
+
+    #[test]
+    fn test_fixed_arrays_and_structs() {
+        let fixed = |len| Type::FixedArray(Box::new(Type::Int), len);
+        let array = Type::Array(Box::new(Type::Int));
+        // 長さを忘れて `[T]` には渡せるが、逆や長さの違うものには渡せない
+        assert!(fixed(3).is_assignable_to(&array));
+        assert!(fixed(3).is_assignable_to(&Type::Optional(Box::new(array.clone()))));
+        assert!(!array.is_assignable_to(&fixed(3)));
+        assert!(!fixed(3).is_assignable_to(&fixed(2)));
+
+        let named = |name: &str| Type::Named(name.to_string());
+        let point = Type::Struct("Point".to_string());
+        let resolved = Type::Function { params: vec![named("Point"), named("T")], return_type: Box::new(named("Point")), priority: None }
+            .resolve_structs(&|name| name == "Point");
+        assert_eq!(
+            resolved,
+            Type::Function { params: vec![point.clone(), named("T")], return_type: Box::new(point.clone()), priority: None }
+        );
+        assert!(!point.unifies_with(&named("Point")));
     }
 
     #[test]
@@ -341,12 +508,15 @@ mod tests {
         let complex = Type::Complex(Box::new(Type::Float));
         assert_eq!(format!("{}", complex), "complex<float>");
 
+        assert_eq!(format!("{}", Type::FixedArray(Box::new(Type::Int), 3)), "[int; 3]");
+        assert_eq!(format!("{}", Type::Struct("Point".to_string())), "Point");
+
         let func = Type::Function {
             params: vec![Type::Int, Type::String],
             return_type: Box::new(Type::Bool),
             priority: Some(1),
         };
-        assert_eq!(format!("{}", func), "fn(int, string) -> bool @1");
+        assert_eq!(format!("{}", func), "Function:type:priority: 1 fn(int, string) -> bool");
 
         let ptr = Type::Pointer(Box::new(Type::Int));
         assert_eq!(format!("{}", ptr), "*int");